@@ -0,0 +1,581 @@
+//! Compilation of the type-checked AST into bytecode.
+
+use syntax::ast::{Expr, LValue, Decl, TType, OptionalExpr, OptionalTypeExprTupleList};
+use syntax::ptr::B;
+use instr::*;
+
+enum Entry{
+    //`level` is the nesting depth of the function the variable belongs to
+    Var{slot : u32, level : u32},
+    //`level` is the nesting depth of the function the declaration is in
+    Fun{index : u32, level : u32, arity : u32, returns : bool},
+    Type(TType)
+}
+
+//the function whose body is being compiled
+struct Frame{
+    code : Vec<Instr>,
+    lines : Vec<(u32, u32)>,
+    locals : u32,
+    //jumps out of the enclosing loops waiting for their target, innermost last
+    breaks : Vec<Vec<usize>>
+}
+
+struct Compiler{
+    //scopes are separated by None markers
+    env : Vec<(String, Option<Entry>)>,
+    module : Module,
+    frames : Vec<Frame>,
+    //the source line of the expression being compiled, 0 if it's unknown
+    line : usize
+}
+
+//whether an expression left a value on the stack
+type CompileResult = Result<bool, String>;
+
+impl Compiler{
+    fn new() -> Self{
+        Compiler { env : Vec::new(), module : Module::default(), frames : Vec::new(), line : 0 }
+    }
+
+    fn level(&self) -> u32{
+        self.frames.len() as u32 - 1
+    }
+
+    fn frame(&mut self) -> &mut Frame{
+        self.frames.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instr : Instr) -> usize{
+        let line = self.line as u32;
+        let f = self.frame();
+        if line != 0 && f.lines.last().map(|&(_, l)| l) != Some(line){
+            f.lines.push((f.code.len() as u32, line));
+        }
+        f.code.push(instr);
+        f.code.len() - 1
+    }
+
+    fn here(&mut self) -> u32{
+        self.frame().code.len() as u32
+    }
+
+    //points the jump at `at` to the next instruction
+    fn patch(&mut self, at : usize){
+        let target = self.here();
+        match self.frame().code[at]{
+            Instr::Jump(ref mut t) | Instr::JumpIfFalse(ref mut t) => *t = target,
+            _ => unreachable!()
+        }
+    }
+
+    fn new_slot(&mut self) -> u32{
+        let f = self.frame();
+        f.locals += 1;
+        f.locals - 1
+    }
+
+    fn string(&mut self, s : &[u8]) -> u32{
+        match self.module.strings.iter().position(|v| &**v == s){
+            Some(i) => i as u32,
+            None => {
+                self.module.strings.push(s.to_vec());
+                self.module.strings.len() as u32 - 1
+            }
+        }
+    }
+
+    fn layout(&mut self, names : Vec<u32>) -> u32{
+        match self.module.layouts.iter().position(|l| *l == names){
+            Some(i) => i as u32,
+            None => {
+                self.module.layouts.push(names);
+                self.module.layouts.len() as u32 - 1
+            }
+        }
+    }
+
+    fn lookup(&self, id : &str) -> Option<&Entry>{
+        for &(ref name, ref entry) in self.env.iter().rev(){
+            if name == id && entry.is_some(){
+                return entry.as_ref()
+            }
+        }
+        None
+    }
+
+    fn begin_scope(&mut self){
+        self.env.push((String::from("<marker>"), None));
+    }
+
+    fn end_scope(&mut self){
+        while self.env.pop().unwrap().1.is_some(){}
+    }
+
+    //follows type aliases
+    fn resolve(&self, ty : &TType) -> TType{
+        match *ty{
+            TType::TCustom(ref name) => match self.lookup(name){
+                Some(&Entry::Type(ref t)) if *t != *ty => self.resolve(t),
+                _ => ty.clone()
+            },
+            _ => ty.clone()
+        }
+    }
+
+    fn var(&self, id : &str) -> Result<(u32, u32), String>{
+        match self.lookup(id){
+            Some(&Entry::Var{slot, level}) => Ok((slot, self.level() - level)),
+            Some(_) => Err(format!("Invalid reference to variable '{}'. Different binding found.", id)),
+            None => Err(format!("Invalid reference to variable '{}'", id))
+        }
+    }
+
+    fn load(&mut self, id : &str) -> Result<(), String>{
+        let (slot, depth) = try!(self.var(id));
+        self.emit(if depth == 0 {Instr::Load(slot)} else {Instr::LoadUp(depth, slot)});
+        Ok(())
+    }
+
+    fn store(&mut self, id : &str) -> Result<(), String>{
+        let (slot, depth) = try!(self.var(id));
+        self.emit(if depth == 0 {Instr::Store(slot)} else {Instr::StoreUp(depth, slot)});
+        Ok(())
+    }
+
+    //compiles an expression that has to produce a value
+    fn value(&mut self, expr : &B<Expr>) -> Result<(), String>{
+        if try!(self.exp(expr)){
+            Ok(())
+        }
+        else{
+            Err(format!("{:?} doesn't produce a value", expr))
+        }
+    }
+
+    //compiles an expression whose value isn't needed
+    fn effect(&mut self, expr : &B<Expr>) -> Result<(), String>{
+        if try!(self.exp(expr)){
+            self.emit(Instr::Pop);
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, instr : Instr, lhs : &B<Expr>, rhs : &B<Expr>) -> CompileResult{
+        try!(self.value(lhs));
+        try!(self.value(rhs));
+        self.emit(instr);
+        Ok(true)
+    }
+
+    //pushes the value `lvalue` names
+    fn lvalue(&mut self, lvalue : &LValue) -> CompileResult{
+        match *lvalue{
+            LValue::SimpleVar(ref id) => try!(self.load(id)),
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                try!(self.lvalue(arr));
+                try!(self.value(idx));
+                self.emit(Instr::Index);
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                try!(self.lvalue(rec));
+                let s = self.string(field.as_bytes());
+                self.emit(Instr::GetField(s));
+            }
+        }
+        Ok(true)
+    }
+
+    //the array or record the last subscript or field is in is read like
+    //any other lvalue
+    fn assign(&mut self, lhs : &LValue, rhs : &B<Expr>) -> CompileResult{
+        match *lhs{
+            LValue::SimpleVar(ref id) => {
+                try!(self.value(rhs));
+                try!(self.store(id));
+            },
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                try!(self.lvalue(arr));
+                try!(self.value(idx));
+                try!(self.value(rhs));
+                self.emit(Instr::SetIndex);
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                try!(self.lvalue(rec));
+                try!(self.value(rhs));
+                let s = self.string(field.as_bytes());
+                self.emit(Instr::SetField(s));
+            }
+        }
+        Ok(false)
+    }
+
+    fn call(&mut self, name : &str, args : &OptionalTypeExprTupleList) -> CompileResult{
+        let given = args.as_ref().map(|a| a.len()).unwrap_or(0);
+        let (instr, arity, returns) = match self.lookup(name){
+            Some(&Entry::Fun{index, level, arity, returns}) => (Instr::Call(index, self.level() - level), arity as usize, returns),
+            Some(_) => return Err(format!("Invalid reference to function '{}'. Different binding found.", name)),
+            None => match Prim::from_name(name){
+                Some(p) => (Instr::Prim(p), p.arity(), p.returns()),
+                None => return Err(format!("Call to '{}' not found", name))
+            }
+        };
+        if given != arity{
+            return Err(format!("{}() expects {} argument(s) but {} were given", name, arity, given))
+        }
+        if let Some(ref list) = *args{
+            for &(_, ref e) in list{
+                try!(self.value(e));
+            }
+        }
+        self.emit(instr);
+        Ok(returns)
+    }
+
+    //the body of a loop, with the breaks in it jumping to the end of the loop
+    fn loop_body(&mut self, body : &B<Expr>) -> Result<Vec<usize>, String>{
+        self.frame().breaks.push(Vec::new());
+        let result = self.effect(body);
+        let breaks = self.frame().breaks.pop().unwrap();
+        try!(result);
+        Ok(breaks)
+    }
+
+    //nodes built without a span keep the line of their parent
+    fn exp(&mut self, expr : &B<Expr>) -> CompileResult{
+        let line = self.line;
+        if expr.span.line != 0{
+            self.line = expr.span.line;
+        }
+        let result = self.exp_kind(expr);
+        self.line = line;
+        result
+    }
+
+    fn exp_kind(&mut self, expr : &Expr) -> CompileResult{
+        match *expr{
+            Expr::NumExpr(n) => {
+                self.emit(Instr::Int(n));
+                Ok(true)
+            },
+            Expr::StringExpr(ref s) => {
+                let s = self.string(s);
+                self.emit(Instr::Str(s));
+                Ok(true)
+            },
+            Expr::NilExpr => {
+                self.emit(Instr::Nil);
+                Ok(true)
+            },
+            Expr::NoOpExpr => Ok(false),
+            Expr::IdExpr(ref id) => {
+                try!(self.load(id));
+                Ok(true)
+            },
+            Expr::LValueExpr(ref lvalue) => self.lvalue(lvalue),
+            Expr::AssignExpr(ref lhs, ref rhs) => self.assign(lhs, rhs),
+            Expr::AddExpr(ref l, ref r) => self.binary(Instr::Add, l, r),
+            Expr::SubExpr(ref l, ref r) => self.binary(Instr::Sub, l, r),
+            Expr::MulExpr(ref l, ref r) => self.binary(Instr::Mul, l, r),
+            Expr::DivExpr(ref l, ref r) => self.binary(Instr::Div, l, r),
+            Expr::ModExpr(ref l, ref r) => self.binary(Instr::Mod, l, r),
+            Expr::EqualsExpr(ref l, ref r) => self.binary(Instr::Eq, l, r),
+            Expr::NotEqualsExpr(ref l, ref r) => self.binary(Instr::Ne, l, r),
+            Expr::LessThanExpr(ref l, ref r) => self.binary(Instr::Lt, l, r),
+            Expr::LessThanEqualsExpr(ref l, ref r) => self.binary(Instr::Le, l, r),
+            Expr::GreaterThanExpr(ref l, ref r) => self.binary(Instr::Gt, l, r),
+            Expr::GreaterThanEqualsExpr(ref l, ref r) => self.binary(Instr::Ge, l, r),
+            Expr::IfThenExpr(ref cond, ref then_expr) => {
+                try!(self.value(cond));
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                try!(self.effect(then_expr));
+                self.patch(skip);
+                Ok(false)
+            },
+            Expr::IfThenElseExpr(ref cond, ref then_expr, ref else_expr) => {
+                try!(self.value(cond));
+                let to_else = self.emit(Instr::JumpIfFalse(0));
+                let then_value = try!(self.exp(then_expr));
+                let to_end = self.emit(Instr::Jump(0));
+                self.patch(to_else);
+                let else_value = try!(self.exp(else_expr));
+                if then_value != else_value{
+                    return Err(format!("the branches of {:?} don't agree on producing a value", expr))
+                }
+                self.patch(to_end);
+                Ok(then_value)
+            },
+            Expr::WhileExpr(ref cond, ref body) => {
+                let test = self.here();
+                try!(self.value(cond));
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                let breaks = try!(self.loop_body(body));
+                self.emit(Instr::Jump(test));
+                self.patch(exit);
+                for b in breaks{
+                    self.patch(b);
+                }
+                Ok(false)
+            },
+            Expr::ForExpr(ref id, ref lo, ref hi, ref body, _) => {
+                //the bounds are inclusive and evaluated once. The exit test happens
+                //before the increment so that hi = maxint doesn't overflow
+                let (i, limit) = (self.new_slot(), self.new_slot());
+                try!(self.value(lo));
+                self.emit(Instr::Store(i));
+                try!(self.value(hi));
+                self.emit(Instr::Store(limit));
+                self.emit(Instr::Load(i));
+                self.emit(Instr::Load(limit));
+                self.emit(Instr::Le);
+                let skip = self.emit(Instr::JumpIfFalse(0));
+                let start = self.here();
+                self.begin_scope();
+                let level = self.level();
+                self.env.push((id.clone(), Some(Entry::Var { slot : i, level : level })));
+                let breaks = self.loop_body(body);
+                self.end_scope();
+                let breaks = try!(breaks);
+                self.emit(Instr::Load(i));
+                self.emit(Instr::Load(limit));
+                self.emit(Instr::Lt);
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.emit(Instr::Load(i));
+                self.emit(Instr::Int(1));
+                self.emit(Instr::Add);
+                self.emit(Instr::Store(i));
+                self.emit(Instr::Jump(start));
+                for b in breaks.into_iter().chain(vec![skip, exit]){
+                    self.patch(b);
+                }
+                Ok(false)
+            },
+            Expr::BreakExpr => {
+                let at = self.emit(Instr::Jump(0));
+                match self.frame().breaks.last_mut(){
+                    Some(b) => b.push(at),
+                    None => return Err(String::from("'break' outside of a loop"))
+                }
+                Ok(false)
+            },
+            Expr::CallExpr(ref name, ref args) => self.call(name, args),
+            Expr::SeqExpr(ref exprs) => {
+                let exprs = match *exprs{
+                    Some(ref e) if !e.is_empty() => e,
+                    _ => return Ok(false)
+                };
+                for e in exprs[..exprs.len() - 1].iter(){
+                    try!(self.effect(e));
+                }
+                self.exp(exprs.last().unwrap())
+            },
+            Expr::LetExpr(ref decls, ref body) => {
+                self.begin_scope();
+                let result = self.let_expr(decls, body);
+                self.end_scope();
+                result
+            },
+            Expr::ArrayExpr(_, ref dim, ref init) | Expr::TypedArrayExpr(_, ref dim, ref init) => {
+                try!(self.value(dim));
+                try!(self.value(init));
+                self.emit(Instr::NewArray);
+                Ok(true)
+            },
+            Expr::RecordExpr(ref fields) => {
+                //fields start out as 0, "" or nil depending on their type
+                let mut names = Vec::new();
+                for &(ref name, ref ty) in fields.iter().flat_map(|f| f.iter()){
+                    let instr = match self.resolve(ty){
+                        TType::TInt32 => Instr::Int(0),
+                        TType::TString => Instr::Str(self.string(b"")),
+                        _ => Instr::Nil
+                    };
+                    self.emit(instr);
+                    names.push(self.string(name.as_bytes()));
+                }
+                let layout = self.layout(names);
+                self.emit(Instr::NewRecord(layout));
+                Ok(true)
+            },
+            Expr::TypedRecordExpr(_, ref fields) => {
+                let mut names = Vec::new();
+                for &(ref name, ref e) in fields.iter(){
+                    try!(self.value(e));
+                    names.push(self.string(name.as_bytes()));
+                }
+                let layout = self.layout(names);
+                self.emit(Instr::NewRecord(layout));
+                Ok(true)
+            },
+            ref e => Err(format!("{:?} can't be compiled to bytecode", e))
+        }
+    }
+
+    fn let_expr(&mut self, decls : &[Decl], body : &OptionalExpr) -> CompileResult{
+        let mut i = 0;
+        while i < decls.len(){
+            match decls[i]{
+                Decl::TypeDec(ref name, ref ty) => {
+                    self.env.push((name.clone(), Some(Entry::Type(ty.clone()))));
+                    i += 1;
+                },
+                Decl::VarDec(ref name, _, ref rhs, _) => {
+                    try!(self.value(rhs));
+                    let slot = self.new_slot();
+                    self.emit(Instr::Store(slot));
+                    let level = self.level();
+                    self.env.push((name.clone(), Some(Entry::Var { slot : slot, level : level })));
+                    i += 1;
+                },
+                Decl::FunDec(..) => {
+                    //consecutive functions may call each other, so all of them
+                    //are declared before any body is compiled
+                    let start = i;
+                    while i < decls.len(){
+                        match decls[i]{
+                            Decl::FunDec(ref name, ref params, ref ret, _, _) => {
+                                let arity = params.as_ref().map(|p| p.len()).unwrap_or(0) as u32;
+                                let returns = self.resolve(ret) != TType::TVoid;
+                                let index = self.module.functions.len() as u32;
+                                self.module.functions.push(Function { name : name.clone(), arity : arity, locals : arity,
+                                                                      returns : returns, code : Vec::new(), lines : Vec::new() });
+                                let level = self.level();
+                                self.env.push((name.clone(), Some(Entry::Fun { index : index, level : level,
+                                                                               arity : arity, returns : returns })));
+                            },
+                            _ => break
+                        }
+                        i += 1;
+                    }
+                    for d in decls[start..i].iter(){
+                        try!(self.function(d));
+                    }
+                },
+                Decl::ErrorDec => return Err(String::from("a declaration with a syntax error can't be compiled to bytecode"))
+            }
+        }
+        match *body{
+            Some(ref b) => self.exp(b),
+            None => Ok(false)
+        }
+    }
+
+    fn function(&mut self, decl : &Decl) -> Result<(), String>{
+        let (name, params, body) = match *decl{
+            Decl::FunDec(ref name, ref params, _, ref body, _) => (name, params, body),
+            _ => unreachable!()
+        };
+        let (index, returns) = match self.lookup(name){
+            Some(&Entry::Fun{index, returns, ..}) => (index, returns),
+            _ => unreachable!()
+        };
+        self.frames.push(Frame { code : Vec::new(), lines : Vec::new(), locals : 0, breaks : Vec::new() });
+        self.begin_scope();
+        let level = self.level();
+        for &(ref p, _, _) in params.iter().flat_map(|ps| ps.iter()){
+            let slot = self.new_slot();
+            self.env.push((p.clone(), Some(Entry::Var { slot : slot, level : level })));
+        }
+        let result = self.exp(body);
+        self.end_scope();
+        let has_value = match result{
+            Ok(v) => v,
+            Err(e) => {
+                self.frames.pop();
+                return Err(e)
+            }
+        };
+        if has_value && !returns{
+            self.emit(Instr::Pop);
+        }
+        else if !has_value && returns{
+            self.frames.pop();
+            return Err(format!("the body of '{}' doesn't produce the value it returns", name))
+        }
+        self.emit(Instr::Ret);
+        let frame = self.frames.pop().unwrap();
+        let f = &mut self.module.functions[index as usize];
+        f.code = frame.code;
+        f.lines = frame.lines;
+        f.locals = frame.locals;
+        Ok(())
+    }
+}
+
+//compiles a whole program. Its top level expression becomes function 0,
+//which leaves nothing on the stack
+pub fn compile(expr : &B<Expr>) -> Result<Module, String>{
+    let mut c = Compiler::new();
+    c.module.functions.push(Function { name : String::from("main"), arity : 0, locals : 0, returns : false,
+                                       code : Vec::new(), lines : Vec::new() });
+    c.frames.push(Frame { code : Vec::new(), lines : Vec::new(), locals : 0, breaks : Vec::new() });
+    try!(c.effect(expr));
+    c.emit(Instr::Ret);
+    let frame = c.frames.pop().unwrap();
+    c.module.functions[0].code = frame.code;
+    c.module.functions[0].lines = frame.lines;
+    c.module.functions[0].locals = frame.locals;
+    Ok(c.module)
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::parse::parser::{Parser};
+    use syntax::ast::Expr::*;
+    use syntax::ptr::{B};
+    use instr::Instr::*;
+    use instr::Prim;
+    use super::*;
+
+    fn compile_src(src : &str) -> Result<Module, String>{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        compile(&expr)
+    }
+
+    #[test]
+    fn test_compile_print(){
+        let m = compile_src("print(1+2)").unwrap();
+        assert_eq!(m.functions[0].code, vec![Int(1), Int(2), Add, Prim(Prim::Print), Ret]);
+    }
+
+    #[test]
+    fn test_strings_are_interned(){
+        let m = compile_src("(print(\"hi\"); print(\"hi\"))").unwrap();
+        assert_eq!(m.strings, vec![b"hi".to_vec()]);
+    }
+
+    #[test]
+    fn test_nested_function_uses_static_link(){
+        let m = compile_src("let var a : int := 1 in let function f() : int = a in print(f()) end end").unwrap();
+        assert_eq!(m.functions[1].code, vec![LoadUp(1, 0), Ret]);
+        assert_eq!(m.functions[0].code[2..4], [Call(1, 0), Prim(Prim::Print)]);
+    }
+
+    #[test]
+    fn test_break_jumps_past_the_loop(){
+        let e = B(WhileExpr(B(GreaterThanExpr(B(NumExpr(1)), B(NumExpr(0)))), B(BreakExpr)));
+        assert_eq!(compile(&e).unwrap().functions[0].code, vec![Int(1), Int(0), Gt, JumpIfFalse(6), Jump(6), Jump(0), Ret]);
+        assert_eq!(compile(&B(BreakExpr)).err(), Some(String::from("'break' outside of a loop")));
+    }
+
+    #[test]
+    fn test_field_assignment(){
+        let m = compile_src("let var r : rec := {f:int, g:string} in r.g := \"x\" end").unwrap();
+        assert_eq!(m.layouts, vec![vec![0, 2]]);
+        assert_eq!(m.functions[0].code, vec![Int(0), Str(1), NewRecord(0), Store(0), Load(0), Str(3), SetField(2), Ret]);
+    }
+
+    #[test]
+    fn test_unknown_function(){
+        assert_eq!(compile_src("foo(1)").err(), Some(String::from("Call to 'foo' not found")));
+    }
+
+    #[test]
+    fn test_line_table(){
+        let m = compile_src("let var a : int := 1\nin\n  print(a / 0);\n  a := 2\nend").unwrap();
+        assert_eq!(m.functions[0].lines, vec![(0, 1), (2, 3), (6, 4)]);
+        assert_eq!((m.functions[0].line(4), m.functions[0].line(8)), (Some(3), Some(4)));
+    }
+}
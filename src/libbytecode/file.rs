@@ -0,0 +1,327 @@
+//! The .tbc file format. Numbers are LEB128 varints, zigzag encoded when
+//! they are signed:
+//!
+//! ```text
+//! magic      "TBC" 0, then the version byte
+//! strings    count, then the length and bytes of each
+//! layouts    count, then the number of fields of each and their string entries
+//! functions  count, then for each: name, arity, locals, returns (0 or 1),
+//!            the number of instructions and the instructions, each an
+//!            opcode byte followed by its operands, then the number of
+//!            entries of the line table and for each the first instruction
+//!            and the line
+//! ```
+
+use instr::*;
+
+pub const MAGIC : &'static [u8] = b"TBC\0";
+pub const VERSION : u8 = 2;
+
+struct Writer{
+    bytes : Vec<u8>
+}
+
+impl Writer{
+    fn uint(&mut self, mut n : u32){
+        loop{
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0{
+                self.bytes.push(b);
+                return
+            }
+            self.bytes.push(b | 0x80);
+        }
+    }
+
+    fn int(&mut self, n : i32){
+        self.uint(((n << 1) ^ (n >> 31)) as u32);
+    }
+
+    fn bytes(&mut self, s : &[u8]){
+        self.uint(s.len() as u32);
+        self.bytes.extend_from_slice(s);
+    }
+
+    fn string(&mut self, s : &str){
+        self.bytes(s.as_bytes());
+    }
+
+    fn instr(&mut self, instr : &Instr){
+        let (op, operands) : (u8, &[u32]) = match *instr{
+            Instr::Int(n) => {
+                self.bytes.push(0);
+                self.int(n);
+                return
+            },
+            Instr::Str(s) => (1, &[s]),
+            Instr::Nil => (2, &[]),
+            Instr::Pop => (3, &[]),
+            Instr::Load(s) => (4, &[s]),
+            Instr::Store(s) => (5, &[s]),
+            Instr::LoadUp(d, s) => (6, &[d, s]),
+            Instr::StoreUp(d, s) => (7, &[d, s]),
+            Instr::Add => (8, &[]),
+            Instr::Sub => (9, &[]),
+            Instr::Mul => (10, &[]),
+            Instr::Div => (11, &[]),
+            Instr::Mod => (12, &[]),
+            Instr::Eq => (13, &[]),
+            Instr::Ne => (14, &[]),
+            Instr::Lt => (15, &[]),
+            Instr::Le => (16, &[]),
+            Instr::Gt => (17, &[]),
+            Instr::Ge => (18, &[]),
+            Instr::Jump(t) => (19, &[t]),
+            Instr::JumpIfFalse(t) => (20, &[t]),
+            Instr::Call(f, d) => (21, &[f, d]),
+            Instr::Prim(p) => {
+                self.bytes.push(22);
+                self.bytes.push(PRIMS.iter().position(|&q| q == p).unwrap() as u8);
+                return
+            },
+            Instr::Ret => (23, &[]),
+            Instr::NewArray => (24, &[]),
+            Instr::NewRecord(l) => (25, &[l]),
+            Instr::Index => (26, &[]),
+            Instr::SetIndex => (27, &[]),
+            Instr::GetField(s) => (28, &[s]),
+            Instr::SetField(s) => (29, &[s])
+        };
+        self.bytes.push(op);
+        for &n in operands{
+            self.uint(n);
+        }
+    }
+}
+
+pub fn encode(module : &Module) -> Vec<u8>{
+    let mut w = Writer { bytes : MAGIC.to_vec() };
+    w.bytes.push(VERSION);
+    w.uint(module.strings.len() as u32);
+    for s in module.strings.iter(){
+        w.bytes(s);
+    }
+    w.uint(module.layouts.len() as u32);
+    for l in module.layouts.iter(){
+        w.uint(l.len() as u32);
+        for &s in l.iter(){
+            w.uint(s);
+        }
+    }
+    w.uint(module.functions.len() as u32);
+    for f in module.functions.iter(){
+        w.string(&f.name);
+        w.uint(f.arity);
+        w.uint(f.locals);
+        w.bytes.push(f.returns as u8);
+        w.uint(f.code.len() as u32);
+        for instr in f.code.iter(){
+            w.instr(instr);
+        }
+        w.uint(f.lines.len() as u32);
+        for &(start, line) in f.lines.iter(){
+            w.uint(start);
+            w.uint(line);
+        }
+    }
+    w.bytes
+}
+
+struct Reader<'b>{
+    bytes : &'b [u8],
+    pos : usize
+}
+
+impl<'b> Reader<'b>{
+    fn byte(&mut self) -> Result<u8, String>{
+        match self.bytes.get(self.pos){
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            },
+            None => Err(String::from("the bytecode file is truncated"))
+        }
+    }
+
+    fn uint(&mut self) -> Result<u32, String>{
+        let mut n : u64 = 0;
+        let mut shift = 0;
+        loop{
+            let b = try!(self.byte());
+            n |= ((b & 0x7f) as u64) << shift;
+            if n > u32::max_value() as u64 || shift > 28{
+                return Err(String::from("a number in the bytecode file is too large"))
+            }
+            if b & 0x80 == 0{
+                return Ok(n as u32)
+            }
+            shift += 7;
+        }
+    }
+
+    fn int(&mut self) -> Result<i32, String>{
+        let n = try!(self.uint());
+        Ok(((n >> 1) as i32) ^ -((n & 1) as i32))
+    }
+
+    //a count of things that take at least a byte each, so that a corrupt
+    //count can't make us allocate more than the file holds
+    fn count(&mut self) -> Result<usize, String>{
+        let n = try!(self.uint()) as usize;
+        if n > self.bytes.len() - self.pos{
+            return Err(String::from("the bytecode file is truncated"))
+        }
+        Ok(n)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String>{
+        let len = try!(self.count());
+        let bytes = self.bytes[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, String>{
+        let bytes = try!(self.bytes());
+        String::from_utf8(bytes).map_err(|_| String::from("invalid utf-8 in the bytecode file"))
+    }
+
+    fn instr(&mut self) -> Result<Instr, String>{
+        let op = try!(self.byte());
+        Ok(match op{
+            0 => Instr::Int(try!(self.int())),
+            1 => Instr::Str(try!(self.uint())),
+            2 => Instr::Nil,
+            3 => Instr::Pop,
+            4 => Instr::Load(try!(self.uint())),
+            5 => Instr::Store(try!(self.uint())),
+            6 => Instr::LoadUp(try!(self.uint()), try!(self.uint())),
+            7 => Instr::StoreUp(try!(self.uint()), try!(self.uint())),
+            8 => Instr::Add,
+            9 => Instr::Sub,
+            10 => Instr::Mul,
+            11 => Instr::Div,
+            12 => Instr::Mod,
+            13 => Instr::Eq,
+            14 => Instr::Ne,
+            15 => Instr::Lt,
+            16 => Instr::Le,
+            17 => Instr::Gt,
+            18 => Instr::Ge,
+            19 => Instr::Jump(try!(self.uint())),
+            20 => Instr::JumpIfFalse(try!(self.uint())),
+            21 => Instr::Call(try!(self.uint()), try!(self.uint())),
+            22 => {
+                let p = try!(self.byte());
+                match PRIMS.get(p as usize){
+                    Some(&p) => Instr::Prim(p),
+                    None => return Err(format!("unknown builtin {} in the bytecode file", p))
+                }
+            },
+            23 => Instr::Ret,
+            24 => Instr::NewArray,
+            25 => Instr::NewRecord(try!(self.uint())),
+            26 => Instr::Index,
+            27 => Instr::SetIndex,
+            28 => Instr::GetField(try!(self.uint())),
+            29 => Instr::SetField(try!(self.uint())),
+            op => return Err(format!("unknown opcode {} in the bytecode file", op))
+        })
+    }
+}
+
+//reads a module back and verifies it
+pub fn decode(bytes : &[u8]) -> Result<Module, String>{
+    if !bytes.starts_with(MAGIC){
+        return Err(String::from("not a Tiger bytecode file"))
+    }
+    let mut r = Reader { bytes : bytes, pos : MAGIC.len() };
+    let version = try!(r.byte());
+    if version != VERSION{
+        return Err(format!("unsupported bytecode version {}. Expected {}", version, VERSION))
+    }
+    let mut module = Module::default();
+    for _ in 0..try!(r.count()){
+        module.strings.push(try!(r.bytes()));
+    }
+    for _ in 0..try!(r.count()){
+        let mut layout = Vec::new();
+        for _ in 0..try!(r.count()){
+            layout.push(try!(r.uint()));
+        }
+        module.layouts.push(layout);
+    }
+    for _ in 0..try!(r.count()){
+        let name = try!(r.string());
+        let arity = try!(r.uint());
+        let locals = try!(r.uint());
+        let returns = match try!(r.byte()){
+            0 => false,
+            1 => true,
+            b => return Err(format!("invalid return flag {} in the bytecode file", b))
+        };
+        let mut code = Vec::new();
+        for _ in 0..try!(r.count()){
+            code.push(try!(r.instr()));
+        }
+        let mut lines = Vec::new();
+        for _ in 0..try!(r.count()){
+            let start = try!(r.uint());
+            lines.push((start, try!(r.uint())));
+        }
+        module.functions.push(Function { name : name, arity : arity, locals : locals, returns : returns,
+                                         code : code, lines : lines });
+    }
+    if r.pos != bytes.len(){
+        return Err(String::from("unexpected bytes after the last function of the bytecode file"))
+    }
+    try!(module.verify());
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::parse::parser::{Parser};
+    use compile::compile;
+    use instr::Instr;
+    use super::*;
+
+    fn module(src : &str) -> Module{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        compile(&expr).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip(){
+        let m = module("let var r : rec := {f:int, g:string} in let function f(n:int) : int = n + 70000 in (r.f := f(3); print(r.f); print(\"\")) end end");
+        assert_eq!(decode(&encode(&m)), Ok(m));
+    }
+
+    #[test]
+    fn test_varints(){
+        let mut m = module("print(1)");
+        m.functions[0].code = vec![Instr::Int(-1), Instr::Int(300), Instr::Int(i32::min_value()), Instr::Pop, Instr::Pop, Instr::Pop, Instr::Ret];
+        let bytes = encode(&m);
+        assert_eq!(decode(&bytes), Ok(m));
+        assert!(bytes.windows(3).any(|w| w == [0, 0xd8, 0x04]));
+    }
+
+    #[test]
+    fn test_invalid_files(){
+        let bytes = encode(&module("print(\"hi\")"));
+        assert_eq!(decode(b"\x7fELF").err(), Some(String::from("not a Tiger bytecode file")));
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).err(), Some(String::from("the bytecode file is truncated")));
+        let mut newer = bytes.clone();
+        newer[4] = 9;
+        assert_eq!(decode(&newer).err(), Some(String::from("unsupported bytecode version 9. Expected 2")));
+        //print's string points past the string table. The line table of
+        //one entry takes the last three bytes
+        let mut bad = bytes.clone();
+        let at = bad.len() - 7;
+        bad[at] = 7;
+        assert_eq!(decode(&bad).err(), Some(String::from("string 7 is out of range")));
+    }
+}
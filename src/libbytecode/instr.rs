@@ -0,0 +1,244 @@
+//! The instruction set of the virtual machine and the compiled module.
+//!
+//! Every instruction works on an operand stack. Variables live in the slots
+//! of their function's frame; frames of nested functions are linked to the
+//! frame of the enclosing function, so `LoadUp(2, s)` reads slot `s` of the
+//! frame two static links away.
+
+use std::ascii;
+use std::fmt;
+
+//the builtin functions of Tiger
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prim{
+    Print,
+    Flush,
+    GetChar,
+    Ord,
+    Chr,
+    Size,
+    Substring,
+    Concat,
+    Not,
+    Exit
+}
+
+pub const PRIMS : [Prim; 10] = [Prim::Print, Prim::Flush, Prim::GetChar, Prim::Ord, Prim::Chr,
+                                Prim::Size, Prim::Substring, Prim::Concat, Prim::Not, Prim::Exit];
+
+impl Prim{
+    pub fn from_name(name : &str) -> Option<Prim>{
+        PRIMS.iter().cloned().find(|p| p.name() == name)
+    }
+
+    pub fn name(self) -> &'static str{
+        match self{
+            Prim::Print => "print",
+            Prim::Flush => "flush",
+            Prim::GetChar => "getchar",
+            Prim::Ord => "ord",
+            Prim::Chr => "chr",
+            Prim::Size => "size",
+            Prim::Substring => "substring",
+            Prim::Concat => "concat",
+            Prim::Not => "not",
+            Prim::Exit => "exit"
+        }
+    }
+
+    pub fn arity(self) -> usize{
+        match self{
+            Prim::Flush | Prim::GetChar => 0,
+            Prim::Concat => 2,
+            Prim::Substring => 3,
+            _ => 1
+        }
+    }
+
+    //whether a value is pushed
+    pub fn returns(self) -> bool{
+        match self{
+            Prim::Print | Prim::Flush | Prim::Exit => false,
+            _ => true
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Instr{
+    Int(i32),
+    //index into the string table
+    Str(u32),
+    Nil,
+    Pop,
+    Load(u32),
+    Store(u32),
+    //(static links to follow, slot)
+    LoadUp(u32, u32),
+    StoreUp(u32, u32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    //comparisons push 1 or 0. Strings compare by contents, arrays and
+    //records by identity
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    //absolute instruction index
+    Jump(u32),
+    JumpIfFalse(u32),
+    //(function, static links to follow from the caller to the callee's parent).
+    //The arguments are on the stack, first one deepest
+    Call(u32, u32),
+    Prim(Prim),
+    //returns the top of the stack from functions that return a value
+    Ret,
+    //pops the initial value and the size
+    NewArray,
+    //pops the values of the fields of the given layout, last field on top
+    NewRecord(u32),
+    //pops the index and the array
+    Index,
+    //pops the value, the index and the array
+    SetIndex,
+    //the field named by a string table entry. SetField pops the value and the record
+    GetField(u32),
+    SetField(u32)
+}
+
+impl fmt::Display for Instr{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Instr::Int(n) => write!(f, "int {}", n),
+            Instr::Str(s) => write!(f, "str {}", s),
+            Instr::Nil => f.write_str("nil"),
+            Instr::Pop => f.write_str("pop"),
+            Instr::Load(s) => write!(f, "load {}", s),
+            Instr::Store(s) => write!(f, "store {}", s),
+            Instr::LoadUp(d, s) => write!(f, "loadup {} {}", d, s),
+            Instr::StoreUp(d, s) => write!(f, "storeup {} {}", d, s),
+            Instr::Add => f.write_str("add"),
+            Instr::Sub => f.write_str("sub"),
+            Instr::Mul => f.write_str("mul"),
+            Instr::Div => f.write_str("div"),
+            Instr::Mod => f.write_str("mod"),
+            Instr::Eq => f.write_str("eq"),
+            Instr::Ne => f.write_str("ne"),
+            Instr::Lt => f.write_str("lt"),
+            Instr::Le => f.write_str("le"),
+            Instr::Gt => f.write_str("gt"),
+            Instr::Ge => f.write_str("ge"),
+            Instr::Jump(t) => write!(f, "jump {}", t),
+            Instr::JumpIfFalse(t) => write!(f, "jumpifnot {}", t),
+            Instr::Call(func, d) => write!(f, "call {} {}", func, d),
+            Instr::Prim(p) => write!(f, "prim {}", p.name()),
+            Instr::Ret => f.write_str("ret"),
+            Instr::NewArray => f.write_str("newarray"),
+            Instr::NewRecord(l) => write!(f, "newrecord {}", l),
+            Instr::Index => f.write_str("index"),
+            Instr::SetIndex => f.write_str("setindex"),
+            Instr::GetField(s) => write!(f, "getfield {}", s),
+            Instr::SetField(s) => write!(f, "setfield {}", s)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function{
+    pub name : String,
+    pub arity : u32,
+    //slots of a frame, the parameters included
+    pub locals : u32,
+    pub returns : bool,
+    pub code : Vec<Instr>,
+    //(first instruction, source line) pairs in the order of the code. Each
+    //line runs up to the next pair
+    pub lines : Vec<(u32, u32)>
+}
+
+impl Function{
+    //the source line of the instruction at `pc`, if it's known
+    pub fn line(&self, pc : usize) -> Option<u32>{
+        match self.lines.iter().rposition(|&(start, _)| start as usize <= pc){
+            Some(i) => Some(self.lines[i].1),
+            None => None
+        }
+    }
+}
+
+//a compiled program. Function 0 is the top level expression
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Module{
+    //the string literals and the field names
+    pub strings : Vec<Vec<u8>>,
+    //field names of the records, as string table entries
+    pub layouts : Vec<Vec<u32>>,
+    pub functions : Vec<Function>
+}
+
+impl Module{
+    //checks that every index in the code is in range, so that a module read
+    //from a file can't make the vm go wrong
+    pub fn verify(&self) -> Result<(), String>{
+        let strings = self.strings.len() as u32;
+        let string = |s : u32| if s < strings {Ok(())} else {Err(format!("string {} is out of range", s))};
+        if self.functions.first().map(|f| f.arity) != Some(0){
+            return Err(String::from("the module has no top level function"))
+        }
+        for l in self.layouts.iter(){
+            for &s in l.iter(){
+                try!(string(s));
+            }
+        }
+        for f in self.functions.iter(){
+            if f.locals < f.arity{
+                return Err(format!("'{}' has fewer slots than parameters", f.name))
+            }
+            let check = |ok : bool, instr : &Instr| if ok {Ok(())} else {Err(format!("invalid instruction '{}' in '{}'", instr, f.name))};
+            for instr in f.code.iter(){
+                match *instr{
+                    Instr::Str(s) | Instr::GetField(s) | Instr::SetField(s) => try!(string(s)),
+                    Instr::Load(s) | Instr::Store(s) => try!(check(s < f.locals, instr)),
+                    Instr::Jump(t) | Instr::JumpIfFalse(t) => try!(check((t as usize) < f.code.len(), instr)),
+                    Instr::Call(func, _) => try!(check((func as usize) < self.functions.len(), instr)),
+                    Instr::NewRecord(l) => try!(check((l as usize) < self.layouts.len(), instr)),
+                    _ => ()
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Function{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        try!(writeln!(f, "function {}({}) locals {}{}", self.name, self.arity, self.locals,
+                      if self.returns {" returns"} else {""}));
+        for (i, instr) in self.code.iter().enumerate(){
+            try!(writeln!(f, "{:>5}  {}", i, instr));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Module{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        for (i, s) in self.strings.iter().enumerate(){
+            let escaped : String = s.iter().flat_map(|&b| ascii::escape_default(b)).map(|b| b as char).collect();
+            try!(writeln!(f, "string {} \"{}\"", i, escaped));
+        }
+        for (i, l) in self.layouts.iter().enumerate(){
+            let names : Vec<String> = l.iter().map(|&s| String::from_utf8_lossy(&self.strings[s as usize]).into_owned()).collect();
+            try!(writeln!(f, "record {} {{{}}}", i, names.join(", ")));
+        }
+        for func in self.functions.iter(){
+            try!(write!(f, "\n{}", func));
+        }
+        Ok(())
+    }
+}
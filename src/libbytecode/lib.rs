@@ -0,0 +1,13 @@
+#![crate_name = "bytecode"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! Portable backend: the type-checked AST is compiled to a stack based
+//! bytecode that can be saved to a .tbc file and run by a virtual machine,
+//! with no native toolchain involved.
+
+extern crate syntax;
+
+pub mod instr;
+pub mod compile;
+pub mod file;
+pub mod vm;
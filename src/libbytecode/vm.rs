@@ -0,0 +1,523 @@
+//! The virtual machine that runs compiled modules.
+//!
+//! Strings, arrays and records live on the heap and are shared by reference
+//! counting; strings are byte strings like the ones of the native runtime.
+//! Out of range indices, division by zero and field accesses on nil stop the
+//! program with an error that names the source line it happened on.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use instr::*;
+
+//deeper recursion than this is reported instead of eating all the memory
+pub const MAX_FRAMES : usize = 100000;
+
+#[derive(Debug, Clone)]
+pub enum Value{
+    Int(i32),
+    Str(Rc<Vec<u8>>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Record(Rc<Record>),
+    Nil
+}
+
+#[derive(Debug)]
+pub struct Record{
+    layout : u32,
+    fields : RefCell<Vec<Value>>
+}
+
+impl fmt::Display for Value{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(ref s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            Value::Array(ref elems) => write!(f, "<array of {}>", elems.borrow().len()),
+            Value::Record(_) => f.write_str("<record>"),
+            Value::Nil => f.write_str("nil")
+        }
+    }
+}
+
+//strings compare by contents, arrays and records by identity
+fn equal(a : &Value, b : &Value) -> bool{
+    match (a, b){
+        (&Value::Int(l), &Value::Int(r)) => l == r,
+        (&Value::Str(ref l), &Value::Str(ref r)) => l == r,
+        (&Value::Array(ref l), &Value::Array(ref r)) => Rc::ptr_eq(l, r),
+        (&Value::Record(ref l), &Value::Record(ref r)) => Rc::ptr_eq(l, r),
+        (&Value::Nil, &Value::Nil) => true,
+        _ => false
+    }
+}
+
+struct Frame{
+    func : usize,
+    pc : usize,
+    //where the slots of the frame start
+    base : usize,
+    //the frame of the enclosing function
+    link : usize
+}
+
+//why the program stopped early
+enum Stop{
+    Exit(i32),
+    Error(String)
+}
+
+macro_rules! runtime_error{
+    ($($arg : tt)*) => { return Err(Stop::Error(format!($($arg)*))) }
+}
+
+struct Vm<'a>{
+    module : &'a Module,
+    strings : Vec<Rc<Vec<u8>>>,
+    stack : Vec<Value>,
+    slots : Vec<Value>,
+    frames : Vec<Frame>,
+    out : &'a mut Write,
+    input : &'a mut Read
+}
+
+impl<'a> Vm<'a>{
+    fn pop(&mut self) -> Result<Value, Stop>{
+        match self.stack.pop(){
+            Some(v) => Ok(v),
+            None => runtime_error!("stack underflow")
+        }
+    }
+
+    fn pop_int(&mut self) -> Result<i32, Stop>{
+        match try!(self.pop()){
+            Value::Int(n) => Ok(n),
+            v => runtime_error!("Expected an int but found '{}'", v)
+        }
+    }
+
+    fn pop_str(&mut self) -> Result<Rc<Vec<u8>>, Stop>{
+        match try!(self.pop()){
+            Value::Str(s) => Ok(s),
+            v => runtime_error!("Expected a string but found '{}'", v)
+        }
+    }
+
+    fn pop_array(&mut self) -> Result<Rc<RefCell<Vec<Value>>>, Stop>{
+        match try!(self.pop()){
+            Value::Array(a) => Ok(a),
+            v => runtime_error!("Expected an array but found '{}'", v)
+        }
+    }
+
+    fn pop_args(&mut self, n : usize) -> Result<Vec<Value>, Stop>{
+        if self.stack.len() < n{
+            runtime_error!("stack underflow");
+        }
+        let at = self.stack.len() - n;
+        Ok(self.stack.split_off(at))
+    }
+
+    fn write(&mut self, bytes : &[u8]) -> Result<(), Stop>{
+        match self.out.write_all(bytes){
+            Ok(_) => Ok(()),
+            Err(e) => runtime_error!("couldn't write the output: {}", e)
+        }
+    }
+
+    //the frame `depth` static links away from the current one
+    fn frame_up(&self, depth : u32) -> usize{
+        let mut f = self.frames.len() - 1;
+        for _ in 0..depth{
+            f = self.frames[f].link;
+        }
+        f
+    }
+
+    fn slot(&self, depth : u32, slot : u32) -> Result<usize, Stop>{
+        let f = &self.frames[self.frame_up(depth)];
+        if slot >= self.module.functions[f.func].locals{
+            runtime_error!("slot {} is out of range", slot);
+        }
+        Ok(f.base + slot as usize)
+    }
+
+    fn element(&mut self, elems : &Rc<RefCell<Vec<Value>>>, i : i32) -> Result<usize, Stop>{
+        let len = elems.borrow().len();
+        if i < 0 || i as usize >= len{
+            runtime_error!("Index {} out of bounds for an array of size {}", i, len);
+        }
+        Ok(i as usize)
+    }
+
+    fn field(&mut self, rec : Value, name : u32) -> Result<(Rc<Record>, usize), Stop>{
+        let rec = match rec{
+            Value::Record(r) => r,
+            Value::Nil => runtime_error!("Field access on a nil record"),
+            v => runtime_error!("Field access on '{}' which is not a record", v)
+        };
+        match self.module.layouts[rec.layout as usize].iter().position(|&f| f == name){
+            Some(pos) => Ok((rec, pos)),
+            None => runtime_error!("Record has no field named '{}'", String::from_utf8_lossy(&self.module.strings[name as usize]))
+        }
+    }
+
+    fn call(&mut self, func : u32, depth : u32) -> Result<(), Stop>{
+        if self.frames.len() >= MAX_FRAMES{
+            runtime_error!("stack overflow");
+        }
+        let f = &self.module.functions[func as usize];
+        let args = try!(self.pop_args(f.arity as usize));
+        let base = self.slots.len();
+        self.slots.extend(args);
+        self.slots.resize(base + f.locals as usize, Value::Nil);
+        let link = self.frame_up(depth);
+        self.frames.push(Frame { func : func as usize, pc : 0, base : base, link : link });
+        Ok(())
+    }
+
+    //false once the top level function returned
+    fn ret(&mut self) -> Result<bool, Stop>{
+        let frame = self.frames.pop().unwrap();
+        let returns = self.module.functions[frame.func].returns;
+        let value = if returns {Some(try!(self.pop()))} else {None};
+        self.slots.truncate(frame.base);
+        if let Some(v) = value{
+            self.stack.push(v);
+        }
+        Ok(!self.frames.is_empty())
+    }
+
+    fn prim(&mut self, p : Prim) -> Result<(), Stop>{
+        let result = match p{
+            Prim::Print => {
+                match try!(self.pop()){
+                    Value::Str(s) => try!(self.write(&s)),
+                    Value::Int(n) => try!(self.write(format!("{}\n", n).as_bytes())),
+                    v => runtime_error!("Cannot print '{}'", v)
+                }
+                None
+            },
+            Prim::Flush => {
+                let _ = self.out.flush();
+                None
+            },
+            Prim::GetChar => {
+                let mut buf = [0u8; 1];
+                match self.input.read(&mut buf){
+                    Ok(1) => Some(Value::Str(Rc::new(vec![buf[0]]))),
+                    _ => Some(Value::Str(Rc::new(Vec::new())))
+                }
+            },
+            Prim::Ord => {
+                let s = try!(self.pop_str());
+                Some(Value::Int(s.first().map(|&b| b as i32).unwrap_or(-1)))
+            },
+            Prim::Chr => {
+                let n = try!(self.pop_int());
+                if n < 0 || n > 255{
+                    runtime_error!("chr({}) out of range", n);
+                }
+                Some(Value::Str(Rc::new(vec![n as u8])))
+            },
+            Prim::Size => {
+                let s = try!(self.pop_str());
+                Some(Value::Int(s.len() as i32))
+            },
+            Prim::Substring => {
+                let n = try!(self.pop_int());
+                let first = try!(self.pop_int());
+                let s = try!(self.pop_str());
+                if first < 0 || n < 0 || first as usize + n as usize > s.len(){
+                    runtime_error!("substring({:?}, {}, {}) out of range", String::from_utf8_lossy(&s), first, n);
+                }
+                Some(Value::Str(Rc::new(s[first as usize..(first + n) as usize].to_vec())))
+            },
+            Prim::Concat => {
+                let s2 = try!(self.pop_str());
+                let s1 = try!(self.pop_str());
+                let mut s = (*s1).clone();
+                s.extend_from_slice(&s2);
+                Some(Value::Str(Rc::new(s)))
+            },
+            Prim::Not => {
+                let n = try!(self.pop_int());
+                Some(Value::Int((n == 0) as i32))
+            },
+            Prim::Exit => return Err(Stop::Exit(try!(self.pop_int())))
+        };
+        if let Some(v) = result{
+            self.stack.push(v);
+        }
+        Ok(())
+    }
+
+    fn arith(&mut self, instr : Instr) -> Result<(), Stop>{
+        let r = try!(self.pop_int());
+        let l = try!(self.pop_int());
+        let v = match instr{
+            Instr::Add => l.wrapping_add(r),
+            Instr::Sub => l.wrapping_sub(r),
+            Instr::Mul => l.wrapping_mul(r),
+            _ if r == 0 => runtime_error!("division by zero"),
+            Instr::Div => l.wrapping_div(r),
+            _ => l.wrapping_rem(r)
+        };
+        self.stack.push(Value::Int(v));
+        Ok(())
+    }
+
+    fn compare(&mut self, instr : Instr) -> Result<(), Stop>{
+        let r = try!(self.pop());
+        let l = try!(self.pop());
+        let result = match instr{
+            Instr::Eq => equal(&l, &r),
+            Instr::Ne => !equal(&l, &r),
+            _ => {
+                let ord = match (&l, &r){
+                    (&Value::Int(a), &Value::Int(b)) => a.cmp(&b),
+                    (&Value::Str(ref a), &Value::Str(ref b)) => a.cmp(b),
+                    _ => runtime_error!("Cannot compare '{}' and '{}'", l, r)
+                };
+                match instr{
+                    Instr::Lt => ord == Ordering::Less,
+                    Instr::Le => ord != Ordering::Greater,
+                    Instr::Gt => ord == Ordering::Greater,
+                    _ => ord != Ordering::Less
+                }
+            }
+        };
+        self.stack.push(Value::Int(result as i32));
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<bool, Stop>{
+        let (func, pc, base) = {
+            let f = self.frames.last_mut().unwrap();
+            f.pc += 1;
+            (f.func, f.pc - 1, f.base)
+        };
+        let instr = match self.module.functions[func].code.get(pc){
+            Some(&i) => i,
+            None => runtime_error!("ran past the end of '{}'", self.module.functions[func].name)
+        };
+        match instr{
+            Instr::Int(n) => self.stack.push(Value::Int(n)),
+            Instr::Str(s) => {
+                let s = self.strings[s as usize].clone();
+                self.stack.push(Value::Str(s));
+            },
+            Instr::Nil => self.stack.push(Value::Nil),
+            Instr::Pop => { try!(self.pop()); },
+            Instr::Load(s) => {
+                let v = self.slots[base + s as usize].clone();
+                self.stack.push(v);
+            },
+            Instr::Store(s) => self.slots[base + s as usize] = try!(self.pop()),
+            Instr::LoadUp(d, s) => {
+                let v = self.slots[try!(self.slot(d, s))].clone();
+                self.stack.push(v);
+            },
+            Instr::StoreUp(d, s) => {
+                let at = try!(self.slot(d, s));
+                self.slots[at] = try!(self.pop());
+            },
+            Instr::Add | Instr::Sub | Instr::Mul | Instr::Div | Instr::Mod => try!(self.arith(instr)),
+            Instr::Eq | Instr::Ne | Instr::Lt | Instr::Le | Instr::Gt | Instr::Ge => try!(self.compare(instr)),
+            Instr::Jump(t) => self.frames.last_mut().unwrap().pc = t as usize,
+            Instr::JumpIfFalse(t) => {
+                if try!(self.pop_int()) == 0{
+                    self.frames.last_mut().unwrap().pc = t as usize;
+                }
+            },
+            Instr::Call(f, d) => try!(self.call(f, d)),
+            Instr::Prim(p) => try!(self.prim(p)),
+            Instr::Ret => return self.ret(),
+            Instr::NewArray => {
+                let init = try!(self.pop());
+                let n = try!(self.pop_int());
+                if n < 0{
+                    runtime_error!("Negative array size {}", n);
+                }
+                self.stack.push(Value::Array(Rc::new(RefCell::new(vec![init; n as usize]))));
+            },
+            Instr::NewRecord(l) => {
+                let fields = try!(self.pop_args(self.module.layouts[l as usize].len()));
+                self.stack.push(Value::Record(Rc::new(Record { layout : l, fields : RefCell::new(fields) })));
+            },
+            Instr::Index => {
+                let i = try!(self.pop_int());
+                let elems = try!(self.pop_array());
+                let i = try!(self.element(&elems, i));
+                let v = elems.borrow()[i].clone();
+                self.stack.push(v);
+            },
+            Instr::SetIndex => {
+                let v = try!(self.pop());
+                let i = try!(self.pop_int());
+                let elems = try!(self.pop_array());
+                let i = try!(self.element(&elems, i));
+                elems.borrow_mut()[i] = v;
+            },
+            Instr::GetField(name) => {
+                let rec = try!(self.pop());
+                let (rec, pos) = try!(self.field(rec, name));
+                let v = rec.fields.borrow()[pos].clone();
+                self.stack.push(v);
+            },
+            Instr::SetField(name) => {
+                let v = try!(self.pop());
+                let rec = try!(self.pop());
+                let (rec, pos) = try!(self.field(rec, name));
+                rec.fields.borrow_mut()[pos] = v;
+            }
+        }
+        Ok(true)
+    }
+}
+
+//runs the program and returns its exit code: 0 unless it called exit()
+pub fn run(module : &Module, out : &mut Write, input : &mut Read) -> Result<i32, String>{
+    try!(module.verify());
+    let mut vm = Vm {
+        module : module,
+        strings : module.strings.iter().map(|s| Rc::new(s.clone())).collect(),
+        stack : Vec::new(),
+        slots : Vec::new(),
+        frames : Vec::new(),
+        out : out,
+        input : input
+    };
+    vm.slots.resize(module.functions[0].locals as usize, Value::Nil);
+    vm.frames.push(Frame { func : 0, pc : 0, base : 0, link : 0 });
+    let result = (|| {
+        while try!(vm.step()){}
+        Ok(0)
+    })();
+    let _ = vm.out.flush();
+    match result{
+        Ok(code) | Err(Stop::Exit(code)) => Ok(code),
+        Err(Stop::Error(msg)) => {
+            //the pc has already moved past the instruction that failed
+            let at = vm.frames.last().map(|f| (&module.functions[f.func], f.pc - 1));
+            match at.and_then(|(func, pc)| func.line(pc)){
+                Some(line) => Err(format!("line {}: {}", line, msg)),
+                None => Err(format!("{} in '{}'", msg, at.map(|(func, _)| &*func.name).unwrap_or("main")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::parse::parser::{Parser};
+    use syntax::ast::{Expr, TType, Escape};
+    use syntax::ast::Expr::*;
+    use syntax::ptr::{B};
+    use compile::compile;
+    use super::*;
+
+    //the exit code and output of a program, with "xy" as its input
+    fn run_expr(expr : &B<Expr>) -> Result<(i32, String), String>{
+        let module = try!(compile(expr));
+        let mut out = Vec::new();
+        let code = try!(run(&module, &mut out, &mut &b"xy"[..]));
+        Ok((code, String::from_utf8(out).unwrap()))
+    }
+
+    fn run_src(src : &str) -> Result<(i32, String), String>{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        run_expr(&expr)
+    }
+
+    fn output(src : &str) -> String{
+        run_src(src).unwrap().1
+    }
+
+    fn print(e : Expr) -> Expr{
+        CallExpr(String::from("print"), Some(vec![(TType::TVoid, B(e))]))
+    }
+
+    #[test]
+    fn test_vm_loops(){
+        let src = "let var a : int := 0 in let var b : int := 2 in for i := 1 to 4 do (a := a + i; b := a + 1; print(b)) end end end";
+        assert_eq!(output(src), "2\n4\n7\n11\n");
+        assert_eq!(output("let var a : int := 0 in while 10 > a do (a := a + 3; print(a)) end"), "3\n6\n9\n12\n");
+    }
+
+    #[test]
+    fn test_vm_break(){
+        //for i := 1 to 10 do (print(i); if i > 2 then break)
+        let body = SeqExpr(Some(vec![B(print(IdExpr(String::from("i")))),
+                                     B(IfThenExpr(B(GreaterThanExpr(B(IdExpr(String::from("i"))), B(NumExpr(2)))), B(BreakExpr)))]));
+        let e = ForExpr(String::from("i"), B(NumExpr(1)), B(NumExpr(10)), B(body), Escape::new());
+        assert_eq!(run_expr(&B(e)), Ok((0, String::from("1\n2\n3\n"))));
+    }
+
+    #[test]
+    fn test_vm_recursion(){
+        let src = "let function fact(i:int, n:int) : int = if 5 < i then 1 else fact(i + 1, n) * i in print(fact(1, 5)) end";
+        assert_eq!(output(src), "120\n");
+    }
+
+    #[test]
+    fn test_vm_nested_functions_share_variables(){
+        let src = "let var a : int := 1 in let function f() = let function g() = a := a + 10 in g() end in (f(); print(a)) end end";
+        assert_eq!(output(src), "11\n");
+    }
+
+    #[test]
+    fn test_vm_arrays_and_records(){
+        let src = "let var a : array := array of int[3] of 7 in (a[1] := 5; print(a[1]); print(a[2])) end";
+        assert_eq!(output(src), "5\n7\n");
+        let src = "let var r : rec := {f:int, g:string} in (r.f := 4; r.g := \"x\"; print(r.g); print(r.f)) end";
+        assert_eq!(output(src), "x4\n");
+    }
+
+    #[test]
+    fn test_vm_nested_lvalues(){
+        let src = "let type row = array of int\n type matrix = array of row\n var m := matrix [2] of nil in \
+                   (m[0] := row [3] of 0; m[1] := row [3] of 1; m[1][2] := 7; print(m[0][2]); print(m[1][2])) end";
+        assert_eq!(output(src), "0\n7\n");
+        let src = "let type person = {name : string, age : int}\n type people = array of person\n var ps := people [2] of nil in \
+                   (ps[0] := person {name = \"a\", age = 1}; ps[1] := person {name = \"b\", age = 2}; ps[1].name := \"x\"; \
+                   print(ps[0].name); print(ps[1].name)) end";
+        assert_eq!(output(src), "ax");
+        let src = "let type list = {value : int, next : list}\n var l := list {value = 1, next = list {value = 2, next = nil}} in \
+                   (l.next.value := 5; print(l.value + l.next.value)) end";
+        assert_eq!(output(src), "6\n");
+    }
+
+    #[test]
+    fn test_vm_strings(){
+        assert_eq!(output("(print(chr(100)); print(size(\"abc\")); print(ord(\"b\")))"), "d3\n98\n");
+        let src = "let function f(a:string) = (print(concat(a, a)); print(substring(a, 1, 2)); print(concat(a, getchar()))) in f(\"hey\") end";
+        assert_eq!(output(src), "heyheyeyheyx");
+        let lt = LessThanExpr(B(StringExpr(b"abc".to_vec())), B(StringExpr(b"abd".to_vec())));
+        assert_eq!(run_expr(&B(print(lt))), Ok((0, String::from("1\n"))));
+    }
+
+    #[test]
+    fn test_vm_exit_code(){
+        assert_eq!(run_src("(print(1); exit(4); print(2))"), Ok((4, String::from("1\n"))));
+    }
+
+    #[test]
+    fn test_vm_runtime_errors(){
+        assert_eq!(run_src("let var a : array := array of int[3] of 1 in print(a[3]) end").err(),
+                   Some(String::from("line 1: Index 3 out of bounds for an array of size 3")));
+        assert_eq!(run_src("(print(1);\n print(10 / 0))").err(), Some(String::from("line 2: division by zero")));
+        assert_eq!(run_src("let function f(n:int) : int =\n  n / 0\nin print(f(0)) end").err(),
+                   Some(String::from("line 2: division by zero")));
+        //a program built by hand has no lines
+        assert_eq!(run_expr(&B(print(ModExpr(B(NumExpr(7)), B(NumExpr(0)))))).err(), Some(String::from("division by zero in 'main'")));
+        assert_eq!(run_src("let function f(n:int) : int = f(n) in print(f(0)) end").err(),
+                   Some(String::from("line 1: stack overflow")));
+        assert_eq!(run_src("let var r : rec := {f:int, g:node} in print(r.g.f) end").err(),
+                   Some(String::from("line 1: Field access on a nil record")));
+    }
+}
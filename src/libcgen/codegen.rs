@@ -0,0 +1,1044 @@
+//! Translation of the type-checked AST into c99.
+//!
+//! Every Tiger function becomes a c function. The parameters and variables
+//! that the escape analysis found nested functions to use are the fields of
+//! a struct `env` on its stack, which starts with `up`, the env of the
+//! function it is nested in; `env.up->up->x` is a variable two functions
+//! out. The others are plain c locals and parameters. Functions with no
+//! escaping variables and nothing to reach through `up` have no env.
+//!
+//! Each line of a function body remembers the Tiger line it comes from, and
+//! a `#line` directive goes wherever the c compiler would otherwise count
+//! wrong, so that its messages and debug information point at the .tig file.
+
+use std::collections::HashSet;
+use syntax::ast::{Expr, LValue, Decl, TType, Escape, OptionalExpr, OptionalTypeExprTupleList};
+use syntax::ptr::B;
+use runtime;
+
+//c99 keywords and the object-like macros of the headers the output
+//includes. Tiger names that are one of them get a "v_" prefix, as do the
+//ones that look like a function or runtime name
+const RESERVED : &'static [&'static str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+    "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while",
+    "NULL", "EOF", "BUFSIZ", "FILENAME_MAX", "FOPEN_MAX", "L_tmpnam", "TMP_MAX", "SEEK_CUR", "SEEK_END",
+    "SEEK_SET", "EXIT_FAILURE", "EXIT_SUCCESS", "MB_CUR_MAX", "RAND_MAX", "stdin", "stdout", "stderr",
+    "errno", "strcmp"
+];
+
+fn ident(name : &str) -> String{
+    if RESERVED.contains(&name) || name.starts_with("f_") || name.starts_with("tiger_"){
+        format!("v_{}", name)
+    }
+    else{
+        String::from(name)
+    }
+}
+
+//a c string literal
+pub fn string_literal(s : &[u8]) -> String{
+    let mut out = String::from("\"");
+    let mut prev = 0;
+    for &b in s{
+        match b{
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            //no trigraphs
+            b'?' if prev == b'?' => out.push_str("\\?"),
+            b if b >= 0x20 && b < 0x7f => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b))
+        }
+        prev = b;
+    }
+    out.push('"');
+    out
+}
+
+fn number(n : i32) -> String{
+    //-2147483648 would be the negation of a long
+    if n == ::std::i32::MIN {String::from("(-2147483647 - 1)")} else {n.to_string()}
+}
+
+fn pointer(ty : &str) -> String{
+    if ty.ends_with('*') {format!("{}*", ty)} else {format!("{} *", ty)}
+}
+
+fn declaration(ty : &str, name : &str) -> String{
+    if ty.ends_with('*') {format!("{}{}", ty, name)} else {format!("{} {}", ty, name)}
+}
+
+//whether a function body declares escaping variables, which go into its env
+fn declares_vars(expr : &Expr) -> bool{
+    match *expr{
+        Expr::LetExpr(ref decls, ref body) => decls.iter().any(|d| match *d{
+            Decl::VarDec(_, _, ref rhs, ref escape) => escape.escapes() || declares_vars(rhs),
+            _ => false
+        }) || body.as_ref().map_or(false, |b| declares_vars(b)),
+        Expr::ForExpr(_, ref lo, ref hi, ref body, ref escape) =>
+            escape.escapes() || declares_vars(lo) || declares_vars(hi) || declares_vars(body),
+        Expr::CallExpr(_, ref args) => args.iter().flat_map(|a| a.iter()).any(|&(_, ref e)| declares_vars(e)),
+        Expr::SeqExpr(ref exprs) => exprs.iter().flat_map(|e| e.iter()).any(|e| declares_vars(e)),
+        Expr::LValueExpr(ref lvalue) => lvalue_declares_vars(lvalue),
+        Expr::AssignExpr(ref lvalue, ref rhs) => lvalue_declares_vars(lvalue) || declares_vars(rhs),
+        Expr::ArrayExpr(_, ref l, ref r) |
+        Expr::AddExpr(ref l, ref r) | Expr::SubExpr(ref l, ref r) | Expr::MulExpr(ref l, ref r) |
+        Expr::DivExpr(ref l, ref r) | Expr::ModExpr(ref l, ref r) | Expr::EqualsExpr(ref l, ref r) |
+        Expr::NotEqualsExpr(ref l, ref r) | Expr::LessThanExpr(ref l, ref r) | Expr::LessThanEqualsExpr(ref l, ref r) |
+        Expr::GreaterThanExpr(ref l, ref r) | Expr::GreaterThanEqualsExpr(ref l, ref r) |
+        Expr::IfThenExpr(ref l, ref r) | Expr::WhileExpr(ref l, ref r) =>
+            declares_vars(l) || declares_vars(r),
+        Expr::IfThenElseExpr(ref c, ref t, ref e) => declares_vars(c) || declares_vars(t) || declares_vars(e),
+        _ => false
+    }
+}
+
+fn lvalue_declares_vars(lvalue : &LValue) -> bool{
+    match *lvalue{
+        LValue::SimpleVar(_) => false,
+        LValue::FieldVar(ref rec, _) => lvalue_declares_vars(rec),
+        LValue::SubscriptVar(ref arr, ref idx) => lvalue_declares_vars(arr) || declares_vars(idx)
+    }
+}
+
+enum Entry{
+    //`level` is the nesting depth of the function the variable belongs to.
+    //`record` is the struct of the record literal it was initialized with.
+    //`field` is a c local instead when the variable isn't in the env
+    Var{field : String, ty : TType, level : usize, record : Option<usize>, in_env : bool},
+    //`level` is the nesting depth of the function the declaration is in
+    Fun{name : String, ret : TType, level : usize, arity : usize, has_up : bool},
+    Type(TType)
+}
+
+//a c expression with the value of a Tiger expression
+#[derive(Clone)]
+struct Value{
+    code : String,
+    ty : TType,
+    record : Option<usize>,
+    //a binary operation, which needs parentheses as an operand
+    binary : bool,
+    effects : bool,
+    //it has the same value wherever it is evaluated
+    constant : bool
+}
+
+impl Value{
+    fn new(code : String, ty : TType) -> Value{
+        Value { code : code, ty : ty, record : None, binary : false, effects : false, constant : false }
+    }
+
+    fn constant(code : String, ty : TType) -> Value{
+        Value { constant : true, ..Value::new(code, ty) }
+    }
+
+    fn operand(&self) -> String{
+        if self.binary {format!("({})", self.code)} else {self.code.clone()}
+    }
+}
+
+//(Tiger line, indentation, text)
+type Line = (usize, usize, String);
+
+//the function whose body is being generated
+struct Function{
+    name : String,
+    //its slot in Codegen::functions
+    slot : usize,
+    ret : String,
+    params : Vec<String>,
+    //the name of the env struct, when it has one
+    env : Option<String>,
+    fields : Vec<String>,
+    //the names taken by fields, parameters and temps
+    names : HashSet<String>,
+    body : Vec<Line>,
+    indent : usize,
+    //the loops around the code being generated
+    loops : usize,
+    line : usize
+}
+
+struct Finished{
+    prototype : String,
+    //the definition of the env struct
+    env : Option<String>,
+    lines : Vec<Line>
+}
+
+struct Codegen{
+    //scopes are separated by None markers
+    env : Vec<(String, Option<Entry>)>,
+    path : String,
+    //the line of the expression being generated
+    line : usize,
+    fn_names : HashSet<String>,
+    frames : Vec<Function>,
+    //in the order the functions start in the source; main is the first
+    functions : Vec<Option<Finished>>,
+    //the fields of the record structs
+    records : Vec<Vec<(String, TType)>>,
+    //the runtime functions called
+    used : Vec<&'static str>
+}
+
+//the value of the expression, if it has one
+type GenResult = Result<Option<Value>, String>;
+
+impl Codegen{
+    fn new(path : &str) -> Self{
+        Codegen {
+            env : Vec::new(),
+            path : String::from(path),
+            line : 0,
+            fn_names : HashSet::new(),
+            frames : Vec::new(),
+            functions : Vec::new(),
+            records : Vec::new(),
+            used : Vec::new()
+        }
+    }
+
+    fn level(&self) -> usize{
+        self.frames.len() - 1
+    }
+
+    fn frame(&mut self) -> &mut Function{
+        self.frames.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, text : String){
+        let line = self.line;
+        let f = self.frame();
+        let indent = f.indent;
+        f.body.push((line, indent, text));
+    }
+
+    //emits a line ending in '{'
+    fn open(&mut self, text : String){
+        self.emit(text);
+        self.frame().indent += 1;
+    }
+
+    //emits a line starting with '}'. It needs no Tiger line of its own
+    fn close(&mut self, text : &str){
+        let f = self.frame();
+        f.indent -= 1;
+        let indent = f.indent;
+        f.body.push((0, indent, String::from(text)));
+    }
+
+    fn mark(&mut self) -> usize{
+        self.frame().body.len()
+    }
+
+    fn insert(&mut self, at : usize, text : String){
+        let line = self.line;
+        let f = self.frame();
+        let indent = f.indent;
+        f.body.insert(at, (line, indent, text));
+    }
+
+    //a name no field, parameter or temp of the current function has
+    fn unique(&mut self, base : &str) -> String{
+        let f = self.frame();
+        let mut name = String::from(base);
+        let mut i = 2;
+        while f.names.contains(&name){
+            name = format!("{}_{}", base, i);
+            i += 1;
+        }
+        f.names.insert(name.clone());
+        name
+    }
+
+    fn temp(&mut self) -> String{
+        let mut i = 1;
+        while self.frame().names.contains(&format!("t{}", i)){
+            i += 1;
+        }
+        self.unique(&format!("t{}", i))
+    }
+
+    //function names get an "f_" prefix, so that they can't clash with the c
+    //library, and a number when another function has the same name
+    fn fn_name(&mut self, name : &str) -> String{
+        let base = format!("f_{}", name);
+        let mut c_name = base.clone();
+        let mut i = 2;
+        while self.fn_names.contains(&c_name){
+            c_name = format!("{}_{}", base, i);
+            i += 1;
+        }
+        self.fn_names.insert(c_name.clone());
+        c_name
+    }
+
+    fn runtime(&mut self, name : &'static str) -> &'static str{
+        if !self.used.contains(&name){
+            self.used.push(name);
+        }
+        name
+    }
+
+    fn record(&mut self, fields : &[(String, TType)]) -> usize{
+        let fields : Vec<(String, TType)> = fields.iter().map(|&(ref n, ref t)| (n.clone(), self.resolve(t))).collect();
+        match self.records.iter().position(|r| *r == fields){
+            Some(i) => i,
+            None => {
+                self.records.push(fields);
+                self.records.len() - 1
+            }
+        }
+    }
+
+    fn lookup(&self, id : &str) -> Option<&Entry>{
+        for &(ref name, ref entry) in self.env.iter().rev(){
+            if name == id && entry.is_some(){
+                return entry.as_ref()
+            }
+        }
+        None
+    }
+
+    fn begin_scope(&mut self){
+        self.env.push((String::from("<marker>"), None));
+    }
+
+    fn end_scope(&mut self){
+        while self.env.pop().unwrap().1.is_some(){}
+    }
+
+    //follows type aliases
+    fn resolve(&self, ty : &TType) -> TType{
+        match *ty{
+            TType::TCustom(ref name) => match self.lookup(name){
+                Some(&Entry::Type(ref t)) if *t != *ty => self.resolve(t),
+                _ => ty.clone()
+            },
+            _ => ty.clone()
+        }
+    }
+
+    fn c_type(&self, ty : &TType, record : Option<usize>) -> String{
+        match self.resolve(ty){
+            TType::TInt32 => String::from("int"),
+            TType::TString => String::from("char *"),
+            TType::TArray(ref elem) => pointer(&self.c_type(elem, None)),
+            TType::TVoid => String::from("void"),
+            _ => match record{
+                Some(r) => format!("struct rec{} *", r),
+                None => String::from("void *")
+            }
+        }
+    }
+
+    //the env `depth` functions out: env, env.up, env.up->up..
+    fn env_at(depth : usize) -> String{
+        let mut env = String::from("env");
+        for i in 0..depth{
+            env.push_str(if i == 0 {".up"} else {"->up"});
+        }
+        env
+    }
+
+    //a variable of the current function, in the env when it escapes. Returns
+    //its field or local and what its first assignment assigns to: `env.x`,
+    //or the declaration of the local
+    fn declare_var(&mut self, name : &str, ty : TType, record : Option<usize>, escape : &Escape) -> (String, String){
+        let field = self.unique(&ident(name));
+        let decl = declaration(&self.c_type(&ty, record), &field);
+        let in_env = escape.escapes();
+        let target = if in_env{
+            self.frame().fields.push(decl);
+            format!("env.{}", field)
+        }
+        else{
+            decl
+        };
+        let level = self.level();
+        self.env.push((String::from(name), Some(Entry::Var { field : field.clone(), ty : ty, level : level, record : record, in_env : in_env })));
+        (field, target)
+    }
+
+    fn var(&self, id : &str) -> Result<Value, String>{
+        match self.lookup(id){
+            Some(&Entry::Var{ref field, ref ty, level, record, in_env}) => {
+                let depth = self.level() - level;
+                let env = Codegen::env_at(depth);
+                let code = match (in_env, depth){
+                    (false, 0) => field.clone(),
+                    (false, _) => return Err(format!("'{}' is used by a nested function but wasn't found to escape", id)),
+                    (true, 0) => format!("{}.{}", env, field),
+                    (true, _) => format!("{}->{}", env, field)
+                };
+                Ok(Value { record : record, ..Value::new(code, self.resolve(ty)) })
+            },
+            Some(_) => Err(format!("Invalid reference to variable '{}'. Different binding found.", id)),
+            None => Err(format!("Invalid reference to variable '{}'", id))
+        }
+    }
+
+    //stores a value in a temp, unless it is constant already
+    fn stable(&mut self, v : Value) -> Value{
+        if v.constant{
+            return v
+        }
+        let t = self.temp();
+        let decl = declaration(&self.c_type(&v.ty, v.record), &t);
+        self.emit(format!("{} = {};", decl, v.code));
+        Value { code : t, binary : false, effects : false, constant : true, ..v }
+    }
+
+    //evaluates `exprs` after the values in `vals`. Tiger evaluates operands
+    //from left to right, but c leaves the order open, so an operand that a
+    //later one could change, or that could change a later one, is stored in
+    //a temp right after it is evaluated
+    fn operands(&mut self, mut vals : Vec<Value>, exprs : &[&B<Expr>]) -> Result<Vec<Value>, String>{
+        let mut ends = vec![self.mark(); vals.len()];
+        for e in exprs.iter(){
+            let v = try!(self.value(e));
+            vals.push(v);
+            ends.push(self.mark());
+        }
+        let mut inserted = 0;
+        for k in 0..vals.len(){
+            if vals[k].constant{
+                continue
+            }
+            let before_later = (k + 1..vals.len()).any(|j|
+                ends[j] > ends[j - 1] || vals[j].effects || (vals[k].effects && !vals[j].constant));
+            if before_later{
+                let t = self.temp();
+                let decl = declaration(&self.c_type(&vals[k].ty, vals[k].record), &t);
+                let text = format!("{} = {};", decl, vals[k].code);
+                self.insert(ends[k] + inserted, text);
+                inserted += 1;
+                vals[k] = Value { code : t, binary : false, effects : false, constant : true, ..vals[k].clone() };
+            }
+        }
+        Ok(vals)
+    }
+
+    //generates an expression that has to produce a value
+    fn value(&mut self, expr : &B<Expr>) -> Result<Value, String>{
+        match try!(self.exp(expr)){
+            Some(v) => Ok(v),
+            None => Err(format!("{:?} doesn't produce a value", expr))
+        }
+    }
+
+    //generates an expression whose value isn't needed
+    fn effect(&mut self, expr : &B<Expr>) -> Result<(), String>{
+        if let Some(v) = try!(self.exp(expr)){
+            if v.effects{
+                self.emit(format!("{};", v.code));
+            }
+        }
+        Ok(())
+    }
+
+    //Tiger arithmetic wraps around. Signed overflow is undefined in c while
+    //unsigned arithmetic wraps, so the operation is done on unsigned values
+    //and converted back, which every c compiler does modulo 2^32
+    fn arith(&mut self, op : &str, lhs : &B<Expr>, rhs : &B<Expr>) -> GenResult{
+        let vals = try!(self.operands(Vec::new(), &[lhs, rhs]));
+        let (l, r) = (&vals[0], &vals[1]);
+        Ok(Some(Value {
+            effects : l.effects || r.effects,
+            constant : l.constant && r.constant,
+            ..Value::new(format!("(int)((unsigned){} {} (unsigned){})", l.operand(), op, r.operand()), TType::TInt32)
+        }))
+    }
+
+    //through the runtime, which stops the program on a division by zero
+    fn division(&mut self, f : &'static str, lhs : &B<Expr>, rhs : &B<Expr>) -> GenResult{
+        let vals = try!(self.operands(Vec::new(), &[lhs, rhs]));
+        let f = self.runtime(f);
+        let code = format!("{}({}, {}, {})", f, vals[0].code, vals[1].code, self.line);
+        //ending the program is an effect
+        Ok(Some(Value { effects : true, ..Value::new(code, TType::TInt32) }))
+    }
+
+    fn compare(&mut self, op : &str, lhs : &B<Expr>, rhs : &B<Expr>) -> GenResult{
+        let vals = try!(self.operands(Vec::new(), &[lhs, rhs]));
+        let (l, r) = (&vals[0], &vals[1]);
+        let code = if self.resolve(&l.ty) == TType::TString{
+            format!("strcmp({}, {}) {} 0", l.code, r.code, op)
+        }
+        else{
+            format!("{} {} {}", l.operand(), op, r.operand())
+        };
+        Ok(Some(Value {
+            binary : true,
+            effects : l.effects || r.effects,
+            constant : l.constant && r.constant,
+            ..Value::new(code, TType::TInt32)
+        }))
+    }
+
+    //the c lvalue for `lvalue`. The struct of a record is known from its
+    //type, but a record that isn't in a variable of its own is a `void *`
+    //and has to be cast to it
+    fn lvalue(&mut self, lvalue : &LValue) -> GenResult{
+        match *lvalue{
+            LValue::SimpleVar(ref id) => self.var(id).map(Some),
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                let a = try!(self.lvalue_value(arr));
+                let elem_ty = match self.resolve(&a.ty){
+                    TType::TArray(ref t) => self.resolve(t),
+                    _ => return Err(format!("{:?} is not an array", arr))
+                };
+                let vals = try!(self.operands(vec![a], &[idx]));
+                let effects = vals[0].effects || vals[1].effects;
+                Ok(Some(Value { effects : effects, ..Value::new(format!("{}[{}]", vals[0].code, vals[1].code), elem_ty) }))
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                let r = try!(self.lvalue_value(rec));
+                let fields = match self.resolve(&r.ty){
+                    TType::TRecord(fields) => fields,
+                    _ => return Err(format!("{:?} is not a record", rec))
+                };
+                let (member, ty) = match fields.iter().find(|f| f.0 == *field){
+                    Some(&(ref name, ref ty)) => (ident(name), self.resolve(ty)),
+                    None => return Err(format!("{:?} has no field '{}'", rec, field))
+                };
+                let s = self.record(&fields);
+                let base = if r.record == Some(s) {r.code} else {format!("((struct rec{} *){})", s, r.code)};
+                Ok(Some(Value { effects : r.effects, ..Value::new(format!("{}->{}", base, member), ty) }))
+            }
+        }
+    }
+
+    fn lvalue_value(&mut self, lvalue : &LValue) -> Result<Value, String>{
+        match try!(self.lvalue(lvalue)){
+            Some(v) => Ok(v),
+            None => Err(format!("{:?} doesn't produce a value", lvalue))
+        }
+    }
+
+    fn assign(&mut self, lhs : &LValue, rhs : &B<Expr>) -> GenResult{
+        if let LValue::SimpleVar(ref id) = *lhs{
+            let var = try!(self.var(id));
+            let v = try!(self.value(rhs));
+            self.emit(format!("{} = {};", var.code, v.code));
+            return Ok(None)
+        }
+        //the location is found before the rhs is evaluated. When the rhs
+        //could change where it is, its address is kept in a temp, which has
+        //the c type of an array of what it points to
+        let dst = try!(self.lvalue_value(lhs));
+        let addr = format!("&{}", dst.code);
+        let ptr = Value { effects : dst.effects, ..Value::new(addr.clone(), TType::TArray(B(dst.ty.clone()))) };
+        let vals = try!(self.operands(vec![ptr], &[rhs]));
+        if vals[0].code == addr{
+            self.emit(format!("{} = {};", dst.code, vals[1].code));
+        }
+        else{
+            self.emit(format!("*{} = {};", vals[0].code, vals[1].code));
+        }
+        Ok(None)
+    }
+
+    fn call(&mut self, name : &str, args : &OptionalTypeExprTupleList) -> GenResult{
+        let args : Vec<&B<Expr>> = args.iter().flat_map(|a| a.iter()).map(|&(_, ref e)| e).collect();
+        let (c_name, ret, level, arity, has_up) = match self.lookup(name){
+            Some(&Entry::Fun{ref name, ref ret, level, arity, has_up}) => (name.clone(), self.resolve(ret), level, arity, has_up),
+            Some(_) => return Err(format!("Invalid reference to function '{}'. Different binding found.", name)),
+            None => return self.builtin(name, &args)
+        };
+        if args.len() != arity{
+            return Err(format!("{}() expects {} argument(s) but {} were given", name, arity, args.len()))
+        }
+        let vals = try!(self.operands(Vec::new(), &args));
+        let mut codes : Vec<String> = vals.into_iter().map(|v| v.code).collect();
+        if has_up{
+            let depth = self.level() - level;
+            codes.insert(0, if depth == 0 {String::from("&env")} else {Codegen::env_at(depth)});
+        }
+        let code = format!("{}({})", c_name, codes.join(", "));
+        if ret == TType::TVoid{
+            self.emit(format!("{};", code));
+            return Ok(None)
+        }
+        Ok(Some(Value { effects : true, ..Value::new(code, ret) }))
+    }
+
+    fn builtin(&mut self, name : &str, args : &[&B<Expr>]) -> GenResult{
+        let expected = match name{
+            "print" | "ord" | "chr" | "size" | "not" | "exit" => 1,
+            "flush" | "getchar" => 0,
+            "substring" => 3,
+            "concat" => 2,
+            _ => return Err(format!("Call to '{}' not found", name))
+        };
+        if args.len() != expected{
+            return Err(format!("{}() expects {} argument(s) but {} were given", name, expected, args.len()))
+        }
+        let vals = try!(self.operands(Vec::new(), args));
+        let codes : Vec<String> = vals.iter().map(|v| v.code.clone()).collect();
+        let (f, ty) = match name{
+            "print" if self.resolve(&vals[0].ty) == TType::TInt32 => ("tiger_print_int", TType::TVoid),
+            "print" => ("tiger_print", TType::TVoid),
+            "flush" => ("tiger_flush", TType::TVoid),
+            "exit" => ("tiger_exit", TType::TVoid),
+            "not" => return Ok(Some(Value {
+                effects : vals[0].effects,
+                constant : vals[0].constant,
+                ..Value::new(format!("!{}", vals[0].operand()), TType::TInt32)
+            })),
+            "ord" => ("tiger_ord", TType::TInt32),
+            "size" => ("tiger_size", TType::TInt32),
+            "getchar" => ("tiger_getchar", TType::TString),
+            "chr" => ("tiger_chr", TType::TString),
+            "substring" => ("tiger_substring", TType::TString),
+            _ => ("tiger_concat", TType::TString)
+        };
+        let f = self.runtime(f);
+        let code = format!("{}({})", f, codes.join(", "));
+        if ty == TType::TVoid{
+            self.emit(format!("{};", code));
+            return Ok(None)
+        }
+        let effects = name == "getchar" || vals.iter().any(|v| v.effects);
+        Ok(Some(Value { effects : effects, ..Value::new(code, ty) }))
+    }
+
+    fn array(&mut self, elem_ty : &TType, dim : &B<Expr>, init : &B<Expr>) -> GenResult{
+        let mut vals = try!(self.operands(Vec::new(), &[dim, init]));
+        let init = vals.pop().unwrap();
+        let dim = vals.pop().unwrap();
+        let n = self.stable(dim);
+        let v = self.stable(init);
+        let ty = TType::TArray(B(self.resolve(elem_ty)));
+        let a = self.temp();
+        let alloc = self.runtime("tiger_alloc");
+        let decl = declaration(&self.c_type(&ty, None), &a);
+        self.emit(format!("{} = {}({} * sizeof *{});", decl, alloc, n.operand(), a));
+        //the memory is zeroed already
+        if v.code != "0" && v.code != "NULL"{
+            let i = self.temp();
+            self.open(format!("for (int {} = 0; {} < {}; {}++) {{", i, i, n.operand(), i));
+            self.emit(format!("{}[{}] = {};", a, i, v.code));
+            self.close("}");
+        }
+        Ok(Some(Value::constant(a, ty)))
+    }
+
+    fn if_else(&mut self, expr : &Expr, cond : &B<Expr>, then_expr : &B<Expr>, else_expr : &B<Expr>) -> GenResult{
+        let cond = try!(self.value(cond));
+        let at = self.mark();
+        self.open(format!("if ({}) {{", cond.code));
+        let then_value = try!(self.exp(then_expr));
+        let result = match then_value{
+            Some(v) => {
+                let t = self.temp();
+                self.emit(format!("{} = {};", t, v.code));
+                Some((t, v))
+            },
+            None => None
+        };
+        self.close("} else {");
+        self.frame().indent += 1;
+        let else_at = self.mark();
+        let else_value = try!(self.exp(else_expr));
+        match (result, else_value){
+            (Some((t, then_value)), Some(else_value)) => {
+                self.emit(format!("{} = {};", t, else_value.code));
+                self.close("}");
+                //a nil branch says nothing about the type
+                let v = if then_value.ty == TType::TNil {else_value} else {then_value};
+                let decl = declaration(&self.c_type(&v.ty, v.record), &t);
+                self.insert(at, format!("{};", decl));
+                Ok(Some(Value { record : v.record, ..Value::constant(t, v.ty) }))
+            },
+            (None, None) => {
+                if self.mark() == else_at{
+                    //no else
+                    self.frame().body.pop();
+                }
+                self.close("}");
+                Ok(None)
+            },
+            _ => Err(format!("the branches of {:?} don't agree on producing a value", expr))
+        }
+    }
+
+    fn while_loop(&mut self, cond : &B<Expr>, body : &B<Expr>) -> Result<(), String>{
+        let at = self.mark();
+        let cond = try!(self.value(cond));
+        if self.mark() == at{
+            self.open(format!("while ({}) {{", cond.code));
+        }
+        else{
+            //the statements the condition needs run at the start of every iteration
+            let stms = self.frame().body.split_off(at);
+            self.open(String::from("for (;;) {"));
+            for (line, indent, text) in stms{
+                self.frame().body.push((line, indent + 1, text));
+            }
+            self.emit(format!("if (!{}) break;", cond.operand()));
+        }
+        self.frame().loops += 1;
+        try!(self.effect(body));
+        self.frame().loops -= 1;
+        self.close("}");
+        Ok(())
+    }
+
+    fn for_loop(&mut self, id : &str, lo : &B<Expr>, hi : &B<Expr>, body : &B<Expr>, escape : &Escape) -> Result<(), String>{
+        let mut vals = try!(self.operands(Vec::new(), &[lo, hi]));
+        let hi_value = vals.pop().unwrap();
+        let lo_value = vals.pop().unwrap();
+        self.begin_scope();
+        let (_, target) = self.declare_var(id, TType::TInt32, None, escape);
+        let i = try!(self.var(id)).code;
+        match **hi{
+            //the plain loop would never end with hi = maxint
+            Expr::NumExpr(n) if n != ::std::i32::MAX => {
+                self.open(format!("for ({} = {}; {} <= {}; {}++) {{", target, lo_value.code, i, hi_value.code, i));
+                self.frame().loops += 1;
+                let result = self.effect(body);
+                self.end_scope();
+                try!(result);
+                self.frame().loops -= 1;
+            },
+            //the bounds are inclusive and evaluated once. The exit test happens
+            //before the increment so that hi = maxint doesn't overflow
+            _ => {
+                self.emit(format!("{} = {};", target, lo_value.code));
+                let limit = self.stable(hi_value);
+                self.open(format!("if ({} <= {}) {{", i, limit.code));
+                self.open(String::from("for (;;) {"));
+                self.frame().loops += 1;
+                let result = self.effect(body);
+                self.end_scope();
+                try!(result);
+                self.frame().loops -= 1;
+                self.emit(format!("if ({} >= {}) break;", i, limit.code));
+                self.emit(format!("{}++;", i));
+                self.close("}");
+            }
+        }
+        self.close("}");
+        Ok(())
+    }
+
+    fn exp(&mut self, expr : &B<Expr>) -> GenResult{
+        let line = self.line;
+        if expr.span.line != 0{
+            self.line = expr.span.line;
+        }
+        let result = self.exp_kind(expr);
+        self.line = line;
+        result
+    }
+
+    fn exp_kind(&mut self, expr : &Expr) -> GenResult{
+        match *expr{
+            Expr::NumExpr(n) => Ok(Some(Value::constant(number(n), TType::TInt32))),
+            Expr::StringExpr(ref s) => Ok(Some(Value::constant(string_literal(s), TType::TString))),
+            Expr::NilExpr => Ok(Some(Value::constant(String::from("NULL"), TType::TNil))),
+            Expr::NoOpExpr => Ok(None),
+            Expr::IdExpr(ref id) => self.var(id).map(Some),
+            Expr::LValueExpr(ref lvalue) => self.lvalue(lvalue),
+            Expr::AssignExpr(ref lhs, ref rhs) => self.assign(lhs, rhs),
+            Expr::AddExpr(ref l, ref r) => self.arith("+", l, r),
+            Expr::SubExpr(ref l, ref r) => self.arith("-", l, r),
+            Expr::MulExpr(ref l, ref r) => self.arith("*", l, r),
+            Expr::DivExpr(ref l, ref r) => self.division("tiger_div", l, r),
+            Expr::ModExpr(ref l, ref r) => self.division("tiger_mod", l, r),
+            Expr::EqualsExpr(ref l, ref r) => self.compare("==", l, r),
+            Expr::NotEqualsExpr(ref l, ref r) => self.compare("!=", l, r),
+            Expr::LessThanExpr(ref l, ref r) => self.compare("<", l, r),
+            Expr::LessThanEqualsExpr(ref l, ref r) => self.compare("<=", l, r),
+            Expr::GreaterThanExpr(ref l, ref r) => self.compare(">", l, r),
+            Expr::GreaterThanEqualsExpr(ref l, ref r) => self.compare(">=", l, r),
+            Expr::IfThenExpr(ref cond, ref then_expr) => {
+                let cond = try!(self.value(cond));
+                self.open(format!("if ({}) {{", cond.code));
+                try!(self.effect(then_expr));
+                self.close("}");
+                Ok(None)
+            },
+            Expr::IfThenElseExpr(ref cond, ref then_expr, ref else_expr) => self.if_else(expr, cond, then_expr, else_expr),
+            Expr::WhileExpr(ref cond, ref body) => self.while_loop(cond, body).map(|_| None),
+            Expr::ForExpr(ref id, ref lo, ref hi, ref body, ref escape) => self.for_loop(id, lo, hi, body, escape).map(|_| None),
+            Expr::BreakExpr => {
+                if self.frame().loops == 0{
+                    return Err(String::from("'break' outside of a loop"))
+                }
+                self.emit(String::from("break;"));
+                Ok(None)
+            },
+            Expr::CallExpr(ref name, ref args) => self.call(name, args),
+            Expr::SeqExpr(ref exprs) => {
+                let exprs = match *exprs{
+                    Some(ref e) if !e.is_empty() => e,
+                    _ => return Ok(None)
+                };
+                for e in exprs[..exprs.len() - 1].iter(){
+                    try!(self.effect(e));
+                }
+                self.exp(exprs.last().unwrap())
+            },
+            Expr::LetExpr(ref decls, ref body) => {
+                self.begin_scope();
+                let result = self.let_expr(decls, body);
+                self.end_scope();
+                result
+            },
+            Expr::ArrayExpr(ref elem_ty, ref dim, ref init) => self.array(elem_ty, dim, init),
+            Expr::TypedArrayExpr(ref ty_id, ref dim, ref init) => match self.resolve(&TType::TCustom(ty_id.clone())){
+                TType::TArray(ref elem_ty) => self.array(elem_ty, dim, init),
+                _ => Err(format!("'{}' is not an array type", ty_id))
+            },
+            Expr::RecordExpr(ref fields) => {
+                let alloc = self.runtime("tiger_alloc");
+                match *fields{
+                    Some(ref f) if !f.is_empty() => {
+                        let r = self.record(f);
+                        Ok(Some(Value { record : Some(r), ..Value::new(format!("{}(sizeof(struct rec{}))", alloc, r), TType::TRecord(f.clone())) }))
+                    },
+                    _ => Ok(Some(Value::new(format!("{}(1)", alloc), TType::TRecord(Vec::new()))))
+                }
+            },
+            Expr::TypedRecordExpr(ref ty_id, ref inits) => self.typed_record(ty_id, inits),
+            ref e => Err(format!("{:?} can't be compiled to c", e))
+        }
+    }
+
+    //the initializers are evaluated in the order they are written; the
+    //fields without one stay zeroed
+    fn typed_record(&mut self, ty_id : &str, inits : &[(String, B<Expr>)]) -> GenResult{
+        let fields = match self.resolve(&TType::TCustom(String::from(ty_id))){
+            TType::TRecord(fields) => fields,
+            _ => return Err(format!("'{}' is not a record type", ty_id))
+        };
+        if let Some(&(ref name, _)) = inits.iter().find(|i| !fields.iter().any(|f| f.0 == i.0)){
+            return Err(format!("'{}' has no field '{}'", ty_id, name))
+        }
+        let alloc = self.runtime("tiger_alloc");
+        if fields.is_empty(){
+            return Ok(Some(Value::new(format!("{}(1)", alloc), TType::TRecord(fields))))
+        }
+        let exprs : Vec<&B<Expr>> = inits.iter().map(|i| &i.1).collect();
+        let vals = try!(self.operands(Vec::new(), &exprs));
+        let r = self.record(&fields);
+        let ty = TType::TRecord(fields);
+        let t = self.temp();
+        let decl = declaration(&self.c_type(&ty, Some(r)), &t);
+        self.emit(format!("{} = {}(sizeof(struct rec{}));", decl, alloc, r));
+        for (&(ref name, _), v) in inits.iter().zip(vals){
+            self.emit(format!("{}->{} = {};", t, ident(name), v.code));
+        }
+        Ok(Some(Value { record : Some(r), ..Value::constant(t, ty) }))
+    }
+
+    fn let_expr(&mut self, decls : &[Decl], body : &OptionalExpr) -> GenResult{
+        let mut i = 0;
+        while i < decls.len(){
+            match decls[i]{
+                Decl::TypeDec(ref name, ref ty) => {
+                    self.env.push((name.clone(), Some(Entry::Type(ty.clone()))));
+                    i += 1;
+                },
+                Decl::VarDec(ref name, ref ty, ref rhs, ref escape) => {
+                    let v = try!(self.value(rhs));
+                    let ty = match self.resolve(ty){
+                        TType::TNil => v.ty.clone(),
+                        t => t
+                    };
+                    let (_, target) = self.declare_var(name, ty, v.record, escape);
+                    let line = self.line;
+                    if rhs.span.line != 0{
+                        self.line = rhs.span.line;
+                    }
+                    self.emit(format!("{} = {};", target, v.code));
+                    self.line = line;
+                    i += 1;
+                },
+                Decl::FunDec(..) => {
+                    //consecutive functions may call each other, so all of them
+                    //are declared before any body is generated
+                    let start = i;
+                    let has_up = self.frame().env.is_some();
+                    while i < decls.len(){
+                        match decls[i]{
+                            Decl::FunDec(ref name, ref params, ref ret, _, _) => {
+                                let c_name = self.fn_name(name);
+                                let arity = params.as_ref().map(|p| p.len()).unwrap_or(0);
+                                let level = self.level();
+                                self.env.push((name.clone(), Some(Entry::Fun { name : c_name, ret : ret.clone(), level : level,
+                                                                               arity : arity, has_up : has_up })));
+                            },
+                            _ => break
+                        }
+                        i += 1;
+                    }
+                    for d in decls[start..i].iter(){
+                        try!(self.function(d));
+                    }
+                },
+                Decl::ErrorDec => return Err(String::from("a declaration with a syntax error can't be compiled to c"))
+            }
+        }
+        match *body{
+            Some(ref b) => self.exp(b),
+            None => Ok(None)
+        }
+    }
+
+    fn begin_function(&mut self, name : String, ret : String, has_env : bool, line : usize){
+        let slot = self.functions.len();
+        self.functions.push(None);
+        let mut names = HashSet::new();
+        names.insert(String::from("env"));
+        names.insert(String::from("up"));
+        let env = if has_env {Some(format!("{}_env", name))} else {None};
+        self.frames.push(Function { name : name, slot : slot, ret : ret, params : Vec::new(), env : env, fields : Vec::new(),
+                                    names : names, body : Vec::new(), indent : 0, loops : 0, line : line });
+    }
+
+    fn end_function(&mut self){
+        let f = self.frames.pop().unwrap();
+        let params = if f.params.is_empty() {String::from("void")} else {f.params.join(", ")};
+        let prototype = format!("{} {}({})", f.ret, f.name, params);
+        let mut lines = vec![(f.line, 0, prototype.clone()), (0, 0, String::from("{"))];
+        let fields = f.fields;
+        let env = f.env.map(|env| {
+            lines.push((0, 1, format!("struct {} env;", env)));
+            let fields : Vec<String> = fields.iter().map(|d| format!("    {};\n", d)).collect();
+            format!("struct {} {{\n{}}};\n", env, fields.concat())
+        });
+        lines.extend(f.body.into_iter().map(|(line, indent, text)| (line, indent + 1, text)));
+        lines.push((0, 0, String::from("}")));
+        self.functions[f.slot] = Some(Finished { prototype : prototype, env : env, lines : lines });
+    }
+
+    fn function(&mut self, decl : &Decl) -> Result<(), String>{
+        let (params, ret, body) = match *decl{
+            Decl::FunDec(_, ref params, ref ret, ref body, _) => (params, ret, body),
+            _ => unreachable!()
+        };
+        let (name, has_up) = match *decl{
+            Decl::FunDec(ref name, ..) => match self.lookup(name){
+                Some(&Entry::Fun{ref name, has_up, ..}) => (name.clone(), has_up),
+                _ => unreachable!()
+            },
+            _ => unreachable!()
+        };
+        let params : Vec<(String, TType, &Escape)> = params.iter().flat_map(|p| p.iter())
+            .map(|&(ref p, ref t, ref escape)| (p.clone(), self.resolve(t), escape)).collect();
+        let ret = self.resolve(ret);
+        let has_env = has_up || params.iter().any(|p| p.2.escapes()) || declares_vars(body);
+        let parent_env = self.frame().env.clone();
+        let line = if body.span.line != 0 {body.span.line} else {self.line};
+        let old_line = self.line;
+        self.line = line;
+        let c_ret = self.c_type(&ret, None);
+        self.begin_function(name, c_ret, has_env, line);
+        self.begin_scope();
+        if has_up{
+            let up = pointer(&format!("struct {}", parent_env.unwrap()));
+            self.frame().params.push(declaration(&up, "up"));
+            self.frame().fields.push(declaration(&up, "up"));
+            self.emit(String::from("env.up = up;"));
+        }
+        for (p, ty, escape) in params{
+            let param_ty = self.c_type(&ty, None);
+            let (field, target) = self.declare_var(&p, ty, None, escape);
+            if escape.escapes(){
+                //the parameter has the name of its field
+                self.frame().params.push(declaration(&param_ty, &field));
+                self.emit(format!("{} = {};", target, field));
+            }
+            else{
+                self.frame().params.push(target);
+            }
+        }
+        let result = if ret == TType::TVoid{
+            self.effect(body)
+        }
+        else{
+            self.value(body).map(|v| self.emit(format!("return {};", v.code)))
+        };
+        self.end_scope();
+        try!(result);
+        self.end_function();
+        self.line = old_line;
+        Ok(())
+    }
+
+    //appends the lines with a #line directive before every one the c
+    //compiler would attribute to the wrong Tiger line. `presumed` is the line
+    //it attributes the next one to
+    fn write_lines(&self, out : &mut String, presumed : &mut Option<usize>, lines : &[Line]){
+        for &(line, indent, ref text) in lines.iter(){
+            if line != 0 && *presumed != Some(line){
+                out.push_str(&format!("#line {} {}\n", line, string_literal(self.path.as_bytes())));
+                *presumed = Some(line);
+            }
+            if !text.is_empty(){
+                out.push_str(&"    ".repeat(indent));
+                out.push_str(text);
+            }
+            out.push('\n');
+            *presumed = presumed.map(|l| l + 1);
+        }
+    }
+
+    fn output(mut self) -> String{
+        let mut out = String::from(runtime::HEADERS);
+        out.push_str(&runtime::definitions(&self.used));
+        //main goes last
+        let mut functions : Vec<Finished> = ::std::mem::replace(&mut self.functions, Vec::new()).into_iter()
+            .map(|f| f.unwrap()).collect();
+        let main = functions.remove(0);
+        functions.push(main);
+        for (i, fields) in self.records.iter().enumerate(){
+            out.push_str(&format!("\nstruct rec{} {{\n", i));
+            for &(ref name, ref ty) in fields.iter(){
+                out.push_str(&format!("    {};\n", declaration(&self.c_type(ty, None), &ident(name))));
+            }
+            out.push_str("};\n");
+        }
+        for f in functions.iter(){
+            if let Some(ref env) = f.env{
+                out.push('\n');
+                out.push_str(env);
+            }
+        }
+        if functions.len() > 1{
+            out.push('\n');
+            for f in functions[..functions.len() - 1].iter(){
+                out.push_str(&format!("{};\n", f.prototype));
+            }
+        }
+        let mut presumed = None;
+        for f in functions.iter(){
+            self.write_lines(&mut out, &mut presumed, &[(0, 0, String::new())]);
+            self.write_lines(&mut out, &mut presumed, &f.lines);
+        }
+        out
+    }
+}
+
+//the c source of a whole program, runtime included. The top level
+//expression becomes the body of `main`, which returns 0. `path` is the file
+//the #line directives name
+pub fn compile(expr : &B<Expr>, path : &str) -> Result<String, String>{
+    let mut g = Codegen::new(path);
+    g.line = expr.span.line;
+    g.begin_function(String::from("main"), String::from("int"), declares_vars(expr), expr.span.line);
+    try!(g.effect(expr));
+    g.line = 0;
+    g.emit(String::from("return 0;"));
+    g.end_function();
+    Ok(g.output())
+}
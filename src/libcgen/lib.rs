@@ -0,0 +1,225 @@
+#![crate_name = "cgen"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! C backend: turns the type-checked AST into readable c99 that any local c
+//! compiler can build, with `#line` directives that point back to the Tiger
+//! source. Tiger arithmetic wraps around, which the output does through
+//! unsigned arithmetic, so it needs no compiler flags.
+
+extern crate syntax;
+#[cfg(test)]
+extern crate session;
+
+pub mod runtime;
+pub mod codegen;
+
+pub use codegen::compile;
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::escape::find_escapes;
+    use syntax::parse::parser::{Parser};
+    use syntax::ast::{Expr, Decl, TType, Escape};
+    use syntax::ptr::{B};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use session::link::TempDir;
+    use super::*;
+
+    fn c_source(src : &str) -> String{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        find_escapes(&b_expr);
+        compile(&b_expr, "test.tig").unwrap()
+    }
+
+    //builds the c source with every warning an error and runs it. Undefined
+    //behaviour, such as a signed overflow, kills the program with a trap
+    fn run_c(c : &str, input : &str) -> (i32, String){
+        let tmp = TempDir::new().unwrap();
+        let (source, exe) = (tmp.path.join("main.c"), tmp.path.join("main"));
+        ::std::fs::File::create(&source).unwrap().write_all(c.as_bytes()).unwrap();
+        let out = Command::new("cc").args(&["-std=c99", "-pedantic", "-Wall", "-Wextra", "-Werror",
+                                            "-fsanitize=undefined", "-fsanitize-undefined-trap-on-error", "-o"])
+            .arg(&exe).arg(&source).output().unwrap();
+        assert!(out.status.success(), "{}\n{}", String::from_utf8_lossy(&out.stderr), c);
+        let mut child = Command::new(&exe).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let out = child.wait_with_output().unwrap();
+        (out.status.code().unwrap(), String::from_utf8(out.stdout).unwrap())
+    }
+
+    fn run_with_input(src : &str, input : &str) -> (i32, String){
+        run_c(&c_source(src), input)
+    }
+
+    fn run(src : &str) -> (i32, String){
+        run_with_input(src, "")
+    }
+
+    #[test]
+    fn test_c_print(){
+        assert_eq!(run("(print(\"sum: \"); print(1+2))"), (0, String::from("sum: 3\n")));
+    }
+
+    #[test]
+    fn test_c_loops(){
+        let src = "let var a : int := 0 in let var b : int := 2 in for i := 1 to 4 do (a := a + i; b := a + 1; print(b)) end end end";
+        assert_eq!(run(src).1, "2\n4\n7\n11\n");
+        assert_eq!(run("let var a : int := 0 in while 10 > a do (a := a + 3; print(a)) end").1, "3\n6\n9\n12\n");
+        //the upper bound is evaluated once
+        let src = "let var n : int := 3 in for i := 1 to n do (n := n + 1; print(i)) end end";
+        assert_eq!(run(src).1, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_c_break(){
+        let body = B(Expr::SeqExpr(Some(vec![B(Expr::CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(Expr::NumExpr(1)))]))),
+                                            B(Expr::BreakExpr)])));
+        let e = B(Expr::WhileExpr(B(Expr::GreaterThanExpr(B(Expr::NumExpr(1)), B(Expr::NumExpr(0)))), body));
+        assert_eq!(run_c(&compile(&e, "test.tig").unwrap(), "").1, "1\n");
+        assert_eq!(compile(&B(Expr::BreakExpr), "test.tig").err(), Some(String::from("'break' outside of a loop")));
+    }
+
+    #[test]
+    fn test_c_functions(){
+        let src = "let function add(a:int, b:int) : int = a + b in print(add(40, 2)) end";
+        assert_eq!(run(src).1, "42\n");
+        let src = "let function g(n:int) : int = n + 1 in let function f(n:int) : int = g(5) + n in print(f(3)) end end";
+        assert_eq!(run(src).1, "9\n");
+    }
+
+    #[test]
+    fn test_c_nested_functions_use_the_env(){
+        let src = "let var a : int := 1 in let function f(b:int) : int = let function g() : int = a + b in g() end in (a := 40; print(f(2))) end end";
+        let c = c_source(src);
+        assert!(c.contains("struct f_g_env {\n    struct f_f_env *up;\n};"), "{}", c);
+        assert!(c.contains("return (int)((unsigned)env.up->up->a + (unsigned)env.up->b);"), "{}", c);
+        assert_eq!(run_c(&c, "").1, "42\n");
+    }
+
+    #[test]
+    fn test_c_only_escaping_variables_are_in_the_env(){
+        let src = "let var a : int := 1 in let var b : int := 2 in let function f(c:int) : int = a + c in \
+                   for i := 1 to 2 do print(f(b) + i) end end end end";
+        let c = c_source(src);
+        assert!(c.contains("struct main_env {\n    int a;\n};"), "{}", c);
+        assert!(c.contains("    int b = 2;\n"), "{}", c);
+        assert!(c.contains("int f_f(struct main_env *up, int c)"), "{}", c);
+        assert!(c.contains("return (int)((unsigned)env.up->a + (unsigned)c);"), "{}", c);
+        assert!(c.contains("for (int i = 1; i <= 2; i++) {"), "{}", c);
+        assert_eq!(run_c(&c, "").1, "4\n5\n");
+    }
+
+    #[test]
+    fn test_c_arrays_and_records(){
+        let src = "let var a : array := array of int[3] of 7 in (a[1] := 5; print(a[1]); print(a[2])) end";
+        assert_eq!(run(src).1, "5\n7\n");
+        let src = "let var r : rec := {f:int, g:string} in (r.f := 4; print(r.f)) end";
+        let c = c_source(src);
+        assert!(c.contains("struct rec0 {\n    int f;\n    char *g;\n};"), "{}", c);
+        assert_eq!(run_c(&c, "").1, "4\n");
+    }
+
+    #[test]
+    fn test_c_nested_lvalues(){
+        //the rows of m start out as the same array
+        let src = "let type row = array of int\n type matrix = array of row\n var m := matrix [2] of row [3] of 0 in \
+                   (m[0][1] := 4; m[1] := row [3] of 1; m[1][2] := 7; print(m[0][1]); print(m[1][1]); print(m[1][2])) end";
+        assert_eq!(run(src).1, "4\n1\n7\n");
+    }
+
+    #[test]
+    fn test_c_typed_records(){
+        let src = "let type list = {value:int, next:list}\n var l := list{value=1, next=list{next=nil, value=2}} in \
+                   (l.next.value := 5; print(l.value); print(l.next.value)) end";
+        let c = c_source(src);
+        assert!(c.contains("struct rec0 {\n    int value;\n    void *next;\n};"), "{}", c);
+        assert!(c.contains("t1->next = NULL;\n"), "{}", c);
+        assert_eq!(run_c(&c, "").1, "1\n5\n");
+    }
+
+    #[test]
+    fn test_c_strings(){
+        assert_eq!(run("(print(chr(100)); print(size(\"abc\")); print(ord(\"b\")); print(ord(\"\")))").1, "d3\n98\n-1\n");
+        let src = "let function f(a:string) = (print(concat(a, a)); print(substring(a, 1, 2)); print(concat(a, getchar()))) in f(\"hey\") end";
+        assert_eq!(run_with_input(src, "!").1, "heyheyeyhey!");
+        //string comparisons compare the contents
+        let cmp = |l : &str, r : &str| B(Expr::CallExpr(String::from("print"), Some(vec![(TType::TInt32,
+            B(Expr::LessThanExpr(B(Expr::StringExpr(l.as_bytes().to_vec())), B(Expr::StringExpr(r.as_bytes().to_vec())))))])));
+        let e = B(Expr::SeqExpr(Some(vec![cmp("ab", "abc"), cmp("b", "abc")])));
+        assert_eq!(run_c(&compile(&e, "test.tig").unwrap(), "").1, "1\n0\n");
+    }
+
+    #[test]
+    fn test_c_evaluation_order(){
+        //f changes a after its old value is read
+        let src = "let var a : int := 1 in let function f() : int = (a := 10; 2) in print(a + f()) end end";
+        assert_eq!(run(src).1, "3\n");
+    }
+
+    #[test]
+    fn test_c_division(){
+        let src = "let var a : int := 0 - 7 in (print(17 mod 5); print(a mod 3); print(a / 2)) end";
+        assert_eq!(run(src), (0, String::from("2\n-1\n-3\n")));
+        let src = "let var a : int := 0 in (print(1); print(a mod a)) end";
+        let c = c_source(src);
+        assert!(c.contains("tiger_print_int(tiger_mod(a, a, 1));"), "{}", c);
+        assert_eq!(run_c(&c, ""), (1, String::from("1\n")));
+        //INT_MIN / -1 and INT_MIN mod -1
+        let int_min = || B(Expr::SubExpr(B(Expr::SubExpr(B(Expr::NumExpr(0)), B(Expr::NumExpr(2147483647)))), B(Expr::NumExpr(1))));
+        let minus_one = || B(Expr::SubExpr(B(Expr::NumExpr(0)), B(Expr::NumExpr(1))));
+        let print = |e : Expr| B(Expr::CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(e))])));
+        let e = B(Expr::SeqExpr(Some(vec![print(Expr::DivExpr(int_min(), minus_one())), print(Expr::ModExpr(int_min(), minus_one()))])));
+        assert_eq!(run_c(&compile(&e, "test.tig").unwrap(), ""), (0, String::from("-2147483648\n0\n")));
+    }
+
+    #[test]
+    fn test_c_arithmetic_wraps_around(){
+        let src = "let var a : int := 2147483647 in (print(a + 1); print(0 - a - 2); print(a * 2); print(65536 * 65536)) end";
+        let c = c_source(src);
+        assert!(c.contains("tiger_print_int((int)((unsigned)a + (unsigned)1));"), "{}", c);
+        assert_eq!(run_c(&c, ""), (0, String::from("-2147483648\n2147483647\n-2\n0\n")));
+    }
+
+    #[test]
+    fn test_c_exit_code(){
+        assert_eq!(run("exit(4)").0, 4);
+        assert_eq!(run("print(1)").0, 0);
+    }
+
+    #[test]
+    fn test_c_names(){
+        //Tiger names that are c keywords or clash with each other
+        let src = "let var double : int := 1 in let function exit(char:int) : int = char + double in print(exit(2)) end end";
+        let c = c_source(src);
+        assert!(c.contains("int f_exit(struct main_env *up, int v_char)"), "{}", c);
+        assert_eq!(run_c(&c, "").1, "3\n");
+    }
+
+    #[test]
+    fn test_c_line_directives(){
+        let mut print = B(Expr::CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(Expr::IdExpr(String::from("a"))))])));
+        print.span.line = 3;
+        let mut init = B(Expr::NumExpr(1));
+        init.span.line = 2;
+        let mut e = B(Expr::LetExpr(vec![Decl::VarDec(String::from("a"), TType::TInt32, init, Escape::new())], Some(print)));
+        e.span.line = 1;
+        let c = compile(&e, "dir/a.tig").unwrap();
+        assert!(c.ends_with("\
+#line 1 \"dir/a.tig\"
+int main(void)
+{
+    struct main_env env;
+#line 2 \"dir/a.tig\"
+    env.a = 1;
+    tiger_print_int(env.a);
+    return 0;
+}
+"), "{}", c);
+    }
+}
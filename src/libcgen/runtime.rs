@@ -0,0 +1,142 @@
+//! The runtime library as c source. It behaves like the one of the native
+//! backend: strings are NUL terminated and allocations zeroed. Only the
+//! functions a program uses end up in its output.
+
+pub const HEADERS : &'static str = "\
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+";
+
+//(name, the runtime functions it calls, definition)
+pub const RUNTIME : &'static [(&'static str, &'static [&'static str], &'static str)] = &[
+    ("tiger_alloc", &[], "\
+/* zeroed memory of the given number of bytes */
+static void *tiger_alloc(size_t n)
+{
+    void *p = calloc(n ? n : 1, 1);
+    if (p == NULL) {
+        fputs(\"out of memory\\n\", stderr);
+        exit(1);
+    }
+    return p;
+}
+"),
+    ("tiger_print", &[], "\
+static void tiger_print(char *s)
+{
+    fputs(s, stdout);
+}
+"),
+    ("tiger_print_int", &[], "\
+static void tiger_print_int(int n)
+{
+    printf(\"%d\\n\", n);
+}
+"),
+    ("tiger_flush", &[], "\
+static void tiger_flush(void)
+{
+    fflush(stdout);
+}
+"),
+    ("tiger_chr", &["tiger_alloc"], "\
+static char *tiger_chr(int c)
+{
+    char *s = tiger_alloc(2);
+    s[0] = (char)c;
+    return s;
+}
+"),
+    ("tiger_getchar", &["tiger_chr"], "\
+/* \"\" at the end of the input */
+static char *tiger_getchar(void)
+{
+    int c = getchar();
+    return tiger_chr(c < 0 ? 0 : c);
+}
+"),
+    ("tiger_ord", &[], "\
+/* -1 for the empty string */
+static int tiger_ord(char *s)
+{
+    return s[0] ? (unsigned char)s[0] : -1;
+}
+"),
+    ("tiger_size", &[], "\
+static int tiger_size(char *s)
+{
+    return (int)strlen(s);
+}
+"),
+    ("tiger_substring", &["tiger_alloc"], "\
+static char *tiger_substring(char *s, int first, int n)
+{
+    char *r = tiger_alloc((size_t)n + 1);
+    memcpy(r, s + first, (size_t)n);
+    return r;
+}
+"),
+    ("tiger_concat", &["tiger_alloc"], "\
+static char *tiger_concat(char *a, char *b)
+{
+    size_t m = strlen(a), n = strlen(b);
+    char *r = tiger_alloc(m + n + 1);
+    memcpy(r, a, m);
+    memcpy(r + m, b, n);
+    return r;
+}
+"),
+    ("tiger_div", &[], "\
+/* INT_MIN / -1 wraps around like the other operators */
+static int tiger_div(int a, int b, int line)
+{
+    if (b == 0) {
+        fprintf(stderr, \"line %d: division by zero\\n\", line);
+        exit(1);
+    }
+    return b == -1 ? (int)(0u - (unsigned)a) : a / b;
+}
+"),
+    ("tiger_mod", &[], "\
+static int tiger_mod(int a, int b, int line)
+{
+    if (b == 0) {
+        fprintf(stderr, \"line %d: division by zero\\n\", line);
+        exit(1);
+    }
+    return b == -1 ? 0 : a % b;
+}
+"),
+    ("tiger_exit", &[], "\
+static void tiger_exit(int code)
+{
+    exit(code);
+}
+")
+];
+
+//the definitions of the `used` runtime functions and the ones they call,
+//in the order of RUNTIME so that every function comes after its callees
+pub fn definitions(used : &[&str]) -> String{
+    let mut needed : Vec<&str> = used.to_vec();
+    let mut i = 0;
+    while i < needed.len(){
+        if let Some(&(_, deps, _)) = RUNTIME.iter().find(|r| r.0 == needed[i]){
+            for d in deps.iter(){
+                if !needed.contains(d){
+                    needed.push(d);
+                }
+            }
+        }
+        i += 1;
+    }
+    let mut out = String::new();
+    for &(name, _, code) in RUNTIME.iter(){
+        if needed.contains(&name){
+            out.push('\n');
+            out.push_str(code);
+        }
+    }
+    out
+}
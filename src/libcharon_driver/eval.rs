@@ -0,0 +1,589 @@
+//! A tree-walking evaluator for type-checked Tiger expressions.
+//! Used by the repl to run an expression without going through llvm.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use syntax::ast::{Expr, LValue, Decl, TType, OptionalTypeExprTupleList};
+use syntax::ast::Expr::*;
+use syntax::ast::Decl::*;
+
+#[derive(Clone)]
+pub enum Value{
+    Int(i32),
+    //the bytes of a string, as the compiled programs see them
+    Str(Vec<u8>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    Record(Rc<RefCell<Vec<(String, Value)>>>),
+    Func(Rc<Closure>),
+    Nil,
+    Unit
+}
+
+impl fmt::Display for Value{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(ref s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            Value::Array(ref elems) => {
+                try!(f.write_str("["));
+                for (i, e) in elems.borrow().iter().enumerate(){
+                    if i > 0 { try!(f.write_str(", ")); }
+                    try!(write!(f, "{}", e));
+                }
+                f.write_str("]")
+            },
+            Value::Record(ref fields) => {
+                try!(f.write_str("{"));
+                for (i, &(ref id, ref v)) in fields.borrow().iter().enumerate(){
+                    if i > 0 { try!(f.write_str(", ")); }
+                    try!(write!(f, "{} = {}", id, v));
+                }
+                f.write_str("}")
+            },
+            Value::Func(ref c) => write!(f, "<function/{}>", c.params.len()),
+            Value::Nil => f.write_str("nil"),
+            Value::Unit => f.write_str("()")
+        }
+    }
+}
+
+pub struct Closure{
+    params : Vec<String>,
+    body : Expr,
+    //set once all the functions of a declaration group are bound
+    //so that they can refer to each other
+    env : RefCell<Env>
+}
+
+pub struct Binding{
+    id : String,
+    value : RefCell<Value>,
+    next : Env
+}
+
+pub type Env = Option<Rc<Binding>>;
+
+//reasons for abandoning the evaluation of an expression
+#[derive(Debug, PartialEq)]
+pub enum Unwind{
+    Break,
+    Exit(i32),
+    Error(String)
+}
+
+pub type EvalResult = Result<Value, Unwind>;
+
+macro_rules! runtime_error{
+    ($($arg : tt)*) => { return Err(Unwind::Error(format!($($arg)*))) }
+}
+
+pub struct Interpreter{
+    env : Env
+}
+
+//how an lvalue reads in an error message. Subscripts are left out
+fn lvalue_name(lvalue : &LValue) -> String{
+    match *lvalue{
+        LValue::SimpleVar(ref id) => id.clone(),
+        LValue::FieldVar(ref rec, ref field) => format!("{}.{}", lvalue_name(rec), field),
+        LValue::SubscriptVar(ref arr, _) => format!("{}[..]", lvalue_name(arr))
+    }
+}
+
+impl Interpreter{
+    pub fn new() -> Self{
+        Interpreter { env : None }
+    }
+
+    //evaluates `decls` in the global scope so that they are visible to later evaluations
+    pub fn declare(&mut self, decls : &[Decl]) -> Result<(), Unwind>{
+        let env = self.env.clone();
+        self.env = try!(self.eval_decls(decls, env));
+        Ok(())
+    }
+
+    pub fn eval(&mut self, expr : &Expr) -> EvalResult{
+        let env = self.env.clone();
+        self.eval_in(expr, &env)
+    }
+
+    fn bind(id : &str, value : Value, env : Env) -> Env{
+        Some(Rc::new(Binding { id : id.to_string(), value : RefCell::new(value), next : env }))
+    }
+
+    fn lookup(id : &str, env : &Env) -> Option<Rc<Binding>>{
+        let mut cur = env.clone();
+        while let Some(b) = cur{
+            if b.id == id{
+                return Some(b)
+            }
+            cur = b.next.clone();
+        }
+        None
+    }
+
+    fn eval_decls(&mut self, decls : &[Decl], env : Env) -> Result<Env, Unwind>{
+        let mut env = env;
+        let mut pending : Vec<Rc<Closure>> = Vec::new();
+        for decl in decls{
+            match *decl{
+                FunDec(ref id, ref params, _, ref body, _) => {
+                    let params = match *params{
+                        Some(ref p) => p.iter().map(|p| p.0.clone()).collect(),
+                        None => Vec::new()
+                    };
+                    let closure = Rc::new(Closure { params : params,
+                                                    body : (**body).clone(),
+                                                    env : RefCell::new(None) });
+                    pending.push(closure.clone());
+                    env = Self::bind(id, Value::Func(closure), env);
+                    continue;
+                },
+                VarDec(ref id, _, ref rhs, _) => {
+                    Self::close_over(&mut pending, &env);
+                    let value = try!(self.eval_in(rhs, &env));
+                    env = Self::bind(id, value, env);
+                },
+                TypeDec(_, _) => {},
+                ErrorDec => runtime_error!("Cannot evaluate a declaration with a syntax error")
+            }
+        }
+        Self::close_over(&mut pending, &env);
+        Ok(env)
+    }
+
+    //functions declared next to each other can be mutually recursive
+    fn close_over(pending : &mut Vec<Rc<Closure>>, env : &Env){
+        for c in pending.drain(..){
+            *c.env.borrow_mut() = env.clone();
+        }
+    }
+
+    fn eval_int(&mut self, expr : &Expr, env : &Env) -> Result<i32, Unwind>{
+        match try!(self.eval_in(expr, env)){
+            Value::Int(n) => Ok(n),
+            v => runtime_error!("Expected an int but found '{}'", v)
+        }
+    }
+
+    fn eval_in(&mut self, expr : &Expr, env : &Env) -> EvalResult{
+        macro_rules! arith{
+            ($e1 : expr, $e2 : expr, $op : ident) => {{
+                let l = try!(self.eval_int($e1, env));
+                let r = try!(self.eval_int($e2, env));
+                Ok(Value::Int(l.$op(r)))
+            }}
+        }
+        macro_rules! compare{
+            ($e1 : expr, $e2 : expr, $op : tt) => {{
+                let l = try!(self.eval_in($e1, env));
+                let r = try!(self.eval_in($e2, env));
+                match (l, r){
+                    (Value::Int(l), Value::Int(r)) => Ok(Value::Int((l $op r) as i32)),
+                    (Value::Str(l), Value::Str(r)) => Ok(Value::Int((l $op r) as i32)),
+                    (l, r) => runtime_error!("Cannot compare '{}' and '{}'", l, r)
+                }
+            }}
+        }
+        match *expr{
+            NumExpr(n) => Ok(Value::Int(n)),
+            StringExpr(ref s) => Ok(Value::Str(s.clone())),
+            NilExpr => Ok(Value::Nil),
+            LitExpr | NoOpExpr | LabelExpr(_) | GotoExpr(_) => Ok(Value::Unit),
+            BreakExpr => Err(Unwind::Break),
+            ErrorExpr => runtime_error!("Cannot evaluate an expression with a syntax error"),
+            IdExpr(ref id) => {
+                match Self::lookup(id, env){
+                    Some(b) => Ok(b.value.borrow().clone()),
+                    None => runtime_error!("Invalid reference to variable '{0}'", id)
+                }
+            },
+            AddExpr(ref e1, ref e2) => arith!(e1, e2, wrapping_add),
+            SubExpr(ref e1, ref e2) => arith!(e1, e2, wrapping_sub),
+            MulExpr(ref e1, ref e2) => arith!(e1, e2, wrapping_mul),
+            DivExpr(ref e1, ref e2) |
+            ModExpr(ref e1, ref e2) => {
+                let l = try!(self.eval_int(e1, env));
+                let r = try!(self.eval_int(e2, env));
+                if r == 0{
+                    runtime_error!("division by zero");
+                }
+                match *expr{
+                    DivExpr(_, _) => Ok(Value::Int(l.wrapping_div(r))),
+                    _ => Ok(Value::Int(l.wrapping_rem(r)))
+                }
+            },
+            LessThanExpr(ref e1, ref e2) => compare!(e1, e2, <),
+            LessThanEqualsExpr(ref e1, ref e2) => compare!(e1, e2, <=),
+            GreaterThanExpr(ref e1, ref e2) => compare!(e1, e2, >),
+            GreaterThanEqualsExpr(ref e1, ref e2) => compare!(e1, e2, >=),
+            EqualsExpr(ref e1, ref e2) |
+            NotEqualsExpr(ref e1, ref e2) => {
+                let l = try!(self.eval_in(e1, env));
+                let r = try!(self.eval_in(e2, env));
+                let eq = match (l, r){
+                    (Value::Int(l), Value::Int(r)) => l == r,
+                    (Value::Str(l), Value::Str(r)) => l == r,
+                    (Value::Nil, Value::Nil) => true,
+                    (Value::Array(l), Value::Array(r)) => Rc::ptr_eq(&l, &r),
+                    (Value::Record(l), Value::Record(r)) => Rc::ptr_eq(&l, &r),
+                    (Value::Record(_), Value::Nil) |
+                    (Value::Nil, Value::Record(_)) => false,
+                    (l, r) => runtime_error!("Cannot compare '{}' and '{}'", l, r)
+                };
+                match *expr{
+                    EqualsExpr(_, _) => Ok(Value::Int(eq as i32)),
+                    _ => Ok(Value::Int(!eq as i32))
+                }
+            },
+            SeqExpr(ref opt_list) => {
+                let mut last = Value::Unit;
+                if let Some(ref list) = *opt_list{
+                    for e in list{
+                        last = try!(self.eval_in(e, env));
+                    }
+                }
+                Ok(last)
+            },
+            LetExpr(ref decls, ref body) => {
+                let inner = try!(self.eval_decls(decls, env.clone()));
+                match *body{
+                    Some(ref e) => self.eval_in(e, &inner),
+                    None => Ok(Value::Unit)
+                }
+            },
+            IfThenExpr(ref cond, ref then_expr) => {
+                if try!(self.eval_int(cond, env)) != 0{
+                    try!(self.eval_in(then_expr, env));
+                }
+                Ok(Value::Unit)
+            },
+            IfThenElseExpr(ref cond, ref then_expr, ref else_expr) => {
+                if try!(self.eval_int(cond, env)) != 0{
+                    self.eval_in(then_expr, env)
+                }
+                else{
+                    self.eval_in(else_expr, env)
+                }
+            },
+            WhileExpr(ref cond, ref body) => {
+                while try!(self.eval_int(cond, env)) != 0{
+                    match self.eval_in(body, env){
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                        Ok(_) => {}
+                    }
+                }
+                Ok(Value::Unit)
+            },
+            ForExpr(ref id, ref from, ref to, ref body, _) => {
+                let from = try!(self.eval_int(from, env));
+                let to = try!(self.eval_int(to, env));
+                let inner = Self::bind(id, Value::Int(from), env.clone());
+                let mut i = from;
+                while i <= to{
+                    *inner.as_ref().unwrap().value.borrow_mut() = Value::Int(i);
+                    match self.eval_in(body, &inner){
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                        Ok(_) => {}
+                    }
+                    if i == to{
+                        break;
+                    }
+                    i += 1;
+                }
+                Ok(Value::Unit)
+            },
+            ArrayExpr(_, ref dim, ref init) |
+            TypedArrayExpr(_, ref dim, ref init) => {
+                let n = try!(self.eval_int(dim, env));
+                if n < 0{
+                    runtime_error!("Negative array size {}", n);
+                }
+                let init = try!(self.eval_in(init, env));
+                Ok(Value::Array(Rc::new(RefCell::new(vec![init; n as usize]))))
+            },
+            RecordExpr(ref field_decls) => {
+                let mut fields = Vec::new();
+                if let Some(ref list) = *field_decls{
+                    for &(ref id, ref ty) in list{
+                        fields.push((id.clone(), match *ty{
+                            TType::TInt32 => Value::Int(0),
+                            TType::TString => Value::Str(Vec::new()),
+                            _ => Value::Nil
+                        }));
+                    }
+                }
+                Ok(Value::Record(Rc::new(RefCell::new(fields))))
+            },
+            TypedRecordExpr(_, ref field_inits) => {
+                let mut fields = Vec::new();
+                for &(ref id, ref e) in field_inits{
+                    fields.push((id.clone(), try!(self.eval_in(e, env))));
+                }
+                Ok(Value::Record(Rc::new(RefCell::new(fields))))
+            },
+            LValueExpr(ref lvalue) => self.lvalue(lvalue, env),
+            AssignExpr(ref lhs, ref rhs) => {
+                let v = try!(self.eval_in(rhs, env));
+                match *lhs{
+                    LValue::SimpleVar(ref id) => {
+                        match Self::lookup(id, env){
+                            Some(b) => *b.value.borrow_mut() = v,
+                            None => runtime_error!("Invalid reference to variable '{0}'", id)
+                        }
+                    },
+                    LValue::SubscriptVar(ref arr, ref idx) => {
+                        let (elems, i) = try!(self.element(arr, idx, env));
+                        elems.borrow_mut()[i] = v;
+                    },
+                    LValue::FieldVar(ref rec, ref field) => {
+                        let (fields, pos) = try!(self.field(rec, field, env));
+                        fields.borrow_mut()[pos].1 = v;
+                    }
+                }
+                Ok(Value::Unit)
+            },
+            CallExpr(ref id, ref args) => {
+                let mut values = Vec::new();
+                if let Some(ref list) = *args{
+                    for &(_, ref e) in list{
+                        values.push(try!(self.eval_in(e, env)));
+                    }
+                }
+                match Self::lookup(id, env).map(|b| b.value.borrow().clone()){
+                    Some(Value::Func(closure)) => self.call(&closure, values),
+                    Some(_) => runtime_error!("Invalid reference to function '{0}'. Different binding found.", id),
+                    None => self.call_std_function(id, values, args)
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, closure : &Closure, args : Vec<Value>) -> EvalResult{
+        if args.len() != closure.params.len(){
+            runtime_error!("Expected {} args but found {}", closure.params.len(), args.len());
+        }
+        let mut env = closure.env.borrow().clone();
+        for (p, v) in closure.params.iter().zip(args.into_iter()){
+            env = Self::bind(p, v, env);
+        }
+        match self.eval_in(&closure.body, &env){
+            Err(Unwind::Break) => runtime_error!("'break' outside of a loop"),
+            r => r
+        }
+    }
+
+    fn lvalue(&mut self, lvalue : &LValue, env : &Env) -> EvalResult{
+        match *lvalue{
+            LValue::SimpleVar(ref id) => match Self::lookup(id, env){
+                Some(b) => Ok(b.value.borrow().clone()),
+                None => runtime_error!("Invalid reference to variable '{0}'", id)
+            },
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                let (elems, i) = try!(self.element(arr, idx, env));
+                let v = elems.borrow()[i].clone();
+                Ok(v)
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                let (fields, pos) = try!(self.field(rec, field, env));
+                let v = fields.borrow()[pos].1.clone();
+                Ok(v)
+            }
+        }
+    }
+
+    //the elements of the array `arr` names and the position `idx` is at
+    fn element(&mut self, arr : &LValue, idx : &Expr, env : &Env) -> Result<(Rc<RefCell<Vec<Value>>>, usize), Unwind>{
+        let elems = match try!(self.lvalue(arr, env)){
+            Value::Array(elems) => elems,
+            v => runtime_error!("'{}' is not an array but '{}'", lvalue_name(arr), v)
+        };
+        let i = try!(self.eval_int(idx, env));
+        let len = elems.borrow().len();
+        if i < 0 || i as usize >= len{
+            runtime_error!("Index {} out of bounds for array '{}' of size {}", i, lvalue_name(arr), len);
+        }
+        Ok((elems, i as usize))
+    }
+
+    //the fields of the record `rec` names and the position of `field`
+    fn field(&mut self, rec : &LValue, field : &str, env : &Env) -> Result<(Rc<RefCell<Vec<(String, Value)>>>, usize), Unwind>{
+        let fields = match try!(self.lvalue(rec, env)){
+            Value::Record(fields) => fields,
+            Value::Nil => runtime_error!("Field access on a nil record"),
+            v => runtime_error!("Field access on '{}' which is not a record", v)
+        };
+        let pos = match fields.borrow().iter().position(|f| f.0 == *field){
+            Some(pos) => pos,
+            None => runtime_error!("Record has no field named '{}'", field)
+        };
+        Ok((fields, pos))
+    }
+
+    fn call_std_function(&mut self, fn_name : &str, args : Vec<Value>, _typed_args : &OptionalTypeExprTupleList) -> EvalResult{
+        let mut args = args.into_iter();
+        let mut arg = || args.next().unwrap_or(Value::Unit);
+        match fn_name{
+            "print" => {
+                match arg(){
+                    Value::Str(s) => {
+                        let _ = io::stdout().write_all(&s);
+                    },
+                    Value::Int(n) => println!("{}", n),
+                    v => runtime_error!("Cannot print '{}'", v)
+                }
+                Ok(Value::Unit)
+            },
+            "flush" => {
+                let _ = io::stdout().flush();
+                Ok(Value::Unit)
+            },
+            "getchar" => {
+                let mut buf = [0u8; 1];
+                match io::stdin().read(&mut buf){
+                    Ok(1) => Ok(Value::Str(vec![buf[0]])),
+                    _ => Ok(Value::Str(Vec::new()))
+                }
+            },
+            "ord" => {
+                match arg(){
+                    Value::Str(s) => Ok(Value::Int(s.first().map(|&b| b as i32).unwrap_or(-1))),
+                    v => runtime_error!("Expected a string but found '{}'", v)
+                }
+            },
+            "chr" => {
+                match arg(){
+                    Value::Int(n) if n >= 0 && n < 256 => Ok(Value::Str(vec![n as u8])),
+                    Value::Int(n) => runtime_error!("chr({}) out of range", n),
+                    v => runtime_error!("Expected an int but found '{}'", v)
+                }
+            },
+            "size" => {
+                match arg(){
+                    Value::Str(s) => Ok(Value::Int(s.len() as i32)),
+                    v => runtime_error!("Expected a string but found '{}'", v)
+                }
+            },
+            "substring" => {
+                match (arg(), arg(), arg()){
+                    (Value::Str(s), Value::Int(first), Value::Int(n)) => {
+                        if first < 0 || n < 0 || (first + n) as usize > s.len(){
+                            runtime_error!("substring({}, {}, {}) out of range", Value::Str(s), first, n);
+                        }
+                        Ok(Value::Str(s[first as usize..(first + n) as usize].to_vec()))
+                    },
+                    _ => runtime_error!("Invalid arguments to substring")
+                }
+            },
+            "concat" => {
+                match (arg(), arg()){
+                    (Value::Str(mut s1), Value::Str(s2)) => {
+                        s1.extend(s2);
+                        Ok(Value::Str(s1))
+                    },
+                    _ => runtime_error!("Invalid arguments to concat")
+                }
+            },
+            "not" => {
+                match arg(){
+                    Value::Int(n) => Ok(Value::Int((n == 0) as i32)),
+                    v => runtime_error!("Expected an int but found '{}'", v)
+                }
+            },
+            "exit" => {
+                match arg(){
+                    Value::Int(n) => Err(Unwind::Exit(n)),
+                    v => runtime_error!("Expected an int but found '{}'", v)
+                }
+            },
+            _ => runtime_error!("Invalid call to '{0}'. Function not found.", fn_name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::parse::parser::{Parser};
+    use syntax::ast::Expr::*;
+    use syntax::ptr::{B};
+    use super::*;
+
+    fn eval_src(src : &str) -> EvalResult{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        Interpreter::new().eval(&*expr)
+    }
+
+    fn eval_int(src : &str) -> i32{
+        match eval_src(src){
+            Ok(Value::Int(n)) => n,
+            Ok(v) => panic!("Expected an int but found {}", v),
+            Err(e) => panic!("{:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_eval_add_expr(){
+        assert_eq!(eval_int("1+2+3"), 6);
+    }
+
+    #[test]
+    fn test_eval_let_with_function_call(){
+        assert_eq!(eval_int("let function add(a:int, b:int) : int = a+b\n in add(1, 2) end"), 3);
+    }
+
+    #[test]
+    fn test_eval_array_element_modification(){
+        assert_eq!(eval_int("let var a : array := array of int[3] of 1+1 in (a[2]:=99;a[2]) end"), 99);
+    }
+
+    #[test]
+    fn test_eval_array_index_out_of_bounds(){
+        match eval_src("let var a : array := array of int[3] of 1 in a[3] end"){
+            Err(Unwind::Error(msg)) => assert_eq!(msg, "Index 3 out of bounds for array 'a' of size 3"),
+            _ => panic!("Expected an out of bounds error")
+        }
+    }
+
+    #[test]
+    fn test_eval_nested_lvalues(){
+        let src = "let type row = array of int\n type matrix = array of row\n var m := matrix [2] of nil in \
+                   (m[0] := row [3] of 0; m[1] := row [3] of 1; m[1][2] := 7; m[0][2] + m[1][2]) end";
+        assert_eq!(eval_int(src), 7);
+        let src = "let type person = {age : int}\n type people = array of person\n var ps := people [2] of nil in \
+                   (ps[1] := person {age = 2}; ps[1].age := 30; ps[1].age) end";
+        assert_eq!(eval_int(src), 30);
+        let src = "let type list = {value : int, next : list}\n var l := list {value = 1, next = list {value = 2, next = nil}} in \
+                   (l.next.value := 5; l.value + l.next.value) end";
+        assert_eq!(eval_int(src), 6);
+        match eval_src("let type list = {value : int, next : list}\n var l := list {value = 1, next = nil} in l.next.value end"){
+            Err(Unwind::Error(msg)) => assert_eq!(msg, "Field access on a nil record"),
+            _ => panic!("Expected a nil record error")
+        }
+    }
+
+    #[test]
+    fn test_eval_division_by_zero(){
+        let e = DivExpr(B(NumExpr(1)), B(NumExpr(0)));
+        assert_eq!(Interpreter::new().eval(&e).err(), Some(Unwind::Error(String::from("division by zero"))));
+    }
+
+    #[test]
+    fn test_eval_declarations_persist(){
+        let mut i = Interpreter::new();
+        let mut p = Parser::new(String::from("var a : int := 41\nfunction inc(n:int) : int = n+1"));
+        i.declare(&p.decls()).unwrap();
+        let mut p = Parser::new(String::from("inc(a)"));
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        match i.eval(&*expr){
+            Ok(Value::Int(n)) => assert_eq!(n, 42),
+            _ => panic!("Expected 42")
+        }
+    }
+}
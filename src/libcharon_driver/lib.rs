@@ -3,23 +3,232 @@
 #![crate_type = "rlib"]
 
 extern crate syntax;
+extern crate ir;
+#[cfg(feature = "llvm")]
 extern crate trans;
+extern crate native;
+extern crate bytecode;
+extern crate wasm;
+extern crate cgen;
+extern crate session;
 
+use std::fs::File;
+use std::io::{self, Read, Write};
+use syntax::ast::Expr;
+use syntax::ptr::B;
 use syntax::parse::parser::{Parser};
-use trans::base::translate;
+use syntax::parse::lexer::decode_source;
+use syntax::visit::{Visitor};
+use syntax::visitor_impl::{TypeChecker};
+use syntax::escape::find_escapes;
+#[cfg(feature = "llvm")]
+use trans::base::{translate_with, TransOptions};
+#[cfg(feature = "llvm")]
+use trans::link::link;
+#[cfg(feature = "llvm")]
+use trans::jit;
+#[cfg(feature = "llvm")]
+use trans::lower::lower;
+#[cfg(feature = "llvm")]
+use trans::opt::optimize;
+#[cfg(feature = "llvm")]
+use trans::target::TargetMachine;
+use ir::tree::Program;
+use options::{Options, Backend, USAGE};
+
+pub mod eval;
+pub mod options;
+pub mod repl;
+
 pub fn run(args: Vec<String>) -> i32{
-	run_compiler();
-	0
-}
-
-fn run_compiler(){
-	let mut p = Parser::new("break".to_string());
-	let optional_blk = p.run();
-    if optional_blk.is_none(){
-        panic!("Could not compile");
-    }
-   
-	//TODO extract the expr and call pass it to trans to get the llvm context
-    let expr = &*optional_blk.unwrap().expr.unwrap();
-    translate(expr);
-} 
+	if args.len() > 1 && args[1] == "repl" {
+		return repl::run();
+	}
+	if args.len() > 1 && args[1] == "run" {
+		return run_file(&args[2..]);
+	}
+	match Options::parse(&args) {
+		Ok(opts) => run_compiler(&opts),
+		Err(msg) => {
+			let _ = writeln!(io::stderr(), "error: {}\n{}", msg, USAGE);
+			1
+		}
+	}
+}
+
+fn run_compiler(opts : &Options) -> i32{
+	match compile(opts) {
+		Ok(code) => code,
+		Err(msg) => {
+			let _ = writeln!(io::stderr(), "error: {}", msg);
+			1
+		}
+	}
+}
+
+//returns the exit code of the program when it was run with --jit
+fn compile(opts : &Options) -> Result<i32, String>{
+	let path = opts.input.as_ref().unwrap();
+	let mut bytes = Vec::new();
+	try!(File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+		.map_err(|e| format!("couldn't read {}: {}", path, e)));
+	let src = try!(decode_source(bytes).map_err(|e| format!("{}: {}", path, e)));
+
+	//every syntax error in the file is reported, one per line
+	let mut expr = try!(Parser::new(src).parse().map_err(|errors| {
+		errors.iter().map(|e| format!("{}: {}", path, e)).collect::<Vec<_>>().join("\n")
+	}));
+	TypeChecker::new().visit_expr(&mut *expr);
+	find_escapes(&expr);
+	if opts.backend == Backend::Bytecode {
+		return compile_bytecode(&expr, opts)
+	}
+	if opts.backend == Backend::C {
+		return compile_c(&expr, path, opts)
+	}
+	if opts.emit_ir || opts.backend != Backend::Llvm {
+		let mut prog = try!(ir::translate::translate(&*expr));
+		let mut stderr = io::stderr();
+		let dump : Option<&mut Write> = if opts.ir_dump { Some(&mut stderr) } else { None };
+		try!(ir::opt::optimize(&mut prog, &opts.ir_passes(), dump)
+			.map_err(|e| format!("couldn't write the IR dump: {}", e)));
+		if opts.emit_ir {
+			try!(write_output(&opts.output, prog.to_string().as_bytes()));
+			return Ok(0)
+		}
+		if opts.debug_info {
+			return Err(String::from("-g isn't supported by the ir, native and wasm backends yet"))
+		}
+		if opts.backend == Backend::Native {
+			return compile_native(prog, opts)
+		}
+		if opts.backend == Backend::Wasm {
+			return compile_wasm(prog, opts)
+		}
+		return compile_llvm(&*expr, Some(prog), path, opts)
+	}
+	compile_llvm(&*expr, None, path, opts)
+}
+
+//builds the llvm module, from the mid-level IR when there is `prog` and
+//from the AST otherwise, and runs it or links it for the target
+#[cfg(feature = "llvm")]
+fn compile_llvm(expr : &Expr, prog : Option<Program>, path : &str, opts : &Options) -> Result<i32, String>{
+	let machine = try!(TargetMachine::new(&opts.target));
+	let ctxt = match prog {
+		Some(prog) => try!(lower(&prog)),
+		None => {
+			let trans_opts = TransOptions {
+				debug_info : if opts.debug_info { Some(path.to_string()) } else { None },
+				overflow_checks : opts.overflow_checks,
+				data_layout : Some(machine.data_layout())
+			};
+			try!(translate_with(expr, &trans_opts))
+		}
+	};
+	try!(ctxt.verify());
+	let passes = opts.opt_passes.as_ref().map(|p| &**p);
+	if opts.jit {
+		try!(optimize(&ctxt, None, opts.target.opt_level, passes));
+		return jit::run(&ctxt)
+	}
+	machine.configure(&ctxt);
+	try!(optimize(&ctxt, Some(&machine), opts.target.opt_level, passes));
+	try!(link(&ctxt, &machine, &opts.link));
+	Ok(0)
+}
+
+#[cfg(not(feature = "llvm"))]
+fn compile_llvm(_ : &Expr, _ : Option<Program>, _ : &str, _ : &Options) -> Result<i32, String>{
+	Err(String::from("charon was built without llvm. Use --backend native, bytecode, wasm or c"))
+}
+
+//assembles and links with the system c compiler; with --jit the executable
+//is built in a temp dir and run
+fn compile_native(prog : Program, opts : &Options) -> Result<i32, String>{
+	let asm = native::compile(prog);
+	if opts.emit_asm {
+		try!(write_output(&opts.output, asm.as_bytes()));
+		return Ok(0)
+	}
+	if opts.jit {
+		return native::link::run(&asm, &opts.link)
+	}
+	try!(native::link::link(&asm, &opts.link));
+	Ok(0)
+}
+
+//builds the c source like compile_native does the assembly. Its #line
+//directives name `path`, so -g debugs the Tiger source
+fn compile_c(expr : &B<Expr>, path : &str, opts : &Options) -> Result<i32, String>{
+	let c = try!(cgen::compile(expr, path));
+	if opts.emit_c {
+		try!(write_output(&opts.output, c.as_bytes()));
+		return Ok(0)
+	}
+	if opts.jit {
+		return native::link::build_and_run("main.c", &c, &opts.link)
+	}
+	try!(native::link::build("main.c", &c, &opts.link));
+	Ok(0)
+}
+
+//with --jit the module runs right away instead of being written out
+fn compile_bytecode(expr : &B<Expr>, opts : &Options) -> Result<i32, String>{
+	let module = try!(bytecode::compile::compile(expr));
+	if opts.jit {
+		return bytecode::vm::run(&module, &mut io::stdout(), &mut io::stdin())
+	}
+	try!(write_output(&opts.output, &bytecode::file::encode(&module)));
+	Ok(0)
+}
+
+//with --jit the module runs in the wasm interpreter instead of being written out
+fn compile_wasm(prog : Program, opts : &Options) -> Result<i32, String>{
+	let module = wasm::compile(prog);
+	if opts.emit_wat {
+		try!(write_output(&opts.output, wasm::wat::format(&module).as_bytes()));
+		return Ok(0)
+	}
+	let bytes = wasm::encode::encode(&module);
+	if opts.jit {
+		return wasm::host::run(&bytes, &mut io::stdout(), &mut io::stdin())
+	}
+	try!(write_output(&opts.output, &bytes));
+	Ok(0)
+}
+
+//charon run <file.tbc|file.wasm>
+fn run_file(args : &[String]) -> i32{
+	if args.len() != 1 {
+		let _ = writeln!(io::stderr(), "error: 'run' expects one .tbc or .wasm file\n{}", USAGE);
+		return 1
+	}
+	let path = &args[0];
+	let mut bytes = Vec::new();
+	let result = File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+		.map_err(|e| format!("couldn't read {}: {}", path, e))
+		.and_then(|_| if bytes.starts_with(wasm::encode::MAGIC) {
+			wasm::host::run(&bytes, &mut io::stdout(), &mut io::stdin())
+		} else {
+			bytecode::file::decode(&bytes)
+				.and_then(|module| bytecode::vm::run(&module, &mut io::stdout(), &mut io::stdin()))
+		});
+	match result {
+		Ok(code) => code,
+		Err(msg) => {
+			let _ = writeln!(io::stderr(), "error: {}", msg);
+			1
+		}
+	}
+}
+
+//"-" is stdout
+fn write_output(path : &str, contents : &[u8]) -> Result<(), String>{
+	let result = if path == "-" {
+		io::stdout().write_all(contents)
+	} else {
+		File::create(path).and_then(|mut f| f.write_all(contents))
+	};
+	result.map_err(|e| format!("couldn't write {}: {}", path, e))
+}
@@ -0,0 +1,380 @@
+//! Command line options of the compiler.
+
+use std::path::Path;
+use std::str::FromStr;
+use session::config::{OptLevel, TargetOptions};
+use session::link::{Linker, LinkOptions};
+use ir::opt::{Pass, ALL_PASSES, parse_passes};
+
+pub const USAGE : &'static str = "\
+usage: charon [options] <file.tig>
+       charon run <file.tbc|file.wasm>
+       charon repl
+
+options:
+    --jit                       run the program in memory instead of producing an executable
+    --backend <llvm|ir|native|bytecode|wasm|c>
+                                translate the AST straight to llvm (the default), through the mid-level
+                                IR, from the mid-level IR to x86-64 assembly without llvm, to a
+                                .tbc bytecode file for 'charon run', to a WebAssembly module or to
+                                c99 built with the system c compiler
+    --target <triple>           generate code for <triple> instead of the host
+    --cpu <name>                generate code for a specific cpu; 'native' uses the host cpu
+    --features <+f,-g>          enable or disable target features
+    --relocation-model <model>  default, static, pic, pie or dynamic-no-pic
+    --code-model <model>        default, small, kernel, medium or large
+    -g                          emit DWARF debug information
+    -O<0|1|2|3>                 optimization level; -O is -O2
+    -C opt-passes=<p1,p2..>     run these llvm passes instead of the ones of the -O level
+    -C ir-passes=<p1,p2..>      optimize the mid-level IR with these passes: constprop, copyprop,
+                                cse, licm, dce, 'all' or 'none'. -O1 and above run all of them
+    -C ir-dump                  print the IR before and after every IR pass to stderr
+    -C overflow-checks          stop with the line of the expression when + - or * overflow instead
+                                of wrapping around (llvm backend only)
+    -o <file>                   write the output to <file>
+    --emit <exe|obj|ir|asm|wat|c>
+                                produce an executable (the default), just the object file, a
+                                dump of the mid-level IR, the assembly of the native backend, the
+                                text format of the wasm module or the source of the c backend
+                                ('-o -' prints the last four)
+    --linker <cc|clang|ld|none> program used to link the executable; 'none' is --emit obj
+    -L <dir>                    add <dir> to the library search path
+    -l <lib>                    link with <lib>
+    --static                    link statically";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend{
+    Llvm,
+    //lowers the mid-level IR to llvm
+    Ir,
+    //x86-64 assembly from the mid-level IR
+    Native,
+    //.tbc files for the virtual machine
+    Bytecode,
+    //WebAssembly modules from the mid-level IR
+    Wasm,
+    //c source from the AST
+    C
+}
+
+impl FromStr for Backend{
+    type Err = String;
+    fn from_str(s : &str) -> Result<Backend, String>{
+        match s{
+            "llvm" => Ok(Backend::Llvm),
+            "ir" => Ok(Backend::Ir),
+            "native" => Ok(Backend::Native),
+            "bytecode" => Ok(Backend::Bytecode),
+            "wasm" => Ok(Backend::Wasm),
+            "c" => Ok(Backend::C),
+            _ => Err(format!("unknown backend '{}'. Expected llvm, ir, native, bytecode, wasm or c", s))
+        }
+    }
+}
+
+impl Default for Backend{
+    fn default() -> Self{
+        Backend::Llvm
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Options{
+    pub input : Option<String>,
+    pub jit : bool,
+    pub backend : Backend,
+    //write the mid-level IR to `output` instead of compiling
+    pub emit_ir : bool,
+    //write the assembly of the native backend to `output`
+    pub emit_asm : bool,
+    //write the wasm module in the text format
+    pub emit_wat : bool,
+    //write the source of the c backend
+    pub emit_c : bool,
+    pub output : String,
+    pub target : TargetOptions,
+    pub link : LinkOptions,
+    //custom llvm pass pipeline given with -C opt-passes
+    pub opt_passes : Option<String>,
+    //IR passes given with -C ir-passes
+    pub ir_passes : Option<Vec<Pass>>,
+    pub ir_dump : bool,
+    pub debug_info : bool,
+    pub overflow_checks : bool
+}
+
+impl Options{
+    //args[0] is the program name
+    pub fn parse(args : &[String]) -> Result<Options, String>{
+        let mut opts = Options::default();
+        let mut output = None;
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next(){
+            //both "--opt value" and "--opt=value" are accepted, as are "-x value" and "-xvalue"
+            let (name, inline_value) = match arg.find('='){
+                Some(pos) if arg.starts_with("--") => (&arg[..pos], Some(arg[pos + 1..].to_string())),
+                _ if !arg.starts_with("--") && arg.starts_with('-') && arg.len() > 2 =>
+                    (&arg[..2], Some(arg[2..].to_string())),
+                _ => (&**arg, None)
+            };
+            let mut value = || -> Result<String, String>{
+                match inline_value.clone().or_else(|| args.next().cloned()){
+                    Some(v) => Ok(v),
+                    None => Err(format!("option '{}' expects a value", name))
+                }
+            };
+            match name{
+                "--jit" => opts.jit = true,
+                "--backend" => opts.backend = try!(try!(value()).parse()),
+                "--target" => opts.target.triple = Some(try!(value())),
+                "--cpu" => opts.target.cpu = try!(value()),
+                "--features" => opts.target.features = try!(value()),
+                "--relocation-model" => opts.target.reloc_model = try!(try!(value()).parse()),
+                "--code-model" => opts.target.code_model = try!(try!(value()).parse()),
+                "-g" => opts.debug_info = true,
+                "-O" => opts.target.opt_level = match inline_value{
+                    Some(ref level) => try!(level.parse()),
+                    None => OptLevel::O2
+                },
+                "-C" => try!(opts.codegen_option(&try!(value()))),
+                "-o" => output = Some(try!(value())),
+                "--emit" => match &*try!(value()){
+                    "exe" => {},
+                    "obj" => opts.link.linker = Linker::None,
+                    "ir" => opts.emit_ir = true,
+                    "asm" => opts.emit_asm = true,
+                    "wat" => opts.emit_wat = true,
+                    "c" => opts.emit_c = true,
+                    e => return Err(format!("unknown output kind '{}'. Expected exe, obj, ir, asm, wat or c", e))
+                },
+                "--linker" => opts.link.linker = try!(try!(value()).parse()),
+                "-L" => opts.link.lib_paths.push(try!(value())),
+                "-l" => opts.link.libs.push(try!(value())),
+                "--static" => opts.link.static_link = true,
+                a if a.starts_with('-') => return Err(format!("unknown option '{}'", a)),
+                a => {
+                    if opts.input.is_some(){
+                        return Err(format!("unexpected argument '{}'. Only one input file is allowed.", a))
+                    }
+                    opts.input = Some(a.to_string());
+                }
+            }
+        }
+        if opts.input.is_none(){
+            return Err(String::from("no input file"))
+        }
+        if opts.emit_asm && opts.backend != Backend::Native{
+            return Err(String::from("--emit asm needs --backend=native"))
+        }
+        if opts.backend == Backend::Bytecode && (opts.emit_ir || opts.link.linker == Linker::None){
+            return Err(String::from("--backend=bytecode only writes .tbc files"))
+        }
+        if opts.emit_wat && opts.backend != Backend::Wasm{
+            return Err(String::from("--emit wat needs --backend=wasm"))
+        }
+        if opts.backend == Backend::Wasm && opts.link.linker == Linker::None{
+            return Err(String::from("--backend=wasm writes .wasm or .wat files, not objects"))
+        }
+        if opts.emit_c && opts.backend != Backend::C{
+            return Err(String::from("--emit c needs --backend=c"))
+        }
+        if opts.overflow_checks && (opts.backend != Backend::Llvm || opts.emit_ir){
+            return Err(String::from("-C overflow-checks needs --backend=llvm"))
+        }
+        if opts.backend == Backend::C && opts.emit_ir{
+            return Err(String::from("--backend=c doesn't go through the mid-level IR"))
+        }
+        let stem = Path::new(opts.input.as_ref().unwrap()).file_stem().unwrap().to_string_lossy().into_owned();
+        opts.output = match output{
+            Some(o) => o,
+            None if opts.emit_ir => format!("{}.ir", stem),
+            None if opts.emit_asm => format!("{}.s", stem),
+            None if opts.backend == Backend::Bytecode => format!("{}.tbc", stem),
+            None if opts.emit_wat => format!("{}.wat", stem),
+            None if opts.backend == Backend::Wasm => format!("{}.wasm", stem),
+            None if opts.emit_c => format!("{}.c", stem),
+            None if opts.link.linker == Linker::None => format!("{}.o", stem),
+            None => String::from("a.out")
+        };
+        opts.link.output = opts.output.clone();
+        opts.link.debug_info = opts.debug_info;
+        Ok(opts)
+    }
+
+    //-C <key>=<value>
+    fn codegen_option(&mut self, opt : &str) -> Result<(), String>{
+        let (key, value) = match opt.find('='){
+            Some(pos) => (&opt[..pos], &opt[pos + 1..]),
+            None => (opt, "")
+        };
+        match key{
+            "opt-passes" => self.opt_passes = Some(value.to_string()),
+            "ir-passes" => self.ir_passes = Some(try!(parse_passes(value))),
+            "ir-dump" => self.ir_dump = true,
+            "overflow-checks" => self.overflow_checks = true,
+            _ => return Err(format!("unknown codegen option '{}'", key))
+        }
+        Ok(())
+    }
+
+    //the IR passes to run: the ones given with -C ir-passes, or all of them
+    //when optimizing
+    pub fn ir_passes(&self) -> Vec<Pass>{
+        match self.ir_passes{
+            Some(ref passes) => passes.clone(),
+            None if self.target.opt_level == OptLevel::O0 => Vec::new(),
+            None => ALL_PASSES.to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use session::config::{RelocModel, CodeModel};
+    use super::*;
+
+    fn args(a : &[&str]) -> Vec<String>{
+        let mut v = vec![String::from("charon")];
+        v.extend(a.iter().map(|s| s.to_string()));
+        v
+    }
+
+    #[test]
+    fn test_parse_jit(){
+        let opts = Options::parse(&args(&["--jit", "a.tig"])).unwrap();
+        assert!(opts.jit);
+        assert_eq!(opts.input, Some(String::from("a.tig")));
+    }
+
+    #[test]
+    fn test_parse_unknown_option(){
+        assert_eq!(Options::parse(&args(&["--foo", "a.tig"])).err(), Some(String::from("unknown option '--foo'")));
+    }
+
+    #[test]
+    fn test_parse_target_options(){
+        let opts = Options::parse(&args(&["--target", "x86_64-unknown-linux-gnu", "--cpu=native",
+                                          "--relocation-model", "pie", "--code-model=large", "a.tig"])).unwrap();
+        assert_eq!(opts.target.triple, Some(String::from("x86_64-unknown-linux-gnu")));
+        assert_eq!(opts.target.cpu, "native");
+        assert_eq!(opts.target.reloc_model, RelocModel::Pie);
+        assert_eq!(opts.target.code_model, CodeModel::Large);
+    }
+
+    #[test]
+    fn test_parse_missing_value(){
+        assert_eq!(Options::parse(&args(&["a.tig", "--cpu"])).err(), Some(String::from("option '--cpu' expects a value")));
+    }
+
+    #[test]
+    fn test_parse_invalid_reloc_model(){
+        assert!(Options::parse(&args(&["--relocation-model", "ropi", "a.tig"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_link_options(){
+        let opts = Options::parse(&args(&["-L", "/opt/lib", "-lm", "--static", "--linker=ld", "-o", "prog", "a.tig"])).unwrap();
+        assert_eq!(opts.link.lib_paths, vec![String::from("/opt/lib")]);
+        assert_eq!(opts.link.libs, vec![String::from("m")]);
+        assert!(opts.link.static_link);
+        assert_eq!(opts.link.linker, Linker::Ld);
+        assert_eq!(opts.link.output, "prog");
+    }
+
+    #[test]
+    fn test_parse_emit_obj_output_name(){
+        let opts = Options::parse(&args(&["--emit=obj", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.link.linker, Linker::None);
+        assert_eq!(opts.link.output, "queens.o");
+        assert_eq!(Options::parse(&args(&["a.tig"])).unwrap().link.output, "a.out");
+    }
+
+    #[test]
+    fn test_parse_backend_and_emit_ir(){
+        let opts = Options::parse(&args(&["--backend=ir", "--emit", "ir", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.backend, Backend::Ir);
+        assert!(opts.emit_ir);
+        assert_eq!(opts.output, "queens.ir");
+        assert_eq!(Options::parse(&args(&["a.tig"])).unwrap().backend, Backend::Llvm);
+        assert!(Options::parse(&args(&["--backend", "gcc", "a.tig"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_native_backend(){
+        let opts = Options::parse(&args(&["--backend=native", "--emit=asm", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.backend, Backend::Native);
+        assert!(opts.emit_asm);
+        assert_eq!(opts.output, "queens.s");
+        assert_eq!(Options::parse(&args(&["--emit=asm", "a.tig"])).err(), Some(String::from("--emit asm needs --backend=native")));
+    }
+
+    #[test]
+    fn test_parse_bytecode_backend(){
+        let opts = Options::parse(&args(&["--backend", "bytecode", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.backend, Backend::Bytecode);
+        assert_eq!(opts.output, "queens.tbc");
+        assert_eq!(Options::parse(&args(&["--backend=bytecode", "--emit=obj", "a.tig"])).err(),
+                   Some(String::from("--backend=bytecode only writes .tbc files")));
+    }
+
+    #[test]
+    fn test_parse_wasm_backend(){
+        let opts = Options::parse(&args(&["--backend=wasm", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.backend, Backend::Wasm);
+        assert_eq!(opts.output, "queens.wasm");
+        let opts = Options::parse(&args(&["--backend=wasm", "--emit", "wat", "tests/queens.tig"])).unwrap();
+        assert!(opts.emit_wat);
+        assert_eq!(opts.output, "queens.wat");
+        assert_eq!(Options::parse(&args(&["--emit=wat", "a.tig"])).err(), Some(String::from("--emit wat needs --backend=wasm")));
+        assert!(Options::parse(&args(&["--backend=wasm", "--linker=none", "a.tig"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_c_backend(){
+        let opts = Options::parse(&args(&["--backend=c", "--emit=c", "tests/queens.tig"])).unwrap();
+        assert_eq!(opts.backend, Backend::C);
+        assert!(opts.emit_c);
+        assert_eq!(opts.output, "queens.c");
+        assert_eq!(Options::parse(&args(&["--backend", "c", "--emit", "obj", "tests/queens.tig"])).unwrap().output, "queens.o");
+        assert_eq!(Options::parse(&args(&["--emit", "c", "a.tig"])).err(), Some(String::from("--emit c needs --backend=c")));
+    }
+
+    #[test]
+    fn test_parse_opt_level(){
+        assert_eq!(Options::parse(&args(&["-O3", "a.tig"])).unwrap().target.opt_level, OptLevel::O3);
+        assert_eq!(Options::parse(&args(&["-O", "a.tig"])).unwrap().target.opt_level, OptLevel::O2);
+        assert_eq!(Options::parse(&args(&["a.tig"])).unwrap().target.opt_level, OptLevel::O0);
+        assert!(Options::parse(&args(&["-O4", "a.tig"])).is_err());
+        assert!(Options::parse(&args(&["-g", "-O1", "a.tig"])).unwrap().debug_info);
+    }
+
+    #[test]
+    fn test_parse_opt_passes(){
+        let opts = Options::parse(&args(&["-C", "opt-passes=mem2reg,gvn", "a.tig"])).unwrap();
+        assert_eq!(opts.opt_passes, Some(String::from("mem2reg,gvn")));
+        assert_eq!(Options::parse(&args(&["-C", "foo=1", "a.tig"])).err(), Some(String::from("unknown codegen option 'foo'")));
+    }
+
+    #[test]
+    fn test_parse_ir_passes(){
+        let opts = Options::parse(&args(&["-C", "ir-passes=constprop,dce", "-C", "ir-dump", "-O2", "a.tig"])).unwrap();
+        assert_eq!(opts.ir_passes(), vec![Pass::ConstProp, Pass::Dce]);
+        assert!(opts.ir_dump);
+        assert_eq!(Options::parse(&args(&["-O1", "a.tig"])).unwrap().ir_passes(), ALL_PASSES.to_vec());
+        assert!(Options::parse(&args(&["a.tig"])).unwrap().ir_passes().is_empty());
+        assert!(Options::parse(&args(&["-C", "ir-passes=gvn", "a.tig"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_overflow_checks(){
+        assert!(Options::parse(&args(&["-C", "overflow-checks", "a.tig"])).unwrap().overflow_checks);
+        assert!(!Options::parse(&args(&["a.tig"])).unwrap().overflow_checks);
+        assert_eq!(Options::parse(&args(&["-C", "overflow-checks", "--backend=c", "a.tig"])).err(),
+                   Some(String::from("-C overflow-checks needs --backend=llvm")));
+    }
+
+    #[test]
+    fn test_parse_no_input(){
+        assert_eq!(Options::parse(&args(&["--jit"])).err(), Some(String::from("no input file")));
+    }
+}
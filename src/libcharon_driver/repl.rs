@@ -0,0 +1,346 @@
+//! `charon repl`: reads Tiger expressions, type-checks and evaluates them.
+//! Declarations made at the top level (either bare or in a `let`) stay
+//! visible to the inputs that follow.
+
+use std::any::Any;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use syntax::ast::{Expr, Decl};
+use syntax::ast::Expr::*;
+use syntax::ast::Decl::*;
+use syntax::parse::lexer::{Lexer};
+use syntax::parse::parser::{Parser, SyntaxError};
+use syntax::parse::tokens::{Token};
+use syntax::ptr::{B};
+use syntax::visit::{Visitor};
+use syntax::visitor_impl::{TypeChecker};
+#[cfg(feature = "llvm")]
+use trans::base::translate;
+use eval::{Interpreter, Unwind};
+
+const PROMPT : &'static str = "tiger> ";
+const CONTINUATION_PROMPT : &'static str = "  ...> ";
+
+const HELP : &'static str = "\
+:type <expr>   show the type of <expr> without evaluating it
+:ast <expr>    show the syntax tree of <expr>
+:ir <expr>     show the llvm IR generated for <expr>
+:help          show this message
+:quit          leave the repl";
+
+pub enum Outcome{
+    Output(String),
+    Exit(i32)
+}
+
+pub struct Repl{
+    checker : TypeChecker,
+    interp : Interpreter,
+    //every declaration evaluated so far; needed to build the IR for :ir
+    decls : Vec<Decl>
+}
+
+impl Repl{
+    pub fn new() -> Self{
+        Repl {
+            checker : TypeChecker::new(),
+            interp : Interpreter::new(),
+            decls : Vec::new()
+        }
+    }
+
+    //runs one complete input (an expression, declarations or a command).
+    //parser and type-checker errors surface as Err
+    pub fn eval_line(&mut self, src : &str) -> Result<Outcome, String>{
+        let src = src.trim();
+        if src.is_empty(){
+            return Ok(Outcome::Output(String::new()))
+        }
+        let sym_tab_len = self.checker.sym_tab.len();
+        let result = catch_errors(|| self.eval_line_unchecked(src));
+        if result.is_err(){
+            //forget whatever the failed input managed to declare
+            self.checker.sym_tab.truncate(sym_tab_len);
+        }
+        result
+    }
+
+    fn eval_line_unchecked(&mut self, src : &str) -> Result<Outcome, String>{
+        if src.starts_with(':'){
+            let (cmd, arg) = match src.find(char::is_whitespace){
+                Some(pos) => (&src[..pos], src[pos..].trim()),
+                None => (src, "")
+            };
+            return match cmd{
+                ":type" | ":t" => {
+                    let mut expr = try!(parse_expr(arg));
+                    let len = self.checker.sym_tab.len();
+                    self.checker.visit_expr(&mut expr);
+                    self.checker.sym_tab.truncate(len);
+                    Ok(Outcome::Output(format!("{}", self.checker.ty)))
+                },
+                ":ast" => Ok(Outcome::Output(format!("{:#?}", try!(parse_expr(arg))))),
+                ":ir" => {
+                    let expr = try!(parse_expr(arg));
+                    let mut program = if self.decls.is_empty(){
+                        expr
+                    }
+                    else{
+                        LetExpr(self.decls.clone(), Some(B(expr)))
+                    };
+                    TypeChecker::new().visit_expr(&mut program);
+                    llvm_ir(&program).map(Outcome::Output)
+                },
+                ":help" | ":h" => Ok(Outcome::Output(String::from(HELP))),
+                ":quit" | ":q" => Ok(Outcome::Exit(0)),
+                _ => Err(format!("Unknown command '{}'. Type :help for the list of commands.", cmd))
+            }
+        }
+
+        let (decls, body) = if starts_with_decl(src){
+            (try!(parse_decls(src)), None)
+        }
+        else{
+            match try!(parse_expr(src)){
+                LetExpr(decls, body) => (decls, body.map(|b| (*b).clone())),
+                e => (Vec::new(), Some(e))
+            }
+        };
+
+        let mut output = String::new();
+        if !decls.is_empty(){
+            output = try!(self.declare(decls));
+        }
+        if let Some(mut body) = body{
+            self.checker.visit_expr(&mut body);
+            let ty = self.checker.ty.clone();
+            let value = match self.interp.eval(&body){
+                Ok(v) => v,
+                Err(Unwind::Exit(code)) => return Ok(Outcome::Exit(code)),
+                Err(Unwind::Break) => return Err(String::from("'break' outside of a loop")),
+                Err(Unwind::Error(msg)) => return Err(msg)
+            };
+            if !output.is_empty(){
+                output.push('\n');
+            }
+            output.push_str(&format!("{} : {}", value, ty));
+        }
+        Ok(Outcome::Output(output))
+    }
+
+    fn declare(&mut self, mut decls : Vec<Decl>) -> Result<String, String>{
+        let sym_tab_len = self.checker.sym_tab.len();
+        for decl in decls.iter_mut(){
+            self.checker.visit_decl(decl);
+        }
+        match self.interp.declare(&decls){
+            Ok(_) => {},
+            Err(e) => {
+                self.checker.sym_tab.truncate(sym_tab_len);
+                return Err(match e{
+                    Unwind::Error(msg) => msg,
+                    _ => String::from("Declarations cannot break or exit")
+                })
+            }
+        }
+        let lines : Vec<String> = decls.iter().map(|decl| match *decl{
+            VarDec(ref id, ref ty, _, _) => format!("var {} : {}", id, ty),
+            FunDec(ref id, _, ref ty, _, _) => format!("function {} : {}", id, ty),
+            TypeDec(ref id, ref ty) => format!("type {} = {}", id, ty),
+            ErrorDec => unreachable!("declarations with syntax errors aren't declared")
+        }).collect();
+        self.decls.extend(decls);
+        Ok(lines.join("\n"))
+    }
+}
+
+//the llvm IR :ir shows
+#[cfg(feature = "llvm")]
+fn llvm_ir(program : &Expr) -> Result<String, String>{
+    translate(program).map(|ctxt| ctxt.ir())
+}
+
+#[cfg(not(feature = "llvm"))]
+fn llvm_ir(_ : &Expr) -> Result<String, String>{
+    Err(String::from("charon was built without llvm, so there is no IR to show"))
+}
+
+fn parse_expr(src : &str) -> Result<Expr, String>{
+    Parser::new(src.to_string()).parse().map(|e| (*e).clone()).map_err(|errors| syntax_errors(&errors))
+}
+
+fn parse_decls(src : &str) -> Result<Vec<Decl>, String>{
+    let mut p = Parser::new(src.to_string());
+    let decls = p.decls();
+    if p.errors.is_empty() {Ok(decls)} else {Err(syntax_errors(&p.errors))}
+}
+
+fn syntax_errors(errors : &[SyntaxError]) -> String{
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+fn starts_with_decl(src : &str) -> bool{
+    match Lexer::new(src.to_string()).next().map(|t| t.token){
+        Some(Token::Var) | Some(Token::Function) | Some(Token::Type) => true,
+        _ => false
+    }
+}
+
+//an input is complete once its strings, parens and let..end blocks are closed
+pub fn is_complete(src : &str) -> bool{
+    let mut in_string = false;
+    let mut chars = src.chars();
+    while let Some(c) = chars.next(){
+        match c{
+            '\\' if in_string => { chars.next(); },
+            '"' => in_string = !in_string,
+            _ => {}
+        }
+    }
+    if in_string{
+        return false
+    }
+    let mut parens = 0;
+    let mut lets = 0;
+    for t in Lexer::new(src.to_string()){
+        match t.token{
+            Token::LeftParen => parens += 1,
+            Token::RightParen => parens -= 1,
+            Token::Let => lets += 1,
+            Token::End => lets -= 1,
+            Token::Error => break,
+            _ => {}
+        }
+    }
+    parens <= 0 && lets <= 0
+}
+
+//held while the panic hook is swapped out, so that two threads swapping it
+//can't leave the silent hook in place
+static HOOK_LOCK : Mutex<()> = Mutex::new(());
+
+//the type checker reports errors by panicking. They come back as Err,
+//without the panic hook printing them; the previous hook is put back after
+fn catch_errors<T, F : FnOnce() -> Result<T, String>>(f : F) -> Result<T, String>{
+    let _lock = HOOK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    match result{
+        Ok(r) => r,
+        Err(payload) => Err(panic_message(payload))
+    }
+}
+
+fn panic_message(payload : Box<Any + Send>) -> String{
+    if let Some(s) = payload.downcast_ref::<&str>(){
+        return s.to_string()
+    }
+    match payload.downcast_ref::<String>(){
+        Some(s) => s.clone(),
+        None => String::from("internal compiler error")
+    }
+}
+
+pub fn run() -> i32{
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    let mut buffer = String::new();
+    loop{
+        print!("{}", if buffer.is_empty() {PROMPT} else {CONTINUATION_PROMPT});
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line){
+            Ok(0) | Err(_) => {
+                println!("");
+                return 0
+            },
+            Ok(_) => {}
+        }
+        buffer.push_str(&line);
+        if !buffer.trim().starts_with(':') &&
+           !catch_errors(|| Ok(is_complete(&buffer))).unwrap_or(true){
+            continue;
+        }
+        let input = buffer.clone();
+        buffer.clear();
+        match repl.eval_line(&input){
+            Ok(Outcome::Output(ref out)) if out.is_empty() => {},
+            Ok(Outcome::Output(out)) => println!("{}", out),
+            Ok(Outcome::Exit(code)) => return code,
+            Err(msg) => println!("error: {}", msg)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(repl : &mut Repl, src : &str) -> String{
+        match repl.eval_line(src){
+            Ok(Outcome::Output(out)) => out,
+            Ok(Outcome::Exit(code)) => panic!("exited with {}", code),
+            Err(msg) => panic!("{}", msg)
+        }
+    }
+
+    #[test]
+    fn test_is_complete(){
+        assert!(is_complete("1+1"));
+        assert!(!is_complete("(1;"));
+        assert!(!is_complete("let var a : int := 1 in a"));
+        assert!(is_complete("let var a : int := 1 in a end"));
+        assert!(!is_complete("print(\"abc"));
+    }
+
+    #[test]
+    fn test_repl_prints_value_and_type(){
+        let mut r = Repl::new();
+        assert_eq!(output(&mut r, "1+2"), "3 : Number");
+    }
+
+    #[test]
+    fn test_repl_keeps_let_declarations(){
+        let mut r = Repl::new();
+        output(&mut r, "let var a : int := 1 in a end");
+        assert_eq!(output(&mut r, "a+1"), "2 : Number");
+    }
+
+    #[test]
+    fn test_repl_keeps_bare_declarations(){
+        let mut r = Repl::new();
+        assert_eq!(output(&mut r, "function inc(n:int) : int = n+1"), "function inc : Number");
+        assert_eq!(output(&mut r, "inc(41)"), "42 : Number");
+    }
+
+    #[test]
+    fn test_repl_recovers_from_type_errors(){
+        let mut r = Repl::new();
+        assert!(r.eval_line("let var a : int := \"abc\" in a end").is_err());
+        assert!(r.eval_line("a").is_err());
+        assert_eq!(output(&mut r, ":type 1+1"), "Number");
+    }
+
+    #[test]
+    fn test_repl_restores_the_panic_hook(){
+        use std::cell::Cell;
+        use std::panic;
+        thread_local!(static HOOK_CALLS : Cell<usize> = Cell::new(0));
+        let hook = {
+            let _lock = HOOK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(|_| HOOK_CALLS.with(|c| c.set(c.get() + 1))));
+            hook
+        };
+        let error = Repl::new().eval_line("1 + \"a\"").err();
+        let _lock = HOOK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = panic::catch_unwind(|| panic!("after the repl"));
+        panic::set_hook(hook);
+        //the type error didn't reach the hook, the panic after it did
+        assert_eq!(HOOK_CALLS.with(|c| c.get()), 1);
+        assert_eq!(error, Some(String::from("Expected right operand of int type")));
+    }
+}
@@ -0,0 +1,210 @@
+//! Canonical form of the trees: ESEQs are gone and every statement is a
+//! three-address instruction whose operands are atoms, so that the optimizer
+//! can reason about one value per statement.
+
+use tree::*;
+
+struct Flattener<'g>{
+    gen : &'g mut Gen,
+    out : Vec<Stm>
+}
+
+fn has_eseq(e : &Exp) -> bool{
+    match *e{
+        Exp::ESeq(_, _) => true,
+        Exp::BinOp(_, ref l, ref r) => has_eseq(l) || has_eseq(r),
+        Exp::Mem(_, ref a) => has_eseq(a),
+        Exp::Call(_, ref args, _) => args.iter().any(has_eseq),
+        _ => false
+    }
+}
+
+impl<'g> Flattener<'g>{
+    //evaluates the expressions left to right. A temp read early is copied when a
+    //statement hidden in one of the later expressions could write it
+    fn atoms(&mut self, es : Vec<Exp>) -> Vec<Exp>{
+        let mut atoms = Vec::new();
+        for i in 0..es.len(){
+            let a = self.atom(es[i].clone());
+            let a = match a{
+                Exp::Temp(t) if es[i + 1..].iter().any(has_eseq) => {
+                    let copy = self.gen.temp(t.ty);
+                    self.out.push(Stm::Move(Exp::Temp(copy), a));
+                    Exp::Temp(copy)
+                },
+                a => a
+            };
+            atoms.push(a);
+        }
+        atoms
+    }
+
+    fn atom(&mut self, e : Exp) -> Exp{
+        let e = self.simple(e);
+        if e.is_atom(){
+            return e
+        }
+        let t = self.gen.temp(e.ty());
+        self.out.push(Stm::Move(Exp::Temp(t), e));
+        Exp::Temp(t)
+    }
+
+    //an atom or a single operation on atoms
+    fn simple(&mut self, e : Exp) -> Exp{
+        match e{
+            Exp::BinOp(op, l, r) => {
+                let mut v = self.atoms(vec![*l, *r]);
+                let r = v.pop().unwrap();
+                Exp::BinOp(op, Box::new(v.pop().unwrap()), Box::new(r))
+            },
+            Exp::Mem(ty, a) => Exp::Mem(ty, Box::new(self.atom(*a))),
+            Exp::Call(l, args, ty) => Exp::Call(l, self.atoms(args), ty),
+            Exp::ESeq(s, e) => {
+                self.stm(*s);
+                self.simple(*e)
+            },
+            e => e
+        }
+    }
+
+    fn stm(&mut self, s : Stm){
+        match s{
+            Stm::Move(Exp::Temp(t), src) => {
+                let src = self.simple(src);
+                self.out.push(Stm::Move(Exp::Temp(t), src));
+            },
+            Stm::Move(Exp::Mem(ty, addr), src) => {
+                let mut v = self.atoms(vec![*addr, src]);
+                let src = v.pop().unwrap();
+                self.out.push(Stm::Move(Exp::Mem(ty, Box::new(v.pop().unwrap())), src));
+            },
+            Stm::Move(Exp::ESeq(s, dst), src) => {
+                self.stm(*s);
+                self.stm(Stm::Move(*dst, src));
+            },
+            Stm::Exp(e) => {
+                //only calls have effects worth keeping
+                if let Exp::Call(l, args, ty) = self.simple(e){
+                    self.out.push(Stm::Exp(Exp::Call(l, args, ty)));
+                }
+            },
+            Stm::CJump(op, l, r, t, f) => {
+                let mut v = self.atoms(vec![l, r]);
+                let r = v.pop().unwrap();
+                self.out.push(Stm::CJump(op, v.pop().unwrap(), r, t, f));
+            },
+            Stm::Seq(stms) => {
+                for s in stms{
+                    self.stm(s);
+                }
+            },
+            Stm::Return(Some(e)) => {
+                let e = self.atom(e);
+                self.out.push(Stm::Return(Some(e)));
+            },
+            s => self.out.push(s)
+        }
+    }
+}
+
+//the statements of `body` in canonical form, without SEQs
+pub fn linearize(body : Vec<Stm>, gen : &mut Gen) -> Vec<Stm>{
+    let mut f = Flattener { gen : gen, out : Vec::new() };
+    for s in body{
+        f.stm(s);
+    }
+    f.out
+}
+
+pub fn is_terminator(s : &Stm) -> bool{
+    match *s{
+        Stm::Jump(_) | Stm::CJump(..) | Stm::Return(_) => true,
+        _ => false
+    }
+}
+
+//a label, straight-line code and a jump or return at the end
+pub struct BasicBlock{
+    pub label : Label,
+    pub stms : Vec<Stm>
+}
+
+//splits linearized statements into basic blocks. Code falling into a label
+//gets an explicit jump and the last block returns if it doesn't already
+pub fn basic_blocks(stms : Vec<Stm>, ret : Ty, gen : &mut Gen) -> Vec<BasicBlock>{
+    let mut blocks : Vec<BasicBlock> = Vec::new();
+    let mut current : Option<BasicBlock> = None;
+    for s in stms{
+        if let Stm::Label(l) = s{
+            if let Some(mut b) = current.take(){
+                b.stms.push(Stm::Jump(l.clone()));
+                blocks.push(b);
+            }
+            current = Some(BasicBlock { label : l, stms : Vec::new() });
+            continue
+        }
+        let mut b = current.take().unwrap_or_else(|| BasicBlock { label : gen.label(), stms : Vec::new() });
+        let ends_block = is_terminator(&s);
+        b.stms.push(s);
+        if ends_block{
+            blocks.push(b);
+        }
+        else{
+            current = Some(b);
+        }
+    }
+    let last = match current{
+        Some(b) => Some(b),
+        None if blocks.is_empty() => Some(BasicBlock { label : gen.label(), stms : Vec::new() }),
+        None => None
+    };
+    if let Some(mut b) = last{
+        b.stms.push(Stm::Return(if ret == Ty::Void {None} else {Some(Exp::zero(ret))}));
+        blocks.push(b);
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(id : usize) -> Exp{
+        Exp::Temp(Temp { id : id, ty : Ty::I32 })
+    }
+
+    #[test]
+    fn test_linearize_lifts_eseq(){
+        let mut gen = Gen { next_temp : 10, next_label : 0 };
+        //t0 + ESEQ(MOVE(t0, 5), t0) must add the old value of t0
+        let e = Exp::BinOp(BinOp::Plus, Box::new(t(0)),
+                           Box::new(Exp::ESeq(Box::new(Stm::Move(t(0), Exp::Const(5))), Box::new(t(0)))));
+        let stms = linearize(vec![Stm::Move(t(1), e)], &mut gen);
+        assert_eq!(stms, vec![Stm::Move(t(10), t(0)),
+                              Stm::Move(t(0), Exp::Const(5)),
+                              Stm::Move(t(1), Exp::BinOp(BinOp::Plus, Box::new(t(10)), Box::new(t(0))))]);
+    }
+
+    #[test]
+    fn test_linearize_names_nested_operations(){
+        let mut gen = Gen { next_temp : 10, next_label : 0 };
+        let e = Exp::BinOp(BinOp::Mul, Box::new(Exp::BinOp(BinOp::Plus, Box::new(t(0)), Box::new(Exp::Const(1)))),
+                           Box::new(t(1)));
+        let stms = linearize(vec![Stm::Return(Some(e))], &mut gen);
+        assert_eq!(stms.len(), 3);
+        assert_eq!(stms[2], Stm::Return(Some(t(11))));
+    }
+
+    #[test]
+    fn test_basic_blocks(){
+        let mut gen = Gen { next_temp : 0, next_label : 5 };
+        let l1 = Label(String::from("L1"));
+        let stms = vec![Stm::Move(t(0), Exp::Const(1)),
+                        Stm::Label(l1.clone()),
+                        Stm::Return(Some(t(0)))];
+        let blocks = basic_blocks(stms, Ty::I32, &mut gen);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].label, Label(String::from("L5")));
+        assert_eq!(blocks[0].stms[1], Stm::Jump(l1));
+    }
+}
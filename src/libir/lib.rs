@@ -0,0 +1,13 @@
+#![crate_name = "ir"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! Mid-level IR: the tree language of Appel's "Modern Compiler Implementation".
+//! The AST is translated into it once and the backends work from here.
+
+extern crate syntax;
+
+pub mod tree;
+pub mod translate;
+pub mod canon;
+pub mod ssa;
+pub mod opt;
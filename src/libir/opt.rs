@@ -0,0 +1,561 @@
+//! Optimization passes over the SSA form of the IR. Each pass can be enabled
+//! on its own; `optimize` runs a list of them over every procedure.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::str::FromStr;
+use tree::*;
+use ssa::{self, Func, Block, Phi, each_use, def, retarget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass{
+    //constant propagation and folding, including of conditional jumps
+    ConstProp,
+    //replaces temps that are copies of other temps or constants by the original
+    CopyProp,
+    //dead code elimination
+    Dce,
+    //common subexpression elimination
+    Cse,
+    //loop-invariant code motion
+    Licm
+}
+
+//the passes -C ir-passes=all runs, in order
+pub const ALL_PASSES : &'static [Pass] = &[Pass::ConstProp, Pass::Cse, Pass::CopyProp, Pass::Licm, Pass::Dce];
+
+impl FromStr for Pass{
+    type Err = String;
+    fn from_str(s : &str) -> Result<Pass, String>{
+        match s{
+            "constprop" => Ok(Pass::ConstProp),
+            "copyprop" => Ok(Pass::CopyProp),
+            "dce" => Ok(Pass::Dce),
+            "cse" => Ok(Pass::Cse),
+            "licm" => Ok(Pass::Licm),
+            _ => Err(format!("unknown IR pass '{}'. Expected one of constprop, copyprop, dce, cse or licm", s))
+        }
+    }
+}
+
+impl fmt::Display for Pass{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(match *self{
+            Pass::ConstProp => "constprop",
+            Pass::CopyProp => "copyprop",
+            Pass::Dce => "dce",
+            Pass::Cse => "cse",
+            Pass::Licm => "licm"
+        })
+    }
+}
+
+//a comma separated list of passes, "all" or "none"
+pub fn parse_passes(s : &str) -> Result<Vec<Pass>, String>{
+    match s{
+        "all" => Ok(ALL_PASSES.to_vec()),
+        "none" | "" => Ok(Vec::new()),
+        _ => s.split(',').map(|p| p.trim().parse()).collect()
+    }
+}
+
+//runs `passes` in order over every procedure of the program. With `dump`, the
+//SSA form of each procedure is written to it before and after every pass
+pub fn optimize(prog : &mut Program, passes : &[Pass], mut dump : Option<&mut io::Write>) -> io::Result<()>{
+    if passes.is_empty(){
+        return Ok(())
+    }
+    let procs = mem::replace(&mut prog.procs, Vec::new());
+    for p in procs{
+        let mut f = ssa::to_ssa(p, &mut prog.gen);
+        for &pass in passes{
+            if let Some(ref mut w) = dump{
+                try!(write!(w, ";; before {}\n{}\n", pass, f));
+            }
+            run(pass, &mut f, &mut prog.gen);
+            if let Some(ref mut w) = dump{
+                try!(write!(w, ";; after {}\n{}\n", pass, f));
+            }
+        }
+        prog.procs.push(ssa::from_ssa(f, &mut prog.gen));
+    }
+    Ok(())
+}
+
+pub fn run(pass : Pass, f : &mut Func, gen : &mut Gen){
+    match pass{
+        Pass::ConstProp => const_prop(f),
+        Pass::CopyProp => copy_prop(f),
+        Pass::Dce => dce(f),
+        Pass::Cse => cse(f),
+        Pass::Licm => licm(f, gen)
+    }
+}
+
+//wraps around like the generated code. Division by zero and the one overflowing
+//division are left for run time
+fn fold(op : BinOp, a : i32, b : i32) -> Option<i32>{
+    match op{
+        BinOp::Plus => Some(a.wrapping_add(b)),
+        BinOp::Minus => Some(a.wrapping_sub(b)),
+        BinOp::Mul => Some(a.wrapping_mul(b)),
+        BinOp::Div => a.checked_div(b)
+    }
+}
+
+fn compare(op : RelOp, a : i32, b : i32) -> bool{
+    match op{
+        RelOp::Eq => a == b,
+        RelOp::Ne => a != b,
+        RelOp::Lt => a < b,
+        RelOp::Le => a <= b,
+        RelOp::Gt => a > b,
+        RelOp::Ge => a >= b
+    }
+}
+
+fn const_prop(f : &mut Func){
+    loop{
+        let mut consts : HashMap<usize, i32> = HashMap::new();
+        let mut changed = true;
+        while changed{
+            changed = false;
+            let mut subst = |e : &mut Exp| if let Exp::Temp(t) = *e{
+                if let Some(&c) = consts.get(&t.id){
+                    *e = Exp::Const(c);
+                }
+            };
+            let mut found = Vec::new();
+            for b in f.blocks.iter_mut(){
+                for phi in b.phis.iter_mut(){
+                    for arg in phi.args.iter_mut(){
+                        subst(&mut arg.1);
+                    }
+                    if let Some(&(_, Exp::Const(c))) = phi.args.first(){
+                        if phi.args.iter().all(|a| a.1 == Exp::Const(c)){
+                            found.push((phi.dst.id, c));
+                        }
+                    }
+                }
+                for s in b.stms.iter_mut(){
+                    each_use(s, &mut subst);
+                    if let Stm::Move(Exp::Temp(t), ref mut src) = *s{
+                        let folded = match *src{
+                            Exp::BinOp(op, ref l, ref r) => match (&**l, &**r){
+                                (&Exp::Const(a), &Exp::Const(b)) => fold(op, a, b),
+                                _ => None
+                            },
+                            _ => None
+                        };
+                        if let Some(c) = folded{
+                            *src = Exp::Const(c);
+                        }
+                        if let Exp::Const(c) = *src{
+                            found.push((t.id, c));
+                        }
+                    }
+                }
+            }
+            for (id, c) in found{
+                if consts.insert(id, c).is_none(){
+                    changed = true;
+                }
+            }
+        }
+
+        //branches on constants always go the same way
+        let mut pruned = false;
+        for b in f.blocks.iter_mut(){
+            let target = match *b.terminator(){
+                Stm::CJump(op, Exp::Const(l), Exp::Const(r), ref t, ref e) =>
+                    Some(if compare(op, l, r) {t.clone()} else {e.clone()}),
+                _ => None
+            };
+            if let Some(l) = target{
+                *b.terminator_mut() = Stm::Jump(l);
+                pruned = true;
+            }
+        }
+        if !pruned{
+            return
+        }
+        //fewer predecessors can make more PHIs constant
+        f.cleanup();
+    }
+}
+
+//follows a chain of copies to the value it started with
+fn resolve(copies : &HashMap<usize, Exp>, e : &Exp) -> Exp{
+    let mut e = e.clone();
+    while let Exp::Temp(t) = e.clone(){
+        match copies.get(&t.id){
+            Some(v) => e = v.clone(),
+            None => break
+        }
+    }
+    e
+}
+
+fn copy_prop(f : &mut Func){
+    let mut copies : HashMap<usize, Exp> = HashMap::new();
+    loop{
+        let before = copies.len();
+        for b in f.blocks.iter(){
+            for phi in b.phis.iter(){
+                //a PHI whose arguments are all one value, or itself around a loop,
+                //is a copy of that value
+                let mut values = phi.args.iter().map(|a| resolve(&copies, &a.1)).filter(|v| *v != Exp::Temp(phi.dst));
+                if let Some(v) = values.next(){
+                    if values.all(|w| w == v) && !copies.contains_key(&phi.dst.id){
+                        copies.insert(phi.dst.id, v);
+                    }
+                }
+            }
+            for s in b.stms.iter(){
+                if let Stm::Move(Exp::Temp(t), ref src) = *s{
+                    if src.is_atom() && !copies.contains_key(&t.id){
+                        let v = resolve(&copies, src);
+                        if v != Exp::Temp(t){
+                            copies.insert(t.id, v);
+                        }
+                    }
+                }
+            }
+        }
+        if copies.len() == before{
+            break
+        }
+    }
+
+    let mut subst = |e : &mut Exp| *e = resolve(&copies, e);
+    for b in f.blocks.iter_mut(){
+        b.phis.retain(|phi| !copies.contains_key(&phi.dst.id));
+        b.stms.retain(|s| def(s).map_or(true, |t| !copies.contains_key(&t.id)));
+        for phi in b.phis.iter_mut(){
+            for arg in phi.args.iter_mut(){
+                subst(&mut arg.1);
+            }
+        }
+        for s in b.stms.iter_mut(){
+            each_use(s, &mut subst);
+        }
+    }
+}
+
+fn temps_of(s : &Stm) -> Vec<usize>{
+    let mut temps = Vec::new();
+    each_use(&mut s.clone(), &mut |e| if let Exp::Temp(t) = *e {temps.push(t.id)});
+    temps
+}
+
+//removes the MOVEs and PHIs whose temps nothing with an effect depends on.
+//Calls are kept for their effects even when their result isn't used
+fn dce(f : &mut Func){
+    let mut operands : HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut work = Vec::new();
+    for b in f.blocks.iter(){
+        for phi in b.phis.iter(){
+            let temps = phi.args.iter().filter_map(|a| if let Exp::Temp(t) = a.1 {Some(t.id)} else {None}).collect();
+            operands.insert(phi.dst.id, temps);
+        }
+        for s in b.stms.iter(){
+            match *s{
+                Stm::Move(Exp::Temp(t), ref src) if !is_call(src) => {
+                    operands.insert(t.id, temps_of(s));
+                },
+                _ => work.extend(temps_of(s))
+            }
+        }
+    }
+    let mut live = HashSet::new();
+    while let Some(t) = work.pop(){
+        if live.insert(t){
+            if let Some(ops) = operands.get(&t){
+                work.extend(ops.iter().cloned());
+            }
+        }
+    }
+    for b in f.blocks.iter_mut(){
+        b.phis.retain(|phi| live.contains(&phi.dst.id));
+        let stms = mem::replace(&mut b.stms, Vec::new());
+        for s in stms{
+            match s{
+                Stm::Move(Exp::Temp(t), src) => {
+                    if live.contains(&t.id){
+                        b.stms.push(Stm::Move(Exp::Temp(t), src));
+                    }
+                    else if is_call(&src){
+                        b.stms.push(Stm::Exp(src));
+                    }
+                },
+                s => b.stms.push(s)
+            }
+        }
+    }
+}
+
+fn is_call(e : &Exp) -> bool{
+    if let Exp::Call(..) = *e {true} else {false}
+}
+
+//reuses the result of an arithmetic operation computed in a dominating block
+//or earlier in the same one. Operands are SSA temps, so equal operations have
+//equal values
+fn cse(f : &mut Func){
+    let idom = f.dominators();
+    let children = Func::dominator_tree(&idom);
+    cse_block(f, &children, 0, &mut HashMap::new());
+}
+
+fn cse_block(f : &mut Func, children : &[Vec<usize>], b : usize, available : &mut HashMap<Exp, Temp>){
+    let mut added = Vec::new();
+    for s in f.blocks[b].stms.iter_mut(){
+        if let Stm::Move(Exp::Temp(t), ref mut src) = *s{
+            if let Exp::BinOp(..) = *src{
+                match available.get(src).cloned(){
+                    Some(prev) => *src = Exp::Temp(prev),
+                    None => {
+                        available.insert(src.clone(), t);
+                        added.push(src.clone());
+                    }
+                }
+            }
+        }
+    }
+    for &c in children[b].iter(){
+        cse_block(f, children, c, available);
+    }
+    for e in added{
+        available.remove(&e);
+    }
+}
+
+//the natural loops of the function by header, merging the loops of back edges
+//that share one
+fn natural_loops(f : &Func) -> Vec<(usize, HashSet<usize>)>{
+    let idom = f.dominators();
+    let preds = f.preds();
+    let mut loops : Vec<(usize, HashSet<usize>)> = Vec::new();
+    for (b, succs) in f.succs().into_iter().enumerate(){
+        for h in succs{
+            if !Func::dominates(&idom, h, b){
+                continue
+            }
+            let i = match loops.iter().position(|l| l.0 == h){
+                Some(i) => i,
+                None => {
+                    let mut body = HashSet::new();
+                    body.insert(h);
+                    loops.push((h, body));
+                    loops.len() - 1
+                }
+            };
+            let mut work = vec![b];
+            while let Some(n) = work.pop(){
+                if loops[i].1.insert(n){
+                    work.extend(preds[n].iter().cloned());
+                }
+            }
+        }
+    }
+    loops
+}
+
+//a block that runs right before the loop is entered and jumps to the header.
+//The header's PHI arguments from outside the loop move into it
+fn preheader(f : &mut Func, gen : &mut Gen, h : usize, body : &HashSet<usize>) -> usize{
+    let preds = f.preds();
+    let outside : Vec<usize> = preds[h].iter().cloned().filter(|p| !body.contains(p)).collect();
+    if outside.len() == 1 && f.blocks[outside[0]].succs().len() == 1{
+        return outside[0]
+    }
+    let label = gen.label();
+    let header = f.blocks[h].label.clone();
+    let outside : Vec<Label> = outside.iter().map(|&p| f.blocks[p].label.clone()).collect();
+    for b in f.blocks.iter_mut(){
+        if outside.contains(&b.label){
+            retarget(b.terminator_mut(), &header, &label);
+        }
+    }
+    let mut phis = Vec::new();
+    for phi in f.blocks[h].phis.iter_mut(){
+        let (out, mut inside) : (Vec<(Label, Exp)>, Vec<(Label, Exp)>) =
+            phi.args.drain(..).partition(|a| outside.contains(&a.0));
+        let value = if out.len() == 1{
+            out[0].1.clone()
+        }
+        else{
+            let t = gen.temp(phi.dst.ty);
+            phis.push(Phi { dst : t, args : out });
+            Exp::Temp(t)
+        };
+        inside.push((label.clone(), value));
+        phi.args = inside;
+    }
+    f.blocks.insert(h, Block { label : label, phis : phis, stms : vec![Stm::Jump(header)] });
+    h
+}
+
+//moves arithmetic whose operands don't change inside a loop in front of it,
+//innermost loops first. Divisions stay where they are since the loop might not
+//run and they can trap
+fn licm(f : &mut Func, gen : &mut Gen){
+    let mut loops = natural_loops(f);
+    loops.sort_by_key(|l| l.1.len());
+    let headers : Vec<Label> = loops.iter().map(|l| f.blocks[l.0].label.clone()).collect();
+    for header in headers{
+        //earlier preheaders shift the blocks around
+        let (h, body) = natural_loops(f).into_iter().find(|l| f.blocks[l.0].label == header).unwrap();
+        let mut body_order : Vec<usize> = body.iter().cloned().collect();
+        body_order.sort();
+        let mut defined = HashSet::new();
+        for &b in body_order.iter(){
+            defined.extend(f.blocks[b].phis.iter().map(|phi| phi.dst.id));
+            defined.extend(f.blocks[b].stms.iter().filter_map(def).map(|t| t.id));
+        }
+        let mut hoisted = Vec::new();
+        let mut changed = true;
+        while changed{
+            changed = false;
+            for &b in body_order.iter(){
+                let stms = mem::replace(&mut f.blocks[b].stms, Vec::new());
+                for s in stms{
+                    let invariant = match s{
+                        Stm::Move(Exp::Temp(_), Exp::BinOp(op, _, _)) if op != BinOp::Div =>
+                            temps_of(&s).iter().all(|t| !defined.contains(t)),
+                        _ => false
+                    };
+                    if invariant{
+                        defined.remove(&def(&s).unwrap().id);
+                        hoisted.push(s);
+                        changed = true;
+                    }
+                    else{
+                        f.blocks[b].stms.push(s);
+                    }
+                }
+            }
+        }
+        if hoisted.is_empty(){
+            continue
+        }
+        let p = preheader(f, gen, h, &body);
+        let terminator = f.blocks[p].stms.pop().unwrap();
+        f.blocks[p].stms.extend(hoisted);
+        f.blocks[p].stms.push(terminator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use translate::translate;
+    use super::*;
+
+    fn main_ssa(src : &str) -> (Func, Gen){
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        let main = prog.procs.pop().unwrap();
+        let f = ssa::to_ssa(main, &mut prog.gen);
+        (f, prog.gen)
+    }
+
+    fn optimized(src : &str, passes : &[Pass]) -> Func{
+        let (mut f, mut gen) = main_ssa(src);
+        for &p in passes{
+            run(p, &mut f, &mut gen);
+        }
+        f
+    }
+
+    #[test]
+    fn test_parse_passes(){
+        assert_eq!(parse_passes("dce,licm"), Ok(vec![Pass::Dce, Pass::Licm]));
+        assert_eq!(parse_passes("all"), Ok(ALL_PASSES.to_vec()));
+        assert_eq!(parse_passes("none"), Ok(Vec::new()));
+        assert_eq!(parse_passes("dce,gvn"), Err(String::from("unknown IR pass 'gvn'. Expected one of constprop, copyprop, dce, cse or licm")));
+    }
+
+    #[test]
+    fn test_const_prop_folds(){
+        let f = optimized("let var a : int := 2 in (a := a + 8; print(a)) end", &[Pass::ConstProp]);
+        assert!(f.to_string().contains("EXP(CALL tiger_print_int(CONST 10))"), "{}", f);
+    }
+
+    #[test]
+    fn test_const_prop_keeps_division_by_zero(){
+        let f = optimized("print(1 / 0)", &[Pass::ConstProp]);
+        assert!(f.to_string().contains("BINOP(DIV, CONST 1, CONST 0)"), "{}", f);
+    }
+
+    #[test]
+    fn test_const_prop_prunes_branches(){
+        let f = optimized("let var a : int := 3 in if a then print(1) else print(2) end", &[Pass::ConstProp]);
+        assert_eq!(f.blocks.iter().map(|b| b.label.clone()).collect::<HashSet<Label>>().len(), f.blocks.len());
+        let dump = f.to_string();
+        assert!(dump.contains("tiger_print_int(CONST 1)") && !dump.contains("tiger_print_int(CONST 2)"), "{}", dump);
+    }
+
+    #[test]
+    fn test_copy_prop(){
+        let (mut f, mut gen) = main_ssa("let var a : int := 0 in let var b : int := a in print(b) end end");
+        //make a a parameter so it is not a constant
+        let a = match f.blocks[0].stms[0]{
+            Stm::Move(Exp::Temp(t), _) => t,
+            _ => panic!()
+        };
+        f.blocks[0].stms.remove(0);
+        f.params.push(a);
+        run(Pass::CopyProp, &mut f, &mut gen);
+        assert_eq!(f.blocks[0].stms[0], Stm::Exp(Exp::Call(Label(String::from("tiger_print_int")), vec![Exp::Temp(a)], Ty::Void)));
+    }
+
+    #[test]
+    fn test_dce(){
+        let f = optimized("let var a : int := 1 in let var b : int := 4 in (a := b + 2; print(b)) end end", &[Pass::Dce]);
+        let dump = f.to_string();
+        assert!(!dump.contains("PLUS"), "{}", dump);
+        assert!(dump.contains("tiger_print_int"));
+    }
+
+    #[test]
+    fn test_cse(){
+        let src = "let var a : int := 5 in let var b : int := 0 in (b := a + 1; print(b); b := a + 1; print(b)) end end";
+        let dump = optimized(src, &[Pass::Cse]).to_string();
+        assert_eq!(dump.matches("PLUS").count(), 1, "{}", dump);
+    }
+
+    #[test]
+    fn test_licm(){
+        let src = "let var a : int := 5 in let var b : int := 0 in for i := 1 to 3 do (b := a + 7; print(b)) end end";
+        let f = optimized(src, &[Pass::Licm]);
+        let loops = natural_loops(&f);
+        assert_eq!(loops.len(), 1);
+        let add = f.blocks.iter().position(|b| b.stms.iter().any(|s| s.to_string().contains("CONST 7"))).unwrap();
+        assert!(!loops[0].1.contains(&add), "{}", f);
+        assert!(Func::dominates(&f.dominators(), add, loops[0].0));
+    }
+
+    #[test]
+    fn test_optimize_dumps(){
+        let src = "let var a : int := 0 in for i := 1 to 3 do (a := a + i; print(a)) end";
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        let mut dump = Vec::new();
+        optimize(&mut prog, ALL_PASSES, Some(&mut dump)).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with(";; before constprop\nfunction main() : i32\n"), "{}", dump);
+        assert!(dump.contains(";; after dce\n"));
+        assert!(!prog.to_string().contains("PHI"));
+    }
+}
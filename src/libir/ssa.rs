@@ -0,0 +1,554 @@
+//! Static single assignment form of a procedure: the canonical statements
+//! grouped into basic blocks, with every temp written exactly once and PHI
+//! nodes where control flow merges.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tree::*;
+use canon;
+
+//the value of `dst` is the argument belonging to the predecessor control came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phi{
+    pub dst : Temp,
+    pub args : Vec<(Label, Exp)>
+}
+
+//the last statement is the only jump or return of the block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block{
+    pub label : Label,
+    pub phis : Vec<Phi>,
+    pub stms : Vec<Stm>
+}
+
+impl Block{
+    pub fn terminator(&self) -> &Stm{
+        self.stms.last().unwrap()
+    }
+
+    pub fn terminator_mut(&mut self) -> &mut Stm{
+        self.stms.last_mut().unwrap()
+    }
+
+    pub fn succs(&self) -> Vec<Label>{
+        match *self.terminator(){
+            Stm::Jump(ref l) => vec![l.clone()],
+            Stm::CJump(_, _, _, ref t, ref f) => vec![t.clone(), f.clone()],
+            _ => Vec::new()
+        }
+    }
+}
+
+//a function in SSA form. The first block is the entry and has no predecessors
+pub struct Func{
+    pub name : Label,
+    pub params : Vec<Temp>,
+    pub ret : Ty,
+    pub blocks : Vec<Block>
+}
+
+//redirects the jumps of a terminator that go to `from`
+pub fn retarget(s : &mut Stm, from : &Label, to : &Label){
+    match *s{
+        Stm::Jump(ref mut l) => if l == from {*l = to.clone()},
+        Stm::CJump(_, _, _, ref mut t, ref mut f) => {
+            if t == from {*t = to.clone()}
+            if f == from {*f = to.clone()}
+        },
+        _ => ()
+    }
+}
+
+//calls `f` on every operand the statement reads. The destination temp of a
+//MOVE is written, not read, and is skipped
+pub fn each_use<F : FnMut(&mut Exp)>(s : &mut Stm, f : &mut F){
+    match *s{
+        Stm::Move(Exp::Temp(_), ref mut src) => each_atom(src, f),
+        Stm::Move(ref mut dst, ref mut src) => {
+            each_atom(dst, f);
+            each_atom(src, f);
+        },
+        Stm::Exp(ref mut e) | Stm::Return(Some(ref mut e)) => each_atom(e, f),
+        Stm::CJump(_, ref mut l, ref mut r, _, _) => {
+            each_atom(l, f);
+            each_atom(r, f);
+        },
+        Stm::Seq(ref mut stms) => for s in stms.iter_mut(){
+            each_use(s, f);
+        },
+        _ => ()
+    }
+}
+
+fn each_atom<F : FnMut(&mut Exp)>(e : &mut Exp, f : &mut F){
+    match *e{
+        Exp::BinOp(_, ref mut l, ref mut r) => {
+            each_atom(l, f);
+            each_atom(r, f);
+        },
+        Exp::Mem(_, ref mut a) => each_atom(a, f),
+        Exp::Call(_, ref mut args, _) => for a in args.iter_mut(){
+            each_atom(a, f);
+        },
+        Exp::ESeq(ref mut s, ref mut e) => {
+            each_use(s, f);
+            each_atom(e, f);
+        },
+        _ => f(e)
+    }
+}
+
+//the temp a statement writes
+pub fn def(s : &Stm) -> Option<Temp>{
+    match *s{
+        Stm::Move(Exp::Temp(t), _) => Some(t),
+        _ => None
+    }
+}
+
+impl Func{
+    pub fn index(&self) -> HashMap<Label, usize>{
+        self.blocks.iter().enumerate().map(|(i, b)| (b.label.clone(), i)).collect()
+    }
+
+    pub fn succs(&self) -> Vec<Vec<usize>>{
+        let index = self.index();
+        self.blocks.iter().map(|b| b.succs().iter().map(|l| index[l]).collect()).collect()
+    }
+
+    pub fn preds(&self) -> Vec<Vec<usize>>{
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, ss) in self.succs().into_iter().enumerate(){
+            for s in ss{
+                preds[s].push(i);
+            }
+        }
+        preds
+    }
+
+    //the blocks reachable from the entry in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<usize>{
+        let succs = self.succs();
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        //(block, index of the next successor to visit)
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some(&mut (b, ref mut next)) = stack.last_mut(){
+            if *next < succs[b].len(){
+                let s = succs[b][*next];
+                *next += 1;
+                if !visited[s]{
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            }
+            else{
+                order.push(b);
+                stack.pop();
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    //drops the blocks control can't reach and the PHI arguments of edges that
+    //no longer exist
+    pub fn cleanup(&mut self){
+        let reachable : HashSet<usize> = self.reverse_postorder().into_iter().collect();
+        let mut i = 0;
+        self.blocks.retain(|_| {
+            i += 1;
+            reachable.contains(&(i - 1))
+        });
+        let preds = self.preds();
+        for (b, ps) in preds.iter().enumerate(){
+            let labels : Vec<Label> = ps.iter().map(|&p| self.blocks[p].label.clone()).collect();
+            for phi in self.blocks[b].phis.iter_mut(){
+                phi.args.retain(|&(ref l, _)| labels.contains(l));
+            }
+        }
+    }
+
+    //the immediate dominator of every block, the entry being its own. Uses the
+    //iterative algorithm of Cooper, Harvey and Kennedy; all blocks must be reachable
+    pub fn dominators(&self) -> Vec<usize>{
+        let rpo = self.reverse_postorder();
+        let mut order = vec![0; self.blocks.len()];
+        for (i, &b) in rpo.iter().enumerate(){
+            order[b] = i;
+        }
+        let preds = self.preds();
+        let mut idom : Vec<Option<usize>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed{
+            changed = false;
+            for &b in rpo.iter().skip(1){
+                let mut new_idom : Option<usize> = None;
+                for &p in preds[b].iter(){
+                    if idom[p].is_none(){
+                        continue
+                    }
+                    new_idom = Some(match new_idom{
+                        None => p,
+                        Some(mut other) => {
+                            let mut p = p;
+                            while p != other{
+                                while order[p] > order[other]{
+                                    p = idom[p].unwrap();
+                                }
+                                while order[other] > order[p]{
+                                    other = idom[other].unwrap();
+                                }
+                            }
+                            p
+                        }
+                    });
+                }
+                if new_idom != idom[b]{
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom.into_iter().map(|d| d.unwrap()).collect()
+    }
+
+    //whether block `a` dominates block `b`
+    pub fn dominates(idom : &[usize], a : usize, mut b : usize) -> bool{
+        loop{
+            if a == b{
+                return true
+            }
+            if b == 0{
+                return false
+            }
+            b = idom[b];
+        }
+    }
+
+    pub fn dominator_tree(idom : &[usize]) -> Vec<Vec<usize>>{
+        let mut children = vec![Vec::new(); idom.len()];
+        for (b, &d) in idom.iter().enumerate().skip(1){
+            children[d].push(b);
+        }
+        children
+    }
+
+    pub fn dominance_frontiers(&self, idom : &[usize]) -> Vec<HashSet<usize>>{
+        let preds = self.preds();
+        let mut df = vec![HashSet::new(); self.blocks.len()];
+        for (b, ps) in preds.iter().enumerate(){
+            if ps.len() < 2{
+                continue
+            }
+            for &p in ps.iter(){
+                let mut runner = p;
+                while runner != idom[b]{
+                    df[runner].insert(b);
+                    runner = idom[runner];
+                }
+            }
+        }
+        df
+    }
+}
+
+struct Renamer<'a>{
+    func : &'a mut Func,
+    gen : &'a mut Gen,
+    children : Vec<Vec<usize>>,
+    succs : Vec<Vec<usize>>,
+    //the temp each PHI was placed for
+    phi_vars : Vec<Vec<Temp>>,
+    stacks : HashMap<usize, Vec<Temp>>
+}
+
+impl<'a> Renamer<'a>{
+    fn current(&self, t : Temp) -> Exp{
+        match self.stacks.get(&t.id).and_then(|s| s.last()){
+            Some(&v) => Exp::Temp(v),
+            None => Exp::zero(t.ty)
+        }
+    }
+
+    fn define(&mut self, t : Temp, pushed : &mut Vec<usize>) -> Temp{
+        let v = self.gen.temp(t.ty);
+        self.stacks.entry(t.id).or_insert(Vec::new()).push(v);
+        pushed.push(t.id);
+        v
+    }
+
+    fn rename(&mut self, b : usize){
+        let mut pushed = Vec::new();
+        for i in 0..self.func.blocks[b].phis.len(){
+            let var = self.phi_vars[b][i];
+            let v = self.define(var, &mut pushed);
+            self.func.blocks[b].phis[i].dst = v;
+        }
+        for i in 0..self.func.blocks[b].stms.len(){
+            let mut s = self.func.blocks[b].stms[i].clone();
+            each_use(&mut s, &mut |e| if let Exp::Temp(t) = *e {*e = self.current(t)});
+            if let Stm::Move(Exp::Temp(t), src) = s{
+                s = Stm::Move(Exp::Temp(self.define(t, &mut pushed)), src);
+            }
+            self.func.blocks[b].stms[i] = s;
+        }
+        let label = self.func.blocks[b].label.clone();
+        for s in self.succs[b].clone(){
+            for i in 0..self.func.blocks[s].phis.len(){
+                let arg = self.current(self.phi_vars[s][i]);
+                self.func.blocks[s].phis[i].args.push((label.clone(), arg));
+            }
+        }
+        for c in self.children[b].clone(){
+            self.rename(c);
+        }
+        for id in pushed{
+            self.stacks.get_mut(&id).unwrap().pop();
+        }
+    }
+}
+
+//builds the SSA form of a procedure, placing PHIs with the dominance frontier
+//algorithm of Cytron et al. Temps that are only live inside one block get no PHIs
+pub fn to_ssa(p : Proc, gen : &mut Gen) -> Func{
+    let stms = canon::linearize(p.body, gen);
+    let mut blocks : Vec<Block> = canon::basic_blocks(stms, p.ret, gen).into_iter().map(|b| {
+        let mut stms = b.stms;
+        //an edge appears once even when both branches take it
+        let same = match *stms.last().unwrap(){
+            Stm::CJump(_, _, _, ref t, ref f) if t == f => Some(t.clone()),
+            _ => None
+        };
+        if let Some(l) = same{
+            *stms.last_mut().unwrap() = Stm::Jump(l);
+        }
+        Block { label : b.label, phis : Vec::new(), stms : stms }
+    }).collect();
+    let entry_label = blocks[0].label.clone();
+    if blocks.iter().any(|b| b.succs().contains(&entry_label)){
+        blocks.insert(0, Block { label : gen.label(), phis : Vec::new(), stms : vec![Stm::Jump(entry_label)] });
+    }
+    let mut func = Func { name : p.name, params : p.params, ret : p.ret, blocks : blocks };
+    func.cleanup();
+
+    //temps read in a block before the block writes them, and where they are written
+    let mut globals = HashSet::new();
+    let mut def_blocks : HashMap<usize, (Temp, Vec<usize>)> = HashMap::new();
+    for (i, b) in func.blocks.iter().enumerate(){
+        let mut defined = HashSet::new();
+        for s in b.stms.iter(){
+            let mut s = s.clone();
+            each_use(&mut s, &mut |e| if let Exp::Temp(t) = *e{
+                if !defined.contains(&t.id){
+                    globals.insert(t.id);
+                }
+            });
+            if let Some(t) = def(&s){
+                defined.insert(t.id);
+                def_blocks.entry(t.id).or_insert((t, Vec::new())).1.push(i);
+            }
+        }
+    }
+    for &t in func.params.iter(){
+        def_blocks.entry(t.id).or_insert((t, Vec::new())).1.push(0);
+    }
+
+    let idom = func.dominators();
+    let df = func.dominance_frontiers(&idom);
+    let mut phi_vars = vec![Vec::new(); func.blocks.len()];
+    let mut ids : Vec<&usize> = def_blocks.keys().filter(|id| globals.contains(id)).collect();
+    ids.sort();
+    for id in ids{
+        let (temp, ref blocks) = def_blocks[id];
+        let mut has_phi = HashSet::new();
+        let mut work = blocks.clone();
+        while let Some(b) = work.pop(){
+            for &f in df[b].iter(){
+                if has_phi.insert(f){
+                    func.blocks[f].phis.push(Phi { dst : temp, args : Vec::new() });
+                    phi_vars[f].push(temp);
+                    work.push(f);
+                }
+            }
+        }
+    }
+
+    let children = Func::dominator_tree(&idom);
+    let succs = func.succs();
+    let mut stacks = HashMap::new();
+    //parameters keep their temps, which are their first definition
+    for &t in func.params.iter(){
+        stacks.insert(t.id, vec![t]);
+    }
+    Renamer { func : &mut func, gen : gen, children : children, succs : succs, phi_vars : phi_vars, stacks : stacks }.rename(0);
+    func
+}
+
+//turns the PHIs back into copies at the end of the predecessors. Critical
+//edges get a block of their own first, so the copies only run on their edge
+pub fn from_ssa(mut func : Func, gen : &mut Gen) -> Proc{
+    let mut split = Vec::new();
+    for b in func.blocks.iter_mut(){
+        let succs = b.succs();
+        if succs.len() < 2{
+            continue
+        }
+        for s in succs{
+            let edge = gen.label();
+            retarget(b.terminator_mut(), &s, &edge);
+            split.push((b.label.clone(), s.clone(), Block { label : edge, phis : Vec::new(), stms : vec![Stm::Jump(s)] }));
+        }
+    }
+    for (from, to, block) in split{
+        let edge = block.label.clone();
+        let has_phis = {
+            let target = func.blocks.iter_mut().find(|b| b.label == to).unwrap();
+            for phi in target.phis.iter_mut(){
+                for arg in phi.args.iter_mut(){
+                    if arg.0 == from{
+                        arg.0 = edge.clone();
+                    }
+                }
+            }
+            !target.phis.is_empty()
+        };
+        if has_phis{
+            func.blocks.push(block);
+        }
+        else{
+            //no copies to place, so the edge can go straight to its target again
+            let b = func.blocks.iter_mut().find(|b| b.label == from).unwrap();
+            retarget(b.terminator_mut(), &edge, &to);
+        }
+    }
+
+    let index = func.index();
+    let mut copies : Vec<Vec<(Temp, Exp)>> = vec![Vec::new(); func.blocks.len()];
+    for b in func.blocks.iter_mut(){
+        for phi in b.phis.drain(..){
+            for (l, arg) in phi.args{
+                copies[index[&l]].push((phi.dst, arg));
+            }
+        }
+    }
+    let mut body = Vec::new();
+    for (b, copies) in func.blocks.into_iter().zip(copies){
+        body.push(Stm::Label(b.label));
+        let mut stms = b.stms;
+        let terminator = stms.pop().unwrap();
+        body.extend(stms);
+        //the copies of one edge happen at once, so every argument is read
+        //before any PHI temp is written
+        let mut moves = Vec::new();
+        for &(dst, ref arg) in copies.iter(){
+            if *arg != Exp::Temp(dst){
+                let tmp = gen.temp(dst.ty);
+                body.push(Stm::Move(Exp::Temp(tmp), arg.clone()));
+                moves.push(Stm::Move(Exp::Temp(dst), Exp::Temp(tmp)));
+            }
+        }
+        body.extend(moves);
+        body.push(terminator);
+    }
+    Proc { name : func.name, params : func.params, ret : func.ret, body : body }
+}
+
+impl fmt::Display for Func{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        try!(write!(f, "function {}(", self.name));
+        for (i, p) in self.params.iter().enumerate(){
+            if i > 0{
+                try!(f.write_str(", "));
+            }
+            try!(write!(f, "{}:{}", p, p.ty));
+        }
+        try!(writeln!(f, ") : {}", self.ret));
+        for b in self.blocks.iter(){
+            try!(writeln!(f, "{}:", b.label));
+            for phi in b.phis.iter(){
+                try!(write!(f, "    MOVE(TEMP {}, PHI(", phi.dst));
+                for (i, &(ref l, ref arg)) in phi.args.iter().enumerate(){
+                    if i > 0{
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}: {}", l, arg));
+                }
+                try!(writeln!(f, "))"));
+            }
+            for s in b.stms.iter(){
+                try!(writeln!(f, "    {}", s));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree::*;
+    use translate::translate;
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+
+    fn main_ssa(src : &str) -> (Func, Gen){
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        let main = prog.procs.pop().unwrap();
+        let f = to_ssa(main, &mut prog.gen);
+        (f, prog.gen)
+    }
+
+    //every temp is written by exactly one MOVE or PHI
+    fn assert_single_assignment(f : &Func){
+        let mut seen = HashSet::new();
+        for b in f.blocks.iter(){
+            for phi in b.phis.iter(){
+                assert!(seen.insert(phi.dst.id), "t{} written twice in\n{}", phi.dst.id, f);
+            }
+            for s in b.stms.iter(){
+                if let Some(t) = def(s){
+                    assert!(seen.insert(t.id), "t{} written twice in\n{}", t.id, f);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_gets_phi(){
+        let (f, _) = main_ssa("let var a : int := 0 in for i := 1 to 3 do (a := a + i; print(a)) end");
+        assert_single_assignment(&f);
+        //the loop header merges the counter and a
+        assert_eq!(f.blocks[1].phis.len(), 2);
+        assert_eq!(f.blocks[1].phis[0].args.len(), 2);
+        assert!(f.blocks[0].phis.is_empty());
+    }
+
+    #[test]
+    fn test_dominators(){
+        let (f, _) = main_ssa("let var a : int := 0 in if a then print(1) else print(2) end");
+        let idom = f.dominators();
+        for b in 1..f.blocks.len(){
+            assert!(Func::dominates(&idom, 0, b));
+        }
+        let children = Func::dominator_tree(&idom);
+        assert!(children[0].len() >= 2);
+    }
+
+    #[test]
+    fn test_from_ssa_removes_phis(){
+        let (f, mut gen) = main_ssa("let var a : int := 0 in while 10 > a do (a := a + 1; print(a)) end");
+        let p = from_ssa(f, &mut gen);
+        assert!(!p.to_string().contains("PHI"));
+        let labels = p.body.iter().filter(|s| if let Stm::Label(_) = **s {true} else {false}).count();
+        assert!(labels >= 3);
+    }
+}
@@ -0,0 +1,972 @@
+//! Translation of the type-checked AST into the tree IR.
+
+use std::cmp;
+use std::collections::HashSet;
+use syntax::ast::{Expr, LValue, Decl, TType, Escape, OptionalExpr, OptionalTypeExprTupleList};
+use syntax::escape::{find_escapes};
+use syntax::ptr::{B};
+use tree::*;
+
+//an AST expression on its way to the IR, as in Appel's Translate module:
+//a value, a statement, or a condition still waiting for its jump targets
+enum Tr{
+    Ex(Exp),
+    Nx(Stm),
+    Cx(RelOp, Exp, Exp)
+}
+
+//where a variable lives. The ones nested functions use live in a slot of
+//the frame of the function that declares them
+#[derive(Clone, Copy)]
+enum Access{
+    InTemp(Temp),
+    InFrame(i32)
+}
+
+enum Entry{
+    //`level` is the nesting depth of the function the variable belongs to
+    Var{access : Access, ty : TType, level : usize},
+    //`level` is the nesting depth of the function that declares it
+    Fun{label : Label, ret : TType, level : usize},
+    Type(TType)
+}
+
+//the frame of a function: a record on the heap holding its escaping
+//variables. Slot 0 is the static link, the frame of the enclosing function,
+//which every function but main receives as a hidden first parameter
+struct Frame{
+    //created the first time the function needs its frame
+    fp : Option<Temp>,
+    link : Option<Temp>,
+    slots : i32
+}
+
+impl Frame{
+    fn new(link : Option<Temp>) -> Self{
+        Frame { fp : None, link : link, slots : 1 }
+    }
+}
+
+type TrResult = Result<(Tr, TType), String>;
+
+struct Translator{
+    //scopes are separated by None markers
+    env : Vec<(String, Option<Entry>)>,
+    gen : Gen,
+    level : usize,
+    //frames of the functions being translated, indexed by level
+    frames : Vec<Frame>,
+    //exit labels of the enclosing loops, innermost last
+    loop_exits : Vec<Label>,
+    fn_names : HashSet<String>,
+    procs : Vec<Proc>,
+    //the frame each finished proc ended up with
+    proc_frames : Vec<Frame>,
+    strings : Vec<(Label, Vec<u8>)>
+}
+
+fn seq(mut stms : Vec<Stm>) -> Stm{
+    if stms.len() == 1{
+        stms.pop().unwrap()
+    }
+    else{
+        Stm::Seq(stms)
+    }
+}
+
+fn binop(op : BinOp, lhs : Exp, rhs : Exp) -> Exp{
+    Exp::BinOp(op, Box::new(lhs), Box::new(rhs))
+}
+
+//address of slot `index` of an array or record
+fn slot(base : Exp, index : Exp) -> Exp{
+    let offset = match index{
+        Exp::Const(i) => Exp::Const(i * WORD_SIZE),
+        i => binop(BinOp::Mul, i, Exp::Const(WORD_SIZE))
+    };
+    binop(BinOp::Plus, base, offset)
+}
+
+fn call(name : &str, args : Vec<Exp>) -> Exp{
+    let (_, ret) = runtime_signature(name).unwrap();
+    Exp::Call(Label(String::from(name)), args, ret)
+}
+
+impl Translator{
+    fn new() -> Self{
+        Translator {
+            env : Vec::new(),
+            gen : Gen::default(),
+            level : 0,
+            frames : vec![Frame::new(None)],
+            loop_exits : Vec::new(),
+            fn_names : HashSet::new(),
+            procs : Vec::new(),
+            proc_frames : Vec::new(),
+            strings : Vec::new()
+        }
+    }
+
+    fn temp(&mut self, ty : Ty) -> Temp{
+        self.gen.temp(ty)
+    }
+
+    fn label(&mut self) -> Label{
+        self.gen.label()
+    }
+
+    //function names are kept unless they clash with main, the runtime or
+    //another function of the same name in a different scope
+    fn fn_label(&mut self, name : &str) -> Label{
+        let mut label = String::from(name);
+        let mut i = 1;
+        while label == "main" || runtime_signature(&label).is_some() || self.fn_names.contains(&label){
+            label = format!("{}_{}", name, i);
+            i += 1;
+        }
+        self.fn_names.insert(label.clone());
+        Label(label)
+    }
+
+    fn string(&mut self, s : &[u8]) -> Label{
+        if let Some(&(ref l, _)) = self.strings.iter().find(|&&(_, ref v)| &**v == s){
+            return l.clone()
+        }
+        let l = self.label();
+        self.strings.push((l.clone(), s.to_vec()));
+        l
+    }
+
+    fn lookup(&self, id : &str) -> Option<&Entry>{
+        for &(ref name, ref entry) in self.env.iter().rev(){
+            if name == id && entry.is_some(){
+                return entry.as_ref()
+            }
+        }
+        None
+    }
+
+    fn begin_scope(&mut self){
+        self.env.push((String::from("<marker>"), None));
+    }
+
+    fn end_scope(&mut self){
+        while self.env.pop().unwrap().1.is_some(){}
+    }
+
+    //follows type aliases
+    fn resolve(&self, ty : &TType) -> TType{
+        match *ty{
+            TType::TCustom(ref name) => match self.lookup(name){
+                Some(&Entry::Type(ref t)) if *t != *ty => self.resolve(t),
+                _ => ty.clone()
+            },
+            _ => ty.clone()
+        }
+    }
+
+    fn ir_ty(&self, ty : &TType) -> Ty{
+        match self.resolve(ty){
+            TType::TInt32 => Ty::I32,
+            TType::TVoid => Ty::Void,
+            _ => Ty::Ptr
+        }
+    }
+
+    fn un_ex(&mut self, tr : Tr) -> Exp{
+        match tr{
+            Tr::Ex(e) => e,
+            Tr::Nx(s) => Exp::ESeq(Box::new(s), Box::new(Exp::Const(0))),
+            Tr::Cx(op, lhs, rhs) => {
+                let r = self.temp(Ty::I32);
+                let (t, f) = (self.label(), self.label());
+                Exp::ESeq(Box::new(Stm::Seq(vec![Stm::Move(Exp::Temp(r), Exp::Const(1)),
+                                                 Stm::CJump(op, lhs, rhs, t.clone(), f.clone()),
+                                                 Stm::Label(f),
+                                                 Stm::Move(Exp::Temp(r), Exp::Const(0)),
+                                                 Stm::Label(t)])),
+                          Box::new(Exp::Temp(r)))
+            }
+        }
+    }
+
+    fn un_nx(&mut self, tr : Tr) -> Stm{
+        match tr{
+            Tr::Ex(e) => Stm::Exp(e),
+            Tr::Nx(s) => s,
+            Tr::Cx(_, lhs, rhs) => Stm::Seq(vec![Stm::Exp(lhs), Stm::Exp(rhs)])
+        }
+    }
+
+    //a conditional jump to `t` when the expression is true and to `f` otherwise
+    fn un_cx(&mut self, tr : Tr, t : Label, f : Label) -> Stm{
+        match tr{
+            Tr::Cx(op, lhs, rhs) => Stm::CJump(op, lhs, rhs, t, f),
+            tr => {
+                let e = self.un_ex(tr);
+                Stm::CJump(RelOp::Ne, e, Exp::Const(0), t, f)
+            }
+        }
+    }
+
+    //the frame of the function at `level`, reached from the current one by
+    //following static links
+    fn frame_ptr(&mut self, level : usize) -> Exp{
+        if level == self.level{
+            let fp = match self.frames[level].fp{
+                Some(fp) => fp,
+                None => {
+                    let fp = self.temp(Ty::Ptr);
+                    self.frames[level].fp = Some(fp);
+                    fp
+                }
+            };
+            return Exp::Temp(fp)
+        }
+        let mut fp = Exp::Temp(self.frames[self.level].link.unwrap());
+        for _ in level + 1..self.level{
+            fp = Exp::Mem(Ty::Ptr, Box::new(slot(fp, Exp::Const(0))));
+        }
+        fp
+    }
+
+    //a place for a new variable of the current function
+    fn local(&mut self, ty : &TType, escape : &Escape) -> Access{
+        if escape.escapes(){
+            let frame = &mut self.frames[self.level];
+            frame.slots += 1;
+            Access::InFrame(frame.slots - 1)
+        }
+        else{
+            Access::InTemp(self.temp(self.ir_ty(ty)))
+        }
+    }
+
+    fn access(&mut self, access : Access, ty : &TType, level : usize) -> Exp{
+        match access{
+            Access::InTemp(temp) => Exp::Temp(temp),
+            Access::InFrame(i) => {
+                let fp = self.frame_ptr(level);
+                Exp::Mem(self.ir_ty(ty), Box::new(slot(fp, Exp::Const(i))))
+            }
+        }
+    }
+
+    fn var(&mut self, id : &str) -> Result<(Exp, TType), String>{
+        let (access, ty, level) = match self.lookup(id){
+            Some(&Entry::Var{access, ref ty, level}) => (access, ty.clone(), level),
+            Some(_) => return Err(format!("Invalid reference to variable '{}'. Different binding found.", id)),
+            None => return Err(format!("Invalid reference to variable '{}'", id))
+        };
+        if let Access::InTemp(_) = access{
+            if level != self.level{
+                return Err(format!("'{}' is used by a nested function but wasn't found to escape", id))
+            }
+        }
+        Ok((self.access(access, &ty, level), ty))
+    }
+    //the temp or memory an lvalue names
+    fn location(&mut self, lvalue : &LValue) -> Result<(Exp, TType), String>{
+        match *lvalue{
+            LValue::SimpleVar(ref id) => self.var(id),
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                let (base, ty) = try!(self.location(arr));
+                let elem_ty = match self.resolve(&ty){
+                    TType::TArray(ref t) => (**t).clone(),
+                    _ => return Err(format!("{:?} is not an array", arr))
+                };
+                let (idx_tr, _) = try!(self.exp(idx));
+                let idx = self.un_ex(idx_tr);
+                Ok((Exp::Mem(self.ir_ty(&elem_ty), Box::new(slot(base, idx))), elem_ty))
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                let (base, ty) = try!(self.location(rec));
+                let fields = match self.resolve(&ty){
+                    TType::TRecord(fields) => fields,
+                    _ => return Err(format!("{:?} is not a record", rec))
+                };
+                match fields.iter().position(|f| f.0 == *field){
+                    Some(i) => {
+                        let ty = fields[i].1.clone();
+                        Ok((Exp::Mem(self.ir_ty(&ty), Box::new(slot(base, Exp::Const(i as i32)))), ty))
+                    },
+                    None => Err(format!("{:?} has no field '{}'", rec, field))
+                }
+            }
+        }
+    }
+
+    fn args(&mut self, args : &OptionalTypeExprTupleList) -> Result<Vec<(Exp, TType)>, String>{
+        let mut v = Vec::new();
+        if let Some(ref list) = *args{
+            for &(_, ref e) in list{
+                let (tr, ty) = try!(self.exp(e));
+                v.push((self.un_ex(tr), ty));
+            }
+        }
+        Ok(v)
+    }
+
+    fn call(&mut self, name : &str, args : &OptionalTypeExprTupleList) -> TrResult{
+        let mut args = try!(self.args(args));
+        let expected = match name{
+            "print" | "ord" | "chr" | "size" | "not" | "exit" => 1,
+            "flush" | "getchar" => 0,
+            "substring" => 3,
+            "concat" => 2,
+            _ => return self.user_call(name, args)
+        };
+        if args.len() != expected{
+            return Err(format!("{}() expects {} argument(s) but {} were given", name, expected, args.len()))
+        }
+        let exps : Vec<Exp> = args.iter().map(|a| a.0.clone()).collect();
+        let (e, ty) = match name{
+            "print" => {
+                let (arg, ty) = args.pop().unwrap();
+                let f = if self.resolve(&ty) == TType::TInt32 {"tiger_print_int"} else {"tiger_print"};
+                (call(f, vec![arg]), TType::TVoid)
+            },
+            "not" => {
+                let arg = args.pop().unwrap().0;
+                return Ok((Tr::Cx(RelOp::Eq, arg, Exp::Const(0)), TType::TInt32))
+            },
+            "flush" | "exit" => (call(&format!("tiger_{}", name), exps), TType::TVoid),
+            "ord" | "size" => (call(&format!("tiger_{}", name), exps), TType::TInt32),
+            _ => (call(&format!("tiger_{}", name), exps), TType::TString)
+        };
+        if ty == TType::TVoid{
+            Ok((Tr::Nx(Stm::Exp(e)), ty))
+        }
+        else{
+            Ok((Tr::Ex(e), ty))
+        }
+    }
+
+    fn user_call(&mut self, name : &str, args : Vec<(Exp, TType)>) -> TrResult{
+        let (label, ret, level) = match self.lookup(name){
+            Some(&Entry::Fun{ref label, ref ret, level}) => (label.clone(), ret.clone(), level),
+            Some(_) => return Err(format!("Invalid reference to function '{}'. Different binding found.", name)),
+            None => return Err(format!("Call to '{}' not found", name))
+        };
+        let ret_ty = self.ir_ty(&ret);
+        let mut exps = vec![self.frame_ptr(level)];
+        exps.extend(args.into_iter().map(|a| a.0));
+        let e = Exp::Call(label, exps, ret_ty);
+        if ret_ty == Ty::Void{
+            Ok((Tr::Nx(Stm::Exp(e)), ret))
+        }
+        else{
+            Ok((Tr::Ex(e), ret))
+        }
+    }
+
+    fn compare(&mut self, op : RelOp, lhs : &Expr, rhs : &Expr) -> TrResult{
+        let (l, lty) = try!(self.exp(lhs));
+        let (r, _) = try!(self.exp(rhs));
+        let (l, r) = (self.un_ex(l), self.un_ex(r));
+        if self.resolve(&lty) == TType::TString{
+            return Ok((Tr::Cx(op, call("tiger_strcmp", vec![l, r]), Exp::Const(0)), TType::TInt32))
+        }
+        Ok((Tr::Cx(op, l, r), TType::TInt32))
+    }
+
+    fn arith(&mut self, op : BinOp, lhs : &Expr, rhs : &Expr) -> TrResult{
+        let (l, _) = try!(self.exp(lhs));
+        let (r, _) = try!(self.exp(rhs));
+        let (l, r) = (self.un_ex(l), self.un_ex(r));
+        Ok((Tr::Ex(binop(op, l, r)), TType::TInt32))
+    }
+
+    //a / b or, for `remainder`, a mod b as a - a / b * b. Dividing by 0 stops
+    //the program with the line of the division. INT_MIN / -1 doesn't fit in an
+    //int and traps like a division by 0, so dividing by -1 is a negation
+    //instead, which wraps around like the other operators, and x mod -1 is 0
+    fn division(&mut self, remainder : bool, lhs : &B<Expr>, rhs : &B<Expr>) -> TrResult{
+        //the division starts where its left operand does
+        let line = lhs.span.line as i32;
+        let (l, _) = try!(self.exp(lhs));
+        let (r, _) = try!(self.exp(rhs));
+        let (l, r) = (self.un_ex(l), self.un_ex(r));
+        let (a, b, q) = (self.temp(Ty::I32), self.temp(Ty::I32), self.temp(Ty::I32));
+        let (zero, nonzero, negate, divide, done) = (self.label(), self.label(), self.label(), self.label(), self.label());
+        let quotient = binop(BinOp::Div, Exp::Temp(a), Exp::Temp(b));
+        let (by_minus_one, value) = if remainder{
+            (Exp::Const(0), binop(BinOp::Minus, Exp::Temp(a), binop(BinOp::Mul, quotient, Exp::Temp(b))))
+        }
+        else{
+            (binop(BinOp::Minus, Exp::Const(0), Exp::Temp(a)), quotient)
+        };
+        let stms = vec![Stm::Move(Exp::Temp(a), l),
+                        Stm::Move(Exp::Temp(b), r),
+                        Stm::CJump(RelOp::Eq, Exp::Temp(b), Exp::Const(0), zero.clone(), nonzero.clone()),
+                        Stm::Label(zero),
+                        Stm::Exp(call("tiger_div_by_zero", vec![Exp::Const(line)])),
+                        Stm::Label(nonzero),
+                        Stm::CJump(RelOp::Eq, Exp::Temp(b), Exp::Const(-1), negate.clone(), divide.clone()),
+                        Stm::Label(negate),
+                        Stm::Move(Exp::Temp(q), by_minus_one),
+                        Stm::Jump(done.clone()),
+                        Stm::Label(divide),
+                        Stm::Move(Exp::Temp(q), value),
+                        Stm::Label(done)];
+        Ok((Tr::Ex(Exp::ESeq(Box::new(Stm::Seq(stms)), Box::new(Exp::Temp(q)))), TType::TInt32))
+    }
+
+    //statements followed by the value of the last translated expression
+    fn sequence(&mut self, mut stms : Vec<Stm>, last : Option<(Tr, TType)>) -> (Tr, TType){
+        match last{
+            Some((Tr::Nx(s), ty)) => {
+                stms.push(s);
+                (Tr::Nx(seq(stms)), ty)
+            },
+            Some((tr, ty)) => {
+                if stms.is_empty(){
+                    return (tr, ty)
+                }
+                let e = self.un_ex(tr);
+                (Tr::Ex(Exp::ESeq(Box::new(seq(stms)), Box::new(e))), ty)
+            },
+            None => (Tr::Nx(seq(stms)), TType::TVoid)
+        }
+    }
+
+    fn array(&mut self, elem_ty : &TType, dim : &Expr, init : &Expr) -> TrResult{
+        let (dim_tr, _) = try!(self.exp(dim));
+        let (init_tr, _) = try!(self.exp(init));
+        let (dim, init) = (self.un_ex(dim_tr), self.un_ex(init_tr));
+        let ty = self.ir_ty(elem_ty);
+        let (n, a, v, i) = (self.temp(Ty::I32), self.temp(Ty::Ptr), self.temp(ty), self.temp(Ty::I32));
+        let (test, body, done) = (self.label(), self.label(), self.label());
+        let stms = vec![Stm::Move(Exp::Temp(n), dim),
+                        Stm::Move(Exp::Temp(a), call("tiger_alloc", vec![binop(BinOp::Mul, Exp::Temp(n), Exp::Const(WORD_SIZE))])),
+                        Stm::Move(Exp::Temp(v), init),
+                        Stm::Move(Exp::Temp(i), Exp::Const(0)),
+                        Stm::Label(test.clone()),
+                        Stm::CJump(RelOp::Lt, Exp::Temp(i), Exp::Temp(n), body.clone(), done.clone()),
+                        Stm::Label(body),
+                        Stm::Move(Exp::Mem(ty, Box::new(slot(Exp::Temp(a), Exp::Temp(i)))), Exp::Temp(v)),
+                        Stm::Move(Exp::Temp(i), binop(BinOp::Plus, Exp::Temp(i), Exp::Const(1))),
+                        Stm::Jump(test),
+                        Stm::Label(done)];
+        Ok((Tr::Ex(Exp::ESeq(Box::new(Stm::Seq(stms)), Box::new(Exp::Temp(a)))),
+            TType::TArray(B(elem_ty.clone()))))
+    }
+
+    fn exp(&mut self, expr : &Expr) -> TrResult{
+        match *expr{
+            Expr::NumExpr(n) => Ok((Tr::Ex(Exp::Const(n)), TType::TInt32)),
+            Expr::StringExpr(ref s) => {
+                let l = self.string(s);
+                Ok((Tr::Ex(Exp::Name(l)), TType::TString))
+            },
+            Expr::NilExpr => Ok((Tr::Ex(Exp::Nil), TType::TNil)),
+            Expr::NoOpExpr => Ok((Tr::Nx(Stm::Seq(Vec::new())), TType::TVoid)),
+            Expr::IdExpr(ref id) => {
+                let (e, ty) = try!(self.var(id));
+                Ok((Tr::Ex(e), ty))
+            },
+            Expr::LValueExpr(ref lvalue) => {
+                let (e, ty) = try!(self.location(lvalue));
+                Ok((Tr::Ex(e), ty))
+            },
+            Expr::AssignExpr(ref lhs, ref rhs) => {
+                let (dst, _) = try!(self.location(lhs));
+                let (src, _) = try!(self.exp(rhs));
+                let src = self.un_ex(src);
+                Ok((Tr::Nx(Stm::Move(dst, src)), TType::TVoid))
+            },
+            Expr::AddExpr(ref l, ref r) => self.arith(BinOp::Plus, l, r),
+            Expr::SubExpr(ref l, ref r) => self.arith(BinOp::Minus, l, r),
+            Expr::MulExpr(ref l, ref r) => self.arith(BinOp::Mul, l, r),
+            Expr::DivExpr(ref l, ref r) => self.division(false, l, r),
+            Expr::ModExpr(ref l, ref r) => self.division(true, l, r),
+            Expr::EqualsExpr(ref l, ref r) => self.compare(RelOp::Eq, l, r),
+            Expr::NotEqualsExpr(ref l, ref r) => self.compare(RelOp::Ne, l, r),
+            Expr::LessThanExpr(ref l, ref r) => self.compare(RelOp::Lt, l, r),
+            Expr::LessThanEqualsExpr(ref l, ref r) => self.compare(RelOp::Le, l, r),
+            Expr::GreaterThanExpr(ref l, ref r) => self.compare(RelOp::Gt, l, r),
+            Expr::GreaterThanEqualsExpr(ref l, ref r) => self.compare(RelOp::Ge, l, r),
+            Expr::IfThenExpr(ref cond, ref then_expr) => {
+                let (c, _) = try!(self.exp(cond));
+                let (t, _) = try!(self.exp(then_expr));
+                let (then_l, join) = (self.label(), self.label());
+                let stms = vec![self.un_cx(c, then_l.clone(), join.clone()),
+                                Stm::Label(then_l),
+                                self.un_nx(t),
+                                Stm::Label(join)];
+                Ok((Tr::Nx(Stm::Seq(stms)), TType::TVoid))
+            },
+            Expr::IfThenElseExpr(ref cond, ref then_expr, ref else_expr) => {
+                let (c, _) = try!(self.exp(cond));
+                let (t, ty) = try!(self.exp(then_expr));
+                let (e, _) = try!(self.exp(else_expr));
+                let (then_l, else_l, join) = (self.label(), self.label(), self.label());
+                let cjump = self.un_cx(c, then_l.clone(), else_l.clone());
+                let ir_ty = self.ir_ty(&ty);
+                if ir_ty == Ty::Void{
+                    let stms = vec![cjump,
+                                    Stm::Label(then_l), self.un_nx(t), Stm::Jump(join.clone()),
+                                    Stm::Label(else_l), self.un_nx(e),
+                                    Stm::Label(join)];
+                    return Ok((Tr::Nx(Stm::Seq(stms)), ty))
+                }
+                let r = self.temp(ir_ty);
+                let (t, e) = (self.un_ex(t), self.un_ex(e));
+                let stms = vec![cjump,
+                                Stm::Label(then_l), Stm::Move(Exp::Temp(r), t), Stm::Jump(join.clone()),
+                                Stm::Label(else_l), Stm::Move(Exp::Temp(r), e),
+                                Stm::Label(join)];
+                Ok((Tr::Ex(Exp::ESeq(Box::new(Stm::Seq(stms)), Box::new(Exp::Temp(r)))), ty))
+            },
+            Expr::WhileExpr(ref cond, ref body) => {
+                let (test, body_l, done) = (self.label(), self.label(), self.label());
+                let (c, _) = try!(self.exp(cond));
+                self.loop_exits.push(done.clone());
+                let b = self.exp(body);
+                self.loop_exits.pop();
+                let (b, _) = try!(b);
+                let stms = vec![Stm::Label(test.clone()),
+                                self.un_cx(c, body_l.clone(), done.clone()),
+                                Stm::Label(body_l),
+                                self.un_nx(b),
+                                Stm::Jump(test),
+                                Stm::Label(done)];
+                Ok((Tr::Nx(Stm::Seq(stms)), TType::TVoid))
+            },
+            Expr::ForExpr(ref id, ref lo, ref hi, ref body, ref escape) => {
+                //the bounds are inclusive and evaluated once. The exit test happens
+                //before the increment so that hi = maxint doesn't overflow
+                let (lo_tr, _) = try!(self.exp(lo));
+                let (hi_tr, _) = try!(self.exp(hi));
+                let (lo, hi) = (self.un_ex(lo_tr), self.un_ex(hi_tr));
+                let (i, limit) = (self.temp(Ty::I32), self.temp(Ty::I32));
+                let (body_l, next, done) = (self.label(), self.label(), self.label());
+                //an escaping index is copied to the frame on every iteration;
+                //the loop itself keeps counting in the temp
+                let access = if escape.escapes() {self.local(&TType::TInt32, escape)} else {Access::InTemp(i)};
+                let level = self.level;
+                let index = self.access(access, &TType::TInt32, level);
+                self.begin_scope();
+                self.env.push((id.clone(), Some(Entry::Var { access : access, ty : TType::TInt32, level : level })));
+                self.loop_exits.push(done.clone());
+                let b = self.exp(body);
+                self.loop_exits.pop();
+                self.end_scope();
+                let (b, _) = try!(b);
+                let mut stms = vec![Stm::Move(Exp::Temp(i), lo),
+                                    Stm::Move(Exp::Temp(limit), hi),
+                                    Stm::CJump(RelOp::Gt, Exp::Temp(i), Exp::Temp(limit), done.clone(), body_l.clone()),
+                                    Stm::Label(body_l.clone())];
+                if index != Exp::Temp(i){
+                    stms.push(Stm::Move(index, Exp::Temp(i)));
+                }
+                stms.extend(vec![self.un_nx(b),
+                                 Stm::CJump(RelOp::Ge, Exp::Temp(i), Exp::Temp(limit), done.clone(), next.clone()),
+                                 Stm::Label(next),
+                                 Stm::Move(Exp::Temp(i), binop(BinOp::Plus, Exp::Temp(i), Exp::Const(1))),
+                                 Stm::Jump(body_l),
+                                 Stm::Label(done)]);
+                Ok((Tr::Nx(Stm::Seq(stms)), TType::TVoid))
+            },
+            Expr::BreakExpr => match self.loop_exits.last(){
+                Some(l) => Ok((Tr::Nx(Stm::Jump(l.clone())), TType::TVoid)),
+                None => Err(String::from("'break' outside of a loop"))
+            },
+            Expr::CallExpr(ref name, ref args) => self.call(name, args),
+            Expr::SeqExpr(ref exprs) => {
+                let exprs = match *exprs{
+                    Some(ref e) if !e.is_empty() => e,
+                    _ => return Ok(self.sequence(Vec::new(), None))
+                };
+                let mut stms = Vec::new();
+                for e in exprs[..exprs.len() - 1].iter(){
+                    let (tr, _) = try!(self.exp(e));
+                    stms.push(self.un_nx(tr));
+                }
+                let last = try!(self.exp(exprs.last().unwrap()));
+                Ok(self.sequence(stms, Some(last)))
+            },
+            Expr::LetExpr(ref decls, ref body) => {
+                self.begin_scope();
+                let result = self.let_expr(decls, body);
+                self.end_scope();
+                result
+            },
+            Expr::ArrayExpr(ref ty, ref dim, ref init) => self.array(ty, dim, init),
+            Expr::RecordExpr(ref fields) => {
+                let fields = fields.clone().unwrap_or(Vec::new());
+                let n = fields.len() as i32;
+                Ok((Tr::Ex(call("tiger_alloc", vec![Exp::Const(n * WORD_SIZE)])), TType::TRecord(fields)))
+            },
+            Expr::TypedArrayExpr(ref ty_id, ref dim, ref init) => {
+                match self.resolve(&TType::TCustom(ty_id.clone())){
+                    TType::TArray(ref elem_ty) => self.array(elem_ty, dim, init),
+                    _ => Err(format!("'{}' is not an array type", ty_id))
+                }
+            },
+            Expr::TypedRecordExpr(ref ty_id, ref inits) => self.record(ty_id, inits),
+            ref e => Err(format!("{:?} can't be translated to the IR", e))
+        }
+    }
+
+    //a word per field, in the order of the type; the initializers run in
+    //the order they are written. Fields without one are 0
+    fn record(&mut self, ty_id : &str, inits : &[(String, B<Expr>)]) -> TrResult{
+        let fields = match self.resolve(&TType::TCustom(ty_id.to_string())){
+            TType::TRecord(fields) => fields,
+            _ => return Err(format!("'{}' is not a record type", ty_id))
+        };
+        let r = self.temp(Ty::Ptr);
+        //a record without fields still gets an address of its own, unlike nil
+        let words = cmp::max(fields.len() as i32, 1);
+        let mut stms = vec![Stm::Move(Exp::Temp(r), call("tiger_alloc", vec![Exp::Const(words * WORD_SIZE)]))];
+        for &(ref name, ref e) in inits{
+            let i = match fields.iter().position(|f| f.0 == *name){
+                Some(i) => i,
+                None => return Err(format!("'{}' has no field '{}'", ty_id, name))
+            };
+            let (tr, _) = try!(self.exp(e));
+            let v = self.un_ex(tr);
+            let ty = self.ir_ty(&fields[i].1);
+            stms.push(Stm::Move(Exp::Mem(ty, Box::new(slot(Exp::Temp(r), Exp::Const(i as i32)))), v));
+        }
+        Ok((Tr::Ex(Exp::ESeq(Box::new(Stm::Seq(stms)), Box::new(Exp::Temp(r)))), TType::TRecord(fields)))
+    }
+
+    fn let_expr(&mut self, decls : &[Decl], body : &OptionalExpr) -> TrResult{
+        let mut stms = Vec::new();
+        let mut i = 0;
+        while i < decls.len(){
+            match decls[i]{
+                Decl::TypeDec(ref name, ref ty) => {
+                    self.env.push((name.clone(), Some(Entry::Type(ty.clone()))));
+                    i += 1;
+                },
+                Decl::VarDec(ref name, ref ty, ref rhs, ref escape) => {
+                    let (tr, rhs_ty) = try!(self.exp(rhs));
+                    let ty = match self.resolve(ty){
+                        TType::TNil => rhs_ty,
+                        t => t
+                    };
+                    let (access, level) = (self.local(&ty, escape), self.level);
+                    let dst = self.access(access, &ty, level);
+                    let src = self.un_ex(tr);
+                    stms.push(Stm::Move(dst, src));
+                    self.env.push((name.clone(), Some(Entry::Var { access : access, ty : ty, level : level })));
+                    i += 1;
+                },
+                Decl::FunDec(..) => {
+                    //consecutive functions may call each other, so all of them
+                    //are declared before any body is translated
+                    let start = i;
+                    while i < decls.len(){
+                        match decls[i]{
+                            Decl::FunDec(ref name, _, ref ret, _, _) => {
+                                let label = self.fn_label(name);
+                                let level = self.level;
+                                self.env.push((name.clone(), Some(Entry::Fun { label : label, ret : ret.clone(), level : level })));
+                            },
+                            _ => break
+                        }
+                        i += 1;
+                    }
+                    for d in decls[start..i].iter(){
+                        try!(self.function(d));
+                    }
+                },
+                Decl::ErrorDec => return Err(String::from("a declaration with a syntax error can't be translated to the IR"))
+            }
+        }
+        let last = match *body{
+            Some(ref b) => Some(try!(self.exp(b))),
+            None => None
+        };
+        Ok(self.sequence(stms, last))
+    }
+
+    fn function(&mut self, decl : &Decl) -> Result<(), String>{
+        let (name, params, ret, body) = match *decl{
+            Decl::FunDec(ref name, ref params, ref ret, ref body, _) => (name, params, ret, body),
+            _ => unreachable!()
+        };
+        let label = match self.lookup(name){
+            Some(&Entry::Fun{ref label, ..}) => label.clone(),
+            _ => unreachable!()
+        };
+        let loop_exits = ::std::mem::replace(&mut self.loop_exits, Vec::new());
+        self.level += 1;
+        self.frames.push(Frame::new(None));
+        self.begin_scope();
+        let mut temps = Vec::new();
+        //escaping parameters are copied to the frame on entry
+        let mut stms = Vec::new();
+        let level = self.level;
+        for &(ref p, ref ty, ref escape) in params.iter().flat_map(|ps| ps.iter()){
+            let temp = self.temp(self.ir_ty(ty));
+            temps.push(temp);
+            let access = if escape.escapes() {self.local(ty, escape)} else {Access::InTemp(temp)};
+            if let Access::InFrame(_) = access{
+                let dst = self.access(access, ty, level);
+                stms.push(Stm::Move(dst, Exp::Temp(temp)));
+            }
+            self.env.push((p.clone(), Some(Entry::Var { access : access, ty : ty.clone(), level : level })));
+        }
+        let link = self.temp(Ty::Ptr);
+        temps.insert(0, link);
+        self.frames[level].link = Some(link);
+        let result = self.exp(body);
+        let frame = self.frames.pop().unwrap();
+        self.end_scope();
+        self.level -= 1;
+        self.loop_exits = loop_exits;
+
+        let (tr, _) = try!(result);
+        let ret_ty = self.ir_ty(ret);
+        if ret_ty == Ty::Void{
+            stms.extend(vec![self.un_nx(tr), Stm::Return(None)]);
+        }
+        else{
+            stms.push(Stm::Return(Some(self.un_ex(tr))));
+        }
+        self.procs.push(Proc { name : label, params : temps, ret : ret_ty, body : stms });
+        self.proc_frames.push(frame);
+        Ok(())
+    }
+}
+
+//applies `f` to every expression of a statement, innermost first
+fn each_exp<F : FnMut(&mut Exp)>(stm : &mut Stm, f : &mut F){
+    match *stm{
+        Stm::Move(ref mut dst, ref mut src) => {
+            each_exp_in(dst, f);
+            each_exp_in(src, f);
+        },
+        Stm::Exp(ref mut e) => each_exp_in(e, f),
+        Stm::CJump(_, ref mut lhs, ref mut rhs, _, _) => {
+            each_exp_in(lhs, f);
+            each_exp_in(rhs, f);
+        },
+        Stm::Seq(ref mut stms) => for s in stms.iter_mut(){
+            each_exp(s, f);
+        },
+        Stm::Return(Some(ref mut e)) => each_exp_in(e, f),
+        Stm::Jump(_) | Stm::Label(_) | Stm::Return(None) => {}
+    }
+}
+
+fn each_exp_in<F : FnMut(&mut Exp)>(e : &mut Exp, f : &mut F){
+    match *e{
+        Exp::BinOp(_, ref mut lhs, ref mut rhs) => {
+            each_exp_in(lhs, f);
+            each_exp_in(rhs, f);
+        },
+        Exp::Mem(_, ref mut addr) => each_exp_in(addr, f),
+        Exp::Call(_, ref mut args, _) => for a in args.iter_mut(){
+            each_exp_in(a, f);
+        },
+        Exp::ESeq(ref mut s, ref mut e) => {
+            each_exp(s, f);
+            each_exp_in(e, f);
+        },
+        Exp::Const(_) | Exp::Nil | Exp::Name(_) | Exp::Temp(_) => {}
+    }
+    f(e);
+}
+
+fn uses(body : &mut [Stm], temp : Temp) -> bool{
+    let mut used = false;
+    for s in body.iter_mut(){
+        each_exp(s, &mut |e| used = used || *e == Exp::Temp(temp));
+    }
+    used
+}
+
+//a function keeps its static link only if it reads it or has a frame, whose
+//slot 0 holds the link. Calls to the others lose their first argument, which
+//can leave their callers with no use for their own links, so this repeats
+//until nothing changes
+fn drop_unused_links(procs : &mut [Proc], frames : &mut [Frame]){
+    loop{
+        let mut unlinked = HashSet::new();
+        for (p, frame) in procs.iter_mut().zip(frames.iter_mut()){
+            if let Some(link) = frame.link{
+                let has_frame = frame.fp.map_or(false, |fp| uses(&mut p.body, fp));
+                if !has_frame && !uses(&mut p.body, link){
+                    p.params.remove(0);
+                    frame.link = None;
+                    unlinked.insert(p.name.clone());
+                }
+            }
+        }
+        if unlinked.is_empty(){
+            return
+        }
+        for p in procs.iter_mut(){
+            for s in p.body.iter_mut(){
+                each_exp(s, &mut |e| if let Exp::Call(ref label, ref mut args, _) = *e{
+                    if unlinked.contains(label){
+                        args.remove(0);
+                    }
+                });
+            }
+        }
+    }
+}
+
+//translates a whole program. Its top level expression becomes the body of `main`,
+//which returns 0; every Tiger function becomes a Proc of its own
+pub fn translate(expr : &Expr) -> Result<Program, String>{
+    find_escapes(expr);
+    let mut t = Translator::new();
+    let (tr, _) = try!(t.exp(expr));
+    let body = vec![t.un_nx(tr), Stm::Return(Some(Exp::Const(0)))];
+    let mut procs = vec![Proc { name : Label(String::from("main")), params : Vec::new(), ret : Ty::I32, body : body }];
+    let mut frames = vec![t.frames.pop().unwrap()];
+    procs.extend(t.procs.drain(..));
+    frames.extend(t.proc_frames.drain(..));
+    drop_unused_links(&mut procs, &mut frames);
+    //frames are allocated on entry, once all of their slots are known
+    for (p, frame) in procs.iter_mut().zip(frames.iter()){
+        let fp = match frame.fp{
+            Some(fp) if uses(&mut p.body, fp) => fp,
+            _ => continue
+        };
+        let mut body = vec![Stm::Move(Exp::Temp(fp), call("tiger_alloc", vec![Exp::Const(frame.slots * WORD_SIZE)]))];
+        if let Some(link) = frame.link{
+            body.push(Stm::Move(Exp::Mem(Ty::Ptr, Box::new(slot(Exp::Temp(fp), Exp::Const(0)))), Exp::Temp(link)));
+        }
+        body.extend(p.body.drain(..));
+        p.body = body;
+    }
+    Ok(Program { procs : procs, strings : t.strings, gen : t.gen })
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use super::*;
+
+    fn ir(src : &str) -> Result<Program, String>{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        translate(&*b_expr)
+    }
+
+    #[test]
+    fn test_print_int(){
+        assert_eq!(ir("print(1+2)").unwrap().to_string(),
+                   "function main() : i32\n    \
+                        EXP(CALL tiger_print_int(BINOP(PLUS, CONST 1, CONST 2)))\n    \
+                        RETURN CONST 0\n");
+    }
+
+    #[test]
+    fn test_strings_are_fragments(){
+        let prog = ir("(print(\"hi\"); print(\"hi\"))").unwrap();
+        assert_eq!(prog.strings, vec![(Label(String::from("L0")), b"hi".to_vec())]);
+        assert!(prog.to_string().starts_with("string L0 \"hi\"\n\nfunction main() : i32\n"));
+    }
+
+    #[test]
+    fn test_var_is_a_temp(){
+        let prog = ir("let var a : int := 3 in a := a + 5 end").unwrap();
+        assert_eq!(prog.procs[0].body[0],
+                   Stm::Seq(vec![Stm::Move(Exp::Temp(Temp { id : 0, ty : Ty::I32 }), Exp::Const(3)),
+                                 Stm::Move(Exp::Temp(Temp { id : 0, ty : Ty::I32 }),
+                                           binop(BinOp::Plus, Exp::Temp(Temp { id : 0, ty : Ty::I32 }), Exp::Const(5)))]));
+    }
+
+    #[test]
+    fn test_function_becomes_proc(){
+        let prog = ir("let function add(a:int, b:int) : int = a + b in print(add(1, 2)) end").unwrap();
+        assert_eq!(prog.procs.len(), 2);
+        assert_eq!(prog.procs[1].to_string(),
+                   "function add(t0:i32, t1:i32) : i32\n    \
+                        RETURN BINOP(PLUS, TEMP t0, TEMP t1)\n");
+        assert!(prog.procs[0].to_string().contains("CALL tiger_print_int(CALL add(CONST 1, CONST 2))"));
+    }
+
+    #[test]
+    fn test_condition_jumps_directly(){
+        let prog = ir("let var a : int := 3 in if 5 > a then print(\"lt\") end").unwrap();
+        let dump = prog.procs[0].to_string();
+        assert!(dump.contains("CJUMP(GT, CONST 5, TEMP t0, L1, L2)\nL1:\n    EXP(CALL tiger_print(NAME L0))\nL2:\n"));
+    }
+
+    #[test]
+    fn test_for_loop_is_inclusive(){
+        let dump = ir("for i := 1 to 3 do print(i)").unwrap().to_string();
+        assert!(dump.contains("CJUMP(GT, TEMP t0, TEMP t1, L2, L0)"));
+        assert!(dump.contains("CJUMP(GE, TEMP t0, TEMP t1, L2, L1)"));
+    }
+
+    #[test]
+    fn test_array_is_allocated(){
+        let dump = ir("let var a : array := array of int[3] of 7 in print(a[1]) end").unwrap().to_string();
+        assert!(dump.contains("CALL tiger_alloc(BINOP(MUL, TEMP t0, CONST 8))"));
+        assert!(dump.contains("MEM[i32](BINOP(PLUS, TEMP t4, CONST 8))"));
+    }
+
+    #[test]
+    fn test_record_fields_are_stored_in_order(){
+        let dump = ir("let type point = {x:int, y:int}\n var p := point{y=2, x=1} in print(p.y) end").unwrap().to_string();
+        assert!(dump.contains("MOVE(TEMP t0, CALL tiger_alloc(CONST 16)), \
+                               MOVE(MEM[i32](BINOP(PLUS, TEMP t0, CONST 8)), CONST 2), \
+                               MOVE(MEM[i32](BINOP(PLUS, TEMP t0, CONST 0)), CONST 1)"), "{}", dump);
+        assert!(dump.contains("CALL tiger_print_int(MEM[i32](BINOP(PLUS, TEMP t1, CONST 8)))"), "{}", dump);
+    }
+
+    #[test]
+    fn test_string_comparison_calls_the_runtime(){
+        let dump = ir("let var n : int := 0 in let function f(a:string) = if a = \"y\" then print(a) in f(\"x\") end end").unwrap().to_string();
+        assert!(dump.contains("CJUMP(EQ, CALL tiger_strcmp(TEMP t1, NAME L0), CONST 0"));
+    }
+
+    #[test]
+    fn test_division_is_guarded(){
+        let dump = ir("let var a : int := 3 in\n print(7 / a) end").unwrap().to_string();
+        assert!(dump.contains("CJUMP(EQ, TEMP t2, CONST 0, L0, L1), LABEL L0, EXP(CALL tiger_div_by_zero(CONST 2))"), "{}", dump);
+        assert!(dump.contains("CJUMP(EQ, TEMP t2, CONST -1, L2, L3), LABEL L2, MOVE(TEMP t3, BINOP(MINUS, CONST 0, TEMP t1))"), "{}", dump);
+    }
+
+    #[test]
+    fn test_function_named_main_is_renamed(){
+        let prog = ir("let function main() = print(1) in main() end").unwrap();
+        assert_eq!(prog.procs[1].name, Label(String::from("main_1")));
+    }
+
+    #[test]
+    fn test_functions_without_free_variables_have_no_link(){
+        let prog = ir("let function f(a:int) : int = let function g(b:int) : int = b + 1 in g(a) end in print(f(1)) end").unwrap();
+        assert!(prog.procs[1..].iter().all(|p| p.params.len() == 1));
+        assert!(!prog.to_string().contains("tiger_alloc"));
+    }
+
+    #[test]
+    fn test_escaping_variable_lives_in_the_frame(){
+        let prog = ir("let var a : int := 1 in let function f() : int = a in print(f()) end end").unwrap();
+        let main = prog.procs[0].to_string();
+        assert!(main.contains("MOVE(TEMP t0, CALL tiger_alloc(CONST 16))"));
+        assert!(main.contains("MOVE(MEM[i32](BINOP(PLUS, TEMP t0, CONST 8)), CONST 1)"));
+        assert!(main.contains("CALL f(TEMP t0)"));
+        assert_eq!(prog.procs[1].to_string(),
+                   "function f(t1:ptr) : i32\n    \
+                        RETURN MEM[i32](BINOP(PLUS, TEMP t1, CONST 8))\n");
+    }
+
+    #[test]
+    fn test_static_links_are_followed(){
+        //h reaches a through the frames of g and f
+        let prog = ir("let var a : int := 1 in \
+                           let function f() = let function g() = let function h() = a := a + 1 in h() end in g() end \
+                           in f(); print(a) end end").unwrap();
+        let h = prog.procs.iter().find(|p| p.name == Label(String::from("h"))).unwrap().to_string();
+        assert!(h.contains("MEM[ptr](BINOP(PLUS, MEM[ptr](BINOP(PLUS, TEMP"));
+        let g = prog.procs.iter().find(|p| p.name == Label(String::from("g"))).unwrap().to_string();
+        assert!(g.contains("CALL tiger_alloc(CONST 8)"));
+    }
+}
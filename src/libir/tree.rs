@@ -0,0 +1,340 @@
+use std::ascii;
+use std::fmt;
+
+//every array element and record field takes a slot of this many bytes,
+//whatever its type
+pub const WORD_SIZE : i32 = 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Ty{
+    I32,
+    //strings, arrays and records
+    Ptr,
+    Void
+}
+
+impl fmt::Display for Ty{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Ty::I32 => f.write_str("i32"),
+            Ty::Ptr => f.write_str("ptr"),
+            Ty::Void => f.write_str("void")
+        }
+    }
+}
+
+//a virtual register. Backends decide where it lives
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Temp{
+    pub id : usize,
+    pub ty : Ty
+}
+
+impl fmt::Display for Temp{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "t{}", self.id)
+    }
+}
+
+//names a block inside a function, a function or a string literal
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
+pub struct Label(pub String);
+
+impl fmt::Display for Label{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum BinOp{
+    Plus,
+    Minus,
+    Mul,
+    Div
+}
+
+impl fmt::Display for BinOp{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(match *self{
+            BinOp::Plus => "PLUS",
+            BinOp::Minus => "MINUS",
+            BinOp::Mul => "MUL",
+            BinOp::Div => "DIV"
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum RelOp{
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+impl RelOp{
+    //the comparison that holds exactly when this one doesn't
+    pub fn negate(&self) -> RelOp{
+        match *self{
+            RelOp::Eq => RelOp::Ne,
+            RelOp::Ne => RelOp::Eq,
+            RelOp::Lt => RelOp::Ge,
+            RelOp::Le => RelOp::Gt,
+            RelOp::Gt => RelOp::Le,
+            RelOp::Ge => RelOp::Lt
+        }
+    }
+}
+
+impl fmt::Display for RelOp{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(match *self{
+            RelOp::Eq => "EQ",
+            RelOp::Ne => "NE",
+            RelOp::Lt => "LT",
+            RelOp::Le => "LE",
+            RelOp::Gt => "GT",
+            RelOp::Ge => "GE"
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Exp{
+    Const(i32),
+    //the null pointer
+    Nil,
+    //address of a string literal
+    Name(Label),
+    Temp(Temp),
+    //PLUS on a Ptr and an I32 offsets the pointer by that many bytes
+    BinOp(BinOp, Box<Exp>, Box<Exp>),
+    //the value of type Ty stored at the address
+    Mem(Ty, Box<Exp>),
+    Call(Label, Vec<Exp>, Ty),
+    //runs the statement, then evaluates to the expression
+    ESeq(Box<Stm>, Box<Exp>)
+}
+
+impl Exp{
+    //the zero value of a type, also used for temps read before they are written
+    pub fn zero(ty : Ty) -> Exp{
+        if ty == Ty::Ptr {Exp::Nil} else {Exp::Const(0)}
+    }
+
+    //a leaf: evaluating it has no effect and needs no other value
+    pub fn is_atom(&self) -> bool{
+        match *self{
+            Exp::Const(_) | Exp::Nil | Exp::Name(_) | Exp::Temp(_) => true,
+            _ => false
+        }
+    }
+
+    pub fn ty(&self) -> Ty{
+        match *self{
+            Exp::Const(_) => Ty::I32,
+            Exp::Nil | Exp::Name(_) => Ty::Ptr,
+            Exp::Temp(t) => t.ty,
+            Exp::BinOp(_, ref lhs, _) => lhs.ty(),
+            Exp::Mem(ty, _) | Exp::Call(_, _, ty) => ty,
+            Exp::ESeq(_, ref e) => e.ty()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Stm{
+    //the destination is a Temp or a Mem
+    Move(Exp, Exp),
+    //evaluates the expression for its side effects
+    Exp(Exp),
+    Jump(Label),
+    //compares the expressions and jumps to the first label when the comparison holds
+    CJump(RelOp, Exp, Exp, Label, Label),
+    Seq(Vec<Stm>),
+    Label(Label),
+    Return(Option<Exp>)
+}
+
+//hands out temps and labels nobody in the program uses yet
+#[derive(Debug, Clone, Default)]
+pub struct Gen{
+    pub next_temp : usize,
+    pub next_label : usize
+}
+
+impl Gen{
+    pub fn temp(&mut self, ty : Ty) -> Temp{
+        self.next_temp += 1;
+        Temp { id : self.next_temp - 1, ty : ty }
+    }
+
+    pub fn label(&mut self) -> Label{
+        self.next_label += 1;
+        Label(format!("L{}", self.next_label - 1))
+    }
+}
+
+pub struct Proc{
+    pub name : Label,
+    pub params : Vec<Temp>,
+    pub ret : Ty,
+    pub body : Vec<Stm>
+}
+
+pub struct Program{
+    pub procs : Vec<Proc>,
+    pub strings : Vec<(Label, Vec<u8>)>,
+    pub gen : Gen
+}
+
+//functions of the runtime library the translated programs call, with their
+//parameter and return types. Every backend has to provide them
+pub const RUNTIME : &'static [(&'static str, &'static [Ty], Ty)] = &[
+    ("tiger_print", &[Ty::Ptr], Ty::Void),
+    ("tiger_print_int", &[Ty::I32], Ty::Void),
+    ("tiger_flush", &[], Ty::Void),
+    ("tiger_getchar", &[], Ty::Ptr),
+    ("tiger_ord", &[Ty::Ptr], Ty::I32),
+    ("tiger_chr", &[Ty::I32], Ty::Ptr),
+    ("tiger_size", &[Ty::Ptr], Ty::I32),
+    ("tiger_substring", &[Ty::Ptr, Ty::I32, Ty::I32], Ty::Ptr),
+    ("tiger_concat", &[Ty::Ptr, Ty::Ptr], Ty::Ptr),
+    ("tiger_exit", &[Ty::I32], Ty::Void),
+    //zeroed memory of the given number of bytes
+    ("tiger_alloc", &[Ty::I32], Ty::Ptr),
+    //negative, zero or positive like strcmp
+    ("tiger_strcmp", &[Ty::Ptr, Ty::Ptr], Ty::I32),
+    //reports a division by zero on the given line and exits with 1
+    ("tiger_div_by_zero", &[Ty::I32], Ty::Void)
+];
+
+pub fn runtime_signature(name : &str) -> Option<(&'static [Ty], Ty)>{
+    RUNTIME.iter().find(|r| r.0 == name).map(|r| (r.1, r.2))
+}
+
+impl fmt::Display for Exp{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Exp::Const(n) => write!(f, "CONST {}", n),
+            Exp::Nil => f.write_str("NIL"),
+            Exp::Name(ref l) => write!(f, "NAME {}", l),
+            Exp::Temp(t) => write!(f, "TEMP {}", t),
+            Exp::BinOp(op, ref lhs, ref rhs) => write!(f, "BINOP({}, {}, {})", op, lhs, rhs),
+            Exp::Mem(ty, ref addr) => write!(f, "MEM[{}]({})", ty, addr),
+            Exp::Call(ref l, ref args, _) => {
+                try!(write!(f, "CALL {}(", l));
+                for (i, a) in args.iter().enumerate(){
+                    if i > 0{
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}", a));
+                }
+                f.write_str(")")
+            },
+            Exp::ESeq(ref s, ref e) => write!(f, "ESEQ({}, {})", s, e)
+        }
+    }
+}
+
+impl fmt::Display for Stm{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match *self{
+            Stm::Move(ref dst, ref src) => write!(f, "MOVE({}, {})", dst, src),
+            Stm::Exp(ref e) => write!(f, "EXP({})", e),
+            Stm::Jump(ref l) => write!(f, "JUMP {}", l),
+            Stm::CJump(op, ref lhs, ref rhs, ref t, ref e) => write!(f, "CJUMP({}, {}, {}, {}, {})", op, lhs, rhs, t, e),
+            Stm::Seq(ref stms) => {
+                try!(f.write_str("SEQ("));
+                for (i, s) in stms.iter().enumerate(){
+                    if i > 0{
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}", s));
+                }
+                f.write_str(")")
+            },
+            Stm::Label(ref l) => write!(f, "LABEL {}", l),
+            Stm::Return(Some(ref e)) => write!(f, "RETURN {}", e),
+            Stm::Return(None) => f.write_str("RETURN")
+        }
+    }
+}
+
+//one statement per line; the statements of top level SEQs are spliced in
+//and labels are outdented so the blocks stand out
+fn fmt_body(stms : &[Stm], f: &mut fmt::Formatter) -> fmt::Result{
+    for s in stms{
+        match *s{
+            Stm::Seq(ref inner) => try!(fmt_body(inner, f)),
+            Stm::Label(ref l) => try!(writeln!(f, "{}:", l)),
+            _ => try!(writeln!(f, "    {}", s))
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Proc{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        try!(write!(f, "function {}(", self.name));
+        for (i, p) in self.params.iter().enumerate(){
+            if i > 0{
+                try!(f.write_str(", "));
+            }
+            try!(write!(f, "{}:{}", p, p.ty));
+        }
+        try!(writeln!(f, ") : {}", self.ret));
+        fmt_body(&self.body, f)
+    }
+}
+
+impl fmt::Display for Program{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        for &(ref l, ref s) in self.strings.iter(){
+            let escaped : String = s.iter().flat_map(|&b| ascii::escape_default(b)).map(|b| b as char).collect();
+            try!(writeln!(f, "string {} \"{}\"", l, escaped));
+        }
+        let mut blank_line = !self.strings.is_empty();
+        for p in self.procs.iter(){
+            if blank_line{
+                try!(writeln!(f, ""));
+            }
+            try!(write!(f, "{}", p));
+            blank_line = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exp_ty(){
+        let t = Temp { id : 0, ty : Ty::Ptr };
+        let addr = Exp::BinOp(BinOp::Plus, Box::new(Exp::Temp(t)), Box::new(Exp::Const(8)));
+        assert_eq!(addr.ty(), Ty::Ptr);
+        assert_eq!(Exp::Mem(Ty::I32, Box::new(addr)).ty(), Ty::I32);
+    }
+
+    #[test]
+    fn test_dump(){
+        let t = Temp { id : 1, ty : Ty::I32 };
+        let p = Proc {
+            name : Label(String::from("f")),
+            params : vec![t],
+            ret : Ty::I32,
+            body : vec![Stm::Seq(vec![Stm::Label(Label(String::from("L0"))),
+                                      Stm::Move(Exp::Temp(t), Exp::BinOp(BinOp::Plus, Box::new(Exp::Temp(t)), Box::new(Exp::Const(1))))]),
+                        Stm::Return(Some(Exp::Temp(t)))]
+        };
+        assert_eq!(p.to_string(), "function f(t1:i32) : i32\n\
+                                   L0:\n    \
+                                       MOVE(TEMP t1, BINOP(PLUS, TEMP t1, CONST 1))\n    \
+                                       RETURN TEMP t1\n");
+    }
+}
@@ -0,0 +1,171 @@
+//! Assembly instructions whose operands are still virtual registers, as in
+//! Appel's Assem module.
+
+use std::collections::HashMap;
+use ir::tree::{Label, RelOp};
+
+//a register operand: one of the machine registers below, or FIRST_TEMP plus
+//the id of an IR temp
+pub type Node = usize;
+
+pub const RAX : Node = 0;
+pub const RCX : Node = 1;
+pub const RDX : Node = 2;
+pub const RBX : Node = 3;
+pub const RSP : Node = 4;
+pub const RBP : Node = 5;
+pub const RSI : Node = 6;
+pub const RDI : Node = 7;
+pub const R8 : Node = 8;
+pub const R9 : Node = 9;
+pub const R10 : Node = 10;
+pub const R11 : Node = 11;
+pub const R12 : Node = 12;
+pub const R13 : Node = 13;
+pub const R14 : Node = 14;
+pub const R15 : Node = 15;
+pub const FIRST_TEMP : Node = 16;
+
+const NAMES_64 : [&'static str; 16] = ["rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+                                       "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"];
+const NAMES_32 : [&'static str; 16] = ["eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi",
+                                       "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"];
+
+pub fn reg_name(reg : Node, wide : bool) -> &'static str{
+    if wide {NAMES_64[reg]} else {NAMES_32[reg]}
+}
+
+//block labels and string literals are assembler locals
+pub fn local(l : &Label) -> String{
+    format!(".{}", l.0)
+}
+
+pub fn condition(op : RelOp) -> &'static str{
+    match op{
+        RelOp::Eq => "e",
+        RelOp::Ne => "ne",
+        RelOp::Lt => "l",
+        RelOp::Le => "le",
+        RelOp::Gt => "g",
+        RelOp::Ge => "ge"
+    }
+}
+
+//In the text of an instruction `s0 and `d0 stand for the 64 bit name of the
+//first source and destination, `S0 and `D0 for the 32 bit one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr{
+    Oper{asm : String, dst : Vec<Node>, src : Vec<Node>},
+    //a register to register copy the allocator may remove
+    Move{dst : Node, src : Node},
+    Label(Label),
+    Jump(Label),
+    //jumps to the first label when the flags satisfy the condition, to the
+    //second otherwise
+    CJump(RelOp, Label, Label)
+}
+
+impl Instr{
+    pub fn oper(asm : &str, dst : Vec<Node>, src : Vec<Node>) -> Instr{
+        Instr::Oper { asm : asm.to_string(), dst : dst, src : src }
+    }
+
+    pub fn uses(&self) -> Vec<Node>{
+        match *self{
+            Instr::Oper { ref src, .. } => src.clone(),
+            Instr::Move { src, .. } => vec![src],
+            _ => Vec::new()
+        }
+    }
+
+    pub fn defs(&self) -> Vec<Node>{
+        match *self{
+            Instr::Oper { ref dst, .. } => dst.clone(),
+            Instr::Move { dst, .. } => vec![dst],
+            _ => Vec::new()
+        }
+    }
+}
+
+fn fill(asm : &str, dst : &[Node], src : &[Node], regs : &HashMap<Node, Node>) -> String{
+    let mut out = String::new();
+    let mut chars = asm.chars();
+    while let Some(c) = chars.next(){
+        if c != '`'{
+            out.push(c);
+            continue
+        }
+        let kind = chars.next().unwrap();
+        let i = chars.next().unwrap().to_digit(10).unwrap() as usize;
+        let node = match kind{
+            's' | 'S' => src[i],
+            _ => dst[i]
+        };
+        out.push('%');
+        out.push_str(reg_name(regs[&node], kind == 's' || kind == 'd'));
+    }
+    out
+}
+
+//the assembly of a function once every node has a register. Moves within one
+//register and jumps to the next instruction are dropped
+pub fn format(instrs : &[Instr], regs : &HashMap<Node, Node>) -> String{
+    let mut out = String::new();
+    for (i, instr) in instrs.iter().enumerate(){
+        let next = match instrs.get(i + 1){
+            Some(&Instr::Label(ref l)) => Some(l),
+            _ => None
+        };
+        match *instr{
+            Instr::Oper { ref asm, ref dst, ref src } => {
+                if !asm.is_empty(){
+                    out.push_str(&format!("    {}\n", fill(asm, dst, src, regs)));
+                }
+            },
+            Instr::Move { dst, src } => {
+                if regs[&dst] != regs[&src]{
+                    out.push_str(&format!("    movq %{}, %{}\n", reg_name(regs[&src], true), reg_name(regs[&dst], true)));
+                }
+            },
+            Instr::Label(ref l) => out.push_str(&format!("{}:\n", local(l))),
+            Instr::Jump(ref l) => if next != Some(l){
+                out.push_str(&format!("    jmp {}\n", local(l)));
+            },
+            Instr::CJump(op, ref t, ref f) => {
+                if next == Some(t){
+                    out.push_str(&format!("    j{} {}\n", condition(op.negate()), local(f)));
+                }
+                else{
+                    out.push_str(&format!("    j{} {}\n", condition(op), local(t)));
+                    if next != Some(f){
+                        out.push_str(&format!("    jmp {}\n", local(f)));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use ir::tree::{Label, RelOp};
+    use super::*;
+
+    #[test]
+    fn test_format(){
+        let t = FIRST_TEMP + 3;
+        let mut regs = HashMap::new();
+        regs.insert(t, RBX);
+        regs.insert(RAX, RAX);
+        regs.insert(FIRST_TEMP, RBX);
+        let l = Label(String::from("L1"));
+        let instrs = vec![Instr::oper("addl $1, `D0", vec![t], vec![t]),
+                          Instr::Move { dst : RAX, src : t },
+                          Instr::Move { dst : FIRST_TEMP, src : t },
+                          Instr::CJump(RelOp::Lt, l.clone(), Label(String::from("L2"))),
+                          Instr::Label(l)];
+        assert_eq!(format(&instrs, &regs), "    addl $1, %ebx\n    movq %rbx, %rax\n    jge .L2\n.L1:\n");
+    }
+}
@@ -0,0 +1,270 @@
+//! Instruction selection by maximal munch over the canonical trees.
+//!
+//! i32 values are computed with the 32 bit forms of the instructions and
+//! pointers with the 64 bit ones. Every temp still gets a whole register, so
+//! moves always copy 64 bits.
+
+use ir::tree::*;
+use ir::canon;
+use assem::*;
+use frame::{self, temp, ARG_REGS, CALLER_SAVED, CALLEE_SAVED};
+
+struct Codegen<'g>{
+    gen : &'g mut Gen,
+    instrs : Vec<Instr>,
+    //where returns go; the epilogue follows it
+    exit : Label
+}
+
+//the immediate operand for a constant atom
+fn immediate(e : &Exp) -> Option<String>{
+    match *e{
+        Exp::Const(c) => Some(format!("${}", c)),
+        Exp::Nil => Some(String::from("$0")),
+        _ => None
+    }
+}
+
+fn suffix(wide : bool) -> &'static str{
+    if wide {"q"} else {"l"}
+}
+
+//the placeholders of source or destination `i` at the width of a value
+fn src(i : usize, wide : bool) -> String{
+    format!("`{}{}", if wide {'s'} else {'S'}, i)
+}
+
+fn dst(i : usize, wide : bool) -> String{
+    format!("`{}{}", if wide {'d'} else {'D'}, i)
+}
+
+impl<'g> Codegen<'g>{
+    fn emit(&mut self, instr : Instr){
+        self.instrs.push(instr);
+    }
+
+    fn oper(&mut self, asm : &str, dst : Vec<Node>, src : Vec<Node>){
+        self.emit(Instr::oper(asm, dst, src));
+    }
+
+    fn new_temp(&mut self, ty : Ty) -> Node{
+        temp(self.gen.temp(ty))
+    }
+
+    //puts the value of an atom into `node`
+    fn move_into(&mut self, node : Node, e : Exp){
+        match e{
+            Exp::Temp(t) => self.emit(Instr::Move { dst : node, src : temp(t) }),
+            Exp::Const(c) => self.oper(&format!("movl ${}, `D0", c), vec![node], vec![]),
+            Exp::Nil => self.oper("movq $0, `d0", vec![node], vec![]),
+            Exp::Name(l) => self.oper(&format!("leaq {}(%rip), `d0", local(&l)), vec![node], vec![]),
+            e => panic!("{} is not an atom", e)
+        }
+    }
+
+    //the register holding the value of an atom
+    fn reg(&mut self, e : Exp) -> Node{
+        match e{
+            Exp::Temp(t) => temp(t),
+            e => {
+                let n = self.new_temp(e.ty());
+                self.move_into(n, e);
+                n
+            }
+        }
+    }
+
+    fn binop(&mut self, d : Node, op : BinOp, a : Exp, b : Exp){
+        let wide = a.ty() == Ty::Ptr;
+        if op == BinOp::Div{
+            self.move_into(RAX, a);
+            self.oper("cltd", vec![RDX], vec![RAX]);
+            let b = self.reg(b);
+            self.oper("idivl `S0", vec![RAX, RDX], vec![b, RAX, RDX]);
+            self.emit(Instr::Move { dst : d, src : RAX });
+            return
+        }
+        let name = match op{
+            BinOp::Plus => "add",
+            BinOp::Minus => "sub",
+            _ => "imul"
+        };
+        //computed in a fresh register in case d is also an operand
+        let r = self.new_temp(a.ty());
+        self.move_into(r, a);
+        match immediate(&b){
+            Some(imm) => self.oper(&format!("{}{} {}, {}", name, suffix(wide), imm, dst(0, wide)), vec![r], vec![r]),
+            None => {
+                let mut b_reg = self.reg(b.clone());
+                if wide && b.ty() == Ty::I32{
+                    //pointer plus a signed offset
+                    let offset = self.new_temp(Ty::Ptr);
+                    self.oper("movslq `S0, `d0", vec![offset], vec![b_reg]);
+                    b_reg = offset;
+                }
+                self.oper(&format!("{}{} {}, {}", name, suffix(wide), src(0, wide), dst(0, wide)), vec![r], vec![b_reg, r]);
+            }
+        }
+        self.emit(Instr::Move { dst : d, src : r });
+    }
+
+    fn call(&mut self, l : &Label, args : Vec<Exp>){
+        let on_stack = args.len().saturating_sub(ARG_REGS.len());
+        //%rsp has to stay 16 byte aligned at the call
+        let padding = on_stack % 2;
+        if padding == 1{
+            self.oper("subq $8, %rsp", vec![], vec![]);
+        }
+        for a in args[ARG_REGS.len().min(args.len())..].iter().rev(){
+            match immediate(a){
+                Some(imm) => self.oper(&format!("pushq {}", imm), vec![], vec![]),
+                None => {
+                    let r = self.reg(a.clone());
+                    self.oper("pushq `s0", vec![], vec![r]);
+                }
+            }
+        }
+        let mut used = Vec::new();
+        for (i, a) in args.into_iter().take(ARG_REGS.len()).enumerate(){
+            self.move_into(ARG_REGS[i], a);
+            used.push(ARG_REGS[i]);
+        }
+        self.oper(&format!("call {}", frame::symbol(l)), CALLER_SAVED.to_vec(), used);
+        if on_stack > 0{
+            self.oper(&format!("addq ${}, %rsp", 8 * (on_stack + padding)), vec![], vec![]);
+        }
+    }
+
+    fn stm(&mut self, s : Stm){
+        match s{
+            Stm::Label(l) => self.emit(Instr::Label(l)),
+            Stm::Jump(l) => self.emit(Instr::Jump(l)),
+            Stm::CJump(op, a, b, t, f) => {
+                let wide = a.ty() == Ty::Ptr || b.ty() == Ty::Ptr;
+                let a = self.reg(a);
+                match immediate(&b){
+                    Some(imm) => self.oper(&format!("cmp{} {}, {}", suffix(wide), imm, src(0, wide)), vec![], vec![a]),
+                    None => {
+                        let b = self.reg(b);
+                        self.oper(&format!("cmp{} {}, {}", suffix(wide), src(1, wide), src(0, wide)), vec![], vec![a, b]);
+                    }
+                }
+                self.emit(Instr::CJump(op, t, f));
+            },
+            Stm::Move(Exp::Temp(d), src_exp) => {
+                let d = temp(d);
+                match src_exp{
+                    Exp::BinOp(op, a, b) => self.binop(d, op, *a, *b),
+                    Exp::Mem(ty, addr) => {
+                        let addr = self.reg(*addr);
+                        let wide = ty == Ty::Ptr;
+                        self.oper(&format!("mov{} (`s0), {}", suffix(wide), dst(0, wide)), vec![d], vec![addr]);
+                    },
+                    Exp::Call(l, args, _) => {
+                        self.call(&l, args);
+                        self.emit(Instr::Move { dst : d, src : RAX });
+                    },
+                    e => self.move_into(d, e)
+                }
+            },
+            Stm::Move(Exp::Mem(ty, addr), value) => {
+                let addr = self.reg(*addr);
+                let wide = ty == Ty::Ptr;
+                match immediate(&value){
+                    Some(imm) => self.oper(&format!("mov{} {}, (`s0)", suffix(wide), imm), vec![], vec![addr]),
+                    None => {
+                        let value = self.reg(value);
+                        self.oper(&format!("mov{} {}, (`s0)", suffix(wide), src(1, wide)), vec![], vec![addr, value]);
+                    }
+                }
+            },
+            Stm::Exp(Exp::Call(l, args, _)) => self.call(&l, args),
+            Stm::Return(value) => {
+                if let Some(e) = value{
+                    self.move_into(RAX, e);
+                }
+                let exit = self.exit.clone();
+                self.emit(Instr::Jump(exit));
+            },
+            s => panic!("{} is not canonical", s)
+        }
+    }
+}
+
+//the instructions of a procedure, without the prologue and epilogue. The
+//callee-saved registers are copied to temps on entry and back on exit, so
+//the allocator only keeps them around where it needs the registers
+pub fn codegen(p : Proc, gen : &mut Gen) -> Vec<Instr>{
+    let body = canon::linearize(p.body, gen);
+    let exit = gen.label();
+    let mut c = Codegen { gen : gen, instrs : Vec::new(), exit : exit.clone() };
+    let saved : Vec<(Node, Node)> = CALLEE_SAVED.iter().map(|&r| (r, c.new_temp(Ty::Ptr))).collect();
+    for &(r, t) in saved.iter(){
+        c.emit(Instr::Move { dst : t, src : r });
+    }
+    for (i, param) in p.params.iter().enumerate(){
+        if i < ARG_REGS.len(){
+            c.emit(Instr::Move { dst : temp(*param), src : ARG_REGS[i] });
+        }
+        else{
+            c.oper(&format!("movq {}(%rbp), `d0", frame::stack_arg_offset(i)), vec![temp(*param)], vec![]);
+        }
+    }
+    for s in body{
+        c.stm(s);
+    }
+    match c.instrs.last(){
+        Some(&Instr::Jump(_)) => (),
+        _ => c.emit(Instr::Jump(exit.clone()))
+    }
+    c.emit(Instr::Label(exit));
+    for &(r, t) in saved.iter(){
+        c.emit(Instr::Move { dst : r, src : t });
+    }
+    //what has to be live when the function returns
+    let mut live = CALLEE_SAVED.to_vec();
+    if p.ret != Ty::Void{
+        live.push(RAX);
+    }
+    c.oper("", vec![], live);
+    c.instrs
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::tree::*;
+    use assem::*;
+    use super::*;
+
+    fn t(id : usize, ty : Ty) -> Temp{
+        Temp { id : id, ty : ty }
+    }
+
+    #[test]
+    fn test_pointer_offset_is_sign_extended(){
+        let mut gen = Gen { next_temp : 10, next_label : 0 };
+        let body = vec![Stm::Move(Exp::Temp(t(0, Ty::Ptr)),
+                                  Exp::BinOp(BinOp::Plus, Box::new(Exp::Temp(t(1, Ty::Ptr))), Box::new(Exp::Temp(t(2, Ty::I32)))))];
+        let p = Proc { name : Label(String::from("f")), params : vec![], ret : Ty::Void, body : body };
+        let instrs = codegen(p, &mut gen);
+        assert!(instrs.contains(&Instr::oper("movslq `S0, `d0", vec![FIRST_TEMP + 16], vec![FIRST_TEMP + 2])));
+        assert!(instrs.contains(&Instr::oper("addq `s0, `d0", vec![FIRST_TEMP + 15], vec![FIRST_TEMP + 16, FIRST_TEMP + 15])));
+    }
+
+    #[test]
+    fn test_call_arguments(){
+        let mut gen = Gen { next_temp : 10, next_label : 0 };
+        let args = (0..8).map(|i| Exp::Const(i)).collect();
+        let body = vec![Stm::Exp(Exp::Call(Label(String::from("g")), args, Ty::Void))];
+        let p = Proc { name : Label(String::from("f")), params : vec![t(0, Ty::I32)], ret : Ty::Void, body : body };
+        let instrs = codegen(p, &mut gen);
+        let asm : Vec<String> = instrs.iter().filter_map(|i| match *i{
+            Instr::Oper { ref asm, .. } => Some(asm.clone()),
+            _ => None
+        }).collect();
+        assert_eq!(&asm[..3], &["pushq $7", "pushq $6", "movl $0, `D0"]);
+        assert!(asm.contains(&String::from("call tiger_fn_g")));
+        assert!(asm.contains(&String::from("addq $16, %rsp")));
+        assert!(instrs.contains(&Instr::Move { dst : FIRST_TEMP, src : RDI }));
+    }
+}
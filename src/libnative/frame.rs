@@ -0,0 +1,88 @@
+//! The x86-64 System V calling convention and the layout of a stack frame.
+//!
+//! Every function keeps %rbp as its frame pointer. Spilled temps live in
+//! 8 byte slots right below it and arguments past the sixth are pushed by the
+//! caller, so they sit above the return address:
+//!
+//! ```text
+//!  16(%rbp)    7th argument, 8th at 24(%rbp) ...
+//!   8(%rbp)    return address
+//!   0(%rbp)    caller's %rbp
+//!  -8(%rbp)    spill slot 0, slot 1 at -16(%rbp) ...
+//! ```
+
+use ir::tree::{Label, Temp, runtime_signature};
+use assem::*;
+
+pub const ARG_REGS : [Node; 6] = [RDI, RSI, RDX, RCX, R8, R9];
+//clobbered by every call
+pub const CALLER_SAVED : [Node; 9] = [RAX, RCX, RDX, RSI, RDI, R8, R9, R10, R11];
+pub const CALLEE_SAVED : [Node; 5] = [RBX, R12, R13, R14, R15];
+//the registers temps can get, in order of preference
+pub const ALLOCATABLE : [Node; 14] = [RAX, RCX, RDX, RSI, RDI, R8, R9, R10, R11, RBX, R12, R13, R14, R15];
+
+pub fn temp(t : Temp) -> Node{
+    FIRST_TEMP + t.id
+}
+
+//offset from %rbp of a spill slot
+pub fn slot_offset(slot : usize) -> i64{
+    -8 * (slot as i64 + 1)
+}
+
+//offset from %rbp of an argument passed on the stack
+pub fn stack_arg_offset(index : usize) -> i64{
+    16 + 8 * (index - ARG_REGS.len()) as i64
+}
+
+//the symbol of a function. The program's own functions get a prefix so none
+//of them clashes with the c library
+pub fn symbol(l : &Label) -> String{
+    if l.0 == "main" || runtime_signature(&l.0).is_some(){
+        l.0.clone()
+    }
+    else{
+        format!("tiger_fn_{}", l.0)
+    }
+}
+
+//wraps the allocated body in the code that sets up and tears down the frame.
+//The body ends by falling into the epilogue
+pub fn function(l : &Label, body : &str, spill_slots : usize) -> String{
+    let name = symbol(l);
+    let mut out = String::new();
+    if name == "main"{
+        out.push_str("    .globl main\n");
+    }
+    out.push_str(&format!("    .type {}, @function\n{}:\n", name, name));
+    out.push_str("    pushq %rbp\n    movq %rsp, %rbp\n");
+    //calls need %rsp 16 byte aligned
+    let size = (spill_slots * 8 + 15) / 16 * 16;
+    if size > 0{
+        out.push_str(&format!("    subq ${}, %rsp\n", size));
+    }
+    out.push_str(body);
+    out.push_str("    leave\n    ret\n");
+    out.push_str(&format!("    .size {}, .-{}\n", name, name));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::tree::Label;
+    use super::*;
+
+    #[test]
+    fn test_symbol(){
+        assert_eq!(symbol(&Label(String::from("main"))), "main");
+        assert_eq!(symbol(&Label(String::from("tiger_print"))), "tiger_print");
+        assert_eq!(symbol(&Label(String::from("exit_1"))), "tiger_fn_exit_1");
+    }
+
+    #[test]
+    fn test_frame_is_aligned(){
+        let f = function(&Label(String::from("f")), "", 3);
+        assert!(f.contains("subq $32, %rsp\n"));
+        assert!(!function(&Label(String::from("f")), "", 0).contains("subq"));
+    }
+}
@@ -0,0 +1,166 @@
+#![crate_name = "native"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! Native x86-64 backend: turns the mid-level IR into System V assembly for
+//! the GNU assembler without going through llvm.
+
+extern crate ir;
+extern crate session;
+#[cfg(test)]
+extern crate syntax;
+
+pub mod assem;
+pub mod frame;
+pub mod codegen;
+pub mod liveness;
+pub mod regalloc;
+pub mod runtime;
+pub mod link;
+
+use ir::tree::Program;
+
+//a string literal for the .string directive
+fn escape(s : &[u8]) -> String{
+    let mut out = String::new();
+    for &b in s{
+        match b{
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b if b >= 0x20 && b < 0x7f => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b))
+        }
+    }
+    out
+}
+
+//the assembly of a whole program, runtime included
+pub fn compile(prog : Program) -> String{
+    let mut gen = prog.gen;
+    let mut out = String::from("    .text\n");
+    for p in prog.procs{
+        let name = p.name.clone();
+        let instrs = codegen::codegen(p, &mut gen);
+        let (instrs, regs, spill_slots) = regalloc::allocate(instrs, &mut gen);
+        out.push_str(&frame::function(&name, &assem::format(&instrs, &regs), spill_slots));
+        out.push('\n');
+    }
+    out.push_str(runtime::RUNTIME);
+    for (l, s) in prog.strings{
+        out.push_str(&format!("{}:\n    .string \"{}\"\n", assem::local(&l), escape(&s)));
+    }
+    out.push_str("    .section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use std::process::Command;
+    use ir::translate::translate;
+    use ir::opt::{optimize, ALL_PASSES};
+    use session::link::{LinkOptions, TempDir};
+    use link;
+    use super::*;
+
+    fn asm(src : &str, optimized : bool) -> String{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        if optimized{
+            optimize(&mut prog, ALL_PASSES, None).unwrap();
+        }
+        compile(prog)
+    }
+
+    //the exit code and output of the program, built with and without the IR passes
+    fn run(src : &str) -> (i32, String){
+        let mut results = Vec::new();
+        for &optimized in [false, true].iter(){
+            let tmp = TempDir::new().unwrap();
+            let exe = tmp.path.join("out");
+            link::link(&asm(src, optimized), &LinkOptions { output : exe.to_string_lossy().into_owned(), ..LinkOptions::default() }).unwrap();
+            let out = Command::new(&exe).output().unwrap();
+            results.push((out.status.code().unwrap(), String::from_utf8(out.stdout).unwrap()));
+        }
+        assert_eq!(results[0], results[1]);
+        results.pop().unwrap()
+    }
+
+    #[test]
+    fn test_escape(){
+        assert_eq!(escape(b"a\"b\\\n"), "a\\\"b\\\\\\012");
+    }
+
+    #[test]
+    fn test_native_print(){
+        assert_eq!(run("(print(\"sum: \"); print(1+2))"), (0, String::from("sum: 3\n")));
+    }
+
+    #[test]
+    fn test_native_mod(){
+        assert_eq!(run("let var a : int := 0 - 7 in (print(17 mod 5); print(a mod 3)) end").1, "2\n-1\n");
+    }
+
+    #[test]
+    fn test_native_division_checks(){
+        //1 - 1 and 0 - 1 keep the divisors from being folded by the type checker
+        assert_eq!(run("(print(1); print(7 / (1 - 1)))"), (1, String::from("1\n")));
+        assert_eq!(run("print(7 mod (1 - 1))"), (1, String::new()));
+        //INT_MIN / -1 wraps around and INT_MIN mod -1 is 0
+        let src = "let var min : int := 0 - 2147483647 - 1 in (print(min / (0 - 1)); print(min mod (0 - 1)); print(7 / (0 - 1))) end";
+        assert_eq!(run(src), (0, String::from("-2147483648\n0\n-7\n")));
+    }
+
+    #[test]
+    fn test_native_loops(){
+        let src = "let var a : int := 0 in let var b : int := 2 in for i := 1 to 4 do (a := a + i; b := a + 1; print(b)) end end end";
+        assert_eq!(run(src).1, "2\n4\n7\n11\n");
+        assert_eq!(run("let var a : int := 0 in while 10 > a do (a := a + 3; print(a)) end").1, "3\n6\n9\n12\n");
+    }
+
+    #[test]
+    fn test_native_functions(){
+        let src = "let function add(a:int, b:int) : int = a + b in print(add(40, 2)) end";
+        assert_eq!(run(src).1, "42\n");
+        //n is live across the call
+        let src = "let function g(n:int) : int = n + 1 in let function f(n:int) : int = g(5) + n in print(f(3)) end end";
+        assert_eq!(run(src).1, "9\n");
+    }
+
+    #[test]
+    fn test_native_nested_functions(){
+        //count lives in the frame of main and i in the frame of the loop's function
+        let src = "let var count : int := 0 in \
+                       let function bump(n:int) = let function add() = count := count + n in add() end \
+                           function run() = for i := 1 to 3 do let function f() = bump(i) in f() end \
+                       in (run(); print(count)) end end";
+        assert_eq!(run(src).1, "6\n");
+    }
+
+    #[test]
+    fn test_native_many_arguments(){
+        let src = "let function f(a:int, b:int, c:int, d:int, e:int, g:int, h:int, i:int) : int = h + i in print(f(1, 2, 3, 4, 5, 6, 10, 3)) end";
+        assert_eq!(run(src).1, "13\n");
+    }
+
+    #[test]
+    fn test_native_arrays_and_strings(){
+        let src = "let var a : array := array of int[3] of 7 in (a[1] := 5; print(a[1]); print(a[2])) end";
+        assert_eq!(run(src).1, "5\n7\n");
+        assert_eq!(run("(print(chr(100)); print(size(\"abc\")); print(ord(\"b\")))").1, "d3\n98\n");
+    }
+
+    #[test]
+    fn test_native_exit_code(){
+        assert_eq!(run("exit(4)").0, 4);
+    }
+
+    #[test]
+    fn test_native_run(){
+        assert_eq!(link::run(&asm("exit(3)", false), &LinkOptions::default()), Ok(3));
+    }
+}
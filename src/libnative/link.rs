@@ -0,0 +1,77 @@
+//! Assembling and linking the output of the native and c backends with the
+//! system c compiler, which also brings in the c library the runtime needs.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::Command;
+use session::link::{Linker, LinkOptions, TempDir};
+
+//the c compiler that builds and links for `opts`
+fn driver(opts : &LinkOptions) -> Result<&'static str, String>{
+    match opts.linker{
+        Linker::Clang => Ok("clang"),
+        Linker::Ld => Err(String::from("the native and c backends link with cc or clang")),
+        Linker::Cc | Linker::None => Ok("cc")
+    }
+}
+
+//assembles `asm` into an object file or, unless the linker is None, an executable
+pub fn link(asm : &str, opts : &LinkOptions) -> Result<(), String>{
+    build("main.s", asm, opts)
+}
+
+//like link, for any source the c compiler knows by the extension of
+//`file_name`: main.s or main.c
+pub fn build(file_name : &str, code : &str, opts : &LinkOptions) -> Result<(), String>{
+    let driver = try!(driver(opts));
+    let tmp = try!(TempDir::new());
+    let source = tmp.path.join(file_name);
+    try!(File::create(&source).and_then(|mut f| f.write_all(code.as_bytes()))
+         .map_err(|e| format!("could not write {} - {}", source.display(), e)));
+
+    let object_only = opts.linker == Linker::None;
+    let mut cmd = Command::new(driver);
+    if object_only{
+        cmd.arg("-c");
+    }
+    if opts.debug_info{
+        cmd.arg("-g");
+    }
+    if opts.static_link{
+        cmd.arg("-static");
+    }
+    cmd.arg(&source);
+    if !object_only{
+        for path in opts.lib_paths.iter(){
+            cmd.arg(format!("-L{}", path));
+        }
+        for lib in opts.libs.iter(){
+            cmd.arg(format!("-l{}", lib));
+        }
+    }
+    cmd.arg("-o").arg(&opts.output);
+    let out = try!(cmd.output().map_err(|e| format!("could not run {} - {}", driver, e)));
+    if !out.status.success(){
+        return Err(format!("building with {:?} failed ({})\n{}{}",
+                           cmd, out.status,
+                           String::from_utf8_lossy(&out.stdout),
+                           String::from_utf8_lossy(&out.stderr)))
+    }
+    let _ = io::stderr().write_all(&out.stderr);
+    Ok(())
+}
+
+//links `asm` into a temporary executable and runs it, returning its exit code
+pub fn run(asm : &str, opts : &LinkOptions) -> Result<i32, String>{
+    build_and_run("main.s", asm, opts)
+}
+
+pub fn build_and_run(file_name : &str, code : &str, opts : &LinkOptions) -> Result<i32, String>{
+    let tmp = try!(TempDir::new());
+    let exe = tmp.path.join("main");
+    let linker = if opts.linker == Linker::None {Linker::Cc} else {opts.linker};
+    try!(build(file_name, code, &LinkOptions { linker : linker, output : exe.to_string_lossy().into_owned(), ..opts.clone() }));
+    let status = try!(Command::new(&exe).status().map_err(|e| format!("could not run {} - {}", exe.display(), e)));
+    //killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
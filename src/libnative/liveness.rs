@@ -0,0 +1,129 @@
+//! Liveness analysis over the instructions of a function and the
+//! interference graph built from it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use assem::*;
+
+//the instructions control can go to after each one
+fn successors(instrs : &[Instr]) -> Vec<Vec<usize>>{
+    let labels : HashMap<_, _> = instrs.iter().enumerate().filter_map(|(i, instr)| match *instr{
+        Instr::Label(ref l) => Some((l.clone(), i)),
+        _ => None
+    }).collect();
+    instrs.iter().enumerate().map(|(i, instr)| match *instr{
+        Instr::Jump(ref l) => vec![labels[l]],
+        Instr::CJump(_, ref t, ref f) => vec![labels[t], labels[f]],
+        _ if i + 1 < instrs.len() => vec![i + 1],
+        _ => Vec::new()
+    }).collect()
+}
+
+//the nodes live right after each instruction
+pub fn live_out(instrs : &[Instr]) -> Vec<BTreeSet<Node>>{
+    let succs = successors(instrs);
+    let uses : Vec<Vec<Node>> = instrs.iter().map(|i| i.uses()).collect();
+    let defs : Vec<Vec<Node>> = instrs.iter().map(|i| i.defs()).collect();
+    let mut live_in = vec![BTreeSet::new(); instrs.len()];
+    let mut live_out = vec![BTreeSet::new(); instrs.len()];
+    let mut changed = true;
+    while changed{
+        changed = false;
+        //backwards, so most facts are final after one sweep
+        for i in (0..instrs.len()).rev(){
+            let mut out = BTreeSet::new();
+            for &s in succs[i].iter(){
+                out.extend(live_in[s].iter().cloned());
+            }
+            let mut inn : BTreeSet<Node> = out.iter().cloned().filter(|n| !defs[i].contains(n)).collect();
+            inn.extend(uses[i].iter().cloned());
+            if inn != live_in[i] || out != live_out[i]{
+                live_in[i] = inn;
+                live_out[i] = out;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+pub struct Graph{
+    //every node of the function, with the nodes it can't share a register with
+    pub adj : BTreeMap<Node, BTreeSet<Node>>,
+    //the nodes each one is moved to or from, which it would like to share a register with
+    pub moves : BTreeMap<Node, BTreeSet<Node>>
+}
+
+impl Graph{
+    fn add_node(&mut self, n : Node){
+        self.adj.entry(n).or_insert(BTreeSet::new());
+    }
+
+    fn add_edge(&mut self, a : Node, b : Node){
+        //machine registers always interfere with each other
+        if a == b || (a < FIRST_TEMP && b < FIRST_TEMP){
+            return
+        }
+        self.adj.entry(a).or_insert(BTreeSet::new()).insert(b);
+        self.adj.entry(b).or_insert(BTreeSet::new()).insert(a);
+    }
+
+    pub fn interferes(&self, a : Node, b : Node) -> bool{
+        self.adj.get(&a).map_or(false, |ns| ns.contains(&b))
+    }
+}
+
+//a node defined by an instruction interferes with everything live after it,
+//except that the destination of a move may share a register with its source
+pub fn interference(instrs : &[Instr], live_out : &[BTreeSet<Node>]) -> Graph{
+    let mut g = Graph { adj : BTreeMap::new(), moves : BTreeMap::new() };
+    for (instr, live) in instrs.iter().zip(live_out){
+        for n in instr.uses().into_iter().chain(instr.defs()){
+            g.add_node(n);
+        }
+        match *instr{
+            Instr::Move { dst, src } => {
+                for &l in live.iter().filter(|&&l| l != src){
+                    g.add_edge(dst, l);
+                }
+                g.moves.entry(dst).or_insert(BTreeSet::new()).insert(src);
+                g.moves.entry(src).or_insert(BTreeSet::new()).insert(dst);
+            },
+            _ => for d in instr.defs(){
+                for &l in live.iter(){
+                    g.add_edge(d, l);
+                }
+            }
+        }
+    }
+    g
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::tree::{Label, RelOp};
+    use assem::*;
+    use super::*;
+
+    #[test]
+    fn test_loop_keeps_temp_live(){
+        let (a, b) = (FIRST_TEMP, FIRST_TEMP + 1);
+        let head = Label(String::from("L0"));
+        let done = Label(String::from("L1"));
+        let instrs = vec![Instr::oper("movl $0, `D0", vec![a], vec![]),
+                          Instr::Label(head.clone()),
+                          Instr::oper("movl $1, `D0", vec![b], vec![]),
+                          Instr::oper("cmpl `S1, `S0", vec![], vec![a, b]),
+                          Instr::CJump(RelOp::Lt, head, done.clone()),
+                          Instr::Label(done),
+                          Instr::Move { dst : RAX, src : b },
+                          Instr::oper("", vec![], vec![RAX])];
+        let live = live_out(&instrs);
+        //a is read again on the next iteration
+        assert!(live[4].contains(&a) && live[4].contains(&b));
+        assert!(!live[6].contains(&b));
+        let g = interference(&instrs, &live);
+        assert!(g.interferes(a, b));
+        assert!(!g.interferes(b, RAX));
+        assert!(g.moves[&b].contains(&RAX));
+    }
+}
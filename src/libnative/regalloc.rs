@@ -0,0 +1,171 @@
+//! Register allocation by graph colouring, after Chaitin and Briggs: nodes of
+//! low degree are simplified away, the rest pushed optimistically, and the
+//! ones left without a colour spilled to the frame before trying again.
+//! Instead of coalescing, a node prefers the register of a node it is moved
+//! to or from, which makes most of those moves disappear.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use ir::tree::{Gen, Ty};
+use assem::*;
+use frame::{self, ALLOCATABLE};
+use liveness::{self, Graph};
+
+//the register of every node, or the temps that have to be spilled
+fn color(g : &Graph, no_spill : &HashSet<Node>) -> Result<HashMap<Node, Node>, Vec<Node>>{
+    let k = ALLOCATABLE.len();
+    let mut remaining : BTreeSet<Node> = g.adj.keys().cloned().filter(|&n| n >= FIRST_TEMP).collect();
+    let mut degree : BTreeMap<Node, usize> = remaining.iter().map(|&n| (n, g.adj[&n].len())).collect();
+    let mut stack = Vec::new();
+    while !remaining.is_empty(){
+        let n = match remaining.iter().find(|n| degree[n] < k){
+            Some(&n) => n,
+            //might still get a colour if its neighbours share some. Temps
+            //loaded from a spill slot live too briefly to be worth spilling
+            None => *remaining.iter()
+                .max_by_key(|n| (!no_spill.contains(n), degree[n]))
+                .unwrap()
+        };
+        remaining.remove(&n);
+        for m in g.adj[&n].iter(){
+            if remaining.contains(m){
+                *degree.get_mut(m).unwrap() -= 1;
+            }
+        }
+        stack.push(n);
+    }
+
+    let mut regs : HashMap<Node, Node> = (0..FIRST_TEMP).map(|r| (r, r)).collect();
+    let mut spills = Vec::new();
+    while let Some(n) = stack.pop(){
+        let taken : HashSet<Node> = g.adj[&n].iter().filter_map(|m| regs.get(m).cloned()).collect();
+        let free = |r : &Node| ALLOCATABLE.contains(r) && !taken.contains(r);
+        let preferred = g.moves.get(&n).and_then(|ms| ms.iter().filter_map(|m| regs.get(m).cloned()).find(|r| free(r)));
+        //otherwise leave the machine registers neighbours are moved to or from for them
+        let wanted : HashSet<Node> = g.adj[&n].iter()
+            .filter_map(|m| g.moves.get(m))
+            .flat_map(|ms| ms.iter().cloned().filter(|&r| r < FIRST_TEMP))
+            .collect();
+        let unwanted = ALLOCATABLE.iter().cloned().find(|r| free(r) && !wanted.contains(r));
+        match preferred.or(unwanted).or_else(|| ALLOCATABLE.iter().cloned().find(|r| free(r))){
+            Some(r) => { regs.insert(n, r); },
+            None => spills.push(n)
+        }
+    }
+    if spills.is_empty() {Ok(regs)} else {Err(spills)}
+}
+
+//gives every spilled temp a slot and replaces each of its uses and definitions
+//by a load or store of a new short-lived temp
+fn rewrite(instrs : Vec<Instr>, spills : &[Node], slots : &mut HashMap<Node, usize>, no_spill : &mut HashSet<Node>, gen : &mut Gen) -> Vec<Instr>{
+    for &s in spills{
+        let next = slots.len();
+        slots.entry(s).or_insert(next);
+    }
+    let mut out = Vec::new();
+    for mut instr in instrs{
+        let mut replaced = HashMap::new();
+        let touched : Vec<Node> = instr.uses().into_iter().chain(instr.defs()).filter(|n| spills.contains(n)).collect();
+        for s in touched{
+            if !replaced.contains_key(&s){
+                let t = frame::temp(gen.temp(Ty::Ptr));
+                no_spill.insert(t);
+                replaced.insert(s, t);
+            }
+        }
+        if replaced.is_empty(){
+            out.push(instr);
+            continue
+        }
+        let mut loaded = HashSet::new();
+        for s in instr.uses(){
+            if let Some(&t) = replaced.get(&s){
+                if loaded.insert(s){
+                    out.push(load(t, slots[&s]));
+                }
+            }
+        }
+        let defs : Vec<Node> = instr.defs().into_iter().filter(|d| replaced.contains_key(d)).collect();
+        match instr{
+            Instr::Oper { ref mut dst, ref mut src, .. } => {
+                for n in dst.iter_mut().chain(src.iter_mut()){
+                    if let Some(&t) = replaced.get(n){
+                        *n = t;
+                    }
+                }
+            },
+            Instr::Move { ref mut dst, ref mut src } => {
+                if let Some(&t) = replaced.get(dst) {*dst = t}
+                if let Some(&t) = replaced.get(src) {*src = t}
+            },
+            _ => ()
+        }
+        out.push(instr);
+        for d in defs{
+            let t = replaced[&d];
+            out.push(Instr::oper(&format!("movq `s0, {}(%rbp)", frame::slot_offset(slots[&d])), vec![], vec![t]));
+        }
+    }
+    out
+}
+
+fn load(t : Node, slot : usize) -> Instr{
+    Instr::oper(&format!("movq {}(%rbp), `d0", frame::slot_offset(slot)), vec![t], vec![])
+}
+
+//assigns a machine register to every node, spilling until that succeeds.
+//Returns the rewritten instructions, the registers and the number of spill slots
+pub fn allocate(mut instrs : Vec<Instr>, gen : &mut Gen) -> (Vec<Instr>, HashMap<Node, Node>, usize){
+    let mut slots = HashMap::new();
+    let mut no_spill = HashSet::new();
+    loop{
+        let live = liveness::live_out(&instrs);
+        let g = liveness::interference(&instrs, &live);
+        match color(&g, &no_spill){
+            Ok(regs) => return (instrs, regs, slots.len()),
+            Err(spills) => instrs = rewrite(instrs, &spills, &mut slots, &mut no_spill, gen)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ir::tree::Gen;
+    use assem::*;
+    use super::*;
+
+    #[test]
+    fn test_interfering_temps_get_different_registers(){
+        let mut gen = Gen { next_temp : 100, next_label : 0 };
+        let (a, b) = (FIRST_TEMP, FIRST_TEMP + 1);
+        let instrs = vec![Instr::oper("movl $1, `D0", vec![a], vec![]),
+                          Instr::oper("movl $2, `D0", vec![b], vec![]),
+                          Instr::oper("addl `S0, `D0", vec![a], vec![b, a]),
+                          Instr::Move { dst : RAX, src : a },
+                          Instr::oper("", vec![], vec![RAX])];
+        let (_, regs, slots) = allocate(instrs, &mut gen);
+        assert_eq!(slots, 0);
+        assert!(regs[&a] != regs[&b]);
+        //a prefers the register it is moved to
+        assert_eq!(regs[&a], RAX);
+    }
+
+    #[test]
+    fn test_spills_when_out_of_registers(){
+        let mut gen = Gen { next_temp : 100, next_label : 0 };
+        //20 values all live at once
+        let temps : Vec<Node> = (0..20).map(|i| FIRST_TEMP + i).collect();
+        let mut instrs : Vec<Instr> = temps.iter().map(|&t| Instr::oper("movl $1, `D0", vec![t], vec![])).collect();
+        for &t in temps.iter(){
+            instrs.push(Instr::oper("addl `S0, `D0", vec![RAX], vec![t, RAX]));
+        }
+        instrs.push(Instr::oper("", vec![], vec![RAX]));
+        let (instrs, regs, slots) = allocate(instrs, &mut gen);
+        assert!(slots >= 6);
+        //no two nodes live at the same time share a register
+        let live = liveness::live_out(&instrs);
+        for l in live.iter(){
+            let used : HashSet<Node> = l.iter().map(|n| regs[n]).collect();
+            assert_eq!(used.len(), l.len());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! The runtime library of tree::RUNTIME for the native backend, written
+//! against the c library. It behaves like the one the ir backend defines in
+//! llvm: strings are NUL terminated and allocations zeroed.
+
+pub const RUNTIME : &'static str = r#"
+    .type tiger_print, @function
+tiger_print:
+    pushq %rbp
+    movq %rsp, %rbp
+    movq %rdi, %rsi
+    leaq .Lfmt_str(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    popq %rbp
+    ret
+
+    .type tiger_print_int, @function
+tiger_print_int:
+    pushq %rbp
+    movq %rsp, %rbp
+    movl %edi, %esi
+    leaq .Lfmt_int(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    popq %rbp
+    ret
+
+    .type tiger_flush, @function
+tiger_flush:
+    pushq %rbp
+    movq %rsp, %rbp
+    xorl %edi, %edi
+    call fflush@PLT
+    popq %rbp
+    ret
+
+# "" at the end of the input
+    .type tiger_getchar, @function
+tiger_getchar:
+    pushq %rbp
+    movq %rsp, %rbp
+    call getchar@PLT
+    testl %eax, %eax
+    jns 1f
+    xorl %eax, %eax
+1:
+    movl %eax, %edi
+    call tiger_chr
+    popq %rbp
+    ret
+
+# -1 for the empty string
+    .type tiger_ord, @function
+tiger_ord:
+    movzbl (%rdi), %eax
+    testl %eax, %eax
+    jne 1f
+    movl $-1, %eax
+1:
+    ret
+
+    .type tiger_chr, @function
+tiger_chr:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %rbx
+    subq $8, %rsp
+    movl %edi, %ebx
+    movl $2, %edi
+    movl $1, %esi
+    call calloc@PLT
+    movb %bl, (%rax)
+    movq -8(%rbp), %rbx
+    leave
+    ret
+
+    .type tiger_size, @function
+tiger_size:
+    pushq %rbp
+    movq %rsp, %rbp
+    call strlen@PLT
+    popq %rbp
+    ret
+
+# (s, first, n)
+    .type tiger_substring, @function
+tiger_substring:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    subq $8, %rsp
+    movq %rdi, %r12
+    movslq %esi, %r13
+    movslq %edx, %r14
+    leaq 1(%r14), %rdi
+    movl $1, %esi
+    call calloc@PLT
+    movq %rax, %rdi
+    leaq (%r12,%r13), %rsi
+    movq %r14, %rdx
+    call memcpy@PLT
+    movq -8(%rbp), %r12
+    movq -16(%rbp), %r13
+    movq -24(%rbp), %r14
+    leave
+    ret
+
+    .type tiger_concat, @function
+tiger_concat:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %r12
+    movq %rsi, %r13
+    call strlen@PLT
+    movq %rax, %r14
+    movq %r13, %rdi
+    call strlen@PLT
+    movq %rax, %r15
+    leaq 1(%r14,%r15), %rdi
+    movl $1, %esi
+    call calloc@PLT
+    movq %rax, %rdi
+    movq %r12, %rsi
+    movq %r14, %rdx
+    call memcpy@PLT
+    leaq (%rax,%r14), %rdi
+    movq %r13, %rsi
+    movq %r15, %rdx
+    call memcpy@PLT
+    subq %r14, %rax
+    movq -8(%rbp), %r12
+    movq -16(%rbp), %r13
+    movq -24(%rbp), %r14
+    movq -32(%rbp), %r15
+    leave
+    ret
+
+    .type tiger_exit, @function
+tiger_exit:
+    pushq %rbp
+    movq %rsp, %rbp
+    call exit@PLT
+
+# zeroed memory of the given number of bytes
+    .type tiger_alloc, @function
+tiger_alloc:
+    pushq %rbp
+    movq %rsp, %rbp
+    movslq %edi, %rdi
+    movl $1, %esi
+    call calloc@PLT
+    popq %rbp
+    ret
+
+    .type tiger_strcmp, @function
+tiger_strcmp:
+    pushq %rbp
+    movq %rsp, %rbp
+    call strcmp@PLT
+    popq %rbp
+    ret
+
+# (line)
+    .type tiger_div_by_zero, @function
+tiger_div_by_zero:
+    pushq %rbp
+    movq %rsp, %rbp
+    movl %edi, %edx
+    movl $2, %edi
+    leaq .Lfmt_div(%rip), %rsi
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+    .section .rodata
+.Lfmt_str:
+    .string "%s"
+.Lfmt_int:
+    .string "%d\n"
+.Lfmt_div:
+    .string "line %d: division by zero\n"
+"#;
@@ -0,0 +1,126 @@
+//! The options that pick the target machine and how hard to optimize for
+//! it. The llvm backend turns them into its own settings.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocModel{
+    Default,
+    Static,
+    Pic,
+    Pie,
+    DynamicNoPic
+}
+
+impl FromStr for RelocModel{
+    type Err = String;
+    fn from_str(s : &str) -> Result<RelocModel, String>{
+        match s{
+            "default" => Ok(RelocModel::Default),
+            "static" => Ok(RelocModel::Static),
+            "pic" => Ok(RelocModel::Pic),
+            "pie" => Ok(RelocModel::Pie),
+            "dynamic-no-pic" => Ok(RelocModel::DynamicNoPic),
+            _ => Err(format!("unknown relocation model '{}'. Expected one of default, static, pic, pie, dynamic-no-pic", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeModel{
+    Default,
+    Small,
+    Kernel,
+    Medium,
+    Large
+}
+
+impl FromStr for CodeModel{
+    type Err = String;
+    fn from_str(s : &str) -> Result<CodeModel, String>{
+        match s{
+            "default" => Ok(CodeModel::Default),
+            "small" => Ok(CodeModel::Small),
+            "kernel" => Ok(CodeModel::Kernel),
+            "medium" => Ok(CodeModel::Medium),
+            "large" => Ok(CodeModel::Large),
+            _ => Err(format!("unknown code model '{}'. Expected one of default, small, kernel, medium, large", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TargetOptions{
+    //None means the host
+    pub triple : Option<String>,
+    //"native" picks the cpu and features of the host
+    pub cpu : String,
+    pub features : String,
+    pub reloc_model : RelocModel,
+    pub code_model : CodeModel,
+    pub opt_level : OptLevel
+}
+
+impl Default for TargetOptions{
+    fn default() -> Self{
+        TargetOptions {
+            triple : None,
+            cpu : String::from("generic"),
+            features : String::new(),
+            reloc_model : RelocModel::Default,
+            code_model : CodeModel::Default,
+            opt_level : OptLevel::O0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel{
+    O0,
+    O1,
+    O2,
+    O3
+}
+
+impl FromStr for OptLevel{
+    type Err = String;
+    fn from_str(s : &str) -> Result<OptLevel, String>{
+        match s{
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            _ => Err(format!("unknown optimization level '{}'. Expected 0, 1, 2 or 3", s))
+        }
+    }
+}
+
+impl Default for OptLevel{
+    fn default() -> Self{
+        OptLevel::O0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_opt_level(){
+        assert_eq!("3".parse::<OptLevel>(), Ok(OptLevel::O3));
+        assert!("s".parse::<OptLevel>().is_err());
+    }
+
+    #[test]
+    fn test_parse_reloc_model(){
+        assert_eq!("pie".parse::<RelocModel>(), Ok(RelocModel::Pie));
+        assert_eq!("dynamic-no-pic".parse::<RelocModel>(), Ok(RelocModel::DynamicNoPic));
+        assert!("ropi".parse::<RelocModel>().is_err());
+    }
+
+    #[test]
+    fn test_parse_code_model(){
+        assert_eq!("large".parse::<CodeModel>(), Ok(CodeModel::Large));
+        assert!("huge".parse::<CodeModel>().is_err());
+    }
+}
@@ -0,0 +1,9 @@
+#![crate_name = "session"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! What a compilation is asked to do, independent of the backend that does
+//! it: the options of the driver the backends share and the scratch space
+//! they build in.
+
+pub mod config;
+pub mod link;
@@ -0,0 +1,104 @@
+//! Linking options shared by the backends that hand their output to a
+//! system linker, and the temporary directories they build in.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linker{
+    Cc,
+    Clang,
+    //invokes the system linker directly; the crt objects are located through cc
+    Ld,
+    //stop after writing the object file
+    None
+}
+
+impl FromStr for Linker{
+    type Err = String;
+    fn from_str(s : &str) -> Result<Linker, String>{
+        match s{
+            "cc" => Ok(Linker::Cc),
+            "clang" => Ok(Linker::Clang),
+            "ld" => Ok(Linker::Ld),
+            "none" => Ok(Linker::None),
+            _ => Err(format!("unknown linker '{}'. Expected one of cc, clang, ld, none", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkOptions{
+    pub linker : Linker,
+    //the executable, or the object file when the linker is None
+    pub output : String,
+    pub lib_paths : Vec<String>,
+    pub libs : Vec<String>,
+    pub static_link : bool,
+    //pass -g to the c compiler, for sources with debug information to keep
+    pub debug_info : bool
+}
+
+impl Default for LinkOptions{
+    fn default() -> Self{
+        LinkOptions {
+            linker : Linker::Cc,
+            output : String::from("a.out"),
+            lib_paths : Vec::new(),
+            libs : Vec::new(),
+            static_link : false,
+            debug_info : false
+        }
+    }
+}
+
+//a directory only this process writes to; removed with everything in it on drop
+pub struct TempDir{
+    pub path : PathBuf
+}
+
+impl TempDir{
+    pub fn new() -> Result<TempDir, String>{
+        let base = env::temp_dir();
+        for i in 0..100{
+            let path = base.join(format!("charon-{}-{}", process::id(), i));
+            match fs::create_dir(&path){
+                Ok(_) => return Ok(TempDir { path : path }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("could not create a temporary directory in {} - {}", base.display(), e))
+            }
+        }
+        Err(format!("could not create a temporary directory in {}", base.display()))
+    }
+}
+
+impl Drop for TempDir{
+    fn drop(&mut self){
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_dirs_are_distinct_and_removed(){
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        assert!(a.path != b.path);
+        let path = a.path.clone();
+        assert!(path.is_dir());
+        drop(a);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_parse_linker(){
+        assert_eq!("ld".parse::<Linker>(), Ok(Linker::Ld));
+        assert!("gold".parse::<Linker>().is_err());
+    }
+}
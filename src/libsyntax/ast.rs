@@ -4,20 +4,51 @@ use std::fmt;
 use std::collections::{HashMap, BTreeMap};
 use visit::{Visitor};
 use ptr::{B};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 struct ExpressionEvaluator;
 
 pub type OptionalExprList = Option<Vec<B<Expr>>>;
 pub type OptionalExpr = Option<B<Expr>>;
 pub type OptionalTypeExprTupleList = Option<Vec<(TType, B<Expr>)>>;
-pub type OptionalParamInfoList = Option<Vec<(String, TType)>>;
+pub type OptionalParamInfoList = Option<Vec<(String, TType, Escape)>>;
 pub type OptionalIdTypePairs = Option<Vec<(String, TType)>>;
+//whether a variable is used by a function nested in the one that declares
+//it. Every variable escapes until the escape analysis finds otherwise
+#[derive(Debug, PartialEq, Clone)]
+pub struct Escape(Cell<bool>);
+
+impl Escape{
+    pub fn new() -> Self{
+        Escape(Cell::new(true))
+    }
+
+    pub fn escapes(&self) -> bool{
+        self.0.get()
+    }
+
+    pub fn set(&self, escapes : bool){
+        self.0.set(escapes)
+    }
+}
+
+impl Default for Escape{
+    fn default() -> Self{
+        Escape::new()
+    }
+}
+
+//position of a node in the source. Line 0 means the position is unknown
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span{
+    pub line : usize
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TType{
     TInt32,
     TString,
     TArray(B<TType>), //TType can be anything
-    TRecord,
+    TRecord(Vec<(String, TType)>), //the fields, in order
     TCustom(String),
     TNil,
     TVoid
@@ -29,7 +60,7 @@ impl fmt::Display for TType{
             TType::TInt32 => f.write_str("Number"),
             TType::TString => f.write_str("String"),
             TType::TArray(ref T) => f.write_str("Array of some type"),
-            TType::TRecord => f.write_str("Record"),
+            TType::TRecord(_) => f.write_str("Record"),
             TType::TCustom(ref name) => f.write_str("Custom"),
             TType::TNil => f.write_str("Nil"),
             TType::TVoid => f.write_str("Void")
@@ -120,8 +151,8 @@ pub enum Expr{
    NilExpr,
    //FIXME is this needed?
    LitExpr,
-   //stringLit
-   StringExpr(String),
+   //stringLit, as bytes since escapes can put any byte in it
+   StringExpr(Vec<u8>),
    //break
    BreakExpr,
    //id ( exp*, )
@@ -133,9 +164,14 @@ pub enum Expr{
    //array of int[3] of 0
    ArrayExpr(TType, B<Expr>, B<Expr>),
    RecordExpr(OptionalIdTypePairs),
-   FieldExpr(B<Expr>, B<Expr>),
-   SubscriptExpr(String, B<Expr>),
-   SubscriptSetExpr(String, B<Expr>, B<Expr>),
+   //tyId [exp] of exp
+   TypedArrayExpr(String, B<Expr>, B<Expr>),
+   //tyId {id = exp, }
+   TypedRecordExpr(String, Vec<(String, B<Expr>)>),
+   //lvalue.id or lvalue[exp]. A variable by itself is an IdExpr
+   LValueExpr(LValue),
+   //+ - and * are on 32 bit two's complement integers and wrap around when
+   //they overflow, unless the program is built with -C overflow-checks
    AddExpr(B<Expr>, B<Expr>),
    SubExpr(B<Expr>, B<Expr>),
    MulExpr(B<Expr>, B<Expr>),
@@ -151,11 +187,26 @@ pub enum Expr{
    IfThenExpr(B<Expr>, B<Expr>),
    IfThenElseExpr(B<Expr>, B<Expr>, B<Expr>),
    WhileExpr(B<Expr>, B<Expr>),
-   ForExpr(String, B<Expr>, B<Expr>, B<Expr>),
-   AssignExpr(B<Expr>, B<Expr>),
+   //for id := from to to do body
+   ForExpr(String, B<Expr>, B<Expr>, B<Expr>, Escape),
+   //lvalue := exp
+   AssignExpr(LValue, B<Expr>),
    LabelExpr(String),
    GotoExpr(String),
-   NoOpExpr
+   NoOpExpr,
+   //stands in for an expression that has a syntax error
+   ErrorExpr
+}
+
+//a location that can be read or assigned to
+#[derive(Debug, Clone)]
+pub enum LValue{
+    //id
+    SimpleVar(String),
+    //lvalue.id
+    FieldVar(B<LValue>, String),
+    //lvalue[exp]
+    SubscriptVar(B<LValue>, B<Expr>)
 }
 
 pub struct FieldDec{
@@ -170,10 +221,12 @@ pub enum Decl{
     //type tyId = ty
     TypeDec(String, TType),
     //var a : int := 1
-    VarDec(String, TType, B<Expr>),
+    VarDec(String, TType, B<Expr>, Escape),
     //function id ( fieldDec; ) : tyId = exp
     //last TType is the body's return type
-    FunDec(String, OptionalParamInfoList, TType, B<Expr>, TType)
+    FunDec(String, OptionalParamInfoList, TType, B<Expr>, TType),
+    //stands in for a declaration that has a syntax error
+    ErrorDec
 }
 
 //FIXME remove this
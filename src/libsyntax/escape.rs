@@ -0,0 +1,214 @@
+//! Escape analysis: finds the variables, parameters and loop indices that
+//! functions nested in the one that declares them use. Only those need a
+//! place in memory that inner functions can reach; the others can live in
+//! registers or plain locals.
+//!
+//! The result goes into the `Escape` of each declaration. Run it after the
+//! type checker.
+
+use ast::{Expr, LValue, Decl, Escape};
+
+struct EscapeFinder<'e>{
+    //the variables in scope, with the nesting depth of the function that
+    //declares them. Scopes are separated by None markers
+    env : Vec<(&'e str, Option<(usize, &'e Escape)>)>,
+    //the nesting depth of the function being walked; 0 is the top level
+    depth : usize
+}
+
+impl<'e> EscapeFinder<'e>{
+    fn declare(&mut self, name : &'e str, escape : &'e Escape){
+        escape.set(false);
+        let depth = self.depth;
+        self.env.push((name, Some((depth, escape))));
+    }
+
+    fn begin_scope(&mut self){
+        self.env.push(("<marker>", None));
+    }
+
+    fn end_scope(&mut self){
+        while self.env.pop().unwrap().1.is_some(){}
+    }
+
+    fn use_var(&self, id : &str){
+        for &(name, ref var) in self.env.iter().rev(){
+            if let Some((depth, escape)) = *var{
+                if name == id{
+                    if depth < self.depth{
+                        escape.set(true);
+                    }
+                    return
+                }
+            }
+        }
+    }
+
+    //an lvalue uses the variable it starts with and whatever its subscripts do
+    fn lvalue(&mut self, lvalue : &'e LValue){
+        match *lvalue{
+            LValue::SimpleVar(ref id) => self.use_var(id),
+            LValue::FieldVar(ref rec, _) => self.lvalue(rec),
+            LValue::SubscriptVar(ref arr, ref idx) => {
+                self.lvalue(arr);
+                self.expr(idx);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr : &'e Expr){
+        match *expr{
+            Expr::IdExpr(ref id) => self.use_var(id),
+            Expr::LValueExpr(ref lvalue) => self.lvalue(lvalue),
+            Expr::AssignExpr(ref lvalue, ref rhs) => {
+                self.lvalue(lvalue);
+                self.expr(rhs);
+            },
+            Expr::CallExpr(_, ref args) => {
+                for &(_, ref e) in args.iter().flat_map(|a| a.iter()){
+                    self.expr(e);
+                }
+            },
+            Expr::SeqExpr(ref exprs) => {
+                for e in exprs.iter().flat_map(|e| e.iter()){
+                    self.expr(e);
+                }
+            },
+            Expr::TypedRecordExpr(_, ref fields) => {
+                for &(_, ref e) in fields.iter(){
+                    self.expr(e);
+                }
+            },
+            Expr::ArrayExpr(_, ref l, ref r) | Expr::TypedArrayExpr(_, ref l, ref r) |
+            Expr::AddExpr(ref l, ref r) | Expr::SubExpr(ref l, ref r) | Expr::MulExpr(ref l, ref r) |
+            Expr::DivExpr(ref l, ref r) | Expr::ModExpr(ref l, ref r) | Expr::EqualsExpr(ref l, ref r) |
+            Expr::NotEqualsExpr(ref l, ref r) | Expr::LessThanExpr(ref l, ref r) | Expr::LessThanEqualsExpr(ref l, ref r) |
+            Expr::GreaterThanExpr(ref l, ref r) | Expr::GreaterThanEqualsExpr(ref l, ref r) |
+            Expr::IfThenExpr(ref l, ref r) | Expr::WhileExpr(ref l, ref r) => {
+                self.expr(l);
+                self.expr(r);
+            },
+            Expr::IfThenElseExpr(ref c, ref t, ref e) => {
+                self.expr(c);
+                self.expr(t);
+                self.expr(e);
+            },
+            Expr::ForExpr(ref id, ref from, ref to, ref body, ref escape) => {
+                self.expr(from);
+                self.expr(to);
+                self.begin_scope();
+                self.declare(id, escape);
+                self.expr(body);
+                self.end_scope();
+            },
+            Expr::LetExpr(ref decls, ref body) => {
+                self.begin_scope();
+                for d in decls.iter(){
+                    self.decl(d);
+                }
+                if let Some(ref b) = *body{
+                    self.expr(b);
+                }
+                self.end_scope();
+            },
+            _ => {}
+        }
+    }
+
+    fn decl(&mut self, decl : &'e Decl){
+        match *decl{
+            Decl::VarDec(ref name, _, ref rhs, ref escape) => {
+                self.expr(rhs);
+                self.declare(name, escape);
+            },
+            Decl::FunDec(_, ref params, _, ref body, _) => {
+                self.depth += 1;
+                self.begin_scope();
+                for &(ref p, _, ref escape) in params.iter().flat_map(|p| p.iter()){
+                    self.declare(p, escape);
+                }
+                self.expr(body);
+                self.end_scope();
+                self.depth -= 1;
+            },
+            Decl::TypeDec(..) | Decl::ErrorDec => {}
+        }
+    }
+}
+
+//marks every declaration in `expr` as escaping or not
+pub fn find_escapes(expr : &Expr){
+    let mut finder = EscapeFinder { env : Vec::new(), depth : 0 };
+    finder.expr(expr);
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::{Expr, Decl};
+    use parse::parser::{Parser};
+    use super::*;
+
+    fn analyzed(src : &str) -> Expr{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, b_expr) = p.expr().unwrap();
+        find_escapes(&b_expr);
+        (*b_expr).clone()
+    }
+
+    //the escape flags of the declarations of a let, in order, parameters
+    //of functions included
+    fn flags(e : &Expr) -> Vec<bool>{
+        let mut v = Vec::new();
+        if let Expr::LetExpr(ref decls, _) = *e{
+            for d in decls.iter(){
+                match *d{
+                    Decl::VarDec(_, _, _, ref escape) => v.push(escape.escapes()),
+                    Decl::FunDec(_, ref params, _, _, _) =>
+                        v.extend(params.iter().flat_map(|p| p.iter()).map(|p| p.2.escapes())),
+                    _ => {}
+                }
+            }
+        }
+        v
+    }
+
+    #[test]
+    fn test_escape_locals_dont_escape(){
+        let e = analyzed("let var a : int := 1 in let function f(b:int) : int = b + 1 in print(f(a)) end end");
+        assert_eq!(flags(&e), vec![false]);
+        if let Expr::LetExpr(_, Some(ref body)) = e{
+            assert_eq!(flags(body), vec![false]);
+        }
+    }
+
+    #[test]
+    fn test_escape_used_by_a_nested_function(){
+        let e = analyzed("let var a : int := 1 in let var b : int := 2 in let function f() : int = a in print(f() + b) end end end");
+        assert_eq!(flags(&e), vec![true]);
+        if let Expr::LetExpr(_, Some(ref body)) = e{
+            assert_eq!(flags(body), vec![false]);
+        }
+    }
+
+    #[test]
+    fn test_escape_params_and_loop_indices(){
+        let e = analyzed("let function f(a:int, b:int) : int = let function g() : int = b in g() end in print(f(1, 2)) end");
+        assert_eq!(flags(&e), vec![false, true]);
+        let e = analyzed("for i := 1 to 3 do let function f() : int = i in print(f()) end");
+        match e{
+            Expr::ForExpr(_, _, _, _, ref escape) => assert!(escape.escapes()),
+            _ => panic!("Expected a for loop")
+        }
+    }
+
+    #[test]
+    fn test_escape_shadowing(){
+        //the inner a is the one f uses
+        let e = analyzed("let var a : int := 1 in let var a : int := 2 in let function f() : int = a in print(f()) end end end");
+        assert_eq!(flags(&e), vec![false]);
+        if let Expr::LetExpr(_, Some(ref body)) = e{
+            assert_eq!(flags(body), vec![true]);
+        }
+    }
+}
@@ -18,3 +18,4 @@ pub mod syntax {
 
 pub mod visit;
 pub mod visitor_impl;
+pub mod escape;
@@ -1,27 +1,68 @@
 #![allow(dead_code)]
 
+use std::collections::VecDeque;
+use std::mem;
 use parse::tokens::*;
 
+//a comment as written in the source, kept for tools that need more than
+//the tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment{
+    //the line of the opening '/*'
+    pub line : usize,
+    pub text : String
+}
+
+//turns the source into tokens. The lexer is an iterator over them and can
+//look any number of tokens ahead
 #[derive(Default)]
 pub struct Lexer{
-    pub curr_token : Token,
-    next_token : Token,
+    curr_token : Token,
     curr_char : char,
-    pub curr_string : String,
-    src_code : Vec<u8>,
+    curr_string : String,
+    //the contents of the string literal being lexed. Escapes can put any
+    //byte in a string, so they are kept as bytes
+    curr_bytes : Vec<u8>,
+    src_code : Vec<char>,
     char_pos : usize,
-    pub line_pos : usize
+    line_pos : usize,
+    //the column of curr_char, in characters
+    col_pos : usize,
+    //where the token being lexed starts
+    tok_line : usize,
+    tok_col : usize,
+    //tokens lexed by peek() that next() hasn't handed out yet
+    lookahead : VecDeque<SpannedToken>,
+    //the errors found while lexing a token, handed out as Error tokens
+    //ahead of it
+    pending : VecDeque<SpannedToken>,
+    pub comments : Vec<Comment>
+}
+
+//the source code in `bytes`, or where it stops being UTF-8
+pub fn decode_source(bytes : Vec<u8>) -> Result<String, String>{
+    String::from_utf8(bytes).map_err(|e| {
+        let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+        //the prefix is valid, so it can be read as characters
+        let prefix = String::from_utf8_lossy(valid);
+        let line = prefix.matches('\n').count() + 1;
+        let col = prefix.rsplit('\n').next().unwrap().chars().count() + 1;
+        format!("Invalid UTF-8 on line {}, column {}", line, col)
+    })
 }
 
 impl Lexer{
     pub fn new(src_code : String)->Self{
-        Lexer{ src_code : src_code.as_bytes().to_vec(), line_pos : 1, ..Default::default()}
+        Lexer{ src_code : src_code.chars().collect(), line_pos : 1, ..Default::default()}
     }
 
-    //FIXME: get_char() shouldn't be exposed
-    pub fn get_char(&mut self){
+    fn get_char(&mut self){
+        //the eof has a column of its own, right after the last character
+        if self.char_pos == 0 || self.curr_char != '\0' {
+            self.col_pos = if self.curr_char == '\n' {1} else {self.col_pos + 1};
+        }
         if self.char_pos < self.src_code.len() {
-            self.curr_char = self.src_code[self.char_pos] as char;
+            self.curr_char = self.src_code[self.char_pos];
             self.char_pos += 1;
         }
         else{
@@ -29,22 +70,182 @@ impl Lexer{
         }
     }
 
-    pub fn peek_next(&mut self) -> Token{
-        //save context
-        let old_pos = self.char_pos;
-        let old_tok = self.curr_token;
-        let old_string = self.curr_string.clone(); 
-        let t = self.get_token();
-        //load saved context
-        self.char_pos = old_pos;
-        self.curr_token = old_tok;
-        self.curr_string = old_string;
-        t
+    //the character after curr_char, without moving on to it
+    fn next_char(&self) -> char{
+        if self.char_pos < self.src_code.len() {self.src_code[self.char_pos]} else {'\0'}
+    }
+
+    //reports a lexical error at `line`, `col`. The error becomes an Error
+    //token, with the message as its text
+    fn error(&mut self, line : usize, col : usize, message : String){
+        self.pending.push_back(SpannedToken { token : Token::Error, text : message, line : line, col : col, ..Default::default() });
+    }
+
+    //the token k places after the one next() returns next, so peek(0) is that
+    //token itself. None past the eof
+    pub fn peek(&mut self, k : usize) -> Option<&SpannedToken>{
+        while self.lookahead.len() <= k{
+            match self.lex(){
+                Some(t) => self.lookahead.push_back(t),
+                None => break
+            }
+        }
+        self.lookahead.get(k)
+    }
+
+    //the next token from the source. The eof is the last one
+    fn lex(&mut self) -> Option<SpannedToken>{
+        if self.pending.is_empty(){
+            if self.curr_token == Token::Eof{
+                return None
+            }
+            if self.char_pos == 0{
+                self.get_char();
+            }
+            let token = self.get_token();
+            let spanned = SpannedToken{
+                token : token,
+                text : mem::replace(&mut self.curr_string, String::new()),
+                bytes : mem::replace(&mut self.curr_bytes, Vec::new()),
+                line : self.tok_line,
+                col : self.tok_col
+            };
+            //after the errors found on the way
+            self.pending.push_back(spanned);
+        }
+        self.pending.pop_front()
+    }
+
+    //reads the escape sequence after a '\' in a string literal into
+    //curr_bytes. curr_char is the '\' and is left on the last character of
+    //the escape. An invalid escape is reported and leaves out what follows
+    //the '\', so the string still ends at its closing '"'
+    fn escape(&mut self){
+        let (line, col) = (self.line_pos, self.col_pos);
+        match self.next_char(){
+            c @ 'n' | c @ 't' | c @ '"' | c @ '\\' => {
+                self.get_char();
+                self.curr_bytes.push(match c { 'n' => b'\n', 't' => b'\t', c => c as u8 });
+            },
+            '0' ... '9' => {
+                //\ddd, the byte with the decimal code ddd
+                let mut code = 0;
+                for _ in 0..3{
+                    match self.next_char().to_digit(10){
+                        Some(d) => {
+                            self.get_char();
+                            code = code * 10 + d;
+                        },
+                        None => {
+                            self.error(line, col, String::from("Invalid escape sequence. '\\ddd' takes exactly three decimal digits"));
+                            return
+                        }
+                    }
+                }
+                if code > 255{
+                    self.error(line, col, format!("Invalid escape sequence '\\{:03}'. Character codes go up to 255", code));
+                    return
+                }
+                self.curr_bytes.push(code as u8);
+            },
+            '^' => {
+                //\^c, the control character c. Lowercase letters stand for
+                //the same characters as uppercase ones
+                self.get_char();
+                let code = match self.next_char(){
+                    '?' => 127,
+                    c @ '@' ... '_' => c as u8 - b'@',
+                    c @ 'a' ... 'z' => c as u8 - b'a' + 1,
+                    _ => {
+                        self.error(line, col, String::from("Invalid escape sequence. '\\^' must be followed by a letter or one of @[\\]^_?"));
+                        return
+                    }
+                };
+                self.get_char();
+                self.curr_bytes.push(code);
+            },
+            ' ' | '\t' | '\n' | '\r' | '\x0c' => {
+                //\f___f\, whitespace that isn't part of the string so that
+                //a literal can span lines
+                self.get_char();
+                loop{
+                    if self.curr_char == '\n'{
+                        self.line_pos += 1;
+                    }
+                    match self.next_char(){
+                        ' ' | '\t' | '\n' | '\r' | '\x0c' => self.get_char(),
+                        '\\' => {
+                            self.get_char();
+                            break;
+                        },
+                        //the string is unterminated, which the caller reports
+                        '\0' => break,
+                        c => {
+                            let c_col = if self.curr_char == '\n' {1} else {self.col_pos + 1};
+                            let line_pos = self.line_pos;
+                            self.error(line_pos, c_col, format!("Unexpected '{}'. Only whitespace can follow the '\\' of line {} until the next '\\'", c.escape_default(), line));
+                            break;
+                        }
+                    }
+                }
+            },
+            //the string is unterminated, which the caller reports
+            '\0' => {},
+            c => {
+                self.get_char();
+                self.error(line, col, format!("Unrecognized escape sequence '\\{}'", c.escape_default()));
+            }
+        }
+    }
+
+    //skips a comment, comments nested in it included. curr_char is the '*'
+    //of the opening '/*'
+    fn skip_comment(&mut self){
+        //where the '/' is
+        let (line, col) = (self.line_pos, self.col_pos - 1);
+        let mut text = String::from("/*");
+        let mut depth = 1;
+        self.get_char();
+        while depth > 0{
+            match self.curr_char{
+                '\0' => {
+                    self.error(line, col, String::from("Unterminated comment. Expected a closing '*/'"));
+                    break;
+                },
+                c @ '/' | c @ '*' => {
+                    text.push(c);
+                    self.get_char();
+                    if c == '/' && self.curr_char == '*'{
+                        depth += 1;
+                    }
+                    else if c == '*' && self.curr_char == '/'{
+                        depth -= 1;
+                    }
+                    else{
+                        continue;
+                    }
+                    text.push(self.curr_char);
+                    self.get_char();
+                },
+                c => {
+                    if c == '\n'{
+                        self.line_pos += 1;
+                    }
+                    text.push(c);
+                    self.get_char();
+                }
+            }
+        }
+        self.comments.push(Comment { line : line, text : text });
     }
 
-    pub fn get_token(&mut self) -> Token{
+    fn get_token(&mut self) -> Token{
         //do not loop over the match
         //this will cause a problem for ident storing (curr_string.clear())
+        self.curr_string.clear();
+        self.curr_bytes.clear();
+        self.tok_line = self.line_pos;
+        self.tok_col = self.col_pos;
         macro_rules! get_cur_tok_and_eat{
             //without the double curly braces, compiler complains saying everything after the
             //first statement will be ignored. So we tell it to treat the body as a block
@@ -54,7 +255,16 @@ impl Lexer{
             '+' => { get_cur_tok_and_eat!(Token::Plus)},
             '-' => { get_cur_tok_and_eat!(Token::Minus)},
             '*' => { get_cur_tok_and_eat!(Token::Mul)},
-            '/' => { get_cur_tok_and_eat!(Token::Div)},
+            '/' => {
+                self.get_char();
+                if self.curr_char != '*'{
+                    self.curr_token = Token::Div;
+                    return self.curr_token
+                }
+                self.skip_comment();
+                self.curr_token = self.get_token();
+                self.curr_token
+            },
             '&' => { get_cur_tok_and_eat!(Token::LogAnd)},
             '|' => { get_cur_tok_and_eat!(Token::LogOr)},
             '>' => {
@@ -113,19 +323,10 @@ impl Lexer{
              },
             ';' => { self.curr_token = Token::SemiColon; self.get_char(); self.curr_token},
             '"' => {
-                self.curr_string.clear();
                 loop {
                     self.get_char();
                     if self.curr_char == '\\' {
-                        self.get_char();
-                        match self.curr_char {
-                            'n' => self.curr_string.push('\n'),
-                            't' => self.curr_string.push('\t'),
-                            '"' => self.curr_string.push('"'),
-                            '\\' => self.curr_string.push('\\'),
-                            _ => panic!("Unrecognized escape sequence")
-                        }
-                        //self.curr_string.push(self.curr_char);
+                        self.escape();
                         continue;
                     }
 
@@ -135,11 +336,19 @@ impl Lexer{
                     }
 
                     if self.curr_char == '\0' {
-                        panic!("Unexpected eof. Expected a closing '\"'.");
+                        //what was read still makes a string, so parsing can go on
+                        let (line, col) = (self.tok_line, self.tok_col);
+                        self.error(line, col, String::from("Unterminated string. Expected a closing '\"'"));
+                        break;
                     }
 
-                    self.curr_string.push(self.curr_char);
+                    if self.curr_char == '\n' {
+                        self.line_pos += 1;
+                    }
+                    let mut utf8 = [0; 4];
+                    self.curr_bytes.extend_from_slice(self.curr_char.encode_utf8(&mut utf8).as_bytes());
                 }
+                self.curr_string = String::from_utf8_lossy(&self.curr_bytes).into_owned();
 
                 self.curr_token = Token::TokString;
                 self.curr_token
@@ -155,14 +364,21 @@ impl Lexer{
                 self.curr_token = Token::Number;
                 self.curr_token
             },
-            'a' ... 'z' => {
+            'a' ... 'z' | 'A' ... 'Z' | '_' => {
                 self.curr_string.clear();
                 self.curr_string.push(self.curr_char);
                 self.get_char();
-                while self.curr_char.is_alphanumeric() && self.curr_char != '\0' {
+                while self.curr_char.is_ascii_alphanumeric() || self.curr_char == '_' {
                     self.curr_string.push(self.curr_char);
                     self.get_char();
                 }
+                //identifiers start with a letter. _main is the name Tiger
+                //gives the main program
+                if self.curr_string.starts_with('_') && self.curr_string != "_main"{
+                    let (line, col) = (self.tok_line, self.tok_col);
+                    let msg = format!("Invalid identifier '{}'. Identifiers must start with a letter", self.curr_string);
+                    self.error(line, col, msg);
+                }
 
                 self.curr_token = self.match_token(); //mat(&self.curr_string) {return Token::} else {return Token::Ident}
                 self.curr_token
@@ -172,9 +388,10 @@ impl Lexer{
             '\0' => {self.curr_token = Token::Eof; self.curr_token},
             '\n' => { self.line_pos += 1; self.curr_token = Token::NewLine; self.get_char(); self.curr_token },
             c if c.is_whitespace() => {
+                //a newline is a token of its own
                 loop{
                     self.get_char();
-                    if !self.curr_char.is_whitespace(){
+                    if !self.curr_char.is_whitespace() || self.curr_char == '\n'{
                         break;
                     }
                 }
@@ -182,7 +399,14 @@ impl Lexer{
                 self.curr_token
             },
 
-            _ => {self.curr_token = Token::Error; self.curr_token}
+            c => {
+                //the character is reported and skipped
+                let (line, col) = (self.line_pos, self.col_pos);
+                self.error(line, col, format!("Unexpected character '{}'", c.escape_default()));
+                self.get_char();
+                self.curr_token = self.get_token();
+                self.curr_token
+            }
         }
     }
 
@@ -204,7 +428,6 @@ impl Lexer{
            "let"      => Token::Let,
            "var"      => Token::Var,
            "array"    => Token::Array,
-           "rec"    => Token::Rec,
            "of"       => Token::Of,
            "type"      => Token::Type,
            "break"      => Token::Break,
@@ -221,11 +444,23 @@ impl Lexer{
            "int"    => Token::Int,
            "string" => Token::TokString,
            "to" => Token::To,
+           "mod" => Token::Mod,
             _ => Token::Ident
         }
     }
 }
 
+impl Iterator for Lexer{
+    type Item = SpannedToken;
+
+    fn next(&mut self) -> Option<SpannedToken>{
+        match self.lookahead.pop_front(){
+            Some(t) => Some(t),
+            None => self.lex()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use parse::tokens::*;
@@ -298,6 +533,12 @@ mod tests {
         assert!(l.line_pos == 4);
     }
 
+    #[test]
+    fn test_newline_after_spaces(){
+        let lines : Vec<(Token, usize)> = Lexer::new("a  \n \n\tb".to_string()).map(|t| (t.token, t.line)).collect();
+        assert_eq!(lines, vec![(Token::Ident, 1), (Token::NewLine, 1), (Token::NewLine, 2), (Token::Ident, 3), (Token::Eof, 3)]);
+    }
+
     #[test]
     fn test_let_block(){
         let mut l = Lexer::new("let var a : int := 1 in end".to_string());
@@ -531,13 +772,48 @@ mod tests {
     }
     #[test] 
     fn test_peek(){
-        let mut l = Lexer::new("1 <> 1".to_string());
-        l.get_char();
-        assert_eq!(l.get_token(), Token::Number);
-        assert_eq!(l.peek_next(), Token::LessThanGreaterThan);
-        assert_eq!(l.peek_next(), Token::LessThanGreaterThan);
-        assert_eq!(l.get_token(), Token::LessThanGreaterThan);
-        assert_eq!(l.get_token(), Token::Number);
+        let mut l = Lexer::new("1 <> 2".to_string());
+        assert_eq!(l.next().unwrap().token, Token::Number);
+        assert_eq!(l.peek(0).unwrap().token, Token::LessThanGreaterThan);
+        assert_eq!(l.peek(0).unwrap().token, Token::LessThanGreaterThan);
+        assert_eq!(l.peek(1).unwrap().text, "2");
+        assert_eq!(l.peek(2).unwrap().token, Token::Eof);
+        assert_eq!(l.peek(3), None);
+        assert_eq!(l.next().unwrap().token, Token::LessThanGreaterThan);
+        assert_eq!(l.next().unwrap().text, "2");
+        assert_eq!(l.next().unwrap().token, Token::Eof);
+        assert_eq!(l.next(), None);
+    }
+
+    fn spanned(token : Token, text : &str, line : usize, col : usize) -> SpannedToken{
+        let bytes = if token == Token::TokString {text.as_bytes().to_vec()} else {Vec::new()};
+        SpannedToken { token : token, text : text.to_string(), bytes : bytes, line : line, col : col }
+    }
+
+    #[test]
+    fn test_tokens_carry_their_text(){
+        let tokens : Vec<SpannedToken> = Lexer::new("let var x := 42 in\n  print(\"a\\tb\") end".to_string()).collect();
+        assert_eq!(tokens, vec![spanned(Token::Let, "let", 1, 1),
+                                spanned(Token::Var, "var", 1, 5),
+                                spanned(Token::Ident, "x", 1, 9),
+                                spanned(Token::ColonEquals, "", 1, 11),
+                                spanned(Token::Number, "42", 1, 14),
+                                spanned(Token::In, "in", 1, 17),
+                                spanned(Token::NewLine, "", 1, 19),
+                                spanned(Token::Ident, "print", 2, 3),
+                                spanned(Token::LeftParen, "", 2, 8),
+                                spanned(Token::TokString, "a\tb", 2, 9),
+                                spanned(Token::RightParen, "", 2, 15),
+                                spanned(Token::End, "end", 2, 17),
+                                spanned(Token::Eof, "", 2, 20)]);
+    }
+
+    #[test]
+    fn test_peeked_tokens_keep_their_text(){
+        let mut l = Lexer::new("a b c".to_string());
+        assert_eq!(l.peek(2).unwrap().text, "c");
+        let texts : Vec<String> = l.map(|t| t.text).collect();
+        assert_eq!(texts, vec!["a", "b", "c", ""]);
     }
     #[test] 
     fn test_div_expr(){
@@ -556,6 +832,16 @@ mod tests {
         assert_eq!(l.get_token(), Token::Number);
     }
 
+    #[test] 
+    fn test_mod_expr(){
+        let mut l = Lexer::new("7 mod 3 modulo".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::Number);
+        assert_eq!(l.get_token(), Token::Mod);
+        assert_eq!(l.get_token(), Token::Number);
+        assert_eq!(l.get_token(), Token::Ident);
+    }
+
     #[test] 
     fn test_function_call_with_two_args(){
         let mut l = Lexer::new("f(a()+1)".to_string());
@@ -648,4 +934,209 @@ mod tests {
         assert_eq!(l.get_token(), Token::Ident);
 
     }
+
+    #[test]
+    fn test_comments_are_skipped(){
+        let mut l = Lexer::new("1 /* a * b / c */ / /**/2".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::Number);
+        assert_eq!(l.get_token(), Token::Div);
+        assert_eq!(l.get_token(), Token::Number);
+        assert_eq!(l.curr_string, "2");
+        assert_eq!(l.get_token(), Token::Eof);
+        assert_eq!(l.comments, vec![Comment { line : 1, text : String::from("/* a * b / c */") },
+                                    Comment { line : 1, text : String::from("/**/") }]);
+    }
+
+    #[test]
+    fn test_nested_comments(){
+        let mut l = Lexer::new("/* a /* b */ c */ x /*/ */ y".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::Ident);
+        assert_eq!(l.curr_string, "x");
+        assert_eq!(l.get_token(), Token::Ident);
+        assert_eq!(l.curr_string, "y");
+        assert_eq!(l.comments[0].text, "/* a /* b */ c */");
+    }
+
+    #[test]
+    fn test_lines_in_comments(){
+        let mut l = Lexer::new("a /* 1\n2\n */\nb".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::Ident);
+        assert!(l.comments.is_empty());
+        assert_eq!(l.get_token(), Token::NewLine);
+        assert_eq!(l.line_pos, 4);
+        assert_eq!(l.get_token(), Token::Ident);
+        assert_eq!(l.comments[0].line, 1);
+    }
+
+    #[test]
+    fn test_unterminated_comment(){
+        let tokens : Vec<SpannedToken> = Lexer::new("a\n  /* /* */ b".to_string()).collect();
+        let kinds : Vec<Token> = tokens.iter().map(|t| t.token).collect();
+        assert_eq!(kinds, vec![Token::Ident, Token::NewLine, Token::Error, Token::Eof]);
+        assert_eq!((tokens[2].line, tokens[2].col), (2, 3));
+        assert_eq!(tokens[2].text, "Unterminated comment. Expected a closing '*/'");
+    }
+
+    fn string(src : &str) -> Vec<u8>{
+        let mut l = Lexer::new(src.to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::TokString);
+        l.curr_bytes
+    }
+
+    //the lexical errors in src, with their line and column
+    fn errors(src : &str) -> Vec<(usize, usize, String)>{
+        Lexer::new(src.to_string()).filter(|t| t.token == Token::Error).map(|t| (t.line, t.col, t.text)).collect()
+    }
+
+    #[test]
+    fn test_string_decimal_escapes(){
+        assert_eq!(string("\"\\065\\066C\\000\\255\""), b"ABC\0\xff");
+    }
+
+    #[test]
+    fn test_string_decimal_escape_out_of_range(){
+        assert_eq!(errors("\"\\256\""), vec![(1, 2, String::from("Invalid escape sequence '\\256'. Character codes go up to 255"))]);
+    }
+
+    #[test]
+    fn test_string_decimal_escape_too_short(){
+        let msg = String::from("Invalid escape sequence. '\\ddd' takes exactly three decimal digits");
+        assert_eq!(errors("x \"\\12\""), vec![(1, 4, msg)]);
+        //the closing quote isn't taken for a digit
+        assert_eq!(string("\"a\\1\""), b"a");
+    }
+
+    #[test]
+    fn test_string_control_escapes(){
+        assert_eq!(string("\"\\^@\\^A\\^a\\^J\\^[\\^_\\^?\""), b"\0\x01\x01\n\x1b\x1f\x7f");
+    }
+
+    #[test]
+    fn test_string_lowercase_control_escapes(){
+        for (lower, upper) in (b'a'..b'z' + 1).zip(b'A'..b'Z' + 1){
+            let src = format!("\"\\^{}\\^{}\"", lower as char, upper as char);
+            assert_eq!(string(&src), vec![upper - b'@'; 2]);
+        }
+    }
+
+    #[test]
+    fn test_string_invalid_control_escape(){
+        let msg = String::from("Invalid escape sequence. '\\^' must be followed by a letter or one of @[\\]^_?");
+        assert_eq!(errors("\"\\^1\""), vec![(1, 2, msg)]);
+        assert_eq!(string("\"\\^1\""), b"1");
+    }
+
+    #[test]
+    fn test_string_whitespace_escape(){
+        let mut l = Lexer::new("\"ab\\  \n\t  \\cd\"\nx".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::TokString);
+        assert_eq!(l.curr_string, "abcd");
+        assert_eq!(l.line_pos, 2);
+        assert_eq!(l.get_token(), Token::NewLine);
+        assert_eq!(l.line_pos, 3);
+    }
+
+    #[test]
+    fn test_string_malformed_whitespace_escape(){
+        let msg = String::from("Unexpected 'x'. Only whitespace can follow the '\\' of line 1 until the next '\\'");
+        assert_eq!(errors("\"ab\\ \n x\\\"\""), vec![(2, 2, msg)]);
+    }
+
+    #[test]
+    fn test_string_unknown_escape(){
+        assert_eq!(errors("\"\\q\""), vec![(1, 2, String::from("Unrecognized escape sequence '\\q'"))]);
+    }
+
+    #[test]
+    fn test_errors_come_before_their_token(){
+        let tokens : Vec<Token> = Lexer::new("f(\"a\\q\")".to_string()).map(|t| t.token).collect();
+        assert_eq!(tokens, vec![Token::Ident, Token::LeftParen, Token::Error, Token::TokString, Token::RightParen, Token::Eof]);
+    }
+
+    fn idents(src : &str) -> Vec<String>{
+        let mut l = Lexer::new(src.to_string());
+        l.get_char();
+        let mut v = Vec::new();
+        while l.get_token() == Token::Ident || l.curr_token == Token::NewLine{
+            if l.curr_token == Token::Ident{
+                v.push(l.curr_string.clone());
+            }
+        }
+        assert_eq!(l.curr_token, Token::Eof);
+        v
+    }
+
+    #[test]
+    fn test_identifiers(){
+        assert_eq!(idents("Point my_var x_1 a__ Z9 _main"), vec!["Point", "my_var", "x_1", "a__", "Z9", "_main"]);
+    }
+
+    #[test]
+    fn test_keywords_are_case_sensitive(){
+        assert_eq!(idents("Let VAR If"), vec!["Let", "VAR", "If"]);
+        let mut l = Lexer::new("let".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::Let);
+    }
+
+    #[test]
+    fn test_identifier_starting_with_underscore(){
+        let msg = String::from("Invalid identifier '_x'. Identifiers must start with a letter");
+        assert_eq!(errors("a _x"), vec![(1, 3, msg)]);
+        //the identifier is still handed out
+        assert_eq!(idents("a _x"), vec!["a", "_x"]);
+    }
+
+    #[test]
+    fn test_invalid_character(){
+        assert_eq!(errors("a\n #"), vec![(2, 2, String::from("Unexpected character '#'"))]);
+        //lexing carries on after it
+        let tokens : Vec<Token> = Lexer::new("a @ 2".to_string()).map(|t| t.token).collect();
+        assert_eq!(tokens, vec![Token::Ident, Token::Error, Token::Number, Token::Eof]);
+    }
+
+    #[test]
+    fn test_non_ascii_letters_arent_identifiers(){
+        assert_eq!(errors("caf\u{e9}"), vec![(1, 4, String::from("Unexpected character '\\u{e9}'"))]);
+    }
+
+    #[test]
+    fn test_unterminated_string(){
+        assert_eq!(errors("x := \"ab\ncd"), vec![(1, 6, String::from("Unterminated string. Expected a closing '\"'"))]);
+        assert_eq!(string("\"ab\\"), b"ab");
+        let tokens : Vec<Token> = Lexer::new("\"ab".to_string()).map(|t| t.token).collect();
+        assert_eq!(tokens, vec![Token::Error, Token::TokString, Token::Eof]);
+    }
+
+    #[test]
+    fn test_utf8_strings(){
+        assert_eq!(string("\"h\u{e9}llo \u{1f600}\""), "h\u{e9}llo \u{1f600}".as_bytes());
+        //\233 is a single byte, not the two of U+00E9
+        assert_eq!(string("\"\\233\""), b"\xe9");
+    }
+
+    #[test]
+    fn test_columns_count_characters(){
+        let mut l = Lexer::new("\"\u{e9}\u{e9}\" +\n  b c".to_string());
+        l.get_char();
+        assert_eq!(l.get_token(), Token::TokString);
+        assert_eq!(l.col_pos, 5);
+        assert_eq!(l.get_token(), Token::Plus);
+        assert_eq!(l.get_token(), Token::NewLine);
+        assert_eq!(l.get_token(), Token::Ident);
+        assert_eq!((l.line_pos, l.col_pos), (2, 4));
+    }
+
+    #[test]
+    fn test_decode_source(){
+        assert_eq!(decode_source("a \u{e9}".as_bytes().to_vec()), Ok(String::from("a \u{e9}")));
+        assert_eq!(decode_source(b"ab\n\xc3\xa9\xff".to_vec()), Err(String::from("Invalid UTF-8 on line 2, column 2")));
+        //a truncated sequence
+        assert_eq!(decode_source(b"\"\xe2\x82\"".to_vec()), Err(String::from("Invalid UTF-8 on line 1, column 2")));
+    }
 }
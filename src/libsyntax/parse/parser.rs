@@ -1,45 +1,219 @@
 #![allow(dead_code)]
 
+use std::fmt;
 use std::mem;
 use std::collections::{HashMap};
 use parse::lexer::*;
 use parse::tokens::*;
-use ast::{Stmt, Expr, Block, TType, Local, Decl, OptionalTypeExprTupleList, OptionalParamInfoList, OptionalIdTypePairs};
+use ast::{Span, Stmt, Expr, LValue, Block, TType, Local, Decl, Escape, OptionalTypeExprTupleList, OptionalParamInfoList, OptionalIdTypePairs};
 use ast::Stmt::*;
 use ast::Expr::*;
 use ast::TType::*;
 use ast::Decl::*;
+use ast::LValue::*;
 //use ast::*;
 use ptr::{B};
 //use ast::{Expr, Stmt};
 
 type BlockStack = Vec<Block>;
 
+//tokens that end a construct or start the next one. After a syntax error
+//the parser skips ahead to one of them and carries on from there
+const SYNC_TOKENS : [Token; 8] = [Token::SemiColon, Token::In, Token::End, Token::RightParen,
+                                  Token::Type, Token::Var, Token::Function, Token::Eof];
+
+//the tokens an expression can start with
+const EXPR_START : [Token; 12] = [Token::Ident, Token::Number, Token::TokString, Token::Nil, Token::LeftParen, Token::Minus,
+                                  Token::If, Token::While, Token::For, Token::Break, Token::Let, Token::Array];
+
+const DECL_START : [Token; 3] = [Token::Type, Token::Var, Token::Function];
+
+//the precedence of the comparison operators, which don't associate
+const COMPARISON : u8 = 3;
+
+//a syntax error, with the tokens that would have been accepted in place of
+//the one found
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError{
+    pub line : usize,
+    pub col : usize,
+    pub message : String,
+    pub expected : Vec<Token>
+}
+
+impl fmt::Display for SyntaxError{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        try!(write!(f, "line {}, column {}: {}", self.line, self.col, self.message));
+        if let Some((last, rest)) = self.expected.split_last(){
+            try!(f.write_str(". Expected "));
+            if !rest.is_empty(){
+                let rest : Vec<String> = rest.iter().map(|t| t.to_string()).collect();
+                try!(write!(f, "{} or ", rest.join(", ")));
+            }
+            try!(write!(f, "{}", last));
+        }
+        Ok(())
+    }
+}
+
 pub struct Parser{
     lexer : Lexer,
+    //the token being parsed. Every parse_* function starts with it on the
+    //first token of its construct and leaves it on the last one
+    curr : SpannedToken,
     block_stack : BlockStack,
-    paren_stack : Vec<char>,
-    square_stack : Vec<char>,
-    seq_expr_list : Vec<B<Expr>>,
-    last_expr_type : Option<TType>
+    //the syntax errors found so far, in the order of the source
+    pub errors : Vec<SyntaxError>,
+    //set after an error until the parser gets past a token. Errors found
+    //meanwhile follow from the first one and aren't reported
+    recovering : bool
 }
 
 impl Parser{
     pub fn new(src : String)->Self{
         Parser {
                 lexer : Lexer::new(src),
+                curr : SpannedToken::default(),
                 block_stack : BlockStack::new(),
-                paren_stack : Vec::new(),
-		square_stack : Vec::new(),
-                seq_expr_list : Vec::new(),
-                last_expr_type : None
+                errors : Vec::new(),
+                recovering : false
         }
 
     }
 
     pub fn start_lexer(&mut self){
-        self.lexer.get_char();
-        self.lexer.get_token();
+        self.next_token();
+    }
+
+    //parses a source made of a single expression. Errors, lexical ones
+    //included, don't stop the parse, so all of them are returned
+    pub fn parse(&mut self) -> Result<B<Expr>, Vec<SyntaxError>>{
+        let (_, expr) = self.get_nxt_and_parse();
+        while self.peek() != Token::Eof{
+            self.unexpected(&[Token::Eof]);
+            self.next_token();
+            //what follows can have errors of its own
+            if EXPR_START.contains(&self.peek()){
+                self.get_nxt_and_parse();
+            }
+        }
+        //onto the eof, past the lexical errors before it
+        self.next_token();
+        if self.errors.is_empty(){
+            Ok(expr)
+        }
+        else{
+            Err(mem::replace(&mut self.errors, Vec::new()))
+        }
+    }
+
+    //moves on to the next token. Newlines are whitespace to the parser and
+    //the eof repeats once the source is exhausted. The lexical errors on the
+    //way are reported and skipped
+    fn next_token(&mut self) -> Token{
+        self.recovering = false;
+        loop{
+            self.curr = match self.lexer.next(){
+                Some(t) => t,
+                None => SpannedToken { token : Token::Eof, line : self.curr.line, col : self.curr.col, ..Default::default() }
+            };
+            match self.curr.token{
+                Token::NewLine => {},
+                Token::Error => {
+                    let message = mem::replace(&mut self.curr.text, String::new());
+                    self.error(message);
+                },
+                t => return t
+            }
+        }
+    }
+
+    //the token after curr
+    fn lookahead(&mut self) -> SpannedToken{
+        let mut k = 0;
+        loop{
+            match self.lexer.peek(k){
+                Some(t) if t.token == Token::NewLine || t.token == Token::Error => k += 1,
+                Some(t) => return t.clone(),
+                None => return SpannedToken { token : Token::Eof, line : self.curr.line, col : self.curr.col, ..Default::default() }
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Token{
+        self.lookahead().token
+    }
+
+    //moves on to the next token if it is `t`
+    fn eat(&mut self, t : Token) -> bool{
+        if self.peek() == t{
+            self.next_token();
+            return true
+        }
+        false
+    }
+
+    //moves on to the next token, which has to be `t`. Otherwise the error is
+    //reported and the parser recovers
+    fn expect(&mut self, t : Token) -> bool{
+        if self.eat(t){
+            return true
+        }
+        self.recover(&[t]);
+        false
+    }
+
+    fn report(&mut self, found : SpannedToken, expected : &[Token]){
+        if self.recovering{
+            return
+        }
+        self.recovering = true;
+        let what = match found.token{
+            Token::Ident => format!("identifier '{}'", found.text),
+            Token::Number => format!("number {}", found.text),
+            t => t.to_string()
+        };
+        self.push_error(SyntaxError { line : found.line, col : found.col, message : format!("Unexpected {}", what),
+                                      expected : expected.to_vec() });
+    }
+
+    //reports that the next token isn't one of `expected`
+    fn unexpected(&mut self, expected : &[Token]){
+        let found = self.lookahead();
+        self.report(found, expected);
+    }
+
+    //reports an error at curr
+    fn error(&mut self, message : String){
+        let (line, col) = (self.curr.line, self.curr.col);
+        self.push_error(SyntaxError { line : line, col : col, message : message, expected : Vec::new() });
+    }
+
+    //adds an error in the order of the source. A lexical error is reported
+    //only once the parser moves past it, which can be after an error about
+    //a token that follows it
+    fn push_error(&mut self, e : SyntaxError){
+        let at = self.errors.iter().rposition(|x| (x.line, x.col) <= (e.line, e.col)).map_or(0, |i| i + 1);
+        self.errors.insert(at, e);
+    }
+
+    //panic-mode recovery: reports the next token and skips ahead to a token
+    //in SYNC_TOKENS
+    fn recover(&mut self, expected : &[Token]){
+        self.unexpected(expected);
+        self.synchronize();
+    }
+
+    fn synchronize(&mut self){
+        let recovering = self.recovering;
+        while !SYNC_TOKENS.contains(&self.peek()){
+            self.next_token();
+        }
+        self.recovering = recovering;
+    }
+
+    fn error_expr(&self) -> Option<(TType, B<Expr>)>{
+        Some((TVoid, B(ErrorExpr)))
     }
 
     pub fn run(& mut self)->Option<Block>{
@@ -50,7 +224,6 @@ impl Parser{
     fn parse_block(& mut self)->Option<Block>{
         //let mut b = Block::new();
         //self.block_stack.push(b);
-        self.lexer.get_char();
         self.program(); //begin parsing
         debug_assert!(self.block_stack.len() == 1, "Only parent block should be on
                                                     the stack when the parsing is finished");
@@ -67,57 +240,57 @@ impl Parser{
 
     fn program(&mut self){
       loop{
-        match self.lexer.get_token(){
+        match self.next_token(){
             //FIXME semicolon handling should change:
             Token::SemiColon => continue,
-            Token::Nil |
-            Token::Number |
-            Token::LeftParen |
-            Token::Minus |
-            Token::If |
-            Token::While |
-            Token::For |
-            Token::Break |
-            Token::Let |
-            Token::Function |
-            Token::Ident |
-            Token::TokString => {
+            t if EXPR_START.contains(&t) => {
                 let expr = Some(self.expr().unwrap().1);
                 self.block_stack.last_mut().unwrap().expr = expr;
                 //FIXME should we break?
                 break;
             },
-            /*Token::Do => {
-                debug_assert!(self.block_stack.len() > 0, "No parent block on the stack");
-                self.block_stack.push(Block::new());
-                self.expr();
-                if self.lexer.curr_token == Token::End{
-                    //TODO make sure we track all block openings
-                    let block = self.block_stack.pop().unwrap();
-                    let mut curr_block = self.block_stack.last_mut().unwrap();
-                    //curr_block.statements.push(Self::mk_block_stmt(block));
-                }
-            },*/
             Token::Eof => {return},
             Token::End => {
                 //TODO block stack pop
                 return
                 //continue;
             },
-            _ => {panic!("Invalid token");}
+            _ => {
+                let found = self.curr.clone();
+                self.report(found, &EXPR_START);
+            }
         }
       }
     }
 
     //FIXME temporarily pub for integration testing
     pub fn expr(&mut self) -> Option<(TType, B<Expr>)> {
-        match self.lexer.curr_token{
+        let lhs = self.operand();
+        Some(self.parse_binary_expr(lhs, 0))
+    }
+
+    //parses the operand that starts at curr, without the binary operators
+    //that follow it
+    fn operand(&mut self) -> (TType, B<Expr>){
+        let span = Span { line : self.curr.line };
+        let (ty, mut e) = self.expr_kind().unwrap();
+        //nested expressions parsed further down keep their own, more precise, span
+        if e.span.line == 0 {
+            e.span = span;
+        }
+        (ty, e)
+    }
+
+    fn expr_kind(&mut self) -> Option<(TType, B<Expr>)> {
+        match self.curr.token{
             Token::Nil => {
                 Some((TNil, B(NilExpr)))
             },
+            Token::Break => {
+                Some((TVoid, B(BreakExpr)))
+            },
             Token::Number => {
                 self.parse_num_expr()
-                //B(NumExpr(self.lexer.curr_string.clone().parse::<i32>().unwrap()))
             },
             Token::Ident => {
                 self.parse_ident_expr()
@@ -125,41 +298,17 @@ impl Parser{
             Token::TokString => {
                 self.parse_string_expr()
             },
+            Token::Minus => {
+                //-e is 0 - e. It binds tighter than the binary operators
+                let (_, e) = self.get_nxt_and_parse_operand();
+                Some((TInt32, B(SubExpr(B(NumExpr(0)), e))))
+            },
             Token::Let =>{
                 self.parse_let_expr()
             },
-            // Token::Function => {
-            //     return self.parse_function_decl()
-            // },
             Token::LeftParen => { //seqexpr
-                self.paren_stack.push('(');
-
-                while self.lexer.get_token() != Token::RightParen {
-                    //Be careful when you set value of self.lexer.curr_token between here and self.expr()
-                    //since logic in expr() assumes that self.lexer.curr_token will already be set
-                    if self.lexer.curr_token == Token::SemiColon { continue; }
-                    if self.lexer.curr_token == Token::Eof { panic!("Unexpected eof encountered") }
-                    let optional_expr = self.expr();
-                    if optional_expr.is_some() {
-                        let (ty, e) = optional_expr.unwrap();
-                        self.seq_expr_list.push(e);
-                        self.last_expr_type = Some(ty);
-                    }
-
-                    //check closing paren here because self.expr() above could have curr_token set to it
-                    if self.lexer.curr_token == Token::RightParen{
-                        break;
-                    }
-                }
-
-                self.paren_stack.pop();
-                if !self.paren_stack.is_empty() {
-                    panic!("Missing ')'");
-                }
-
-                let last_type = mem::replace(&mut self.last_expr_type, None);
-                let expr_list = mem::replace(&mut self.seq_expr_list, Vec::new());
-                Some((last_type.unwrap(), B(SeqExpr(Some(expr_list)))))
+                let (last_type, expr_list) = self.parse_expr_seq(Token::RightParen);
+                Some((last_type, B(SeqExpr(Some(expr_list)))))
             },
             Token::If => {
                 self.parse_if_then_else_expr()
@@ -173,21 +322,54 @@ impl Parser{
             Token::Array => {
                 self.parse_array_expr()
             },
-            // Token::RightParen => {
-            //     if self.paren_stack.is_empty(){
-            //         panic!("Mismatched parenthesis");
-            //     }
-            //     self.paren_stack.pop();
-            //     //TODO mem::replace self.seq_expr_list with Vec::new and assign it to SeqExpr
-            //     Some(B(SeqExpr(None)))
-            // },
-            Token::End => panic!("Unexpected 'end'. Expected an expr."),
-            Token::RightSquare => {
-                panic!("");
+            _ => {
+                let found = self.curr.clone();
+                self.report(found, &EXPR_START);
+                self.error_expr()
+            }
+        }
+    }
 
-            },
-            t =>{ println!("{:?}", t); panic!("FIXME: handle more patterns")}
+    //parses expressions separated by ';' up to `close`. curr is the token
+    //before the first expression
+    fn parse_expr_seq(&mut self, close : Token) -> (TType, Vec<B<Expr>>){
+        let mut expr_list = Vec::new();
+        let mut last_type = TVoid;
+        loop{
+            //a ';' can end the sequence
+            if self.eat(close){
+                break;
+            }
+            let (ty, e) = self.get_nxt_and_parse();
+            expr_list.push(e);
+            last_type = ty;
+            if self.eat(Token::SemiColon){
+                continue;
+            }
+            if self.eat(close){
+                break;
+            }
+            self.recover(&[Token::SemiColon, close]);
+            if !self.eat(Token::SemiColon){
+                self.eat(close);
+                break;
+            }
         }
+        (last_type, expr_list)
+    }
+
+    //parses a standalone list of declarations, e.g. a line typed into the repl
+    pub fn decls(&mut self) -> Vec<Decl>{
+        self.block_stack.push(Block::new());
+        let mut decls : Vec<Decl> = Vec::new();
+        self.parse_decls(&mut decls);
+        if self.peek() != Token::Eof{
+            self.unexpected(&[Token::Type, Token::Var, Token::Function, Token::Eof]);
+        }
+        else{
+            self.next_token();
+        }
+        decls
     }
 
     fn parse_let_expr(&mut self) -> Option<(TType, B<Expr>)>{
@@ -195,496 +377,455 @@ impl Parser{
         //set parent-child relationship
         self.block_stack.push(b);
         let mut decls : Vec<Decl> = Vec::new();
+        self.parse_decls(&mut decls);
+        if !self.expect(Token::In){
+            self.eat(Token::End);
+            return Some((TVoid, B(LetExpr(decls, Some(B(ErrorExpr))))))
+        }
+        //the body is a sequence of expressions, which can be empty
+        let (ty, mut exprs) = self.parse_expr_seq(Token::End);
+        let body = match exprs.len(){
+            0 => None,
+            1 => exprs.pop(),
+            _ => Some(B(SeqExpr(Some(exprs))))
+        };
+        Some((ty, B(LetExpr(decls, body))))
+    }
+
+    fn parse_decls(&mut self, decls : &mut Vec<Decl>){
         loop{
-            match self.lexer.get_token() {
+            match self.peek() {
                 Token::Type => { //typedec
-                    self.parse_type_decl(&mut decls);
+                    self.next_token();
+                    self.parse_type_decl(decls);
                 },
                 Token::Var => { //Vardec
-                    self.parse_var_decl(&mut decls);
+                    self.next_token();
+                    self.parse_var_decl(decls);
                 },
                 Token::Function => { //functiondec
-                    self.parse_function_decl(&mut decls);
+                    self.next_token();
+                    self.parse_function_decl(decls);
                 },
-                Token::NewLine => continue,
-                //FIXME probably all these following guards are useless?
-                Token::In => break,
-                //FIXME Eof occurrence is an error
-                Token::Eof => break,
-                //FIXME End occurrence is an error
-                Token::End => break,
-                t => {println!("{:?}", t);panic!("Unexpected token. Expected a declaration or 'in'") }
-            }
-
-            //this is needed because a var decl parse can set the curr_token to 'in'
-            if self.lexer.curr_token == Token::In{
-                break;
+                Token::In | Token::End | Token::Eof => break,
+                _ => {
+                    self.unexpected(&[Token::Type, Token::Var, Token::Function, Token::In]);
+                    //declarations can't use the token, whatever it is
+                    self.next_token();
+                    self.recovering = true;
+                    self.synchronize();
+                }
             }
         }//let loop ends
-        let (_ty, _expr) =
-        if self.lexer.curr_token == Token::In{
-            //FIXME get the list of exprs and the type of the last expr in the list
-            self.lexer.get_token();
-            let expr = self.expr();
-            debug_assert!(expr.is_some(), "expr expected after 'in'");
-            expr.unwrap()
-        }
-        else{
-            panic!("Expected 'in' after declarations");
-        };
-        Some((_ty, B(LetExpr(decls, Some(_expr)))))
     }
 
     fn parse_type_decl(&mut self, decls : &mut Vec<Decl>){
-        match self.lexer.get_token() {
+        if !self.expect(Token::Ident){
+            decls.push(ErrorDec);
+            return
+        }
+        let id = self.curr.text.clone();
+        if !self.expect(Token::Equals){
+            decls.push(ErrorDec);
+            return
+        }
+        match self.peek(){
+            Token::Int => {
+                self.next_token();
+                decls.push(TypeDec(id, TInt32));
+            },
+            Token::TokString => {
+                self.next_token();
+                decls.push(TypeDec(id, TString));
+            },
             Token::Ident => {
-                let id = self.lexer.curr_string.clone();
-                match self.lexer.get_token(){
-                    Token::Equals => {
-                        match self.lexer.get_token(){
-                            Token::Int => decls.push(TypeDec(id, TInt32)),
-                            Token::TokString => decls.push(TypeDec(id, TString)),
-                            Token::Ident => decls.push(TypeDec(id, TCustom(self.lexer.curr_string.clone()))),
-                            Token::Array => {
-                                match self.lexer.get_token() {
-                                    Token::Of => {
-                                        match self.lexer.get_token() {
-                                            Token::Int => {},
-                                            Token::TokString => {},
-                                            Token::Ident => {},
-                                            _ => panic!("Expected either int, string or type-id")
-                                        }
-                                    },
-                                    _ => panic!("Expected 'of' after 'array'")
-                                }
-                            },
-                            Token::LeftCurly => { //rectype
-
-                            },
-                            _ => panic!("Expected either int, string, type-id, array of, '{' after '='")
-                        }
+                self.next_token();
+                decls.push(TypeDec(id, TCustom(self.curr.text.clone())));
+            },
+            Token::Array => {
+                self.next_token();
+                if !self.expect(Token::Of){
+                    decls.push(ErrorDec);
+                    return
+                }
+                match self.peek() {
+                    Token::Int | Token::TokString | Token::Ident => {
+                        self.next_token();
+                        let elem_ty = Self::get_ty_from_string(self.curr.text.as_str());
+                        decls.push(TypeDec(id, TArray(B(elem_ty))));
                     },
-                    _ => panic!("Expected '=' after type-id")
+                    _ => {
+                        self.recover(&[Token::Int, Token::TokString, Token::Ident]);
+                        decls.push(ErrorDec);
+                    }
                 }
             },
-            _ => panic!("Expected identifier after 'type'")
+            Token::LeftCurly => { //rectype
+                self.next_token();
+                let fields = self.parse_field_decs(Token::RightCurly);
+                decls.push(TypeDec(id, TRecord(fields)));
+            },
+            _ => {
+                self.recover(&[Token::Int, Token::TokString, Token::Ident, Token::Array, Token::LeftCurly]);
+                decls.push(ErrorDec);
+            }
         }
     }
 
-fn parse_record_decl(&mut self) -> OptionalIdTypePairs{
-        match self.lexer.get_token(){
-            Token::ColonEquals => {
-                self.parse_record_fields()
-            },
-            _ => panic!("Expected ':=' after 'rec'")
-        } 
+    fn parse_record_fields(&mut self) -> OptionalIdTypePairs {
+        if !self.expect(Token::LeftCurly){
+            return None
+        }
+        let field_decs = self.parse_field_decs(Token::RightCurly);
+        if field_decs.is_empty() {
+            None
+        }
+        else {
+            Some(field_decs)
+        }
     }
 
-    fn parse_record_fields(&mut self) -> OptionalIdTypePairs {
-        match self.lexer.get_token(){
-            Token::LeftCurly => {
-                let mut field_decs : Vec<(String, TType)> = Vec::new();
-                loop{
-                    match self.lexer.get_token() {
-                        Token::Comma => continue,
-                        Token::RightCurly => { 
-                            break;
-                        },
-                        Token::Eof => panic!("Unexpected eof encountered. Expected a ')' after field-declaration."),
-                        Token::Ident => {
-                            let id = self.lexer.curr_string.clone();
-                            //FIXME should we verify duplicate params here?
-                            //HashMap and BTreeMap do not respect the order of insertions
-                            //which is required to set up args during call.
-                            //Vec will respect the order but cost O(n) for the verification
-                            //Need multi_index kind of a structure from C++ Boost
-                            if field_decs.iter().any(|ref tup| tup.0 == id){
-                                panic!(format!("parameter '{}' found more than once", id));
-                            }
-                            match  self.lexer.get_token() {
-                                Token::Colon => {
-                                    match self.lexer.get_token() {
-                                        Token::Int |
-                                        Token::TokString |
-                                        Token::Ident => {
-                                            let ty = Self::get_ty_from_string(self.lexer.curr_string.as_str());
-                                            field_decs.push((id, ty));
-                                        },
-                                        _ => panic!("Expected type-id after ':'")
-                                    }
-                                },
-                                _ => panic!("Expected ':' after id")
-                            }
-                        },
-                        _ => panic!("Expected a ')' or parameter id")
-                    }
-                }
-                if field_decs.is_empty() {
-                    None
-                }
-                else {
-                    Some(field_decs)
+    //parses `id : type-id` pairs separated by commas, up to `close`. curr is
+    //the opening delimiter
+    fn parse_field_decs(&mut self, close : Token) -> Vec<(String, TType)>{
+        let mut field_decs : Vec<(String, TType)> = Vec::new();
+        if self.eat(close){
+            return field_decs
+        }
+        loop{
+            if !self.expect(Token::Ident){
+                self.eat(close);
+                break;
+            }
+            let id = self.curr.text.clone();
+            //FIXME should we verify duplicate params here?
+            //HashMap and BTreeMap do not respect the order of insertions
+            //which is required to set up args during call.
+            //Vec will respect the order but cost O(n) for the verification
+            //Need multi_index kind of a structure from C++ Boost
+            if field_decs.iter().any(|ref tup| tup.0 == id){
+                self.error(format!("parameter '{}' found more than once", id));
+            }
+            if !self.expect(Token::Colon){
+                self.eat(close);
+                break;
+            }
+            match self.peek() {
+                Token::Int |
+                Token::TokString |
+                Token::Ident => {
+                    self.next_token();
+                    let ty = Self::get_ty_from_string(self.curr.text.as_str());
+                    field_decs.push((id, ty));
+                },
+                _ => {
+                    self.recover(&[Token::Int, Token::TokString, Token::Ident]);
+                    self.eat(close);
+                    break;
                 }
-            },
-            _ => panic!("Expected a '{' after ':='")
+            }
+            if self.eat(Token::Comma){
+                continue;
+            }
+            if !self.eat(close){
+                self.recover(&[Token::Comma, close]);
+                self.eat(close);
+            }
+            break;
         }
+        field_decs
     }
 
     fn parse_var_decl(&mut self,  decls : &mut Vec<Decl>){
-        match self.lexer.get_token() {
-            Token::Ident => {
-                let id = self.lexer.curr_string.clone();
-                match self.lexer.get_token() {
-                    Token::Colon => {
-                        match self.lexer.get_token() {
-                            Token::Int => {
-                                match self.lexer.get_token(){
-                                    Token::ColonEquals => {
-                                        //get rhs expr and its type
-                                        let (ty, expr) = self.get_nxt_and_parse();
-                                        self.block_stack.last_mut().unwrap().sym_tab.borrow_mut().insert(id.clone(), ty);
-                                        decls.push(VarDec(id.clone(), TInt32, expr));
-                                    },
-                                    _ => panic!("Expected ':='")
-                                }
-                            },
-                            Token::TokString => {
-                                match self.lexer.get_token(){
-                                    Token::ColonEquals => {
-                                        self.expr();
-                                    },
-                                    _ => panic!("Expected ':='")
-                                }
-                            },
-                            //FIXME this is just a hack to test arrays
-                            //tiger doesn't mention specifying ':' for arrays
-                            //so we are going to tweak the way arrays are declared
-                            //by doing something like - var a : array := arrayof int[dim] of init;
-                            Token::Array => {
-                                match self.lexer.get_token(){
-                                    Token::ColonEquals => {
-
-                                        match self.lexer.get_token(){
-                                            Token::Array => {
-                                                //Some((TArray(B(array_ty)), B(ArrayExpr(arr_ty, dim_expr, init_expr))))
-                                                let (_ty, _expr) = self.parse_array_expr().unwrap(); 
-                                                decls.push(VarDec(id.clone(), _ty, _expr));
-                                            },
-                                            _ => panic!("Expected 'array' keyword after ':='")
-                                        }
-                                    },
-                                    _ => panic!("Expected ':='")
-                                }
-                            },
-                            Token::Rec => {
-                                let field_decls = self.parse_record_decl();
-                                decls.push(VarDec(id.clone(), TRecord, B(RecordExpr(field_decls))));
-                            },
-                            _ => panic!("expr : pattern not covered")
-                        }
-                    },
-                    _ => panic!("Expected ':' after identifier")
+        if !self.expect(Token::Ident){
+            decls.push(ErrorDec);
+            return
+        }
+        let id = self.curr.text.clone();
+        //without a type the variable takes the type of its initializer
+        let var_ty = if self.eat(Token::Colon){
+            match self.peek() {
+                Token::Int | Token::TokString | Token::Ident => {
+                    self.next_token();
+                    Self::get_ty_from_string(self.curr.text.as_str())
+                },
+                //FIXME this is just a hack to test arrays
+                //tiger doesn't mention specifying ':' for arrays
+                //so we are going to tweak the way arrays are declared
+                //by doing something like - var a : array := arrayof int[dim] of init;
+                Token::Array => {
+                    self.next_token();
+                    if !self.expect(Token::ColonEquals) || !self.expect(Token::Array){
+                        decls.push(ErrorDec);
+                        return
+                    }
+                    //Some((TArray(B(array_ty)), B(ArrayExpr(arr_ty, dim_expr, init_expr))))
+                    let (_ty, _expr) = self.parse_array_expr().unwrap();
+                    decls.push(VarDec(id.clone(), _ty, _expr, Escape::new()));
+                    return
+                },
+                _ => {
+                    self.recover(&[Token::Int, Token::TokString, Token::Ident, Token::Array]);
+                    decls.push(ErrorDec);
+                    return
                 }
-            },
-            _ => panic!("Expected an identifier")
+            }
+        }
+        else{
+            TNil
+        };
+        if !self.expect(Token::ColonEquals){
+            decls.push(ErrorDec);
+            return
         }
+        //FIXME this is just a hack to test records, like the one for arrays:
+        //var a : rec := {f : int} declares a record with the field f
+        if var_ty == TCustom(String::from("rec")) && self.peek() == Token::LeftCurly{
+            let field_decls = self.parse_record_fields();
+            let fields = field_decls.clone().unwrap_or(Vec::new());
+            decls.push(VarDec(id.clone(), TRecord(fields), B(RecordExpr(field_decls)), Escape::new()));
+            return
+        }
+        //get rhs expr and its type
+        let (ty, expr) = self.get_nxt_and_parse();
+        self.block_stack.last_mut().unwrap().sym_tab.borrow_mut().insert(id.clone(), ty);
+        decls.push(VarDec(id.clone(), var_ty, expr, Escape::new()));
     }
 
     fn parse_ident_expr(&mut self) -> Option<(TType, B<Expr>)>{
         //check if symbol defined in the sym tab
-        //if self.block_stack.last().unwrap().contains(self.lexer.curr_string)
-        let op1 = B(IdExpr(self.lexer.curr_string.clone()));
-        let fn_name = self.lexer.curr_string.clone();
-        match self.lexer.get_token(){
-            Token::LeftSquare => { //a[
-                //FIXME check nested brackets
-                //self.square_stack.push('[');
-                let idx_expr = self.get_nxt_and_parse();
-                match self.lexer.curr_token{
-                    Token::RightSquare =>{
-                        //FIXME can something be done about cloning the expression?
-                        let subscript_expr = Some((TVoid, B(SubscriptExpr(fn_name.clone(), idx_expr.1.clone()))));
-                        //check if something getting assigned to the subscript
-                        match self.lexer.get_token(){
-                          Token::ColonEquals => { //a[i] := 
-                              //let rhs_expr = self.get_nxt_and_parse();
-                              return Some((TVoid, B(AssignExpr(B(SubscriptExpr(fn_name.clone(), idx_expr.1)), 
-                                                               self.get_nxt_and_parse().1))))
-                          },
-                          _ => {
-                              return subscript_expr
-                          }
-                        } 
-                    },
-                    _ => {
-                        println!("{:?}", self.lexer.curr_token);
-                        panic!("");
-                    }
-                }
-            }, //subscript
-            Token::Dot => {
-                println!("parsing field access");
-                return Some((TNil, B(FieldExpr(op1, 
-                                              match self.lexer.get_token(){
-                                                  Token::Ident => {
-                                                      self.parse_ident_expr().unwrap().1
-                                                  },
-
-                                                  Token::End => {
-                                                    B(NoOpExpr)    
-                                                  },
-                                                  _ => panic!("Expected an identifier during field access")
-                                              }
-                                             ))))
-
-            }, //fieldexp
+        //if self.block_stack.last().unwrap().contains(self.curr.text)
+        let id = self.curr.text.clone();
+        match self.peek(){
             Token::LeftParen => { //callexpr
-                println!("parsing call");
+                self.next_token();
                 let args_list = self.parse_call_args();
                 //FIXME should a marker type be used instead of TVoid to indicate that the type should be verified by the type-checker?
-                match self.lexer.curr_token{
-                    Token::Plus => {
-                        let (_, op2) = self.get_nxt_and_parse();
-                        return Some((TInt32, B(AddExpr(B(CallExpr(fn_name, args_list)), op2))))
-                    },
-                    Token::Minus => {
-                        let (_, op2) = self.get_nxt_and_parse();
-                        return Some((TInt32, B(SubExpr(B(CallExpr(fn_name, args_list)), op2))))
-                    },
-                    Token::Mul => {
-                        let (_, op2) = self.get_nxt_and_parse();
-                        return Some((TInt32, B(MulExpr(B(CallExpr(fn_name, args_list)), op2))))
-                    },
-                    Token::Div => {
-                        let (_, op2) = self.get_nxt_and_parse();
-                        return Some((TInt32, B(DivExpr(B(CallExpr(fn_name, args_list)), op2))))
-                    },
-                    _ => {}
-                }
-                if let IdExpr(ref fn_name) = *op1 {
-                    return Some((TVoid, B(CallExpr(fn_name.clone(), args_list))))
-                }; 
+                return Some((TVoid, B(CallExpr(id, args_list))))
             },
-            Token::Plus => {
-                let (_, op2) = self.get_nxt_and_parse();
+            Token::LeftCurly => { //tyId {
+                self.next_token();
+                let fields = self.parse_field_inits();
+                return Some((TCustom(id.clone()), B(TypedRecordExpr(id, fields))))
+            },
+            _ => {}
+        }
+        let mut lvalue = SimpleVar(id);
+        loop{
+            match self.peek(){
+                Token::LeftSquare => { //lvalue[
+                    self.next_token();
+                    let (_, idx_expr) = self.get_nxt_and_parse();
+                    if !self.expect(Token::RightSquare){
+                        return self.error_expr()
+                    }
+                    if let SimpleVar(ref ty_id) = lvalue{
+                        if self.eat(Token::Of){ //tyId [exp] of
+                            let (_, init_expr) = self.get_nxt_and_parse();
+                            return Some((TCustom(ty_id.clone()), B(TypedArrayExpr(ty_id.clone(), idx_expr, init_expr))))
+                        }
+                    }
+                    lvalue = SubscriptVar(B(lvalue), idx_expr);
+                },
+                Token::Dot => { //lvalue.
+                    self.next_token();
+                    if !self.expect(Token::Ident){
+                        return self.error_expr()
+                    }
+                    lvalue = FieldVar(B(lvalue), self.curr.text.clone());
+                },
+                _ => break
+            }
+        }
+        if self.eat(Token::ColonEquals){
+            return Some((TVoid, B(AssignExpr(lvalue, self.get_nxt_and_parse().1))))
+        }
+        match lvalue{
+            //TVoid because we dont know the type of the identifier yet.
+            SimpleVar(id) => Some((TVoid, B(IdExpr(id)))),
+            lvalue => Some((TNil, B(LValueExpr(lvalue))))
+        }
+    }
 
-                return Some((TInt32, B(AddExpr(op1, op2))))
+    //parses `id = exp` pairs separated by commas, up to the closing '}'. curr
+    //is the opening '{'
+    fn parse_field_inits(&mut self) -> Vec<(String, B<Expr>)>{
+        let mut fields = Vec::new();
+        if self.eat(Token::RightCurly){
+            return fields
+        }
+        loop{
+            if !self.expect(Token::Ident){
+                self.eat(Token::RightCurly);
+                break;
+            }
+            let id = self.curr.text.clone();
+            if !self.expect(Token::Equals){
+                self.eat(Token::RightCurly);
+                break;
+            }
+            let (_, e) = self.get_nxt_and_parse();
+            fields.push((id, e));
+            if self.eat(Token::Comma){
+                continue;
+            }
+            if !self.eat(Token::RightCurly){
+                self.recover(&[Token::Comma, Token::RightCurly]);
+                self.eat(Token::RightCurly);
+            }
+            break;
+        }
+        fields
+    }
+
+    //how tightly a binary operator binds its operands, if `op` is one
+    fn precedence(op : Token) -> Option<u8>{
+        match op{
+            Token::LogOr => Some(1),
+            Token::LogAnd => Some(2),
+            Token::Equals | Token::LessThanGreaterThan | Token::LessThan |
+            Token::LessEquals | Token::GreaterThan | Token::GreaterEquals => Some(COMPARISON),
+            Token::Plus | Token::Minus => Some(4),
+            Token::Mul | Token::Div | Token::Mod => Some(5),
+            _ => None
+        }
+    }
 
-                //FIXME it's better to let the type-checker do the checking
-                //if t == TInt32{
-                    //return Some((TInt32, B(AddExpr(op1, op2))))
-                //}
-                //else{
-                    //panic!("Expected i32 as the type of rhs expression");
-                //}
-            },
-            Token::ColonEquals => {
-                return Some((TVoid, B(AssignExpr(B(IdExpr(fn_name.clone())), 
-                                                               self.get_nxt_and_parse().1))))
-            },
-            Token::Equals => {
-                let (_, op2) = self.get_nxt_and_parse();
-                return Some((TVoid, B(EqualsExpr(op1, op2))))
-            },
-            _ => {
-                //TVoid because we dont know the type of the identifier yet.
-                return Some((TVoid, op1))
+    //parses the operators and right operands that follow `lhs`, as long as
+    //the operators bind at least as tightly as `min_prec`. Operators of the
+    //same precedence associate to the left
+    fn parse_binary_expr(&mut self, lhs : (TType, B<Expr>), min_prec : u8) -> (TType, B<Expr>){
+        let (mut ty, mut op1) = lhs;
+        loop{
+            let op = self.peek();
+            let prec = match Self::precedence(op){
+                Some(prec) if prec >= min_prec => prec,
+                _ => return (ty, op1)
+            };
+            self.next_token();
+            let rhs = self.get_nxt_and_parse_operand();
+            let (_, op2) = self.parse_binary_expr(rhs, prec + 1);
+            //FIXME it's better to let the type-checker check the operands
+            let span = op1.span;
+            let (e_ty, e) = match op{
+                Token::Plus => (TInt32, AddExpr(op1, op2)),
+                Token::Minus => (TInt32, SubExpr(op1, op2)),
+                Token::Mul => (TInt32, MulExpr(op1, op2)),
+                Token::Div => (TInt32, DivExpr(op1, op2)),
+                Token::Mod => (TInt32, ModExpr(op1, op2)),
+                Token::Equals => (TVoid, EqualsExpr(op1, op2)),
+                Token::LessThanGreaterThan => (TVoid, NotEqualsExpr(op1, op2)),
+                Token::LessThan => (TVoid, LessThanExpr(op1, op2)),
+                Token::LessEquals => (TVoid, LessThanEqualsExpr(op1, op2)),
+                Token::GreaterThan => (TVoid, GreaterThanExpr(op1, op2)),
+                Token::GreaterEquals => (TVoid, GreaterThanEqualsExpr(op1, op2)),
+                //a & b is if a then b else 0
+                Token::LogAnd => (TInt32, IfThenElseExpr(op1, op2, B(NumExpr(0)))),
+                //a | b is if a then 1 else b
+                Token::LogOr => (TInt32, IfThenElseExpr(op1, B(NumExpr(1)), op2)),
+                _ => unreachable!()
+            };
+            ty = e_ty;
+            op1 = B(e);
+            op1.span = span;
+            //comparisons don't associate: a = b = c is an error
+            if prec == COMPARISON && Self::precedence(self.peek()) == Some(COMPARISON){
+                self.unexpected(&[]);
             }
         }
-        Some((TVoid, op1)) 
     }
 
     fn parse_string_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        Some((TString, B(StringExpr(self.lexer.curr_string.clone()))))
+        Some((TString, B(StringExpr(self.curr.bytes.clone()))))
     }
 
     fn parse_num_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        let num = self.lexer.curr_string.parse::<i32>().unwrap();
-
-        let op1 = B(NumExpr(num));
-        match self.lexer.get_token(){
-            Token::Plus => {
-                let (t, op2) = self.get_nxt_and_parse();
-                //FIXME it's better to use a type-checker
-                if t == TInt32{
-                    Some((TInt32, B(AddExpr(op1, op2))))
-                }
-                else{
-                    panic!("Expected i32 as the type of rhs expression");
-                }
-            },
-            Token::Minus => {
-                let (t, op2) = self.get_nxt_and_parse();
-                //FIXME it's better to use a type-checker
-                if t == TInt32{
-                    Some((TInt32, B(SubExpr(op1, op2))))
-                }
-                else{
-                    panic!("Expected i32 as the type of rhs expression");
-                }
-            },
-            Token::Mul => {
-                let (t, op2) = self.get_nxt_and_parse();
-                //FIXME it's better to use a type-checker
-                if t == TInt32{
-                    Some((TInt32, B(MulExpr(op1, op2))))
-                }
-                else{
-                    panic!("Expected i32 as the type of rhs expression");
-                }
-            },
-            Token::Div => {
-                let (t, op2) = self.get_nxt_and_parse();
-                //FIXME it's better to use a type-checker
-                if t == TInt32{
-                    Some((TInt32, B(DivExpr(op1, op2))))
-                }
-                else{
-                    panic!("Expected i32 as the type of rhs expression");
-                }
-            },
-            Token::Equals => {
-                let (_, op2) = self.get_nxt_and_parse();
-                Some((TVoid, B(EqualsExpr(op1, op2))))
-            },
-            Token::LessThan => {
-                let (_, op2) = self.get_nxt_and_parse();
-                Some((TVoid, B(LessThanExpr(op1, op2))))
-            },
-            Token::GreaterThan => {
-                let (_, op2) = self.get_nxt_and_parse();
-                Some((TVoid, B(GreaterThanExpr(op1, op2))))
-            },
-            Token::LessThanGreaterThan => {
-                let (_, op2) = self.get_nxt_and_parse();
-                Some((TVoid, B(NotEqualsExpr(op1, op2))))
-            },
-            //FIXME ';', ')' can be a encountered as well. deal with it.
-            _ => {
-                Some((TInt32, op1))
+        let op1 = match self.curr.text.parse::<i32>(){
+            Ok(n) => B(NumExpr(n)),
+            Err(_) => {
+                let msg = format!("Integer literal {} is out of range. Integers go up to {}.", self.curr.text, i32::max_value());
+                self.error(msg);
+                B(ErrorExpr)
             }
-        }
+        };
+        Some((TInt32, op1))
     }
 
     fn parse_function_decl(&mut self, decls : &mut Vec<Decl>){
-        match self.lexer.get_token(){
-            Token::Ident => {
-                let id = self.lexer.curr_string.clone();
+        if !self.expect(Token::Ident){
+            decls.push(ErrorDec);
+            return
+        }
+        let id = self.curr.text.clone();
 
-                //parse the parameters list
-                let field_decs = self.parse_function_params_list();
+        //parse the parameters list
+        let field_decs = self.parse_function_params_list();
 
-                //parse return type
-                let ret_type = self.parse_function_ret_type();
+        //parse return type
+        let ret_type = self.parse_function_ret_type();
 
-                //parse body here
-                let e = self.expr();
-                debug_assert!(e.is_some(), "Function body cannot be empty");
-                let body = e.unwrap();
+        //parse body here
+        let (body_ty, body) = self.get_nxt_and_parse();
 
-                //function id ( fieldDec; ) : tyId = exp
-                decls.push(FunDec(id, field_decs, ret_type, body.1, body.0));
-            },
-            _ => panic!("Expected an id after 'function'")
-        }
+        //function id ( fieldDec; ) : tyId = exp
+        decls.push(FunDec(id, field_decs, ret_type, body, body_ty));
     }
 
     fn parse_function_params_list(&mut self) -> OptionalParamInfoList {
-        match self.lexer.get_token(){
-            Token::LeftParen => {
-                let mut field_decs : Vec<(String, TType)> = Vec::new();
-                loop{
-                    match self.lexer.get_token() {
-                        Token::Comma => continue,
-                        Token::RightParen => { //parameterless function
-                            break;
-                        },
-                        Token::Eof => panic!("Unexpected eof encountered. Expected a ')' after field-declaration."),
-                        Token::Ident => {
-                            let id = self.lexer.curr_string.clone();
-                            //FIXME should we verify duplicate params here?
-                            //HashMap and BTreeMap do not respect the order of insertions
-                            //which is required to set up args during call.
-                            //Vec will respect the order but cost O(n) for the verification
-                            //Need multi_index kind of a structure from C++ Boost
-                            if field_decs.iter().any(|ref tup| tup.0 == id){
-                                panic!(format!("parameter '{}' found more than once", id));
-                            }
-                            match  self.lexer.get_token() {
-                                Token::Colon => {
-                                    match self.lexer.get_token() {
-                                        Token::Int |
-                                        Token::TokString |
-                                        Token::Ident => {
-                                            let ty = Self::get_ty_from_string(self.lexer.curr_string.as_str());
-                                            field_decs.push((id, ty));
-                                        },
-                                        _ => panic!("Expected type-id after ':'")
-                                    }
-                                },
-                                _ => panic!("Expected ':' after id")
-                            }
-                        },
-                        _ => panic!("Expected a ')' or parameter id")
-                    }
-                }
-                if field_decs.is_empty() {None} else {Some(field_decs)}
-            },
-            _ => panic!("Expected a '(' after function id")
+        if !self.expect(Token::LeftParen){
+            return None
         }
+        let field_decs : Vec<(String, TType, Escape)> = self.parse_field_decs(Token::RightParen).into_iter()
+            .map(|(id, ty)| (id, ty, Escape::new()))
+            .collect();
+        if field_decs.is_empty() {None} else {Some(field_decs)}
     }
 
     fn parse_call_args(&mut self) -> OptionalTypeExprTupleList{
         let mut args_list  = Vec::new();
-        loop {
-            println!("loop");
-            match self.lexer.get_token() {
-                Token::RightParen => break,
-                Token::Number |
-                Token::Ident |
-                Token::TokString => {
-                    println!("{:?}", self.lexer.curr_token);
-                    let e = self.expr();
-                    if e.is_some() {
-                        args_list.push(e.unwrap());
-                    }
-                },
-                _ => {
-                    panic!("Invalid expression used as a call argument");
+        if !self.eat(Token::RightParen){
+            loop {
+                args_list.push(self.get_nxt_and_parse());
+                if self.eat(Token::Comma){
+                    continue;
                 }
-                //_ => panic!("Invalid expression used as a call argument")
-            }
-
-            if self.lexer.curr_token == Token::RightParen {
-                break
+                if !self.eat(Token::RightParen){
+                    self.recover(&[Token::Comma, Token::RightParen]);
+                    self.eat(Token::RightParen);
+                }
+                break;
             }
         }
-        self.lexer.get_token();
         if args_list.is_empty() {None} else {Some(args_list)}
     }
 
     fn parse_function_ret_type(&mut self) -> TType{
-        match self.lexer.get_token() {
+        match self.peek() {
             Token::Colon => {
-                match self.lexer.get_token() {
+                self.next_token();
+                match self.peek() {
                     Token::Int |
                     Token::TokString |
                     Token::Ident => {
-                        let ty = Self::get_ty_from_string(self.lexer.curr_string.as_str()); 
-                        match self.lexer.get_token(){
-                            Token::Equals => {self.lexer.get_token();},
-                            _ => panic!("Expected '=' after the return type")
-                        }
-                        ty                     
+                        self.next_token();
+                        let ty = Self::get_ty_from_string(self.curr.text.as_str());
+                        self.expect(Token::Equals);
+                        ty
                     },
-                    _ => panic!("Expected a type after ':'")
+                    _ => {
+                        self.recover(&[Token::Int, Token::TokString, Token::Ident]);
+                        TVoid
+                    }
                 }
             }
             Token::Equals => {
-                self.lexer.get_token(); //eat '='
+                self.next_token(); //eat '='
+                TVoid
+            }
+            _ => {
+                self.recover(&[Token::Colon, Token::Equals]);
                 TVoid
             }
-            _ => panic!("Expected ':' or '=' after the parameter list")
         }
     }
 
@@ -696,123 +837,88 @@ fn parse_record_decl(&mut self) -> OptionalIdTypePairs{
         }
     }
 
+    //parses the expression that starts at the next token
     fn get_nxt_and_parse(&mut self) -> (TType, B<Expr>){
-        self.lexer.get_token();
-        self.expr().unwrap()
+        let lhs = self.get_nxt_and_parse_operand();
+        self.parse_binary_expr(lhs, 0)
     }
 
-    fn parse_while_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        self.lexer.get_token();
-        let opt_tup = self.expr().unwrap();
-        //Because ident-expr parsing advances to the next token
-        //and returns a TVoid, there is an extra check on the
-        //curr_token
-        if opt_tup.0 != TInt32 && self.lexer.curr_token != Token::Do{
-            self.lexer.get_token();
+    //parses the operand that starts at the next token
+    fn get_nxt_and_parse_operand(&mut self) -> (TType, B<Expr>){
+        if !EXPR_START.contains(&self.peek()){
+            self.recover(&EXPR_START);
+            return (TVoid, B(ErrorExpr))
         }
-        match self.lexer.curr_token {
-            Token::Do => {
-                self.lexer.get_token();
-                let (ty, body) = self.expr().unwrap();
-                Some((ty, B(WhileExpr(opt_tup.1, body))))
-            },
-            _ => panic!("Expected 'do' after the while expression")
+        self.next_token();
+        self.operand()
+    }
+
+    fn parse_while_expr(&mut self) -> Option<(TType, B<Expr>)>{
+        let (_, cond) = self.get_nxt_and_parse();
+        if !self.expect(Token::Do){
+            return self.error_expr()
         }
+        let (ty, body) = self.get_nxt_and_parse();
+        Some((ty, B(WhileExpr(cond, body))))
     }
 
     fn parse_if_then_else_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        //eat 'if'
-        self.lexer.get_token();
         //parse the conditional expr
-        let opt_tup = self.expr().unwrap();
-        //since only arithmetic expr parsing advances to point to the next token,
-        //we do a typecheck in order to determine if we match on the curr_token
-        //or call get_token()
-        if opt_tup.0 != TInt32 && self.lexer.curr_token != Token::Then{
-            self.lexer.get_token();
-        }
-        match self.lexer.curr_token {
-            Token::Then => {
-                self.lexer.get_token(); //advance to the next token
-                let (_, then_expr) = self.expr().unwrap();
-                match self.lexer.curr_token {
-                    Token::Else => {
-                        self.lexer.get_token(); //advance to the next token
-                        let (_, else_body) = self.expr().unwrap();
-                        return Some((TVoid, B(IfThenElseExpr(opt_tup.1, then_expr, else_body))))
-                    }
-                    t => {} //FIXME this isn't an if-then-else expr. should we do something here?
-                }
-                Some((TVoid, B(IfThenExpr(opt_tup.1, then_expr))))
-            },
-            _ => panic!("Expected then after the if expression")
+        let (_, cond) = self.get_nxt_and_parse();
+        if !self.expect(Token::Then){
+            return self.error_expr()
         }
+        let (_, then_expr) = self.get_nxt_and_parse();
+        if self.eat(Token::Else){
+            let (_, else_body) = self.get_nxt_and_parse();
+            return Some((TVoid, B(IfThenElseExpr(cond, then_expr, else_body))))
+        }
+        Some((TVoid, B(IfThenExpr(cond, then_expr))))
     }
 
     fn parse_for_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        match self.lexer.get_token(){
-            Token::Ident => {
-                let id = self.lexer.curr_string.clone();
-                match self.lexer.get_token(){
-                   Token::ColonEquals => {
-                       self.lexer.get_token();
-                       let (_, id_expr) = self.expr().unwrap();
-                       match self.lexer.curr_token{
-                           Token::To => {
-                               self.lexer.get_token();
-                               let (_, to_expr) = self.expr().unwrap();
-                               match self.lexer.curr_token{
-                                   Token::Do => {
-                                       self.lexer.get_token();
-                                       let (_, do_expr) = self.expr().unwrap();
-                                       Some((TVoid, B(ForExpr(id, id_expr, to_expr, do_expr))))
-                                   },
-                                   _ => panic!("Expected 'do' after expression")
-                               }
-                           },
-                           _ => panic!("Expected 'to' after expression")
-                       }
-
-                   },
-                   _ => panic!("Expected := after ident in a for construct")
-                }
-            },
-            _ => panic!("Expected an ident after 'for'")
-        }
-    }
-        
+        if !self.expect(Token::Ident){
+            return self.error_expr()
+        }
+        let id = self.curr.text.clone();
+        if !self.expect(Token::ColonEquals){
+            return self.error_expr()
+        }
+        let (_, id_expr) = self.get_nxt_and_parse();
+        if !self.expect(Token::To){
+            return self.error_expr()
+        }
+        let (_, to_expr) = self.get_nxt_and_parse();
+        if !self.expect(Token::Do){
+            return self.error_expr()
+        }
+        let (_, do_expr) = self.get_nxt_and_parse();
+        Some((TVoid, B(ForExpr(id, id_expr, to_expr, do_expr, Escape::new()))))
+    }
+
     fn parse_array_expr(&mut self) -> Option<(TType, B<Expr>)>{
-        match self.lexer.get_token(){
-            Token::Of => {
-                let mut array_ty = TNil;
-                match self.lexer.get_token(){
-                    Token::Int => array_ty = TInt32,
-                    Token::TokString => array_ty = TString,
-                    _ => panic!("Invalid array type")
-                }
-                match self.lexer.get_token(){
-                    Token::LeftSquare => {
-                        let (dim_ty, dim_expr) = self.get_nxt_and_parse();
-
-                        //match self.lexer.get_token(){ 
-                           // Token::RightSquare => {
-                                match self.lexer.get_token(){
-                                    Token::Of => {
-                                        let (init_ty, init_expr) = self.get_nxt_and_parse();
-                                        let arr_ty = array_ty.clone();
-                                        Some((TArray(B(array_ty)), B(ArrayExpr(arr_ty, dim_expr, init_expr))))
-                                    },
-                                    _ => panic!("Expected array initialization expression")
-                                }
-                          //  },
-                         //   _ => {println!("{:?}", self.lexer.curr_token); panic!("Expected ']' after dimension expression");}
-                        //}
-                    },
-                    _ => panic!("Expected '[' after 'of'")
-                }
-            },
-            _ => panic!("Expected 'of' after 'array'")
+        if !self.expect(Token::Of){
+            return self.error_expr()
         }
+        let array_ty = match self.peek(){
+            Token::Int => TInt32,
+            Token::TokString => TString,
+            _ => {
+                self.recover(&[Token::Int, Token::TokString]);
+                return self.error_expr()
+            }
+        };
+        self.next_token();
+        if !self.expect(Token::LeftSquare){
+            return self.error_expr()
+        }
+        let (_, dim_expr) = self.get_nxt_and_parse();
+        if !self.expect(Token::RightSquare) || !self.expect(Token::Of){
+            return self.error_expr()
+        }
+        let (_, init_expr) = self.get_nxt_and_parse();
+        let arr_ty = array_ty.clone();
+        Some((TArray(B(array_ty)), B(ArrayExpr(arr_ty, dim_expr, init_expr))))
     }
 }
 
@@ -844,11 +950,12 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected="parameter 'a' found more than once")]
     fn test_parse_function_params_list_duplicate_params() {
         let mut p = Parser::new("foo(a:int, a:int)".to_string());
         p.start_lexer();
         p.parse_function_params_list();
+        assert_eq!(p.errors.len(), 1);
+        assert_eq!(p.errors[0].to_string(), "line 1, column 12: parameter 'a' found more than once");
     }
 
     #[test]
@@ -913,7 +1020,7 @@ mod tests {
     fn test_only_string_expr() {
         let mut p = Parser::new("\"abc\"".to_string());
         p.start_lexer();
-        assert_eq!(p.lexer.curr_token, Token::TokString);
+        assert_eq!(p.curr.token, Token::TokString);
     }
 
     #[test]
@@ -934,7 +1041,7 @@ mod tests {
                         let (ref ty, ref b_expr) = l[0usize];
                         assert_eq!(*ty, TString);
                         match &**b_expr {
-                            &StringExpr(ref value) => assert_eq!(*value, "abc"),
+                            &StringExpr(ref value) => assert_eq!(*value, b"abc"),
                             _ => {}
                         }
                     },
@@ -1143,11 +1250,41 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected="Unexpected eof encountered. Expected a ')' after field-declaration.")]
     fn test_field_decs_no_closing_paren(){
         let mut p = Parser::new("f(a: myint".to_string());
         p.start_lexer();
         p.parse_function_params_list();
+        assert_eq!(p.errors[0].to_string(), "line 1, column 11: Unexpected end of file. Expected ',' or ')'");
+    }
+
+    #[test]
+    fn test_mod_expr(){
+        let mut p = Parser::new("17 mod 5".to_string());
+        p.start_lexer();
+        match *p.expr().unwrap().1{
+            ModExpr(ref l, ref r) => match (&**l, &**r){
+                (&NumExpr(17), &NumExpr(5)) => {},
+                _ => panic!("Expected the operands 17 and 5")
+            },
+            _ => panic!("Expected a mod expr")
+        }
+        let mut p = Parser::new("a mod 2".to_string());
+        p.start_lexer();
+        match *p.expr().unwrap().1{
+            ModExpr(ref l, _) => match **l{
+                IdExpr(ref id) => assert_eq!(id, "a"),
+                _ => panic!("Expected an id expr")
+            },
+            _ => panic!("Expected a mod expr")
+        }
+    }
+
+    #[test]
+    fn test_integer_literal_out_of_range(){
+        let mut p = Parser::new("let var a : int := 2147483647\n var b : int := 2147483648 in a end".to_string());
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "line 2, column 17: Integer literal 2147483648 is out of range. Integers go up to 2147483647.");
     }
 
     #[test]
@@ -1165,7 +1302,7 @@ mod tests {
                 assert_eq!(v.len(), 1);
                 assert_eq!(o.is_some(), true);
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             NumExpr(n) => assert_eq!(1, n),
@@ -1196,29 +1333,30 @@ mod tests {
                 assert_eq!(v.len(), 1);
                 assert_eq!(o.is_some(), true);
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
+                            //+ associates to the left
                             AddExpr(ref e1, ref e2) => {
                                 match **e1{
-                                    NumExpr(n) => assert_eq!(n, 1),
-                                    _ => panic!("num expr expected")
-                                }
-
-                                match **e2{
                                     AddExpr(ref e1, ref e2) => {
                                         match **e1{
-                                            NumExpr(n) => assert_eq!(n, 3),
+                                            NumExpr(n) => assert_eq!(n, 1),
                                             _ => panic!("num expr expected")
                                         }
 
                                         match **e2{
-                                            NumExpr(n) => assert_eq!(n, 1),
+                                            NumExpr(n) => assert_eq!(n, 3),
                                             _ => panic!("num expr expected")
                                         }
                                     },
                                     _ => panic!("add expr expected")
                                 }
+
+                                match **e2{
+                                    NumExpr(n) => assert_eq!(n, 1),
+                                    _ => panic!("num expr expected")
+                                }
                             },
                             _ => panic!("add expr expected")
                         }
@@ -1336,8 +1474,8 @@ mod tests {
                 assert_eq!(o.as_ref().unwrap().len(), 2);
                 match *o.as_ref().unwrap()[0]{
                     AssignExpr(ref e1, ref e2) => {
-                        match **e1 {
-                            SubscriptExpr(_, _) => {},
+                        match *e1 {
+                            SubscriptVar(_, _) => {},
                             _ => {panic!("Expected a subscript_expr");}
                         }
                         match **e2 {
@@ -1461,7 +1599,7 @@ mod tests {
         match *expr{
             IfThenExpr(ref conditional_expr, ref then_expr) => {
                 match **conditional_expr{
-                    StringExpr(ref s) => assert_eq!(*s, String::from("abhi")),
+                    StringExpr(ref s) => assert_eq!(*s, b"abhi"),
                     _ => {}
                 }
             },
@@ -1519,7 +1657,7 @@ mod tests {
         let (ty, expr) = p.expr().unwrap();
         match *expr{
             IfThenExpr(ref conditional_expr, _) => match **conditional_expr {
-                StringExpr(ref s) => assert_eq!(*s, "abhi"),
+                StringExpr(ref s) => assert_eq!(*s, b"abhi"),
                 _ =>  panic!("This will not exhecute")
             },
             _ => panic!("This will not execute")
@@ -1571,7 +1709,7 @@ mod tests {
         match *expr{
             WhileExpr(ref conditional_expr, ref do_expr) => {
                 match **conditional_expr{
-                    StringExpr(ref s) => assert_eq!(*s, "abhi"),
+                    StringExpr(ref s) => assert_eq!(*s, b"abhi"),
                     _ => panic!("This will not execute")
                 }
             },
@@ -1664,7 +1802,7 @@ mod tests {
         p.start_lexer();
         let (ty, expr) = p.expr().unwrap();
         match *expr{
-            ForExpr(ref id, ref from_expr, ref to_expr, ref do_expr) => {
+            ForExpr(ref id, ref from_expr, ref to_expr, ref do_expr, _) => {
                 assert_eq!(*id, String::from("id"));
                 match **from_expr{
                     NumExpr(n) => assert_eq!(n, 1),
@@ -1694,7 +1832,7 @@ mod tests {
         p.start_lexer();
         let (ty, expr) = p.expr().unwrap();
         match *expr{
-            ForExpr(ref id, ref from_expr, _, _) => {
+            ForExpr(ref id, ref from_expr, _, _, _) => {
                 match **from_expr{
                     IdExpr(ref i) => assert_eq!(*i, String::from("a")),
                     _ => panic!("this will not execute")
@@ -1709,7 +1847,7 @@ mod tests {
         p.start_lexer();
         let (ty, expr) = p.expr().unwrap();
         match *expr{
-            ForExpr(ref id, ref from_expr, ref to_expr, _) => {
+            ForExpr(ref id, ref from_expr, ref to_expr, _, _) => {
                 match **to_expr{
                     IdExpr(ref i) => assert_eq!(*i, String::from("b")),
                     _ => panic!("This will not execute")
@@ -1722,7 +1860,7 @@ mod tests {
             _ => panic!("This will not execute")
         } 
         match *expr{
-            ForExpr(ref id, ref from_expr, ref to_expr, _) => {
+            ForExpr(ref id, ref from_expr, ref to_expr, _, _) => {
                 match **to_expr{
                     IdExpr(ref i) => assert_eq!(*i, String::from("b")),
                     _ => panic!("This will not execute")
@@ -1784,7 +1922,7 @@ mod tests {
         match *expr{
             LetExpr(ref v, ref o) => {
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             NumExpr(n) => assert_eq!(1, n),
@@ -1815,8 +1953,15 @@ mod tests {
         p.start_lexer();
         let (ty, expr) = p.expr().unwrap();
         match *expr{
-            SubscriptExpr(ref name, ref expr) => {
-                assert_eq!(*name, String::from("a")); 
+            LValueExpr(SubscriptVar(ref arr, ref idx)) => {
+                match **arr{
+                    SimpleVar(ref name) => assert_eq!(*name, String::from("a")),
+                    _ => panic!("Expected a variable")
+                }
+                match **idx{
+                    LValueExpr(SubscriptVar(_, _)) => {},
+                    _ => panic!("Expected a subscript expression")
+                }
             },
             _ => panic!("Expected a subscript expression")
         } 
@@ -1829,11 +1974,14 @@ mod tests {
         let (ty, expr) = p.expr().unwrap();
         match *expr{
             AssignExpr(ref lhs, ref rhs) => {
-                match **lhs{
-                    SubscriptExpr(ref name, _) =>{
-                        assert_eq!(*name, String::from("a"));
+                match *lhs{
+                    SubscriptVar(ref arr, _) =>{
+                        match **arr{
+                            SimpleVar(ref name) => assert_eq!(*name, String::from("a")),
+                            _ => panic!("Expected a variable")
+                        }
                     },
-                    _ => {panic!("Expected a SubscriptExpr");}
+                    _ => {panic!("Expected a SubscriptVar");}
                 }
 
                 match **rhs{
@@ -1853,11 +2001,11 @@ mod tests {
         let (ty, expr) = p.expr().unwrap();
         match *expr{
             AssignExpr(ref lhs, ref rhs) => {
-                match **lhs{
-                    IdExpr(ref name) =>{
+                match *lhs{
+                    SimpleVar(ref name) =>{
                         assert_eq!(*name, String::from("a"));
                     },
-                    _ => {panic!("Expected a SubscriptExpr");}
+                    _ => {panic!("Expected a SimpleVar");}
                 }
 
                 match **rhs{
@@ -1878,7 +2026,7 @@ mod tests {
         match *expr{
             LetExpr(ref v, ref o) => {
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             RecordExpr(ref field_decls) => {
@@ -1905,7 +2053,7 @@ mod tests {
         match *expr{
             LetExpr(ref v, ref o) => {
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             RecordExpr(ref field_decls) => {
@@ -1934,7 +2082,7 @@ mod tests {
         match *expr{
             LetExpr(ref v, ref o) => {
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             RecordExpr(ref field_decls) => {
@@ -1952,21 +2100,12 @@ mod tests {
                 }
 
                 match **o.as_ref().unwrap(){
-                    FieldExpr(ref head, ref tail) =>{
-                        match **head{
-                            IdExpr(ref id) => assert_eq!(*id, "a"),
-                            _ => panic!("Expected id expression")
+                    LValueExpr(FieldVar(ref rec, ref field)) =>{
+                        match **rec{
+                            SimpleVar(ref id) => assert_eq!(*id, "a"),
+                            _ => panic!("Expected a variable")
                         }
-
-                        match **tail{
-                            IdExpr(ref id) =>{
-                                assert_eq!(*id, "f");
-
-                            }
-                            _ => {panic!("Expected field expression")}
-                        }
-
-                    
+                        assert_eq!(*field, "f");
                     },
                     _ => {panic!("Expected a field expression")}
                 }
@@ -1983,7 +2122,7 @@ mod tests {
         match *expr{
             LetExpr(ref v, ref o) => {
                 match v[0]{
-                    VarDec(ref id, ref ty, ref e) => {
+                    VarDec(ref id, ref ty, ref e, _) => {
                         assert_eq!(*id, "a".to_string());
                         match **e{ //**e means deref deref B<T> which results in T
                             RecordExpr(ref field_decls) => {
@@ -2001,38 +2140,369 @@ mod tests {
                 }
 
                 match **o.as_ref().unwrap(){
-                    FieldExpr(ref head, ref tail) =>{
-                        match **head{
+                    LValueExpr(FieldVar(ref rec, ref field)) =>{
+                        assert_eq!(*field, "e");
+                        match **rec{
+                            FieldVar(ref rec, ref field) =>{
+                                assert_eq!(*field, "f");
+                                match **rec{
+                                    SimpleVar(ref id) => assert_eq!(*id, "a"),
+                                    _ => panic!("Expected a variable")
+                                }
+                            },
+                            _ => {panic!("Expected field expression")}
+                        }
+                    },
+                    _ => {panic!("Expected a field expression")}
+                }
+            },
+            _ => {panic!("expected let expr")}
+        }
+    }
+
+    #[test]
+    fn test_all_syntax_errors_are_reported(){
+        let mut p = Parser::new("let var a : int = 1\n var b : int := 2 in (b; if b 1; a) end".to_string());
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "line 1, column 17: Unexpected '='. Expected ':='");
+        assert_eq!(errors[1].line, 2);
+        assert_eq!(errors[1].expected, vec![Token::Then]);
+    }
+
+    #[test]
+    fn test_lexical_and_syntax_errors_are_reported_together(){
+        let mut p = Parser::new("let var s := \"a\\q\"\n var t := # 1 in (if s 1; s) end".to_string());
+        let errors : Vec<String> = p.parse().unwrap_err().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["line 1, column 16: Unrecognized escape sequence '\\q'",
+                                "line 2, column 11: Unexpected character '#'",
+                                "line 2, column 24: Unexpected number 1. Expected 'then'"]);
+    }
+
+    #[test]
+    fn test_bad_decl_is_replaced_by_error_dec(){
+        let mut p = Parser::new("let var a : int = 1\n var b : int := 2 in b end".to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        assert_eq!(p.errors.len(), 1);
+        match *expr{
+            LetExpr(ref decls, _) => {
+                assert_eq!(decls.len(), 2);
+                match decls[0]{
+                    ErrorDec => {},
+                    _ => panic!("Expected an error dec")
+                }
+                match decls[1]{
+                    VarDec(ref name, _, _, _) => assert_eq!(*name, String::from("b")),
+                    _ => panic!("Expected a var dec")
+                }
+            },
+            _ => panic!("Expected a let expr")
+        }
+    }
+
+    #[test]
+    fn test_seq_continues_after_bad_expr(){
+        let mut p = Parser::new("(a := ; b)".to_string());
+        p.start_lexer();
+        let (_, expr) = p.expr().unwrap();
+        assert_eq!(p.errors.len(), 1);
+        assert_eq!(p.errors[0].expected[0], Token::Ident);
+        match *expr{
+            SeqExpr(Some(ref exprs)) => {
+                assert_eq!(exprs.len(), 2);
+                match *exprs[0]{
+                    AssignExpr(_, ref rhs) => match **rhs{
+                        ErrorExpr => {},
+                        _ => panic!("Expected an error expr")
+                    },
+                    _ => panic!("Expected an assignment expression")
+                }
+                match *exprs[1]{
+                    IdExpr(ref id) => assert_eq!(*id, String::from("b")),
+                    _ => panic!("Expected an id expr")
+                }
+            },
+            _ => panic!("Expected a seq expr")
+        }
+    }
+
+    #[test]
+    fn test_errors_dont_cascade(){
+        let mut p = Parser::new("let function f( = 1 in f() end".to_string());
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "line 1, column 17: Unexpected '='. Expected an identifier");
+    }
+
+    #[test]
+    fn test_leftover_tokens(){
+        let mut p = Parser::new("1 2".to_string());
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "line 1, column 3: Unexpected number 2. Expected end of file");
+    }
+
+    #[test]
+    fn test_lexical_errors_keep_the_source_order(){
+        let mut p = Parser::new("let var x := 1 in x @ 2 end".to_string());
+        let errors : Vec<String> = p.parse().unwrap_err().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["line 1, column 21: Unexpected character '@'",
+                                "line 1, column 23: Unexpected number 2. Expected ';' or 'end'"]);
+    }
+
+    #[test]
+    fn test_unterminated_comment_is_a_syntax_error(){
+        let mut p = Parser::new("print(1)\n  /* a /* b */".to_string());
+        let errors : Vec<String> = p.parse().unwrap_err().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["line 2, column 3: Unterminated comment. Expected a closing '*/'"]);
+    }
+
+    #[test]
+    fn test_bad_escapes_are_syntax_errors(){
+        let mut p = Parser::new("print(\"a\\q\\256\")".to_string());
+        let errors : Vec<String> = p.parse().unwrap_err().iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["line 1, column 9: Unrecognized escape sequence '\\q'",
+                                "line 1, column 11: Invalid escape sequence '\\256'. Character codes go up to 255"]);
+    }
+
+    #[test]
+    fn test_mul_binds_tighter_than_add(){
+        let mut p = Parser::new("1 + 2 * 3".to_string());
+        match *p.parse().unwrap(){
+            AddExpr(ref l, ref r) => {
+                match **l{
+                    NumExpr(n) => assert_eq!(n, 1),
+                    _ => panic!("Expected a num expr")
+                }
+                match **r{
+                    MulExpr(_, _) => {},
+                    _ => panic!("Expected a mul expr")
+                }
+            },
+            _ => panic!("Expected an add expr")
+        }
+    }
+
+    #[test]
+    fn test_unary_minus(){
+        let mut p = Parser::new("-a * 2".to_string());
+        match *p.parse().unwrap(){
+            MulExpr(ref l, _) => {
+                match **l{
+                    SubExpr(ref zero, ref a) => {
+                        match **zero{
+                            NumExpr(n) => assert_eq!(n, 0),
+                            _ => panic!("Expected a num expr")
+                        }
+                        match **a{
                             IdExpr(ref id) => assert_eq!(*id, "a"),
-                            _ => panic!("Expected id expression")
+                            _ => panic!("Expected an id expr")
                         }
+                    },
+                    _ => panic!("Expected a sub expr")
+                }
+            },
+            _ => panic!("Expected a mul expr")
+        }
+    }
 
-                        match **tail{
-                            FieldExpr(ref head, ref tail)=>
-                            {
-                                match **head{
-                                    IdExpr(ref id) =>{
-                                        assert_eq!(*id, "f");
-                                    },
-                                    _ => {panic!("Expected id expression")}
-                                }
+    #[test]
+    fn test_and_or_are_ifs(){
+        let mut p = Parser::new("a & b | c".to_string());
+        match *p.parse().unwrap(){
+            IfThenElseExpr(ref cond, ref then_expr, _) => {
+                match **cond{
+                    IfThenElseExpr(_, _, ref else_expr) => {
+                        match **else_expr{
+                            NumExpr(n) => assert_eq!(n, 0),
+                            _ => panic!("Expected a num expr")
+                        }
+                    },
+                    _ => panic!("Expected an if-then-else expr")
+                }
+                match **then_expr{
+                    NumExpr(n) => assert_eq!(n, 1),
+                    _ => panic!("Expected a num expr")
+                }
+            },
+            _ => panic!("Expected an if-then-else expr")
+        }
+    }
 
-                                match **tail{
-                                    IdExpr(ref id) =>{
-                                        assert_eq!(*id, "e");
-                                    },
-                                    _ => panic!("Expected id expression")
-                                }
-                            }
-                            _ => {panic!("Expected field expression")}
+    #[test]
+    fn test_comparisons_dont_associate(){
+        let mut p = Parser::new("a = b = c".to_string());
+        let errors = p.parse().unwrap_err();
+        assert_eq!(errors[0].to_string(), "line 1, column 7: Unexpected '='");
+    }
+
+    #[test]
+    fn test_if_as_an_operand(){
+        let mut p = Parser::new("1 + if a then 2 else 3".to_string());
+        match *p.parse().unwrap(){
+            AddExpr(_, ref r) => {
+                match **r{
+                    IfThenElseExpr(_, _, _) => {},
+                    _ => panic!("Expected an if-then-else expr")
+                }
+            },
+            _ => panic!("Expected an add expr")
+        }
+    }
+
+    #[test]
+    fn test_paren_expr_followed_by_an_operator(){
+        let mut p = Parser::new("(a + 1) * 2".to_string());
+        match *p.parse().unwrap(){
+            MulExpr(ref l, _) => {
+                match **l{
+                    SeqExpr(Some(ref exprs)) => assert_eq!(exprs.len(), 1),
+                    _ => panic!("Expected a seq expr")
+                }
+            },
+            _ => panic!("Expected a mul expr")
+        }
+    }
+
+    #[test]
+    fn test_nil_comparison_and_break(){
+        let mut p = Parser::new("while a <> nil do break".to_string());
+        match *p.parse().unwrap(){
+            WhileExpr(ref cond, ref body) => {
+                match **cond{
+                    NotEqualsExpr(_, ref r) => {
+                        match **r{
+                            NilExpr => {},
+                            _ => panic!("Expected a nil expr")
                         }
+                    },
+                    _ => panic!("Expected a not equals expr")
+                }
+                match **body{
+                    BreakExpr => {},
+                    _ => panic!("Expected a break expr")
+                }
+            },
+            _ => panic!("Expected a while expr")
+        }
+    }
 
-                    
+    #[test]
+    fn test_lvalue_assign(){
+        let mut p = Parser::new("people[k].name := \"x\"".to_string());
+        match *p.parse().unwrap(){
+            AssignExpr(FieldVar(ref rec, ref field), ref rhs) => {
+                assert_eq!(*field, "name");
+                match **rec{
+                    SubscriptVar(ref arr, ref idx) => {
+                        match **arr{
+                            SimpleVar(ref id) => assert_eq!(*id, "people"),
+                            _ => panic!("Expected a variable")
+                        }
+                        match **idx{
+                            IdExpr(ref id) => assert_eq!(*id, "k"),
+                            _ => panic!("Expected an id expr")
+                        }
                     },
-                    _ => {panic!("Expected a field expression")}
+                    _ => panic!("Expected a subscript")
+                }
+                match **rhs{
+                    StringExpr(ref s) => assert_eq!(*s, b"x"),
+                    _ => panic!("Expected a string expr")
                 }
             },
-            _ => {panic!("expected let expr")}
+            _ => panic!("Expected an assign to a field")
+        }
+    }
+
+    #[test]
+    fn test_subscript_of_subscript(){
+        let mut p = Parser::new("matrix[i][j]".to_string());
+        match *p.parse().unwrap(){
+            LValueExpr(SubscriptVar(ref row, _)) => match **row{
+                SubscriptVar(ref arr, _) => match **arr{
+                    SimpleVar(ref id) => assert_eq!(*id, "matrix"),
+                    _ => panic!("Expected a variable")
+                },
+                _ => panic!("Expected a subscript")
+            },
+            _ => panic!("Expected a subscript")
+        }
+    }
+
+    #[test]
+    fn test_lvalue_nests_to_the_left(){
+        let mut p = Parser::new("a[i].f[j] := 1".to_string());
+        match *p.parse().unwrap(){
+            AssignExpr(SubscriptVar(ref lhs, _), _) => match **lhs{
+                FieldVar(ref rec, ref field) => {
+                    assert_eq!(*field, "f");
+                    match **rec{
+                        SubscriptVar(_, _) => {},
+                        _ => panic!("Expected a subscript")
+                    }
+                },
+                _ => panic!("Expected a field")
+            },
+            _ => panic!("Expected an assign to a subscript")
+        }
+    }
+
+    #[test]
+    fn test_typed_array_and_record(){
+        let mut p = Parser::new("let type t = array of int\n type r = {a : t}\n var x := r {a = t [3] of 0} in x end".to_string());
+        match *p.parse().unwrap(){
+            LetExpr(ref decls, _) => {
+                assert_eq!(decls.len(), 3);
+                match decls[0]{
+                    TypeDec(ref id, ref ty) => {
+                        assert_eq!(*id, "t");
+                        assert_eq!(*ty, TArray(B(TInt32)));
+                    },
+                    _ => panic!("Expected a type dec")
+                }
+                match decls[1]{
+                    TypeDec(_, ref ty) => assert_eq!(*ty, TRecord(vec![(String::from("a"), TCustom(String::from("t")))])),
+                    _ => panic!("Expected a type dec")
+                }
+                match decls[2]{
+                    VarDec(_, ref ty, ref e, _) => {
+                        assert_eq!(*ty, TNil);
+                        match **e{
+                            TypedRecordExpr(ref id, ref fields) => {
+                                assert_eq!(*id, "r");
+                                assert_eq!(fields[0].0, "a");
+                                match *fields[0].1{
+                                    TypedArrayExpr(ref id, _, _) => assert_eq!(*id, "t"),
+                                    _ => panic!("Expected a typed array expr")
+                                }
+                            },
+                            _ => panic!("Expected a typed record expr")
+                        }
+                    },
+                    _ => panic!("Expected a var dec")
+                }
+            },
+            _ => panic!("Expected a let expr")
+        }
+    }
+
+    #[test]
+    fn test_let_body_is_a_sequence(){
+        let mut p = Parser::new("let var a : t := nil in a := 1; a end".to_string());
+        match *p.parse().unwrap(){
+            LetExpr(ref decls, Some(ref body)) => {
+                match decls[0]{
+                    VarDec(_, ref ty, _, _) => assert_eq!(*ty, TCustom(String::from("t"))),
+                    _ => panic!("Expected a var dec")
+                }
+                match **body{
+                    SeqExpr(Some(ref exprs)) => assert_eq!(exprs.len(), 2),
+                    _ => panic!("Expected a seq expr")
+                }
+            },
+            _ => panic!("Expected a let expr")
         }
     }
 }
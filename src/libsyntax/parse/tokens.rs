@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Copy, Clone, PartialEq)]
 //Copy and Clone - this is to enable returning of clone of enum members and hence avoid getting `moving-borrow` errors
 //PartialEq - this is to enable comparison during unit tests, etc.
@@ -5,7 +7,6 @@
 pub enum Token{
     //keywords
     Array,
-    Rec,
     Break,
     Do,
     End,
@@ -30,6 +31,7 @@ pub enum Token{
     Minus,
     Mul,
     Div,
+    Mod,
     LogAnd,
     LogNot,
     LogOr,
@@ -61,8 +63,81 @@ pub enum Token{
     Number
 }
 
+//how a token is named in error messages
+impl fmt::Display for Token{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(match *self{
+            Token::Array => "'array'",
+            Token::Break => "'break'",
+            Token::Do => "'do'",
+            Token::End => "'end'",
+            Token::While => "'while'",
+            Token::If => "'if'",
+            Token::Then => "'then'",
+            Token::Else => "'else'",
+            Token::For => "'for'",
+            Token::In => "'in'",
+            Token::Let => "'let'",
+            Token::Of => "'of'",
+            Token::To => "'to'",
+            Token::Type => "'type'",
+            Token::Var => "'var'",
+            Token::Function => "'function'",
+            Token::Nil => "'nil'",
+            Token::Int => "'int'",
+            //the keyword string and string literals are the same token
+            Token::TokString => "a string",
+            Token::Plus => "'+'",
+            Token::Minus => "'-'",
+            Token::Mul => "'*'",
+            Token::Div => "'/'",
+            Token::Mod => "'mod'",
+            Token::LogAnd => "'&'",
+            Token::LogNot => "'!'",
+            Token::LogOr => "'|'",
+            Token::Equals => "'='",
+            Token::LessEquals => "'<='",
+            Token::GreaterEquals => "'>='",
+            Token::LessThan => "'<'",
+            Token::GreaterThan => "'>'",
+            Token::LessThanGreaterThan => "'<>'",
+            Token::ColonEquals => "':='",
+            Token::LeftParen => "'('",
+            Token::RightParen => "')'",
+            Token::LeftCurly => "'{'",
+            Token::RightCurly => "'}'",
+            Token::LeftSquare => "'['",
+            Token::RightSquare => "']'",
+            Token::SemiColon => "';'",
+            Token::Colon => "':'",
+            Token::Comma => "','",
+            Token::Dot => "'.'",
+            Token::Error => "an invalid token",
+            Token::NoToken => "no token",
+            Token::Eof => "end of file",
+            Token::NewLine => "a newline",
+            Token::Ident => "an identifier",
+            Token::Number => "a number"
+        })
+    }
+}
+
 impl Default for Token{
     fn default()->Token{
         Token::NoToken
     }
 }
+
+//a token as the lexer hands it out, with its text and where it starts
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SpannedToken{
+    pub token : Token,
+    //the spelling of an identifier or keyword, the digits of a number, the
+    //message of an error or the decoded contents of a string literal, read
+    //as UTF-8. Empty for symbols
+    pub text : String,
+    //the exact contents of a string literal
+    pub bytes : Vec<u8>,
+    pub line : usize,
+    pub col : usize
+}
@@ -1,13 +1,31 @@
+use std::fmt;
 use std::ops::{Deref, DerefMut};
+use ast::Span;
 
-#[derive(Debug, PartialEq, Clone)] //this is necessary so that TType can be used in assert, compared, cloned
+//the span doesn't take part in comparisons and isn't printed, so that nodes
+//built by hand in tests still compare equal to parsed ones
+#[derive(Clone)]
 pub struct B<T>{
-    ptr : Box<T>
+    ptr : Box<T>,
+    //where the node starts in the source; set by the parser
+    pub span : Span
 }
 
 //acts like a constructor
 pub fn B<T>(value : T)->B<T>{
-    B {ptr : Box::new(value)}
+    B {ptr : Box::new(value), span : Span::default()}
+}
+
+impl<T : PartialEq> PartialEq for B<T>{ //this is necessary so that TType can be used in assert and compared
+    fn eq(&self, other : &B<T>) -> bool{
+        self.ptr == other.ptr
+    }
+}
+
+impl<T : fmt::Debug> fmt::Debug for B<T>{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        self.ptr.fmt(f)
+    }
 }
 
 impl<T> Deref for B<T>{ //allows & to be used for B<T>
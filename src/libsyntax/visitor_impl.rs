@@ -2,11 +2,14 @@
 
 use itertools::Itertools;
 use std::collections::{HashMap};
-use ast::{Binding, Expr, Decl, TType, OptionalIdTypePairs};
+use ast::{Binding, Expr, LValue, Decl, TType, OptionalIdTypePairs};
+#[cfg(test)]
+use ast::Escape;
 use ast::Binding::*;
 use ast::Expr::*;
 use ast::TType::*;
 use ast::Decl::*;
+use ast::LValue::*;
 use visit::{Visitor};
 use std::cell::RefCell;
 use ptr::*;
@@ -46,6 +49,64 @@ impl TypeChecker{
     fn get_type_for(&self){//}->&TType{
         //self.block_stack
     }
+
+    //the type `id` is bound to in the innermost scope that declares it
+    fn var_ty(&self, id : &str) -> TType{
+        for &(ref _id, ref _binding) in self.sym_tab.iter().rev(){
+            if *_id == *id{
+                return match **_binding.as_ref().unwrap(){
+                    TypeBinding(ref ty) |
+                    VarBinding(ref ty) |
+                    FuncBinding(ref ty) => ty.clone()
+                }
+            }
+        }
+        panic!("Invalid reference to variable '{0}'", id);
+    }
+
+    //follows the names of custom types to the type they stand for
+    fn resolve(&self, ty : &TType) -> TType{
+        if let TCustom(ref name) = *ty{
+            for &(ref _id, ref binding) in self.sym_tab.iter().rev(){
+                if *_id == *name{
+                    if let TypeBinding(ref ty) = **binding.as_ref().unwrap(){
+                        return self.resolve(ty)
+                    }
+                }
+            }
+        }
+        ty.clone()
+    }
+
+    //the type of the location `lvalue` names. TNil when it isn't known
+    fn lvalue_ty<'a>(&mut self, lvalue : &'a mut LValue) -> TType{
+        match *lvalue{
+            SimpleVar(ref id) => self.var_ty(id),
+            FieldVar(ref mut rec, ref field) => {
+                let rec_ty = self.lvalue_ty(rec);
+                match self.resolve(&rec_ty){
+                    TRecord(ref fields) => match fields.iter().find(|f| f.0 == *field){
+                        Some(f) => f.1.clone(),
+                        None => panic!("Record has no field named '{0}'", field)
+                    },
+                    TNil => TNil,
+                    ty => panic!("Field '{0}' of '{1}' which is not a record", field, ty)
+                }
+            },
+            SubscriptVar(ref mut arr, ref mut idx) => {
+                let arr_ty = self.lvalue_ty(arr);
+                self.visit_expr(idx);
+                if self.ty != TInt32{
+                    panic!("Expected an index of int type");
+                }
+                match self.resolve(&arr_ty){
+                    TArray(ref elem) => (**elem).clone(),
+                    TNil => TNil,
+                    ty => panic!("Subscript of '{0}' which is not an array", ty)
+                }
+            }
+        }
+    }
 }
 
 impl<'a> Visitor<'a> for TypeChecker{
@@ -65,23 +126,32 @@ impl<'a> Visitor<'a> for TypeChecker{
             NilExpr => self.ty = TString,
             NumExpr(_) => self.ty = TInt32,
             StringExpr(_) => self.ty = TString,
-            IdExpr(ref mut id) =>{
-                //search in the symtab for id's existence and get the type
-                let mut found = false;
-                for &(ref _id, ref _binding) in &self.sym_tab{ //iterator returns a ref to tuple while iterating; so &(_,_) has to be used
-                    if *_id == *id{
-                        found = true;
-                        self.ty = match **_binding.as_ref().unwrap(){
-                            TypeBinding(ref ty) |
-                            VarBinding(ref ty) |
-                            FuncBinding(ref ty) => ty.clone()
-                        };
-                        break;
-                    }
+            //the parser has reported the error already; its type is unknown
+            ErrorExpr => self.ty = TNil,
+            IdExpr(ref mut id) => self.ty = self.var_ty(id),
+            LValueExpr(ref mut lvalue) => self.ty = self.lvalue_ty(lvalue),
+            AssignExpr(ref mut lvalue, ref mut rhs) => {
+                let lhs_ty = self.lvalue_ty(lvalue);
+                self.visit_expr(rhs);
+                let (lhs_ty, rhs_ty) = (self.resolve(&lhs_ty), self.resolve(&self.ty));
+                //nil can be assigned to any record
+                let is_nil = match **rhs {NilExpr => true, _ => false};
+                if lhs_ty != TNil && rhs_ty != TNil && lhs_ty != rhs_ty && !is_nil{
+                    panic!(format!("Types mismatch. Variable type is {0} and expression type is '{1}'", lhs_ty, rhs_ty));
                 }
-                if !found{
-                    panic!("Invalid reference to variable '{0}'", id);
+                self.ty = TVoid;
+            },
+            RecordExpr(ref field_decls) => self.ty = TRecord(field_decls.clone().unwrap_or(Vec::new())),
+            TypedRecordExpr(ref id, ref mut fields) => {
+                for &mut (_, ref mut e) in fields.iter_mut(){
+                    self.visit_expr(e);
                 }
+                self.ty = TCustom(id.clone());
+            },
+            TypedArrayExpr(ref id, ref mut dim, ref mut init) => {
+                visit_verify_error!(dim, TInt32, "Expected an array size of int type");
+                self.visit_expr(init);
+                self.ty = TCustom(id.clone());
             },
             LessThanExpr(ref mut e1, ref mut e2) |
             GreaterThanExpr (ref mut e1, ref mut e2) => {
@@ -97,7 +167,8 @@ impl<'a> Visitor<'a> for TypeChecker{
                 visit_verify_error!(left, TInt32, "Expected left operand of int type");
                 visit_verify_error!(right, TInt32, "Expected right operand of int type");
             },
-            DivExpr(ref mut left, ref mut  right) => {
+            DivExpr(ref mut left, ref mut  right) |
+            ModExpr(ref mut left, ref mut  right) => {
                 visit_verify_error!(left, TInt32, "Expected left operand of int type");
                 visit_verify_error!(right, TInt32, "Expected right operand of int type");
                 if let Expr::NumExpr(n) = **right{
@@ -131,10 +202,13 @@ impl<'a> Visitor<'a> for TypeChecker{
                 visit_verify_error!(conditional_expr, TInt32, "Expected conditional expression of int type");
                 visit_verify_error!(body, TVoid, "Expected while-body of void type");
             },
-            ForExpr(_, ref mut from, ref mut to, ref mut body) => {
+            ForExpr(ref id, ref mut from, ref mut to, ref mut body, _) => {
                 visit_verify_error!(from, TInt32, "Initializing expression type should be int in a for loop");
                 visit_verify_error!(to, TInt32, "To expression type should be int in a for loop");
+                //the index is an int variable of the body
+                self.sym_tab.push((id.clone(), Some(B(VarBinding(TInt32)))));
                 visit_verify_error!(body, TVoid, "A for expression's body must be of type void");
+                self.sym_tab.pop();
             },
             CallExpr(ref id, ref mut optional_ty_expr_list) => {
                 //check if this is a built-in function
@@ -185,7 +259,7 @@ impl<'a> Visitor<'a> for TypeChecker{
                                 }
                                 //FIXME do we panic if function not found?
                             }, 
-                            SubscriptExpr(ref id, _) |
+                            LValueExpr(ref mut lvalue) => *ty = self.lvalue_ty(lvalue),
                             IdExpr(ref id) => {
                                 for &(ref _id, ref binding) in self.sym_tab.iter().rev(){
                                     if *id == *_id{
@@ -230,7 +304,7 @@ impl<'a> Visitor<'a> for TypeChecker{
             }
         }
         match *decl{
-            VarDec(ref id, ref ty, ref mut expr) => {
+            VarDec(ref id, ref mut ty, ref mut expr, _) => {
                 match **expr{
                     IdExpr(ref id) => {
                         for &(ref sym, ref binding) in self.sym_tab.iter().rev(){
@@ -254,6 +328,11 @@ impl<'a> Visitor<'a> for TypeChecker{
                             panic!("Array type doesn't match with the type of the init expression")
                         }
 
+                        //the variable is the array, not its elements
+                        if *ty == TNil{
+                            *ty = TArray(B(_ty.clone()));
+                        }
+                        self.ty = ty.clone();
                         store_into_sym_tab!(self, id, VarBinding);
                         return;
                     },
@@ -281,13 +360,26 @@ impl<'a> Visitor<'a> for TypeChecker{
                 //self.ty can still remain Nil in scenarios
                 //where the body contains a call to an  
                 //intrinsic function which cannot be verified 
-                //by the type-checker
-                if self.ty != TNil && *ty != self.ty{
+                //by the type-checker. A variable declared without a type
+                //(TNil) takes the type of its initializer
+                if self.ty != TNil && *ty != TNil && self.resolve(ty) != self.resolve(&self.ty){
                     panic!(format!("Types mismatch. Variable type is {0} and expression type is '{1}'", *ty, self.ty));
                 }
+                //like the body type of a function, the type inferred for the
+                //variable is filled in for the backends. nil says nothing
+                //about the record it stands for
+                let is_nil = match **expr { NilExpr => true, _ => false };
+                if *ty == TNil && !is_nil{
+                    *ty = self.ty.clone();
+                }
+                if *ty != TNil{
+                    self.ty = ty.clone();
+                }
                 store_into_sym_tab!(self, id, VarBinding);
             },
             FunDec(ref id, ref params, ref ret_type, ref mut body, ref mut body_type) => {
+                //the body can call the function itself
+                self.sym_tab.push((id.clone(), Some(B(FuncBinding(ret_type.clone())))));
                 self.sym_tab.push((String::from("<marker>"), None));
                 if params.is_some(){
                     for p in params.as_ref().unwrap(){
@@ -321,8 +413,10 @@ impl<'a> Visitor<'a> for TypeChecker{
                 store_into_sym_tab!(self, id, FuncBinding);
             },
             TypeDec(ref id, ref ty) => {
+                self.ty = ty.clone();
                 store_into_sym_tab!(self, id, TypeBinding);
-            }
+            },
+            ErrorDec => {}
         }
     }
 }
@@ -345,7 +439,7 @@ fn test_ty_set_for_int_id() {
 #[test]
 fn test_type_match_int_for_var_dec() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TInt32, B(NumExpr(4))));
+    v.visit_decl(&mut VarDec("a".to_string(), TInt32, B(NumExpr(4)), Escape::new()));
     assert_eq!(TInt32, v.ty);
     assert_eq!(v.sym_tab.len(), 1);
     assert_eq!(v.sym_tab[0].0, "a".to_string());
@@ -355,7 +449,7 @@ fn test_type_match_int_for_var_dec() {
 #[test]
 fn test_type_match_string_for_var_dec() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TString, B(NilExpr)));
+    v.visit_decl(&mut VarDec("a".to_string(), TString, B(NilExpr), Escape::new()));
     assert_eq!(TString, v.ty);
     assert_eq!(v.sym_tab.len(), 1);
     assert_eq!(v.sym_tab[0].0, "a".to_string());
@@ -365,28 +459,28 @@ fn test_type_match_string_for_var_dec() {
 #[test]
 fn test_array_type_matches_dim_expr_type() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TInt32, B(NumExpr(1)), B(NumExpr(1))))));
+    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TInt32, B(NumExpr(1)), B(NumExpr(1)))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="Array type doesn't match with the type of the dimension expression")]
 fn test_array_type_mismatches_dim_expr_type() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TString, B(NumExpr(1)), B(NumExpr(1))))));
+    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TString, B(NumExpr(1)), B(NumExpr(1)))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="Array type doesn't match with the type of the init expression")]
 fn test_array_type_mismatches_init_expr_type() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TInt32, B(NumExpr(1)), B(StringExpr(String::from("abhi")))))));
+    v.visit_decl(&mut VarDec("a".to_string(), TArray(B(TInt32)), B(ArrayExpr(TInt32, B(NumExpr(1)), B(StringExpr(b"abhi".to_vec())))), Escape::new()));
 }
 
 #[test]
 #[should_panic]
 fn test_type_check_for_var_dec_type_mismatch() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TInt32, B(NilExpr)));
+    v.visit_decl(&mut VarDec("a".to_string(), TInt32, B(NilExpr), Escape::new()));
     assert_eq!(TInt32, v.ty);
 }
 
@@ -433,7 +527,7 @@ fn test_func_decl_incorrect_return_type() {
 #[should_panic(expected="Expected conditional expression of int type")]
 fn test_if_expr_with_incorrect_conditional_type() {
     let mut v = TypeChecker::new();
-    v.visit_expr(&mut IfThenExpr(B(StringExpr(String::from("a"))), B(StringExpr(String::from("a")))));
+    v.visit_expr(&mut IfThenExpr(B(StringExpr(b"a".to_vec())), B(StringExpr(b"a".to_vec()))));
 }
 
 #[test]
@@ -462,21 +556,21 @@ fn test_if_else_expr_with_matching_types() {
 #[should_panic(expected="Expected then expr and else expr types to be same")]
 fn test_if_else_expr_with_non_matching_types() {
     let mut v = TypeChecker::new();
-    v.visit_expr(&mut IfThenElseExpr(B(NumExpr(1)), B(NumExpr(1)), B(StringExpr(String::from("a")))));
+    v.visit_expr(&mut IfThenElseExpr(B(NumExpr(1)), B(NumExpr(1)), B(StringExpr(b"a".to_vec()))));
 }
 
 #[test]
 #[should_panic(expected="Expected conditional expression of int type")]
 fn test_while_expr_with_incorrect_conditional_type() {
     let mut v = TypeChecker::new();
-    v.visit_expr(&mut WhileExpr(B(StringExpr(String::from("a"))), B(StringExpr(String::from("a")))));
+    v.visit_expr(&mut WhileExpr(B(StringExpr(b"a".to_vec())), B(StringExpr(b"a".to_vec()))));
 }
 
 #[test]
 #[should_panic(expected="Expected while-body of void type")]
 fn test_while_expr_with_int_type() {
     let mut v = TypeChecker::new();
-    v.visit_expr(&mut WhileExpr(B(NumExpr(1)), B(StringExpr(String::from("a")))));
+    v.visit_expr(&mut WhileExpr(B(NumExpr(1)), B(StringExpr(b"a".to_vec()))));
 }
 
 #[test]
@@ -486,6 +580,13 @@ fn test_div_expr_with_0_as_denominator(){
     v.visit_expr(&mut DivExpr(B(NumExpr(1)), B(NumExpr(0))));
 }
 
+#[test]
+#[should_panic(expected="Denominator cannot be 0")]
+fn test_mod_expr_with_0_as_denominator(){
+    let mut v = TypeChecker::new();
+    v.visit_expr(&mut ModExpr(B(NumExpr(1)), B(NumExpr(0))));
+}
+
 #[test]
 fn test_div_expr_with_1_as_denominator(){
     let mut v = TypeChecker::new();
@@ -498,9 +599,10 @@ fn test_div_expr_with_1_as_denominator(){
 fn test_for_loop_expr_init_type(){
     let mut v = TypeChecker::new();
     v.visit_expr(&mut ForExpr(String::from("i"),
-                                B(StringExpr(String::from("adsd"))),
+                                B(StringExpr(b"adsd".to_vec())),
                                 B(NumExpr(1)),
-                                B(NumExpr(2))));
+                                B(NumExpr(2)),
+                                Escape::new()));
 }
 
 #[test]
@@ -508,8 +610,8 @@ fn test_for_loop_expr_init_type(){
 fn test_func_dec_with_duplicate_param_with_same_type(){
     let mut v = TypeChecker::new();
     v.visit_decl(&mut FunDec(String::from("foo"), 
-                               Some(vec![(String::from("a"), TInt32),
-                                         (String::from("a"), TInt32) ]),
+                               Some(vec![(String::from("a"), TInt32, Escape::new()),
+                                         (String::from("a"), TInt32, Escape::new()) ]),
                                TInt32,
                                B(NumExpr(4)),
                                TInt32));
@@ -520,8 +622,8 @@ fn test_func_dec_with_duplicate_param_with_same_type(){
 fn test_func_dec_with_duplicate_param_with_different_types(){
     let mut v = TypeChecker::new();
     v.visit_decl(&mut FunDec(String::from("foo"), 
-                               Some(vec![(String::from("a"), TInt32),
-                                         (String::from("a"), TString) ]),
+                               Some(vec![(String::from("a"), TInt32, Escape::new()),
+                                         (String::from("a"), TString, Escape::new()) ]),
                                TInt32,
                                B(NumExpr(4)),
                                TInt32));
@@ -531,7 +633,7 @@ fn test_func_dec_with_duplicate_param_with_different_types(){
 fn test_call_expr_call_print_with_params(){
     let mut v = TypeChecker::new();
     v.visit_expr(&mut CallExpr(String::from("print"),
-                                     Some(vec![(TString, B(StringExpr(String::from("abhi"))))])));
+                                     Some(vec![(TString, B(StringExpr(b"abhi".to_vec())))])));
     assert_eq!(v.ty, TVoid);
 }
 
@@ -554,7 +656,7 @@ fn test_call_expr_call_undefined_function(){
 fn test_type_fix_func_return_type(){
     let mut v = TypeChecker::new();
     let dec = &mut FunDec(String::from("foo"), 
-                               Some(vec![(String::from("a"), TInt32)
+                               Some(vec![(String::from("a"), TInt32, Escape::new())
                                           ]),
                                TInt32,
                                B(NumExpr(4)),
@@ -569,7 +671,7 @@ fn test_type_fix_func_return_type(){
 fn test_call_expr_ret_type_fix(){
     let mut v = TypeChecker::new();
     let e = &mut LetExpr(vec![FunDec(String::from("foo"), 
-                               Some(vec![(String::from("a"), TInt32)]),
+                               Some(vec![(String::from("a"), TInt32, Escape::new())]),
                                TInt32,
                                B(NumExpr(4)),
                                TInt32)],
@@ -610,77 +712,121 @@ fn test_call_expr_ret_type_fix(){
 #[should_panic(expected="record 'a' contains repetitive fields")]
 fn test_record_dup_fields_1() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("f"), TInt32)])))));
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(Vec::new()), B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("f"), TInt32)]))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="record 'a' contains repetitive fields")]
 fn test_record_dup_fields_2() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("f"), TInt32)])))));
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(Vec::new()), B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("f"), TInt32)]))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="record 'a' contains repetitive fields")]
 fn test_record_dup_fields_3() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("f"), TString)])))));
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(Vec::new()), B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("f"), TString)]))), Escape::new()));
 }
 
 #[test]
 fn test_record_unique_fields() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("h"), TString)])))));
+    let fields = vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("h"), TString)];
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(fields.clone()), B(RecordExpr(Some(fields))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="rec 'a' contains a field of type 'a'. cyclic references to type are not allowed.")]
 fn test_record_contains_cyclic_ref() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TCustom(String::from("a")))])))));
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(Vec::new()), B(RecordExpr(Some(vec![(String::from("f"), TCustom(String::from("a")))]))), Escape::new()));
 }
 
 #[test]
 #[should_panic(expected="rec 'a' contains a field of type 'a'. cyclic references to type are not allowed.")]
 fn test_record_contains_cyclic_ref_2() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("a"), TCustom(String::from("int"))),
-                                                                              (String::from("f"), TCustom(String::from("a")))])))));
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(Vec::new()), B(RecordExpr(Some(vec![(String::from("a"), TCustom(String::from("int"))),
+                                                                              (String::from("f"), TCustom(String::from("a")))]))), Escape::new()));
 }
 
 #[test]
 fn test_record_contains_cyclic_ref_3() {
     let mut v = TypeChecker::new();
-    v.visit_decl(&mut VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("a"), TCustom(String::from("int"))),
-                                                                              (String::from("f"), TCustom(String::from("b")))])))));
+    let fields = vec![(String::from("a"), TCustom(String::from("int"))), (String::from("f"), TCustom(String::from("b")))];
+    v.visit_decl(&mut VarDec("a".to_string(), TRecord(fields.clone()), B(RecordExpr(Some(fields))), Escape::new()));
 }
 
 #[test]
 fn test_record_field_access_type_fix(){
     let mut v = TypeChecker::new();
-    //let var a = rec{b:int} in print(a.b) end
-    let e = &mut LetExpr(vec![VarDec("a".to_string(), TRecord, B(RecordExpr(Some(vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("h"), TString)]))))],
-                               Some(B(CallExpr(String::from("foo"),
-                                                 Some(vec![(TNil, B(FieldExpr(String::from("a"),
-                                                                              String::from("f")
-                                                                             )
-                                                                   )
-                                                           )]
-                                                     )
-                                                )
-                                     )
-                                   )
-                               );
-
+    //let var a : rec := {f : int, g : int, h : string} in print(a.h) end
+    let fields = vec![(String::from("f"), TInt32), (String::from("g"), TInt32), (String::from("h"), TString)];
+    let e = &mut LetExpr(vec![VarDec("a".to_string(), TRecord(fields.clone()), B(RecordExpr(Some(fields))), Escape::new())],
+                         Some(B(CallExpr(String::from("print"),
+                                         Some(vec![(TNil, B(LValueExpr(FieldVar(B(SimpleVar(String::from("a"))), String::from("h")))))])))));
     v.visit_expr(e);
     match *e{
-        LetExpr(_, ref e) => {
-            match **e.as_ref().unwrap(){
-                CallExpr(_, ref l) => {
-                }
-                _ => {}
-            }
-        }
-        _ => {}
+        LetExpr(_, Some(ref e)) => match **e{
+            CallExpr(_, Some(ref args)) => assert_eq!(args[0].0, TString),
+            _ => panic!("Expected a call expr")
+        },
+        _ => panic!("Expected a let expr")
     }
 }
+
+//type list = {value : int, next : list}
+//type matrix = array of row, where type row = array of int
+fn lvalue_checker() -> TypeChecker{
+    let mut v = TypeChecker::new();
+    v.visit_decl(&mut TypeDec(String::from("list"), TRecord(vec![(String::from("value"), TInt32),
+                                                                  (String::from("next"), TCustom(String::from("list")))])));
+    v.visit_decl(&mut TypeDec(String::from("row"), TArray(B(TInt32))));
+    v.visit_decl(&mut TypeDec(String::from("matrix"), TArray(B(TCustom(String::from("row"))))));
+    v.sym_tab.push((String::from("l"), Some(B(VarBinding(TCustom(String::from("list")))))));
+    v.sym_tab.push((String::from("m"), Some(B(VarBinding(TCustom(String::from("matrix")))))));
+    v
+}
+
+fn var(id : &str) -> B<LValue>{
+    B(SimpleVar(String::from(id)))
+}
+
+#[test]
+fn test_lvalue_field_of_field(){
+    let mut v = lvalue_checker();
+    //l.next.value
+    v.visit_expr(&mut LValueExpr(FieldVar(B(FieldVar(var("l"), String::from("next"))), String::from("value"))));
+    assert_eq!(v.ty, TInt32);
+}
+
+#[test]
+fn test_lvalue_subscript_of_subscript(){
+    let mut v = lvalue_checker();
+    //m[0][1]
+    v.visit_expr(&mut LValueExpr(SubscriptVar(B(SubscriptVar(var("m"), B(NumExpr(0)))), B(NumExpr(1)))));
+    assert_eq!(v.ty, TInt32);
+}
+
+#[test]
+#[should_panic(expected="Record has no field named 'prev'")]
+fn test_lvalue_unknown_field(){
+    let mut v = lvalue_checker();
+    v.visit_expr(&mut LValueExpr(FieldVar(var("l"), String::from("prev"))));
+}
+
+#[test]
+#[should_panic(expected="Expected an index of int type")]
+fn test_lvalue_string_index(){
+    let mut v = lvalue_checker();
+    v.visit_expr(&mut LValueExpr(SubscriptVar(var("m"), B(StringExpr(b"0".to_vec())))));
+}
+
+#[test]
+#[should_panic(expected="Types mismatch")]
+fn test_assign_to_field_type_mismatch(){
+    let mut v = lvalue_checker();
+    //l.next.value := "x"
+    v.visit_expr(&mut AssignExpr(FieldVar(B(FieldVar(var("l"), String::from("next"))), String::from("value")), B(StringExpr(b"x".to_vec()))));
+}
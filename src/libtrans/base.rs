@@ -4,18 +4,23 @@ use std::ptr;
 use std::ffi;
 
 use std::borrow::Cow;
-use self::llvm::prelude::{LLVMContextRef, LLVMModuleRef, LLVMBuilderRef, LLVMValueRef, LLVMTypeRef};
+use self::llvm::prelude::{LLVMContextRef, LLVMModuleRef, LLVMBuilderRef, LLVMValueRef, LLVMTypeRef, LLVMBasicBlockRef};
 use self::llvm::core::*;
+use self::llvm::analysis::*;
 use self::llvm::target::*;
 use self::llvm::target_machine::*;
 
 use std::collections::{HashMap};
 use std::mem;
 use std::any::{Any};
-use syntax::ast::{Block, Expr, Decl, TType, OptionalTypeExprTupleList};
+use syntax::ast::{Block, Expr, LValue, Decl, TType, OptionalTypeExprTupleList};
 use syntax::ast::Expr::*;
+use syntax::escape::find_escapes;
 use syntax::ptr::{B};
 use link::link;
+use session::link::LinkOptions;
+use target::{TargetMachine, TargetOptions};
+use debuginfo::DebugInfo;
 use helpers::*;
 use symbol::*;
 
@@ -23,24 +28,45 @@ use symbol::*;
 pub type OptionalSymbolInfo = Option<Box<Any>>;
 
 pub struct Context<'a>{
-    context : LLVMContextRef,
+    pub context : LLVMContextRef,
     pub module : LLVMModuleRef,
-    builder : LLVMBuilderRef,
+    pub builder : LLVMBuilderRef,
     //FIXME pub is only of unit testing
     pub sym_tab : Vec<(Cow<'a, str>, OptionalSymbolInfo)>,
-    bb_stack : Vec<*mut llvm::LLVMBasicBlock>,
-    proto_map : HashMap<&'a str, bool>
+    proto_map : HashMap<&'a str, bool>,
+    //set when translating with -g
+    debug_info : Option<DebugInfo>,
+    //-C overflow-checks: + - * stop the program when they overflow
+    overflow_checks : bool,
+    //the line of the expression being generated, for runtime errors
+    line : usize,
+    //set right before generating an expression whose value the current
+    //function returns; every expression clears it for its operands
+    tail : bool,
+    //the user-defined functions being generated, innermost last
+    functions : Vec<FunctionFrame>
+}
+
+//what a self-recursive call in tail position needs to become a jump back
+//to the start of the function
+struct FunctionFrame{
+    function : LLVMValueRef,
+    //the parameters: allocas for the escaping ones, phis at the start of the
+    //body for the others
+    params : Vec<Storage>,
+    //where the body starts, after the parameters have been stored
+    body : LLVMBasicBlockRef,
+    ret : LLVMTypeRef
 }
 
 impl<'a> Context<'a>{
-    fn new(module_name : &str) -> Self{
+    pub fn new(module_name : &str) -> Self{
         unsafe{
             let llvm_context =  LLVMContextCreate();
             let llvm_module = LLVMModuleCreateWithNameInContext(c_str_ptr!(module_name),
                                                                 llvm_context);
             let builder = LLVMCreateBuilderInContext(llvm_context);
             let sym_tab = Vec::new();
-            let bb_stack = Vec::new();
             let proto_map = HashMap::new();
 
             Context {
@@ -48,8 +74,12 @@ impl<'a> Context<'a>{
                 module : llvm_module,
                 builder : builder,
                 sym_tab : sym_tab,
-                bb_stack : bb_stack,
-                proto_map : proto_map
+                proto_map : proto_map,
+                debug_info : None,
+                overflow_checks : false,
+                line : 0,
+                tail : false,
+                functions : Vec::new()
             }
         }
     }
@@ -59,10 +89,57 @@ impl<'a> Context<'a>{
             LLVMDumpModule(self.module);
         }
     }
+
+    //textual IR of the module; unlike dump() this doesn't go to stderr
+    pub fn ir(&self) -> String{
+        unsafe{
+            let msg = LLVMPrintModuleToString(self.module);
+            let ir = ffi::CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeMessage(msg);
+            ir
+        }
+    }
+
+    //runs llvm's verifier over the module. The error names the Tiger functions
+    //whose IR is broken along with llvm's explanation
+    pub fn verify(&self) -> Result<(), String>{
+        unsafe{
+            let mut broken = Vec::new();
+            let mut function = LLVMGetFirstFunction(self.module);
+            while function != ptr::null_mut(){
+                if LLVMIsDeclaration(function) == 0 &&
+                   LLVMVerifyFunction(function, LLVMVerifierFailureAction::LLVMReturnStatusAction) != 0{
+                    let name = ffi::CStr::from_ptr(LLVMGetValueName(function)).to_string_lossy().into_owned();
+                    broken.push(if name == "main" {
+                        String::from("the main program")
+                    } else {
+                        format!("function '{}'", name)
+                    });
+                }
+                function = LLVMGetNextFunction(function);
+            }
+
+            let mut msg = ptr::null_mut();
+            let failed = LLVMVerifyModule(self.module, LLVMVerifierFailureAction::LLVMReturnStatusAction, &mut msg) != 0;
+            let details = ffi::CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeMessage(msg);
+            if !failed && broken.is_empty(){
+                return Ok(())
+            }
+            if broken.is_empty(){
+                Err(format!("invalid IR generated:\n{}", details))
+            }
+            else{
+                Err(format!("invalid IR generated for {}:\n{}", broken.join(", "), details))
+            }
+        }
+    }
 }
 
 impl<'a> Drop for Context<'a>{
     fn drop(&mut self){
+        //the DIBuilder refers to the module
+        self.debug_info.take();
         unsafe{
             LLVMDisposeBuilder(self.builder);
             LLVMDisposeModule(self.module);
@@ -125,11 +202,12 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                     pf_args.push(l);
                 }
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   print_function,
-                                   pf_args.as_mut_ptr(),
-                                   args_count,
-                                   c_str_ptr!("call")))
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(print_function),
+                                    print_function,
+                                    pf_args.as_mut_ptr(),
+                                    args_count,
+                                    c_str_ptr!("call")))
             },
             "size" => {
                 debug_assert!(args.is_some(), "No args passed to size()");
@@ -158,11 +236,12 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                 let gstr = arg_expr.codegen(ctxt);
                 size_args.push(gstr.unwrap());
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   size_function,
-                                   size_args.as_mut_ptr(),
-                                   args_count,
-                                   c_str_ptr!("call")))
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(size_function),
+                                    size_function,
+                                    size_args.as_mut_ptr(),
+                                    args_count,
+                                    c_str_ptr!("call")))
             },
             "not" => {
                 debug_assert!(args.is_some(), "No args passed to not()");
@@ -179,11 +258,12 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                 };
                 not_args.push(l);
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   not_function,
-                                   not_args.as_mut_ptr(),
-                                   1,
-                                   c_str_ptr!("call")))
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(not_function),
+                                    not_function,
+                                    not_args.as_mut_ptr(),
+                                    1,
+                                    c_str_ptr!("call")))
             },
             "exit" =>{
                 debug_assert!(args.is_some(), "No args passed to exit()");
@@ -211,11 +291,13 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                 let arg = arg_expr.codegen(ctxt);
                 exit_args.push(arg.unwrap());
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   exit_function,
-                                   exit_args.as_mut_ptr(),
-                                   1,
-                                   c_str_ptr!("call")))
+                //exit() returns void and void values can't be named
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(exit_function),
+                                    exit_function,
+                                    exit_args.as_mut_ptr(),
+                                    1,
+                                    c_str_ptr!("")))
 
             },
             "ord" => {
@@ -244,11 +326,12 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                 let gstr = arg_expr.codegen(ctxt);
                 ord_args.push(gstr.unwrap());
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   ord_function,
-                                   ord_args.as_mut_ptr(),
-                                   1,
-                                   c_str_ptr!("call")))
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(ord_function),
+                                    ord_function,
+                                    ord_args.as_mut_ptr(),
+                                    1,
+                                    c_str_ptr!("call")))
             },
             "chr" => {
                 debug_assert!(args.is_some(), "No args passed to chr()");
@@ -265,47 +348,80 @@ fn std_functions_call_factory(fn_name : &str, args : &OptionalTypeExprTupleList,
                 };
                 chr_args.push(l);
 
-                Some(LLVMBuildCall(ctxt.builder,
-                                   chr_function,
-                                   chr_args.as_mut_ptr(),
-                                   1,
-                                   c_str_ptr!("call")))
+                Some(LLVMBuildCall2(ctxt.builder,
+                                    LLVMGlobalGetValueType(chr_function),
+                                    chr_function,
+                                    chr_args.as_mut_ptr(),
+                                    1,
+                                    c_str_ptr!("call")))
             },
             _ => {None}
         }
     }
 }
 
-fn get_llvm_type_for_ttype(ty : &TType, ctxt : &mut Context) -> LLVMTypeRef{
+//what a custom type name stands for, through the type declarations in scope
+fn resolve(ty : &TType, ctxt : &Context) -> TType{
+    if let TType::TCustom(ref name) = *ty{
+        let mut sym = &None;
+        get_symbol(&mut sym, name, &ctxt.sym_tab);
+        if let Some(t) = sym.as_ref().and_then(|s| s.downcast_ref::<Type>()){
+            if *t.ty() != *ty{
+                return resolve(t.ty(), ctxt)
+            }
+        }
+    }
+    ty.clone()
+}
+
+//arrays and records live on the heap; their values are pointers to them,
+//so that a record can have a field of its own type
+fn get_llvm_type_for_ttype(ty : &TType, ctxt : &mut Context) -> Result<LLVMTypeRef, String>{
     unsafe{
-        match ty {
-            &TType::TVoid | &TType::TRecord => LLVMVoidTypeInContext(ctxt.context),
-            &TType::TInt32 => LLVMIntTypeInContext(ctxt.context, 32),
-            &TType::TString => LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0),
-            //FIXME remove array from here
-            &TType::TArray(_) => LLVMArrayType(LLVMIntTypeInContext(ctxt.context, 32), 4), 
-            _ => panic!("Other TTypes not mapped yet to the corresponding LLVM types")
+        match resolve(ty, ctxt) {
+            TType::TVoid => Ok(LLVMVoidTypeInContext(ctxt.context)),
+            TType::TInt32 => Ok(LLVMIntTypeInContext(ctxt.context, 32)),
+            TType::TString |
+            TType::TArray(_) |
+            TType::TRecord(_) |
+            TType::TNil => Ok(LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0)),
+            TType::TCustom(ref name) => Err(format!("Unknown type '{0}'", name))
         }
     }
 }
 
+//the llvm struct the fields of a record are laid out in
+fn get_record_struct_type(fields : &[(String, TType)], ctxt : &mut Context) -> Result<LLVMTypeRef, String>{
+    let mut v = Vec::new();
+    for f in fields{
+        v.push(try!(get_llvm_type_for_ttype(&f.1, ctxt)));
+    }
+    unsafe{
+        Ok(LLVMStructTypeInContext(ctxt.context, v.as_mut_ptr(), v.len() as u32, 0))
+    }
+}
+
 impl IRBuilder for Expr{
     fn codegen(&self, ctxt : &mut Context) -> IRBuildingResult{
         macro_rules! build_binary_instrs{
             ($fun : ident, $e1:ident, $e2:ident, $s : expr) => {{
                 let ev1 = try!($e1.codegen(ctxt));
                 let ev2 = try!($e2.codegen(ctxt));
-                Ok($fun(ctxt.builder, ev1, ev2, $s.as_ptr() as *const i8))
+                Ok($fun(ctxt.builder, ev1, ev2, c_str_ptr!($s)))
             }}
         }
 
+        //comparisons are ints in Tiger, so the i1 llvm produces is widened
         macro_rules! build_relational_instrs{
             ($fun : ident, $pred : path, $e1:ident, $e2:ident, $s : expr) => {{
                 let ev1 = try!($e1.codegen(ctxt));
                 let ev2 = try!($e2.codegen(ctxt));
-                Ok($fun(ctxt.builder, $pred, ev1, ev2, $s.as_ptr() as *const i8))
+                let cmp = $fun(ctxt.builder, $pred, ev1, ev2, c_str_ptr!($s));
+                Ok(LLVMBuildZExt(ctxt.builder, cmp, LLVMIntTypeInContext(ctxt.context, 32), c_str_ptr!("booltmp")))
             }}
         }
+        let tail = mem::replace(&mut ctxt.tail, false);
+        let line = ctxt.line;
         unsafe{
             match self{
                 &Expr::NumExpr(ref i) => {
@@ -317,6 +433,16 @@ impl IRBuilder for Expr{
                                              c_str_ptr!(&*(s.clone())),
                                              c_str_ptr!(".str")))
                 },
+                &Expr::AddExpr(ref e1, ref e2) if ctxt.overflow_checks => {
+                    checked_arith(ctxt, "llvm.sadd.with.overflow.i32", e1, e2, line)
+                },
+                &Expr::SubExpr(ref e1, ref e2) if ctxt.overflow_checks => {
+                    checked_arith(ctxt, "llvm.ssub.with.overflow.i32", e1, e2, line)
+                },
+                &Expr::MulExpr(ref e1, ref e2) if ctxt.overflow_checks => {
+                    checked_arith(ctxt, "llvm.smul.with.overflow.i32", e1, e2, line)
+                },
+                //no nsw flags: the results wrap around
                 &Expr::AddExpr(ref e1, ref e2) => {
                     build_binary_instrs!(LLVMBuildAdd, e1, e2, "add_tmp")
                 },
@@ -327,7 +453,10 @@ impl IRBuilder for Expr{
                     build_binary_instrs!(LLVMBuildMul, e1, e2, "mul_tmp")
                 },
                 &Expr::DivExpr(ref e1, ref e2) => {
-                    build_binary_instrs!(LLVMBuildSDiv, e1, e2, "div_tmp")
+                    checked_division(ctxt, false, e1, e2, line)
+                },
+                &Expr::ModExpr(ref e1, ref e2) => {
+                    checked_division(ctxt, true, e1, e2, line)
                 },
                 &Expr::EqualsExpr(ref e1, ref e2) => {
                     build_relational_instrs!(LLVMBuildICmp, llvm::LLVMIntPredicate::LLVMIntEQ, e1, e2, "eqcmp_tmp")
@@ -342,52 +471,24 @@ impl IRBuilder for Expr{
                     build_relational_instrs!(LLVMBuildICmp, llvm::LLVMIntPredicate::LLVMIntNE, e1, e2, "necmp_tmp")
                 },
                 &Expr::IdExpr(ref id) => {
-                    let mut sym = &None;
-                    get_symbol(&mut sym, id, &ctxt.sym_tab);
-
-                    if sym.is_none(){
-                        panic!(format!("Invalid reference to variable '{0}'", *id));
-                    }
-
-                    let _optional = sym.as_ref().unwrap().downcast_ref::<Var>();
-                    if _optional.is_some(){
-                        Ok(LLVMBuildLoad(ctxt.builder, _optional.as_ref().unwrap().alloca_ref(), c_str_ptr!(&*id.clone())))
-                    }
-                    else{
-                        panic!(format!("Invalid reference to variable '{0}'. Different binding found.", *id));
-                    }
+                    let (value, _) = try!(get_lvalue_value(&LValue::SimpleVar(id.clone()), ctxt));
+                    Ok(value)
                 },
                 &Expr::AssignExpr(ref lhs, ref rhs) => {
                     let val = try!(rhs.codegen(ctxt));
-                    match &**lhs{
-                        &Expr::SubscriptExpr(ref id, ref idx_expr) => {
-                            let elem_ptr = try!(get_gep(id, idx_expr, ctxt));
-                            Ok(LLVMBuildStore(ctxt.builder, val, elem_ptr))
-                        },
-                        &Expr::IdExpr(ref id) => {
-                            //let load = try!(lhs.codegen(ctxt));
-                            let mut sym = &None;
-                            get_symbol(&mut sym, id, &ctxt.sym_tab);
-
-                            if sym.is_none(){
-                                panic!(format!("Invalid reference to variable '{0}'", *id));
-                            }
-
-                            let _optional = sym.as_ref().unwrap().downcast_ref::<Var>();
-                            if _optional.is_some(){
-                                Ok(LLVMBuildStore(ctxt.builder, val, _optional.as_ref().unwrap().alloca_ref()))
-                            }
-                            else{
-                                panic!(format!("Invalid reference to variable '{0}'. Different binding found.", *id));
-                            }
-                            
-                        },
-                        _ => {panic!("Need to cover fields");}
+                    if let LValue::SimpleVar(ref id) = *lhs{
+                        let i = try!(var_index(id, ctxt));
+                        if var_at(ctxt, i).is_ssa(){
+                            set_var_value(ctxt, i, val);
+                            return Ok(LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0, 0))
+                        }
                     }
+                    let (ptr, _, _) = try!(get_lvalue_ptr(lhs, ctxt));
+                    Ok(LLVMBuildStore(ctxt.builder, val, ptr))
                 },
-                &Expr::SubscriptExpr(ref id, ref subscript_expr) => {
-                    let elem_ptr = try!(get_gep(id, subscript_expr, ctxt));
-                    Ok(LLVMBuildLoad(ctxt.builder, elem_ptr,  c_str_ptr!(&*id.clone())))
+                &Expr::LValueExpr(ref lvalue) => {
+                    let (value, _) = try!(get_lvalue_value(lvalue, ctxt));
+                    Ok(value)
                 },
                 &Expr::IfThenElseExpr(ref conditional_expr, ref then_expr, ref else_expr) => {
                     let cond_code = try!(conditional_expr.codegen(ctxt));
@@ -399,43 +500,95 @@ impl IRBuilder for Expr{
                     let else_block = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("elsecond"));
                     let ifcont_block = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("ifcont"));
                     LLVMBuildCondBr(ctxt.builder, if_cond, then_block, else_block); 
+                    //the declarations in a branch end with it
+                    let scope = ctxt.sym_tab.len();
+                    let before = ssa_values(ctxt);
 
                     LLVMPositionBuilderAtEnd(ctxt.builder, then_block);
+                    ctxt.tail = tail;
                     let then_code = try!(then_expr.codegen(ctxt));
-                    LLVMBuildBr(ctxt.builder, ifcont_block);
+                    ctxt.sym_tab.truncate(scope);
+                    let then_values = ssa_values(ctxt);
                     let then_end = LLVMGetInsertBlock(ctxt.builder);
+                    //a branch that ends in a tail call has jumped away already
+                    let then_joins = !block_ended(ctxt);
+                    if then_joins{
+                        LLVMBuildBr(ctxt.builder, ifcont_block);
+                    }
 
                     LLVMPositionBuilderAtEnd(ctxt.builder, else_block);
+                    for (i, value) in before{
+                        set_var_value(ctxt, i, value);
+                    }
+                    ctxt.tail = tail;
                     let else_code = try!(else_expr.codegen(ctxt));
-                    LLVMBuildBr(ctxt.builder, ifcont_block);
+                    ctxt.sym_tab.truncate(scope);
+                    let else_values = ssa_values(ctxt);
                     let else_end = LLVMGetInsertBlock(ctxt.builder);
+                    let else_joins = !block_ended(ctxt);
+                    if else_joins{
+                        LLVMBuildBr(ctxt.builder, ifcont_block);
+                    }
 
+                    let mut branches = Vec::new();
+                    if then_joins{
+                        branches.push((then_end, then_code, then_values));
+                    }
+                    if else_joins{
+                        branches.push((else_end, else_code, else_values));
+                    }
+                    if branches.is_empty(){
+                        //nothing comes after an if whose branches both end in
+                        //tail calls. The builder stays in the ended block
+                        LLVMDeleteBasicBlock(ifcont_block);
+                        return Ok(else_code)
+                    }
                     LLVMPositionBuilderAtEnd(ctxt.builder, ifcont_block);
 
                     let phi_node = LLVMBuildPhi(ctxt.builder, LLVMIntTypeInContext(ctxt.context, 32), c_str_ptr!("ifphi"));
-                    LLVMAddIncoming(phi_node, vec![then_code].as_mut_ptr(), vec![then_end].as_mut_ptr(), 1);
-                    LLVMAddIncoming(phi_node, vec![else_code].as_mut_ptr(), vec![else_end].as_mut_ptr(), 1);
+                    for &(bb, code, _) in branches.iter(){
+                        LLVMAddIncoming(phi_node, vec![code].as_mut_ptr(), vec![bb].as_mut_ptr(), 1);
+                    }
+                    let values : Vec<_> = branches.into_iter().map(|(bb, _, values)| (bb, values)).collect();
+                    join_values(ctxt, &values);
                     Ok(phi_node) 
                 },
-                &Expr::ForExpr(ref id, ref from, ref to, ref do_expr) => {
+                &Expr::ForExpr(ref id, ref from, ref to, ref do_expr, ref escape) => {
                     assert!(!id.is_empty(), "id cannot be empty");
+                    //both bounds are evaluated once, before the loop
                     let from_code = try!(from.codegen(ctxt));
+                    let to_code = try!(to.codegen(ctxt));
+                    let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
                     let bb = LLVMGetInsertBlock(ctxt.builder);
                     let function = LLVMGetBasicBlockParent(bb);
 
                     //i := ...
-                    let from_var = LLVMBuildAlloca(ctxt.builder, LLVMIntTypeInContext(ctxt.context, 32), c_str_ptr!(&*id.clone()));
-                    LLVMBuildStore(ctxt.builder, from_code, from_var);
+                    let scope = ctxt.sym_tab.len();
+                    if escape.escapes(){
+                        let from_var = entry_alloca(ctxt, i32_ty, id);
+                        LLVMBuildStore(ctxt.builder, from_code, from_var);
+                        if let Some(ref di) = ctxt.debug_info{
+                            di.declare_variable(ctxt.builder, from_var, id, &TType::TInt32, None);
+                        }
+                        ctxt.sym_tab.push((id.clone().into(), Some(Box::new(Var::new(id.clone(), TType::TInt32, from_var)))));
+                    }
+                    else{
+                        let debug = ctxt.debug_info.as_ref().and_then(|di| di.ssa_variable(ctxt.builder, from_code, id, &TType::TInt32, None));
+                        ctxt.sym_tab.push((id.clone().into(), Some(Box::new(Var::ssa(id.clone(), TType::TInt32, from_code, debug)))));
+                    }
 
                     let preloop_block = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("preloop"));
                     LLVMBuildBr(ctxt.builder, preloop_block);
                     LLVMPositionBuilderAtEnd(ctxt.builder, preloop_block);
+                    //the variables the body changes come back around the loop
+                    let phis = loop_phis(ctxt, bb);
 
-                    let to_code = try!(to.codegen(ctxt));
-                    let zero = LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0 as u64, 0);
+                    let zero = LLVMConstInt(i32_ty, 0 as u64, 0);
+                    let (cur_value, _) = try!(get_lvalue_value(&LValue::SimpleVar(id.clone()), ctxt));
+                    //the upper bound is part of the range
                     let end_cond = LLVMBuildICmp(ctxt.builder,
-                                                 llvm::LLVMIntPredicate::LLVMIntNE,
-                                                 LLVMBuildLoad(ctxt.builder, from_var, c_str_ptr!(&*id.clone())),
+                                                 llvm::LLVMIntPredicate::LLVMIntSLE,
+                                                 cur_value,
                                                  to_code,
                                                  c_str_ptr!("loopcond"));
 
@@ -444,17 +597,30 @@ impl IRBuilder for Expr{
                     LLVMBuildCondBr(ctxt.builder, end_cond, loop_block, afterloop_block);
                     
                     LLVMPositionBuilderAtEnd(ctxt.builder, loop_block);
-                    let do_expr_code = try!(do_expr.codegen(ctxt));
+                    try!(do_expr.codegen(ctxt));
+                    ctxt.sym_tab.truncate(scope + 1);
 
                     //stepping
-                    let cur_value = LLVMBuildLoad(ctxt.builder, from_var, c_str_ptr!(&*id.clone()));
+                    let (cur_value, _) = try!(get_lvalue_value(&LValue::SimpleVar(id.clone()), ctxt));
                     let next_value = LLVMBuildAdd(ctxt.builder, cur_value, 
-                                                  LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 1 as u64, 0), 
+                                                  LLVMConstInt(i32_ty, 1 as u64, 0), 
                                                   c_str_ptr!("nextvar"));
-                    LLVMBuildStore(ctxt.builder, next_value, from_var);
+                    match var_at(ctxt, scope).storage(){
+                        Storage::Alloca(from_var) => { LLVMBuildStore(ctxt.builder, next_value, from_var); },
+                        Storage::Value(_) => set_var_value(ctxt, scope, next_value)
+                    }
+                    let latch = LLVMGetInsertBlock(ctxt.builder);
+                    add_incoming(ctxt, &phis, latch);
 
                     LLVMBuildBr(ctxt.builder, preloop_block);
                     LLVMPositionBuilderAtEnd(ctxt.builder, afterloop_block);
+                    //the loop is left from its condition, with the values the
+                    //variables have there
+                    for &(i, phi) in phis.iter(){
+                        set_var_value(ctxt, i, phi);
+                    }
+                    remove_trivial_phis(ctxt, &phis);
+                    ctxt.sym_tab.truncate(scope);
 
                     //FIXME remove this 
                     Ok(zero)
@@ -488,11 +654,22 @@ impl IRBuilder for Expr{
 
                             let _optional = sym.as_ref().unwrap().downcast_ref::<Function>();
                             if _optional.is_some(){
-                                Ok(LLVMBuildCall(ctxt.builder,
-                                            _optional.as_ref().unwrap().value_ref(),
-                                            pf_args.as_mut_ptr(),
-                                            pf_args.len() as u32,
-                                            c_str_ptr!("")))
+                                let callee = _optional.as_ref().unwrap().value_ref();
+                                if tail && ctxt.functions.last().map_or(false, |f| f.function == callee){
+                                    return Ok(tail_recurse(ctxt, &pf_args))
+                                }
+                                let call = LLVMBuildCall2(ctxt.builder,
+                                                          LLVMGlobalGetValueType(callee),
+                                                          callee,
+                                                          pf_args.as_mut_ptr(),
+                                                          pf_args.len() as u32,
+                                                          c_str_ptr!(""));
+                                //calls to other functions can reuse the frame
+                                //of the caller when the code generator sees fit
+                                if tail{
+                                    LLVMSetTailCall(call, 1);
+                                }
+                                Ok(call)
                             }
                             else{
                                 panic!(format!("Invalid reference to function '{0}'. Different binding found.", *fn_name));
@@ -501,17 +678,16 @@ impl IRBuilder for Expr{
                     }
                 },
                 &Expr::SeqExpr(ref opt_list) => {
-                    let mut ret_val = Err(String::from(""));
+                    //like a for loop, () has no value but something has to stand for it
+                    let mut ret_val = LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0 as u64, 0);
                     if opt_list.is_some(){
-                        for expr in opt_list.as_ref().unwrap().iter(){
-                            match expr.codegen(ctxt){
-                                Ok(val) => { ret_val = Ok(val)},
-                                Err(msg) => {ret_val = Err(msg)} 
-                            }
+                        let exprs = opt_list.as_ref().unwrap();
+                        for (i, expr) in exprs.iter().enumerate(){
+                            ctxt.tail = tail && i == exprs.len() - 1;
+                            ret_val = try!(expr.codegen(ctxt));
                         }
                     }
-                    //Ok(LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0 as u64, 0))
-                    return ret_val
+                    Ok(ret_val)
 
                 },
                 &Expr::LetExpr(ref decls, ref expr) => {
@@ -520,13 +696,13 @@ impl IRBuilder for Expr{
                     for decl in &*decls {
                         match decl {
                             &Decl::FunDec(ref name, ref params, ref ty, ref body, _) => {
-                                let llvm_ty = get_llvm_type_for_ttype(ty, ctxt);
+                                let llvm_ty = try!(get_llvm_type_for_ttype(ty, ctxt));
                                 let mut type_args = Vec::new();
                                 let optional_params = params.as_ref();
                                 //FIXME simplify this param checking condition
                                 if optional_params.is_some() && optional_params.unwrap().len() > 0{
                                     for p in optional_params.unwrap(){
-                                        let param_llvm_type = get_llvm_type_for_ttype(&p.1, ctxt);
+                                        let param_llvm_type = try!(get_llvm_type_for_ttype(&p.1, ctxt));
                                         type_args.push(param_llvm_type); 
                                     }
                                 }
@@ -549,38 +725,90 @@ impl IRBuilder for Expr{
 
                                 let func = Function::new(cloned_name.clone(), function);
                                 ctxt.sym_tab.push((cloned_name.into(), Some(Box::new(func))));
+                                //the declarations that follow go back to where we are now
+                                let outer_bb = LLVMGetInsertBlock(ctxt.builder);
                                 LLVMPositionBuilderAtEnd(ctxt.builder, bb);
+                                if let Some(ref mut di) = ctxt.debug_info{
+                                    let param_tys : Vec<TType> = optional_params.map(|ps| ps.iter().map(|p| p.1.clone()).collect())
+                                                                                .unwrap_or(Vec::new());
+                                    di.begin_function(ctxt.builder, function, name, ty, &param_tys, body.span.line);
+                                }
                                 //trans_expr(body, &mut ctxt);
                                 
                                 ctxt.sym_tab.push(("<marker>".into(),
                                                    None));
-                                //build allocas for params
+                                //build allocas for the params that escape. The others
+                                //are phis at the start of the body, which the
+                                //self-recursive tail calls add their arguments to
+                                let mut param_storage = Vec::new();
                                 if optional_params.is_some() && optional_params.unwrap().len() > 0{
                                     let c = LLVMCountParams(function) as usize;
                                     let mut params_vec = Vec::with_capacity(c);
                                     let p = params_vec.as_mut_ptr();
                                     mem::forget(params_vec);
                                     LLVMGetParams(function, p);
-                                    let mut v = Vec::from_raw_parts(p, c, c);
+                                    let v = Vec::from_raw_parts(p, c, c);
                                     //assert_eq!(params_vec.len(), 1);
-                                    for (value_ref, param) in v.iter().zip(optional_params.unwrap()){
+                                    for (i, (value_ref, param)) in v.iter().zip(optional_params.unwrap()).enumerate(){
+                                        if !param.2.escapes(){
+                                            param_storage.push(Storage::Value(*value_ref));
+                                            continue;
+                                        }
                                         let alloca = LLVMBuildAlloca(ctxt.builder,
-                                                                     get_llvm_type_for_ttype(&param.1, ctxt),
+                                                                     type_args[i],
                                                                      c_str_ptr!(&*param.0));
+                                        if let Some(ref di) = ctxt.debug_info{
+                                            di.declare_variable(ctxt.builder, alloca, &param.0, &param.1, Some(i as u32 + 1));
+                                        }
                                         LLVMBuildStore(ctxt.builder,
                                                        *value_ref,
                                                        alloca);
-                                        ctxt.sym_tab.push((param.0.clone().into(), 
-                                                           Some(Box::new(Var::new(param.0.clone(), param.1.clone(), alloca)))));
-
+                                        param_storage.push(Storage::Alloca(alloca));
+                                    }
+                                }
+                                //self-recursive tail calls jump back here
+                                let body_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("body"));
+                                LLVMBuildBr(ctxt.builder, body_bb);
+                                LLVMPositionBuilderAtEnd(ctxt.builder, body_bb);
+                                for (storage, param) in param_storage.iter_mut().zip(optional_params.into_iter().flat_map(|ps| ps.iter())){
+                                    if let Storage::Value(value_ref) = *storage{
+                                        let phi = LLVMBuildPhi(ctxt.builder, LLVMTypeOf(value_ref), c_str_ptr!(&*param.0));
+                                        LLVMAddIncoming(phi, vec![value_ref].as_mut_ptr(), vec![bb].as_mut_ptr(), 1);
+                                        *storage = Storage::Value(phi);
                                     }
                                 }
-                                let value_ref = try!(body.codegen(ctxt));
-                                if *ty == TType::TVoid{
-                                    LLVMBuildRetVoid(ctxt.builder);
+                                let mut param_phis = Vec::new();
+                                for (i, (storage, param)) in param_storage.iter().zip(optional_params.into_iter().flat_map(|ps| ps.iter())).enumerate(){
+                                    let var = match *storage{
+                                        Storage::Alloca(alloca) => Var::new(param.0.clone(), param.1.clone(), alloca),
+                                        Storage::Value(phi) => {
+                                            param_phis.push((ctxt.sym_tab.len(), phi));
+                                            let debug = ctxt.debug_info.as_ref().and_then(|di| di.ssa_variable(ctxt.builder, phi, &param.0, &param.1, Some(i as u32 + 1)));
+                                            Var::ssa(param.0.clone(), param.1.clone(), phi, debug)
+                                        }
+                                    };
+                                    ctxt.sym_tab.push((param.0.clone().into(), Some(Box::new(var))));
                                 }
-                                else{
-                                    LLVMBuildRet(ctxt.builder, value_ref);
+                                ctxt.functions.push(FunctionFrame { function : function, params : param_storage, body : body_bb, ret : llvm_ty });
+                                ctxt.tail = true;
+                                let result = body.codegen(ctxt);
+                                ctxt.functions.pop();
+                                let value_ref = try!(result);
+                                //a body that ends in a tail call doesn't return from there
+                                if !block_ended(ctxt){
+                                    if *ty == TType::TVoid{
+                                        LLVMBuildRetVoid(ctxt.builder);
+                                    }
+                                    else{
+                                        LLVMBuildRet(ctxt.builder, value_ref);
+                                    }
+                                }
+                                //the parameters no tail call changes are the arguments.
+                                //This comes after the ret, which may return one of them
+                                remove_trivial_phis(ctxt, &param_phis);
+                                LLVMPositionBuilderAtEnd(ctxt.builder, outer_bb);
+                                if let Some(ref mut di) = ctxt.debug_info{
+                                    di.end_function(ctxt.builder);
                                 }
 
                                 //pop all local symbols belonging to the current function
@@ -589,101 +817,83 @@ impl IRBuilder for Expr{
                                 }
                                 ctxt.sym_tab.pop(); 
                             }, 
-                            &Decl::VarDec(ref name, ref ty, ref rhs) => {
-                                //TODO use match on rhs and separate processing of IdExpr and
-                                //ArrayExpr
-                                let llvm_ty = get_llvm_type_for_ttype(ty, ctxt);
-                                if let TType::TArray(_) = *ty{
-                                    match &**rhs{
-                                        &ArrayExpr(ref _ty, ref _dim_expr, ref _init_expr) => {
-                                            //let dim = try!(_dim_expr.codegen(ctxt));
-                                            match &**_dim_expr{
-                                                &NumExpr(n) => {
-                                                    let _alloca = LLVMBuildAlloca(ctxt.builder,
-                                                                               LLVMArrayType(LLVMIntTypeInContext(ctxt.context, 32), n as u32),
-                                                                               c_str_ptr!("_alloca"));
-
-                                                    let init_val = try!(_init_expr.codegen(ctxt));
-                                                    for i in 0..n{
-                                                        //gep
-                                                        let val = LLVMBuildGEP(ctxt.builder,
-                                                                               _alloca,
-                                                                               vec![LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0u64, 0), 
-                                                                               LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), i as u64, 0)].as_mut_ptr(),
-                                                                               2,
-                                                                               c_str_ptr!("array_gep"));
-                                                        LLVMBuildStore(ctxt.builder,
-                                                                       init_val,
-                                                                       val);
-
-                                                    }
-                                                    //let _load = LLVMBuildLoad(ctxt.builder, dim, c_str_ptr!("arr_load"));
-                                                    /*let _alloca = LLVMBuildArrayAlloca(ctxt.builder, 
-                                                      LLVMArrayType(LLVMIntTypeInContext(ctxt.context, 32), 4),
-                                                      LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 4 as u64, 0),
-                                                      c_str_ptr!("_alloca"));*/
-                                                    ctxt.sym_tab.push((name.clone().into(), 
-                                                                       Some(Box::new(Var::new(name.clone(), ty.clone(), _alloca)))));
-                                                },
-                                                _ => {}
-                                            }
-                                        },
-                                        _ => {}
-                                    }
-
-                                }
-                                else if let TType::TRecord = *ty{
-                                    match &**rhs{
-                                        &RecordExpr(ref field_decls) =>{
-
-                                            let mut v = Vec::new();
-                                            for &(ref field_name, ref field_type) in field_decls.as_ref().unwrap(){
-                                                match field_type{
-                                                    &TType::TInt32 => v.push(LLVMIntTypeInContext(ctxt.context, 32)),
-                                                    &TType::TString => v.push(LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0)), 
-                                                    _ => {} 
-                                                }
-
-                                            }
-                                            let _alloca = LLVMBuildAlloca(ctxt.builder,
-                                                                          LLVMStructType(v.as_mut_ptr(),
-                                                                                        field_decls.as_ref().unwrap().len() as u32,
-                                                                                        0),
-                                                                          c_str_ptr!("alloca"));
-                                            ctxt.sym_tab.push((name.clone().into(), 
-                                                               Some(Box::new(Var::new(name.clone(), ty.clone(), _alloca)))));
-
-                                        },
-                                        _ => {}
-                                    }
-                                }
-
-                                else{
-                                    let alloca = LLVMBuildAlloca(ctxt.builder, llvm_ty, c_str_ptr!(&(*name.clone())));
-                                    let rhs_value_ref = try!(rhs.codegen(ctxt));
-                                    LLVMBuildStore(ctxt.builder,
-                                                   rhs_value_ref,
-                                                   alloca);
-                                    ctxt.sym_tab.push((name.clone().into(), Some(Box::new(Var::new(name.clone(), ty.clone(), alloca)))));
+                            &Decl::VarDec(ref name, ref ty, ref rhs, ref escape) if !escape.escapes() => {
+                                let rhs_value_ref = try!(rhs.codegen(ctxt));
+                                let di_ty = resolve(ty, ctxt);
+                                let debug = match ctxt.debug_info{
+                                    Some(ref mut di) => {
+                                        di.set_location(ctxt.builder, rhs.span.line);
+                                        di.ssa_variable(ctxt.builder, rhs_value_ref, name, &di_ty, None)
+                                    },
+                                    None => None
+                                };
+                                ctxt.sym_tab.push((name.clone().into(), Some(Box::new(Var::ssa(name.clone(), ty.clone(), rhs_value_ref, debug)))));
+                            },
+                            &Decl::VarDec(ref name, ref ty, ref rhs, _) => {
+                                let rhs_value_ref = try!(rhs.codegen(ctxt));
+                                //the type checker fills in the types of the variables
+                                //declared without one; those it couldn't tell take the
+                                //type of their value
+                                let llvm_ty = if *ty == TType::TNil {
+                                    LLVMTypeOf(rhs_value_ref)
+                                } else {
+                                    try!(get_llvm_type_for_ttype(ty, ctxt))
+                                };
+                                let alloca = entry_alloca(ctxt, llvm_ty, name);
+                                let di_ty = resolve(ty, ctxt);
+                                if let Some(ref mut di) = ctxt.debug_info{
+                                    di.set_location(ctxt.builder, rhs.span.line);
+                                    di.declare_variable(ctxt.builder, alloca, name, &di_ty, None);
                                 }
+                                LLVMBuildStore(ctxt.builder,
+                                               rhs_value_ref,
+                                               alloca);
+                                ctxt.sym_tab.push((name.clone().into(), Some(Box::new(Var::new(name.clone(), ty.clone(), alloca)))));
                             },
-                            _ => panic!("More decl types should be covered")
+                            &Decl::TypeDec(ref name, ref ty) => {
+                                ctxt.sym_tab.push((name.clone().into(), Some(Box::new(Type::new(name.clone(), ty.clone())))));
+                            },
+                            &Decl::ErrorDec => return Err(String::from("A declaration with a syntax error can't be translated"))
                         }
 
                     }
                     
-                    //translation of the 'in' expr
-                    
-                    //trans_expr(&*expr.unwrap(), &mut ctxt);
-                    //FIXME should the previous bb be popped here?
-                    let bb = ctxt.bb_stack.pop().unwrap();
-                    LLVMPositionBuilderAtEnd(ctxt.builder, bb);
+                    //translation of the 'in' expr. The function declarations
+                    //have put the builder back where it was
                     let e = &expr.as_ref().unwrap();
+                    ctxt.tail = tail;
                     let v = try!(e.codegen(ctxt));
                     //pop all the symbols declared in the current let block
                     Ok(v)
                 }
-                t => Err(format!("error: {:?}", t))
+                &Expr::NilExpr => {
+                    Ok(LLVMConstPointerNull(LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0)))
+                },
+                &Expr::ArrayExpr(ref elem_ty, ref dim_expr, ref init_expr) => {
+                    build_array(elem_ty, dim_expr, init_expr, ctxt)
+                },
+                //a record with zeroed fields
+                &Expr::RecordExpr(ref field_decls) => {
+                    let fields = field_decls.clone().unwrap_or(Vec::new());
+                    build_record(&fields, &[], ctxt)
+                },
+                &Expr::TypedArrayExpr(ref ty_id, ref dim_expr, ref init_expr) => {
+                    match resolve(&TType::TCustom(ty_id.clone()), ctxt){
+                        TType::TArray(ref elem_ty) => build_array(elem_ty, dim_expr, init_expr, ctxt),
+                        _ => Err(format!("'{0}' is not an array type", ty_id))
+                    }
+                },
+                &Expr::TypedRecordExpr(ref ty_id, ref field_exprs) => {
+                    match resolve(&TType::TCustom(ty_id.clone()), ctxt){
+                        TType::TRecord(ref fields) => build_record(fields, field_exprs, ctxt),
+                        _ => Err(format!("'{0}' is not a record type", ty_id))
+                    }
+                },
+                t => {
+                    //the name of the expression, without the tree under it
+                    let kind = format!("{:?}", t);
+                    Err(format!("{} isn't supported by the llvm backend yet. Try --backend ir", kind.split('(').next().unwrap()))
+                }
             }
         }
     }
@@ -691,6 +901,20 @@ impl IRBuilder for Expr{
         
 }
 
+//sets the source location of the instructions generated for the expression
+impl IRBuilder for B<Expr>{
+    fn codegen(&self, ctxt : &mut Context) -> IRBuildingResult{
+        if let Some(ref mut di) = ctxt.debug_info{
+            di.set_location(ctxt.builder, self.span.line);
+        }
+        //nodes built without a span report the line of their parent
+        if self.span.line != 0{
+            ctxt.line = self.span.line;
+        }
+        (**self).codegen(ctxt)
+    }
+}
+
 fn get_symbol<'a>(sym : &mut &'a OptionalSymbolInfo, id : &String, sym_tab : &'a Vec<(Cow<'a, str>, OptionalSymbolInfo)>){
     for &(ref _id, ref info) in sym_tab.iter().rev(){
         if *_id == *id  {
@@ -700,32 +924,425 @@ fn get_symbol<'a>(sym : &mut &'a OptionalSymbolInfo, id : &String, sym_tab : &'a
     }
 }
 
-//returns the pointer to an element in the array
-fn get_gep(id : &String, subscript_expr : &Expr, ctxt : &mut Context) -> IRBuildingResult {
-    unsafe {
-        //FIXME the following line is the first statement because compiler wont
-        //allow it after the for loop. says ctxt.sym_tab is already borrowed as
-        //mutable. see how this can be put inside if _optional.is_some(){...}
-        let i = try!(subscript_expr.codegen(ctxt));
-        let mut sym = &None;
-        get_symbol(&mut sym, id, &ctxt.sym_tab);
+//where the symbols of the function being generated start: after the marker
+//its declaration pushed, or at the bottom for the main program
+fn frame_start(ctxt : &Context) -> usize{
+    ctxt.sym_tab.iter().rposition(|&(_, ref info)| info.is_none()).map_or(0, |i| i + 1)
+}
+
+//where the variable `id` is in the symbol table
+fn var_index(id : &String, ctxt : &Context) -> Result<usize, String>{
+    match ctxt.sym_tab.iter().rposition(|&(ref _id, _)| *_id == *id){
+        Some(i) => match ctxt.sym_tab[i].1.as_ref().map(|s| s.is::<Var>()){
+            Some(true) => Ok(i),
+            _ => Err(format!("Invalid reference to variable '{0}'. Different binding found.", *id))
+        },
+        None => Err(format!("Invalid reference to variable '{0}'", *id))
+    }
+}
+
+fn var_at<'c>(ctxt : &'c Context, i : usize) -> &'c Var{
+    ctxt.sym_tab[i].1.as_ref().and_then(|s| s.downcast_ref::<Var>()).expect("not a variable")
+}
 
-        if sym.is_none(){
-            panic!(format!("Invalid reference to array '{0}'", *id));
+//the values of the SSA variables of the function being generated, with
+//where they are in the symbol table
+fn ssa_values(ctxt : &Context) -> Vec<(usize, LLVMValueRef)>{
+    let mut values = Vec::new();
+    for i in frame_start(ctxt)..ctxt.sym_tab.len(){
+        if let Some(var) = ctxt.sym_tab[i].1.as_ref().and_then(|s| s.downcast_ref::<Var>()){
+            if let Storage::Value(value) = var.storage(){
+                values.push((i, value));
+            }
         }
+    }
+    values
+}
+
+//gives the SSA variable at `i` in the symbol table a new value
+fn set_var_value(ctxt : &mut Context, i : usize, value : LLVMValueRef){
+    let debug = {
+        let var = ctxt.sym_tab[i].1.as_mut().and_then(|s| s.downcast_mut::<Var>()).expect("not a variable");
+        var.set_value(value);
+        var.debug()
+    };
+    if let (Some(ref di), Some(var)) = (ctxt.debug_info.as_ref(), debug){
+        di.set_value(ctxt.builder, var, value);
+    }
+}
 
-        let _optional = sym.as_ref().unwrap().downcast_ref::<Var>();
-        if _optional.is_some(){
-            let val = LLVMBuildGEP(ctxt.builder,
-                                   _optional.as_ref().unwrap().alloca_ref(), //array alloca
-                                   vec![LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0u64, 0), 
-                                   i].as_mut_ptr(),
-                                   2,
-                                   c_str_ptr!("array_gep"));
-            Ok(val)
+//the SSA variables meet in the current block, coming from the blocks of
+//`branches` with the values listed for each. The variables whose values
+//differ become phis; the others take the value they have in every branch
+fn join_values(ctxt : &mut Context, branches : &[(LLVMBasicBlockRef, Vec<(usize, LLVMValueRef)>)]){
+    let mut phis = Vec::new();
+    unsafe{
+        for (k, &(i, first)) in branches[0].1.iter().enumerate(){
+            if branches.iter().all(|b| b.1[k].1 == first){
+                if var_at(ctxt, i).storage() != Storage::Value(first){
+                    phis.push((i, first));
+                }
+                continue;
+            }
+            let phi = LLVMBuildPhi(ctxt.builder, LLVMTypeOf(first), c_str_ptr!(&*ctxt.sym_tab[i].0));
+            for &(bb, ref values) in branches{
+                LLVMAddIncoming(phi, vec![values[k].1].as_mut_ptr(), vec![bb].as_mut_ptr(), 1);
+            }
+            phis.push((i, phi));
+        }
+    }
+    //the phis all go before the debug values
+    for (i, value) in phis{
+        set_var_value(ctxt, i, value);
+    }
+}
+
+//a phi at the start of a loop for each SSA variable, coming in from `pred`
+//with the variable's current value. The variables take the phis as values
+fn loop_phis(ctxt : &mut Context, pred : LLVMBasicBlockRef) -> Vec<(usize, LLVMValueRef)>{
+    let mut phis = Vec::new();
+    unsafe{
+        for (i, value) in ssa_values(ctxt){
+            let phi = LLVMBuildPhi(ctxt.builder, LLVMTypeOf(value), c_str_ptr!(&*ctxt.sym_tab[i].0));
+            LLVMAddIncoming(phi, vec![value].as_mut_ptr(), vec![pred].as_mut_ptr(), 1);
+            phis.push((i, phi));
+        }
+    }
+    for &(i, phi) in phis.iter(){
+        set_var_value(ctxt, i, phi);
+    }
+    phis
+}
+
+//the values the variables of loop_phis() have at the end of `pred` go back
+//around the loop
+fn add_incoming(ctxt : &Context, phis : &[(usize, LLVMValueRef)], pred : LLVMBasicBlockRef){
+    for &(i, phi) in phis{
+        if let Storage::Value(value) = var_at(ctxt, i).storage(){
+            unsafe{
+                LLVMAddIncoming(phi, vec![value].as_mut_ptr(), vec![pred].as_mut_ptr(), 1);
+            }
+        }
+    }
+}
+
+//the value all the incoming values of `phi` but itself are, if there is one
+fn unique_incoming(phi : LLVMValueRef) -> Option<LLVMValueRef>{
+    let mut unique = None;
+    unsafe{
+        for k in 0..LLVMCountIncoming(phi){
+            let value = LLVMGetIncomingValue(phi, k);
+            match unique{
+                _ if value == phi => {},
+                None => unique = Some(value),
+                Some(u) if u == value => {},
+                Some(_) => return None
+            }
+        }
+    }
+    unique
+}
+
+//removes the phis of a variable that didn't change around the loop or
+//across the tail calls: the phi is the value it had coming in
+fn remove_trivial_phis(ctxt : &mut Context, phis : &[(usize, LLVMValueRef)]){
+    let mut phis = phis.to_vec();
+    while let Some(k) = phis.iter().position(|&(_, phi)| unique_incoming(phi).is_some()){
+        let (_, phi) = phis.remove(k);
+        let value = unique_incoming(phi).unwrap();
+        unsafe{
+            LLVMReplaceAllUsesWith(phi, value);
+            LLVMInstructionEraseFromParent(phi);
+        }
+        for i in frame_start(ctxt)..ctxt.sym_tab.len(){
+            if let Some(var) = ctxt.sym_tab[i].1.as_mut().and_then(|s| s.downcast_mut::<Var>()){
+                if var.storage() == Storage::Value(phi){
+                    var.set_value(value);
+                }
+            }
+        }
+    }
+}
+
+//the function `name`, declared the first time it is needed
+fn declare_function(ctxt : &mut Context, name : &'static str, ret : LLVMTypeRef, params : &mut [LLVMTypeRef], var_arg : bool) -> LLVMValueRef{
+    unsafe{
+        if ctxt.proto_map.contains_key(name){
+            return LLVMGetNamedFunction(ctxt.module, c_str_ptr!(name))
+        }
+        ctxt.proto_map.insert(name, true);
+        let proto = LLVMFunctionType(ret, params.as_mut_ptr(), params.len() as u32, var_arg as i32);
+        LLVMAddFunction(ctxt.module, c_str_ptr!(name), proto)
+    }
+}
+
+//stops the program when `failed` is true: the error and the line go to
+//stderr and the program exits with 1
+fn runtime_check(ctxt : &mut Context, failed : LLVMValueRef, error : &str, line : usize){
+    unsafe{
+        let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
+        let function = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctxt.builder));
+        let fail_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("check_failed"));
+        let ok_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("check_ok"));
+        LLVMBuildCondBr(ctxt.builder, failed, fail_bb, ok_bb);
+
+        LLVMPositionBuilderAtEnd(ctxt.builder, fail_bb);
+        let dprintf = declare_function(ctxt, "dprintf", i32_ty,
+                                       &mut [i32_ty, LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0)], true);
+        let msg = LLVMBuildGlobalStringPtr(ctxt.builder, c_str_ptr!(&*format!("line {}: {}\n", line, error)), c_str_ptr!(".str"));
+        let mut args = vec![LLVMConstInt(i32_ty, 2, 0), msg];
+        LLVMBuildCall2(ctxt.builder, LLVMGlobalGetValueType(dprintf), dprintf, args.as_mut_ptr(), 2, c_str_ptr!(""));
+        let exit = declare_function(ctxt, "exit", LLVMVoidTypeInContext(ctxt.context), &mut [i32_ty], false);
+        let mut args = vec![LLVMConstInt(i32_ty, 1, 0)];
+        LLVMBuildCall2(ctxt.builder, LLVMGlobalGetValueType(exit), exit, args.as_mut_ptr(), 1, c_str_ptr!(""));
+        LLVMBuildUnreachable(ctxt.builder);
+
+        LLVMPositionBuilderAtEnd(ctxt.builder, ok_bb);
+    }
+}
+
+//+, - or * through one of the llvm.*.with.overflow intrinsics, which also
+//tell whether the result wrapped around
+fn checked_arith(ctxt : &mut Context, intrinsic : &'static str, lhs : &B<Expr>, rhs : &B<Expr>, line : usize) -> IRBuildingResult{
+    let l = try!(lhs.codegen(ctxt));
+    let r = try!(rhs.codegen(ctxt));
+    unsafe{
+        let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
+        let mut fields = [i32_ty, LLVMInt1TypeInContext(ctxt.context)];
+        let ret = LLVMStructTypeInContext(ctxt.context, fields.as_mut_ptr(), 2, 0);
+        let f = declare_function(ctxt, intrinsic, ret, &mut [i32_ty, i32_ty], false);
+        let mut args = vec![l, r];
+        let pair = LLVMBuildCall2(ctxt.builder, LLVMGlobalGetValueType(f), f, args.as_mut_ptr(), 2, c_str_ptr!("checked"));
+        let value = LLVMBuildExtractValue(ctxt.builder, pair, 0, c_str_ptr!("value"));
+        let overflow = LLVMBuildExtractValue(ctxt.builder, pair, 1, c_str_ptr!("overflow"));
+        runtime_check(ctxt, overflow, "integer overflow", line);
+        Ok(value)
+    }
+}
+
+//a / b or, for `remainder`, a mod b. Dividing by 0 stops the program.
+//INT_MIN / -1 doesn't fit in an int and would trap like a division by 0,
+//so dividing by -1 is a negation instead, which wraps around like the
+//other operators, and x mod -1 is 0
+fn checked_division(ctxt : &mut Context, remainder : bool, lhs : &B<Expr>, rhs : &B<Expr>, line : usize) -> IRBuildingResult{
+    let l = try!(lhs.codegen(ctxt));
+    let r = try!(rhs.codegen(ctxt));
+    unsafe{
+        let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
+        let is_zero = LLVMBuildICmp(ctxt.builder, llvm::LLVMIntPredicate::LLVMIntEQ, r, LLVMConstInt(i32_ty, 0, 0), c_str_ptr!("is_zero"));
+        runtime_check(ctxt, is_zero, "division by zero", line);
+        let by_minus_one = LLVMBuildICmp(ctxt.builder, llvm::LLVMIntPredicate::LLVMIntEQ, r, LLVMConstInt(i32_ty, -1i64 as u64, 1), c_str_ptr!("by_minus_one"));
+        if ctxt.overflow_checks && !remainder{
+            let is_min = LLVMBuildICmp(ctxt.builder, llvm::LLVMIntPredicate::LLVMIntEQ, l, LLVMConstInt(i32_ty, i32::min_value() as u64, 1), c_str_ptr!("is_min"));
+            let overflow = LLVMBuildAnd(ctxt.builder, is_min, by_minus_one, c_str_ptr!("overflow"));
+            runtime_check(ctxt, overflow, "integer overflow", line);
+        }
+        let divisor = LLVMBuildSelect(ctxt.builder, by_minus_one, LLVMConstInt(i32_ty, 1, 0), r, c_str_ptr!("divisor"));
+        if remainder{
+            return Ok(LLVMBuildSRem(ctxt.builder, l, divisor, c_str_ptr!("rem_tmp")))
+        }
+        let quotient = LLVMBuildSDiv(ctxt.builder, l, divisor, c_str_ptr!("div_tmp"));
+        let negated = LLVMBuildNeg(ctxt.builder, l, c_str_ptr!("neg_tmp"));
+        Ok(LLVMBuildSelect(ctxt.builder, by_minus_one, negated, quotient, c_str_ptr!("quotient")))
+    }
+}
+
+//an alloca at the start of the entry block of the current function, so that
+//a variable declared in a loop doesn't take more stack on every iteration
+fn entry_alloca(ctxt : &mut Context, ty : LLVMTypeRef, name : &str) -> LLVMValueRef{
+    unsafe{
+        let function = LLVMGetBasicBlockParent(LLVMGetInsertBlock(ctxt.builder));
+        let entry = LLVMGetEntryBasicBlock(function);
+        let builder = LLVMCreateBuilderInContext(ctxt.context);
+        let first = LLVMGetFirstInstruction(entry);
+        if first.is_null(){
+            LLVMPositionBuilderAtEnd(builder, entry);
         }
         else{
-            panic!(format!("Invalid reference to array '{0}'. Different binding found.", *id));
+            LLVMPositionBuilderBefore(builder, first);
+        }
+        let alloca = LLVMBuildAlloca(builder, ty, c_str_ptr!(name));
+        LLVMDisposeBuilder(builder);
+        alloca
+    }
+}
+
+//a call of the current function in tail position: the arguments replace the
+//parameters and the body starts over, so that the recursion takes no stack.
+//The current block ends with the jump, and being in tail position, nothing
+//follows the call that needs its value; it is undefined
+fn tail_recurse(ctxt : &mut Context, args : &[LLVMValueRef]) -> LLVMValueRef{
+    unsafe{
+        let (body, ret) = {
+            let f = ctxt.functions.last().unwrap();
+            let bb = LLVMGetInsertBlock(ctxt.builder);
+            //all the arguments are evaluated before any parameter changes
+            for (arg, param) in args.iter().zip(f.params.iter()){
+                match *param{
+                    Storage::Alloca(alloca) => { LLVMBuildStore(ctxt.builder, *arg, alloca); },
+                    Storage::Value(phi) => LLVMAddIncoming(phi, vec![*arg].as_mut_ptr(), vec![bb].as_mut_ptr(), 1)
+                }
+            }
+            (f.body, f.ret)
+        };
+        LLVMBuildBr(ctxt.builder, body);
+        if LLVMGetTypeKind(ret) == llvm::LLVMTypeKind::LLVMVoidTypeKind{
+            LLVMGetUndef(LLVMIntTypeInContext(ctxt.context, 32))
+        }
+        else{
+            LLVMGetUndef(ret)
+        }
+    }
+}
+
+//whether the block being built already ends, as it does after a tail call
+fn block_ended(ctxt : &Context) -> bool{
+    unsafe{
+        !LLVMGetBasicBlockTerminator(LLVMGetInsertBlock(ctxt.builder)).is_null()
+    }
+}
+
+//`count` zeroed objects of `size` bytes on the heap, as an i8*
+fn heap_alloc(ctxt : &mut Context, count : LLVMValueRef, size : LLVMValueRef) -> LLVMValueRef{
+    unsafe{
+        let i64_ty = LLVMInt64TypeInContext(ctxt.context);
+        let i8_ptr_ty = LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 8), 0);
+        let calloc = declare_function(ctxt, "calloc", i8_ptr_ty, &mut [i64_ty, i64_ty], false);
+        let mut args = vec![LLVMBuildSExt(ctxt.builder, count, i64_ty, c_str_ptr!("count")),
+                            LLVMBuildZExtOrBitCast(ctxt.builder, size, i64_ty, c_str_ptr!("size"))];
+        LLVMBuildCall2(ctxt.builder, LLVMGlobalGetValueType(calloc), calloc, args.as_mut_ptr(), 2, c_str_ptr!("heap"))
+    }
+}
+
+//an array of `dim_expr` elements on the heap, each of them the value of
+//`init_expr`. The init expression is evaluated once, so an array of arrays
+//has the same array in every element
+fn build_array(elem_ty : &TType, dim_expr : &B<Expr>, init_expr : &B<Expr>, ctxt : &mut Context) -> IRBuildingResult{
+    let dim = try!(dim_expr.codegen(ctxt));
+    let init = try!(init_expr.codegen(ctxt));
+    let elem_llvm_ty = try!(get_llvm_type_for_ttype(elem_ty, ctxt));
+    unsafe{
+        let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
+        let heap = heap_alloc(ctxt, dim, LLVMSizeOf(elem_llvm_ty));
+        let elems = LLVMBuildBitCast(ctxt.builder, heap, LLVMPointerType(elem_llvm_ty, 0), c_str_ptr!("elems"));
+
+        let pre_bb = LLVMGetInsertBlock(ctxt.builder);
+        let function = LLVMGetBasicBlockParent(pre_bb);
+        let cond_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("fill_cond"));
+        let body_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("fill"));
+        let done_bb = LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("filled"));
+        LLVMBuildBr(ctxt.builder, cond_bb);
+
+        LLVMPositionBuilderAtEnd(ctxt.builder, cond_bb);
+        let cur = LLVMBuildPhi(ctxt.builder, i32_ty, c_str_ptr!("i"));
+        let more = LLVMBuildICmp(ctxt.builder, llvm::LLVMIntPredicate::LLVMIntSLT, cur, dim, c_str_ptr!("more"));
+        LLVMBuildCondBr(ctxt.builder, more, body_bb, done_bb);
+
+        LLVMPositionBuilderAtEnd(ctxt.builder, body_bb);
+        let elem = LLVMBuildGEP2(ctxt.builder, elem_llvm_ty, elems, vec![cur].as_mut_ptr(), 1, c_str_ptr!("elem"));
+        LLVMBuildStore(ctxt.builder, init, elem);
+        let next = LLVMBuildAdd(ctxt.builder, cur, LLVMConstInt(i32_ty, 1, 0), c_str_ptr!("next"));
+        LLVMAddIncoming(cur, vec![LLVMConstInt(i32_ty, 0, 0), next].as_mut_ptr(), vec![pre_bb, body_bb].as_mut_ptr(), 2);
+        LLVMBuildBr(ctxt.builder, cond_bb);
+
+        LLVMPositionBuilderAtEnd(ctxt.builder, done_bb);
+        Ok(heap)
+    }
+}
+
+//a record on the heap whose `fields` are set from `field_exprs`, in the
+//order they are written. The fields left out are zeroed
+fn build_record(fields : &[(String, TType)], field_exprs : &[(String, B<Expr>)], ctxt : &mut Context) -> IRBuildingResult{
+    let struct_ty = try!(get_record_struct_type(fields, ctxt));
+    let mut values = Vec::new();
+    for &(ref name, ref e) in field_exprs{
+        let i = match fields.iter().position(|f| f.0 == *name){
+            Some(i) => i,
+            None => return Err(format!("The record has no field '{0}'", name))
+        };
+        values.push((i, try!(e.codegen(ctxt))));
+    }
+    unsafe{
+        let i32_ty = LLVMIntTypeInContext(ctxt.context, 32);
+        //a record without fields still needs an address of its own
+        let size = if fields.is_empty() {LLVMConstInt(i32_ty, 1, 0)} else {LLVMSizeOf(struct_ty)};
+        let heap = heap_alloc(ctxt, LLVMConstInt(i32_ty, 1, 0), size);
+        let rec = LLVMBuildBitCast(ctxt.builder, heap, LLVMPointerType(struct_ty, 0), c_str_ptr!("rec"));
+        for (i, value) in values{
+            let field = LLVMBuildStructGEP2(ctxt.builder, struct_ty, rec, i as u32, c_str_ptr!("field"));
+            LLVMBuildStore(ctxt.builder, value, field);
+        }
+        Ok(heap)
+    }
+}
+
+//the value of `lvalue` and its Tiger type
+fn get_lvalue_value(lvalue : &LValue, ctxt : &mut Context) -> Result<(LLVMValueRef, TType), String> {
+    if let LValue::SimpleVar(ref id) = *lvalue{
+        let var = var_at(ctxt, try!(var_index(id, ctxt)));
+        if let Storage::Value(value) = var.storage(){
+            return Ok((value, var.var_type().clone()))
+        }
+    }
+    let (ptr, ty, tiger_ty) = try!(get_lvalue_ptr(lvalue, ctxt));
+    let name = match *lvalue{
+        LValue::SimpleVar(ref id) => &**id,
+        _ => "lvalue_load"
+    };
+    unsafe{
+        Ok((LLVMBuildLoad2(ctxt.builder, ty, ptr, c_str_ptr!(name)), tiger_ty))
+    }
+}
+
+//a pointer to what `lvalue` names, the llvm type it points to and its Tiger
+//type. Subscripts and fields get the pointer to the array or the record
+//from the lvalue they apply to. A variable kept in an SSA value has no pointer
+fn get_lvalue_ptr(lvalue : &LValue, ctxt : &mut Context) -> Result<(LLVMValueRef, LLVMTypeRef, TType), String> {
+    unsafe {
+        match *lvalue{
+            LValue::SimpleVar(ref id) => {
+                let var = var_at(ctxt, try!(var_index(id, ctxt)));
+                match var.storage(){
+                    Storage::Alloca(alloca) => Ok((alloca, LLVMGetAllocatedType(alloca), var.var_type().clone())),
+                    Storage::Value(_) => Err(format!("Variable '{0}' is not in memory", *id))
+                }
+            },
+            LValue::SubscriptVar(ref arr, ref subscript_expr) => {
+                let (heap, ty) = try!(get_lvalue_value(arr, ctxt));
+                let elem_ty = match resolve(&ty, ctxt){
+                    TType::TArray(ref elem) => (**elem).clone(),
+                    _ => return Err(format!("{:?} is not an array", arr))
+                };
+                let elem_llvm_ty = try!(get_llvm_type_for_ttype(&elem_ty, ctxt));
+                let elems = LLVMBuildBitCast(ctxt.builder, heap, LLVMPointerType(elem_llvm_ty, 0), c_str_ptr!("elems"));
+                let i = try!(subscript_expr.codegen(ctxt));
+                let val = LLVMBuildGEP2(ctxt.builder,
+                                        elem_llvm_ty,
+                                        elems,
+                                        vec![i].as_mut_ptr(),
+                                        1,
+                                        c_str_ptr!("array_gep"));
+                Ok((val, elem_llvm_ty, elem_ty))
+            },
+            LValue::FieldVar(ref rec, ref field) => {
+                let (heap, ty) = try!(get_lvalue_value(rec, ctxt));
+                let fields = match resolve(&ty, ctxt){
+                    TType::TRecord(fields) => fields,
+                    _ => return Err(format!("{:?} is not a record", rec))
+                };
+                let i = match fields.iter().position(|f| f.0 == *field){
+                    Some(i) => i,
+                    None => return Err(format!("{:?} has no field '{}'", rec, field))
+                };
+                let struct_ty = try!(get_record_struct_type(&fields, ctxt));
+                let rec_val = LLVMBuildBitCast(ctxt.builder, heap, LLVMPointerType(struct_ty, 0), c_str_ptr!("rec"));
+                let val = LLVMBuildStructGEP2(ctxt.builder,
+                                              struct_ty,
+                                              rec_val,
+                                              i as u32,
+                                              c_str_ptr!("field_gep"));
+                Ok((val, LLVMStructGetTypeAtIndex(struct_ty, i as u32), fields[i].1.clone()))
+            }
         }
     }
 }
@@ -740,18 +1357,38 @@ impl StdFunctionCodeBuilder for Expr{
             Expr::NumExpr(_) |
             Expr::StringExpr(_) |
             Expr::IdExpr(_) |
-            //FIXME call std_fn_codegen() for index, dim and init exprs
-            Expr::SubscriptExpr(_, _) |
-            Expr::RecordExpr(_) |
-            Expr::ArrayExpr(_, _, _) |
-            Expr::FieldExpr(_, _) => return,
+            Expr::NilExpr |
+            Expr::LitExpr |
+            Expr::BreakExpr |
+            Expr::LabelExpr(_) |
+            Expr::GotoExpr(_) |
+            Expr::NoOpExpr |
+            Expr::ErrorExpr |
+            Expr::RecordExpr(_) => return,
+            Expr::ArrayExpr(_, ref dim_expr, ref init_expr) |
+            Expr::TypedArrayExpr(_, ref dim_expr, ref init_expr) => {
+                dim_expr.std_fn_codegen(ctxt);
+                init_expr.std_fn_codegen(ctxt);
+            },
+            Expr::TypedRecordExpr(_, ref field_exprs) => {
+                for &(_, ref e) in field_exprs{
+                    e.std_fn_codegen(ctxt);
+                }
+            },
+            Expr::LValueExpr(ref lvalue) => lvalue.std_fn_codegen(ctxt),
             Expr::AddExpr(ref e1, ref e2) |
             Expr::SubExpr(ref e1, ref e2) |
             Expr::MulExpr(ref e1, ref e2) |
             Expr::DivExpr(ref e1, ref e2) |
+            Expr::ModExpr(ref e1, ref e2) |
             Expr::LessThanExpr(ref e1, ref e2) |
+            Expr::LessThanEqualsExpr(ref e1, ref e2) |
             Expr::GreaterThanExpr(ref e1, ref e2) |
-            Expr::EqualsExpr(ref e1, ref e2) => {
+            Expr::GreaterThanEqualsExpr(ref e1, ref e2) |
+            Expr::EqualsExpr(ref e1, ref e2) |
+            Expr::NotEqualsExpr(ref e1, ref e2) |
+            Expr::IfThenExpr(ref e1, ref e2) |
+            Expr::WhileExpr(ref e1, ref e2) => {
                 e1.std_fn_codegen(ctxt);
                 e2.std_fn_codegen(ctxt);
             },
@@ -760,7 +1397,7 @@ impl StdFunctionCodeBuilder for Expr{
                 then_expr.std_fn_codegen(ctxt);
                 else_expr.std_fn_codegen(ctxt); 
             },
-            Expr::ForExpr(_, ref from, ref to, ref do_expr) => {
+            Expr::ForExpr(_, ref from, ref to, ref do_expr, _) => {
                 from.std_fn_codegen(ctxt);
                 to.std_fn_codegen(ctxt);
                 do_expr.std_fn_codegen(ctxt);
@@ -771,13 +1408,14 @@ impl StdFunctionCodeBuilder for Expr{
                         &Decl::FunDec(_, _, _, ref body, _) => {
                             body.std_fn_codegen(ctxt);
                         },
-                        &Decl::VarDec(_, _, ref rhs) => {
+                        &Decl::VarDec(_, _, ref rhs, _) => {
                             rhs.std_fn_codegen(ctxt);
                         }
                         _ => {}
                     }
-
-                    body.as_ref().unwrap().std_fn_codegen(ctxt);
+                }
+                if let Some(ref body) = *body{
+                    body.std_fn_codegen(ctxt);
                 }
             }
             Expr::CallExpr(ref id, ref optional_ty_expr_args) => {
@@ -807,8 +1445,20 @@ impl StdFunctionCodeBuilder for Expr{
             Expr::AssignExpr(ref lhs, ref rhs) => {
                 lhs.std_fn_codegen(ctxt);
                 rhs.std_fn_codegen(ctxt);
-            },
-            _ => {panic!("Expression not covered yet for intrinsic code generation")}
+            }
+        }
+    }
+}
+
+impl StdFunctionCodeBuilder for LValue{
+    fn std_fn_codegen(&self, ctxt : &mut Context){
+        match *self{
+            LValue::SimpleVar(_) => return,
+            LValue::FieldVar(ref rec, _) => rec.std_fn_codegen(ctxt),
+            LValue::SubscriptVar(ref arr, ref subscript_expr) => {
+                arr.std_fn_codegen(ctxt);
+                subscript_expr.std_fn_codegen(ctxt);
+            }
         }
     }
 }
@@ -922,20 +1572,23 @@ fn chr_builder(ctxt : &mut Context){
             let converted_value = LLVMBuildAlloca(ctxt.builder,
                                                  LLVMPointerType(LLVMIntTypeInContext(ctxt.context, 32), 0),
                                                  c_str_ptr!("s"));
-            let mut sprintf_args = vec![LLVMBuildLoad(ctxt.builder,
-                                                      converted_value,
-                                                      c_str_ptr!("s")),
+            let mut sprintf_args = vec![LLVMBuildLoad2(ctxt.builder,
+                                                       LLVMGetAllocatedType(converted_value),
+                                                       converted_value,
+                                                       c_str_ptr!("s")),
                                         LLVMBuildGlobalStringPtr(ctxt.builder, 
                                                                  c_str_ptr!("%d"), 
                                                                  c_str_ptr!(".str")),
-                                        LLVMBuildLoad(ctxt.builder,
-                                                     alloca,
-                                                     c_str_ptr!("a"))];
-            LLVMBuildCall(ctxt.builder,
-                          sprintf_function,
-                          sprintf_args.as_mut_ptr(),
-                          3,
-                          c_str_ptr!("call"));
+                                        LLVMBuildLoad2(ctxt.builder,
+                                                       LLVMIntTypeInContext(ctxt.context, 32),
+                                                       alloca,
+                                                       c_str_ptr!("a"))];
+            LLVMBuildCall2(ctxt.builder,
+                           LLVMGlobalGetValueType(sprintf_function),
+                           sprintf_function,
+                           sprintf_args.as_mut_ptr(),
+                           3,
+                           c_str_ptr!("call"));
 
             LLVMBuildRet(ctxt.builder, converted_value);
             ctxt.sym_tab.pop();
@@ -944,8 +1597,39 @@ fn chr_builder(ctxt : &mut Context){
     }
 }
 
-pub fn translate(expr : &Expr) -> Option<Context>{
+pub fn translate(expr : &Expr) -> Result<Context, String>{
+    translate_with(expr, &TransOptions::default())
+}
+
+//like translate() but also emits DWARF for `src_path`, the file `expr` was parsed from
+pub fn translate_with_debug_info<'a>(expr : &'a Expr, src_path : &str) -> Result<Context<'a>, String>{
+    translate_with(expr, &TransOptions { debug_info : Some(src_path.to_string()), ..TransOptions::default() })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TransOptions{
+    //emit DWARF for this source file
+    pub debug_info : Option<String>,
+    //stop the program when + - * overflow instead of letting them wrap around
+    pub overflow_checks : bool,
+    //the data layout of the target, which the debug info takes the size of
+    //pointers from. None is llvm's default layout
+    pub data_layout : Option<String>
+}
+
+//the module of `expr`, or the error that stopped the translation when the
+//backend can't handle one of its expressions
+pub fn translate_with<'a>(expr : &'a Expr, opts : &TransOptions) -> Result<Context<'a>, String>{
     let mut ctxt = Context::new("main_mod");
+    ctxt.overflow_checks = opts.overflow_checks;
+    if let Some(ref layout) = opts.data_layout{
+        unsafe{
+            LLVMSetDataLayout(ctxt.module, c_str_ptr!(&**layout));
+        }
+    }
+    let src_path = opts.debug_info.as_ref();
+    //the variables no nested function uses stay out of memory
+    find_escapes(expr);
     unsafe{
         let r = LLVM_InitializeNativeTarget();
         assert_eq!(r, 0);
@@ -963,31 +1647,39 @@ pub fn translate(expr : &Expr) -> Option<Context>{
                                                function,
                                                c_str_ptr!("entry"));
         LLVMPositionBuilderAtEnd(ctxt.builder, bb);
-        ctxt.bb_stack.push(bb);
-        trans_expr(expr, &mut ctxt);
-        
+        if let Some(path) = src_path{
+            let mut di = DebugInfo::new(ctxt.context, ctxt.module, path);
+            di.begin_function(ctxt.builder, function, "main", &TType::TInt32, &[], 1);
+            ctxt.debug_info = Some(di);
+        }
+        try!(expr.codegen(&mut ctxt));
+
         LLVMBuildRet(ctxt.builder,
                      LLVMConstInt(LLVMIntTypeInContext(ctxt.context, 32), 0 as u64, 0));
 
+        if let Some(ref mut di) = ctxt.debug_info{
+            di.end_function(ctxt.builder);
+            di.finalize();
+        }
     }
-    Some(ctxt)
+    Ok(ctxt)
 }
 
 fn link_object_code(ctxt : &Context){
-    link(ctxt);
-}
-
-fn trans_expr(expr: &Expr, ctxt : &mut Context){
-    expr.codegen(ctxt).unwrap();
+    let machine = TargetMachine::new(&TargetOptions::default()).unwrap();
+    machine.configure(ctxt);
+    link(ctxt, &machine, &LinkOptions::default()).unwrap();
 }
 
 #[cfg(test)]
 mod tests {
+    use syntax::ast::Escape;
     use syntax::visit::{Visitor};
     use syntax::visitor_impl::{TypeChecker};
     use syntax::parse::*;//{Parser};
     use syntax::parse::parser::{Parser};
     use link::link;
+    use jit::run_captured;
     use helpers::*;
     use symbol::*;
     use super::*;
@@ -1003,7 +1695,7 @@ mod tests {
         // let ctxt = translate();&Expr::CallExpr("print".to_string(),
         //                               Some(vec![(TType::TString,
         //                                          B(Expr::StringExpr("abhi".to_string())))])));
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1025,7 +1717,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1037,7 +1729,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1049,7 +1741,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1061,7 +1753,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1073,7 +1765,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1086,7 +1778,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
     #[test]
     fn test_prsr_bcknd_intgrtion_var_decl() {
@@ -1097,7 +1789,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1109,7 +1801,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
 
     #[test]
@@ -1121,7 +1813,7 @@ mod tests {
         let mut v = TypeChecker::new();
         v.visit_expr(&mut *b_expr);
         let ctxt = translate(&*b_expr);
-        assert_eq!(ctxt.is_some(), true);
+        assert_eq!(ctxt.is_ok(), true);
     }
     #[test]
     #[should_panic(expected="Invalid call to 'foo'. Function not found.")]
@@ -1353,6 +2045,274 @@ mod tests {
         super::link_object_code(ctxt.as_ref().unwrap());
         ctxt.unwrap().dump();
     }
+    #[test]
+    fn test_unknown_variable_is_an_error() {
+        let mut ctxt = Context::new("unknown");
+        let e = LValueExpr(LValue::FieldVar(B(LValue::SimpleVar(String::from("x"))), String::from("f")));
+        assert_eq!(e.codegen(&mut ctxt), Err(String::from("Invalid reference to variable 'x'")));
+    }
+
+    #[test]
+    fn test_verify_accepts_translated_program() {
+        let mut p = Parser::new("let function add(a:int, b:int) : int = a+b\n in print(add(1,2)) end".to_string());
+        p.start_lexer();
+        let mut tup = p.expr();
+        let &mut (ref mut ty, ref mut b_expr) = tup.as_mut().unwrap();
+        let mut v = TypeChecker::new();
+        v.visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr);
+        assert_eq!(ctxt.unwrap().verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_names_broken_function() {
+        let ctxt = Context::new("broken");
+        unsafe{
+            let proto = LLVMFunctionType(LLVMIntTypeInContext(ctxt.context, 32), ptr::null_mut(), 0, 0);
+            let function = LLVMAddFunction(ctxt.module, c_str_ptr!("foo"), proto);
+            //a block without a terminator
+            LLVMAppendBasicBlockInContext(ctxt.context, function, c_str_ptr!("entry"));
+        }
+        let msg = ctxt.verify().err().unwrap();
+        assert!(msg.starts_with("invalid IR generated for function 'foo'"));
+    }
+
+    #[test]
+    fn test_broken_ir_is_left_to_verify() {
+        //the branches disagree on their type, so the phi joining them is invalid
+        let e = IfThenElseExpr(B(NumExpr(1)), B(StringExpr(b"a".to_vec())), B(NumExpr(0)));
+        let msg = translate(&e).unwrap().verify().err().unwrap();
+        assert!(msg.starts_with("invalid IR generated for the main program"), "{}", msg);
+    }
+
+    #[test]
+    fn test_unsupported_expressions_are_errors() {
+        let err = |src : &str| {
+            let mut p = Parser::new(src.to_string());
+            p.start_lexer();
+            let (_, mut b_expr) = p.expr().unwrap();
+            TypeChecker::new().visit_expr(&mut *b_expr);
+            translate(&*b_expr).err().unwrap()
+        };
+        assert_eq!(err("if 1 then print(\"a\")"), "IfThenExpr isn't supported by the llvm backend yet. Try --backend ir");
+        assert!(err("let var a := 1 in while a < 3 do a := a + 1 end").starts_with("WhileExpr isn't supported"));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let src = "let function add(a:int, b:int) : int = a+b\n var c : int := 3\n in for i := 0 to c do print(add(1,c)) end";
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let mut tup = p.expr();
+        let &mut (ref mut ty, ref mut b_expr) = tup.as_mut().unwrap();
+        let mut v = TypeChecker::new();
+        v.visit_expr(&mut *b_expr);
+        let ctxt = translate_with_debug_info(&*b_expr, "tests/add.tig").unwrap();
+        assert_eq!(ctxt.verify(), Ok(()));
+        let ir = ctxt.ir();
+        assert!(ir.contains("!DIFile(filename: \"add.tig\", directory: \"tests\")"));
+        assert!(ir.contains("DISubprogram(name: \"main\""));
+        assert!(ir.contains("DISubprogram(name: \"add\""));
+        assert!(ir.contains("DILocalVariable(name: \"b\", arg: 2"));
+        assert!(ir.contains("DILocalVariable(name: \"c\""));
+        assert!(ir.contains("DILocalVariable(name: \"i\""));
+        assert!(ir.contains("!DILocation(line: 3"));
+    }
+
+    #[test]
+    fn test_debug_info_pointer_size() {
+        let mut p = Parser::new(String::from("let var s : string := \"hi\" in print(s) end"));
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let string_ty = |triple : &str| {
+            let target = TargetOptions { triple : Some(String::from(triple)), ..TargetOptions::default() };
+            let opts = TransOptions { debug_info : Some(String::from("s.tig")),
+                                      data_layout : Some(TargetMachine::new(&target).unwrap().data_layout()),
+                                      ..TransOptions::default() };
+            let ir = translate_with(&*b_expr, &opts).unwrap().ir();
+            ir.lines().find(|l| l.contains("name: \"string\"")).unwrap().to_string()
+        };
+        assert!(string_ty("x86_64-unknown-linux-gnu").contains("size: 64,"));
+        assert!(string_ty("i686-unknown-linux-gnu").contains("size: 32,"));
+    }
+
+    fn jit_output(src : &str) -> (i32, String){
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let mut tup = p.expr();
+        let &mut (ref mut ty, ref mut b_expr) = tup.as_mut().unwrap();
+        let mut v = TypeChecker::new();
+        v.visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr);
+        run_captured(ctxt.as_ref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_prsr_bcknd_intgrtion_jit_print_addition_call_result() {
+        assert_eq!(jit_output("let function add(a:int, b:int) : int = a+b\n in print(add(1,2))"),
+                   (0, String::from("3\n")));
+    }
+
+    #[test]
+    fn test_prsr_bcknd_intgrtion_jit_print_string() {
+        assert_eq!(jit_output("print(\"Grrrr!\n\")"), (0, String::from("Grrrr!\n")));
+    }
+
+    #[test]
+    fn test_prsr_bcknd_intgrtion_jit_int_var_modification() {
+        assert_eq!(jit_output("let var a : int := 3 in (a := 8;print(a);) end"), (0, String::from("8\n")));
+    }
+
+    #[test]
+    fn test_prsr_bcknd_intgrtion_jit_array_element_modification() {
+        assert_eq!(jit_output("let var a : array := array of int[3] of 1+1 in (a[2]:=99;print(a[2]);) end"),
+                   (0, String::from("99\n")));
+    }
+
+    #[test]
+    fn test_prsr_bcknd_intgrtion_jit_exit_code() {
+        assert_eq!(jit_output("exit(7)").0, 7);
+    }
+
+    #[test]
+    fn test_jit_self_tail_calls_take_no_stack() {
+        let src = "let function count(n:int) : int = if 1000000 > n then count(n + 1) else n in print(count(0)) end";
+        assert_eq!(jit_output(src), (0, String::from("1000000\n")));
+        //the arguments are evaluated before the parameters change
+        let src = "let function fib(k:int, a:int, b:int) : int = if 10 > k then fib(k + 1, b, a + b) else a in print(fib(0, 0, 1)) end";
+        assert_eq!(jit_output(src), (0, String::from("55\n")));
+    }
+
+    #[test]
+    fn test_tail_calls_leave_no_dead_code() {
+        //the block of a tail call ends with the jump back, so nothing is
+        //built after it and no undefined value flows into the if
+        for src in &["let function f(n:int) : int = if n > 0 then f(n - 1) else n in print(f(3)) end",
+                     "let function f(n:int) : int = if n > 0 then f(n - 1) else f(n + 1) in print(1) end"]{
+            let mut p = Parser::new(src.to_string());
+            p.start_lexer();
+            let (_, mut b_expr) = p.expr().unwrap();
+            TypeChecker::new().visit_expr(&mut *b_expr);
+            let ctxt = translate(&*b_expr).unwrap();
+            assert_eq!(ctxt.verify(), Ok(()));
+            assert!(!ctxt.ir().contains("undef"), "{}", ctxt.ir());
+        }
+    }
+
+    #[test]
+    fn test_variables_that_dont_escape_take_no_memory() {
+        let src = "let var s := 0\n function sum(n:int, acc:int) : int = if n > 0 then sum(n - 1, acc + n) else acc\n function id(n:int) : int = n\n in (for i := 1 to 10 do (if i > 5 then (s := s + i; 0) else (s := s - 1; 0); print(s)); print(sum(10, 0)); print(id(7))) end";
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr).unwrap();
+        assert_eq!(ctxt.verify(), Ok(()));
+        assert!(!ctxt.ir().contains("alloca"), "{}", ctxt.ir());
+        assert_eq!(run_captured(&ctxt).unwrap(), (0, String::from("-1\n-2\n-3\n-4\n-5\n1\n8\n16\n25\n35\n55\n7\n")));
+    }
+
+    #[test]
+    fn test_tail_calls_are_marked() {
+        //let function g(n:int) : int = n + 1
+        //    function f(n:int) : int = (print(g(n)); g(n))
+        //in print(f(1)) end
+        let call = |f : &str, arg : Expr| B(CallExpr(String::from(f), Some(vec![(TType::TInt32, B(arg))])));
+        let param = || Some(vec![(String::from("n"), TType::TInt32, Escape::new())]);
+        let g = Decl::FunDec(String::from("g"), param(), TType::TInt32, B(AddExpr(B(IdExpr(String::from("n"))), B(NumExpr(1)))), TType::TInt32);
+        let f_body = SeqExpr(Some(vec![call("print", CallExpr(String::from("g"), Some(vec![(TType::TInt32, B(IdExpr(String::from("n"))))]))),
+                                       call("g", IdExpr(String::from("n")))]));
+        let f = Decl::FunDec(String::from("f"), param(), TType::TInt32, B(f_body), TType::TInt32);
+        let e = LetExpr(vec![g, f], Some(call("print", CallExpr(String::from("f"), Some(vec![(TType::TInt32, B(NumExpr(1)))])))));
+        let ir = translate(&e).unwrap().ir();
+        assert_eq!(ir.matches("tail call i32 @g(").count(), 1, "{}", ir);
+        assert_eq!(ir.matches("call i32 @g(").count(), 2, "{}", ir);
+    }
+
+    fn checked(e : &Expr) -> Context{
+        translate_with(e, &TransOptions { overflow_checks : true, ..TransOptions::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_jit_empty_sequence() {
+        assert_eq!(jit_output("let var a := 0 in (for i := 0 to 2 do (a := a + 1; ()); print(a)) end"),
+                   (0, String::from("3\n")));
+    }
+
+    #[test]
+    fn test_jit_comparisons_are_ints() {
+        //the i1 of a comparison is widened so that it can be stored, added and returned
+        let src = "let function lt(a:int, b:int) : int = a < b\n var t : int := 3 = 3 in print(t + lt(1, 2) + (2 > 1) + (1 > 2)) end";
+        assert_eq!(jit_output(src), (0, String::from("3\n")));
+    }
+
+    #[test]
+    fn test_jit_arithmetic_wraps_around() {
+        assert_eq!(jit_output("print(2147483647 + 1)"), (0, String::from("-2147483648\n")));
+        assert_eq!(jit_output("print(65536 * 32769)"), (0, String::from("-2147418112\n")));
+    }
+
+    #[test]
+    fn test_jit_overflow_checks() {
+        let print = |e : Expr| B(CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(e))])));
+        let num = |n| B(NumExpr(n));
+        //(print(6 * 7); print(2147483647 + 1))
+        let e = SeqExpr(Some(vec![print(MulExpr(num(6), num(7))), print(AddExpr(num(2147483647), num(1)))]));
+        assert_eq!(run_captured(&checked(&e)).unwrap(), (1, String::from("42\n")));
+        let e = print(MulExpr(num(65536), num(32769)));
+        assert_eq!(run_captured(&checked(&e)).unwrap(), (1, String::new()));
+        //(print((0 - 2147483647) - 1); print((0 - 2147483647) - 2))
+        let min_minus = |n| print(SubExpr(B(SubExpr(num(0), num(2147483647))), num(n)));
+        let e = SeqExpr(Some(vec![min_minus(1), min_minus(2)]));
+        assert_eq!(run_captured(&checked(&e)).unwrap(), (1, String::from("-2147483648\n")));
+    }
+
+    #[test]
+    fn test_jit_mod() {
+        assert_eq!(jit_output("print(17 mod 5)"), (0, String::from("2\n")));
+        let e = CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(ModExpr(B(SubExpr(B(NumExpr(0)), B(NumExpr(7)))), B(NumExpr(3)))))]));
+        assert_eq!(run_captured(&translate(&e).unwrap()).unwrap(), (0, String::from("-1\n")));
+    }
+
+    #[test]
+    fn test_jit_division_checks() {
+        let print = |e : Expr| B(CallExpr(String::from("print"), Some(vec![(TType::TInt32, B(e))])));
+        let num = |n| B(NumExpr(n));
+        let zero = || B(SubExpr(num(1), num(1)));
+        let run = |e : &Expr| run_captured(&translate(e).unwrap()).unwrap();
+        //(print(1); print(7 / (1 - 1)))
+        assert_eq!(run(&SeqExpr(Some(vec![print(NumExpr(1)), print(DivExpr(num(7), zero()))]))), (1, String::from("1\n")));
+        assert_eq!(run(&print(ModExpr(num(7), zero()))), (1, String::new()));
+        //INT_MIN / -1 wraps around and INT_MIN mod -1 is 0
+        let int_min = || B(SubExpr(B(SubExpr(num(0), num(2147483647))), num(1)));
+        let minus_one = || B(SubExpr(num(0), num(1)));
+        let e = SeqExpr(Some(vec![print(DivExpr(int_min(), minus_one())), print(ModExpr(int_min(), minus_one())),
+                                  print(DivExpr(num(7), minus_one()))]));
+        assert_eq!(run(&e), (0, String::from("-2147483648\n0\n-7\n")));
+        assert_eq!(run_captured(&checked(&e)).unwrap(), (1, String::new()));
+    }
+
+    #[test]
+    fn test_division_checks_report_the_line() {
+        let mut div = B(DivExpr(B(NumExpr(3)), B(NumExpr(4))));
+        div.span.line = 3;
+        let ir = translate(&CallExpr(String::from("print"), Some(vec![(TType::TInt32, div)]))).unwrap().ir();
+        assert!(ir.contains("line 3: division by zero"), "{}", ir);
+    }
+
+    #[test]
+    fn test_overflow_checks_report_the_line() {
+        let mut mul = B(MulExpr(B(NumExpr(3)), B(NumExpr(4))));
+        mul.span.line = 2;
+        let mut e = B(CallExpr(String::from("print"), Some(vec![(TType::TInt32, mul)])));
+        e.span.line = 1;
+        let ir = checked(&e).ir();
+        assert!(ir.contains("@llvm.smul.with.overflow.i32"), "{}", ir);
+        assert!(ir.contains("line 2: integer overflow"), "{}", ir);
+        assert!(!translate(&e).unwrap().ir().contains("overflow"));
+    }
+
     //#[test]
     //fn test_prsr_bcknd_intgrtion_print_with_chr_call() {
     //    let mut p = Parser::new("print(chr(7))".to_string());
@@ -0,0 +1,247 @@
+extern crate llvm_sys as llvm;
+extern crate libc;
+use std::ptr;
+use std::ffi;
+use std::path::Path;
+
+use self::llvm::prelude::{LLVMContextRef, LLVMModuleRef, LLVMBuilderRef, LLVMValueRef, LLVMMetadataRef,
+                          LLVMDIBuilderRef};
+use self::llvm::LLVMModuleFlagBehavior;
+use self::llvm::core::*;
+use self::llvm::debuginfo::*;
+use self::llvm::target::*;
+use syntax::ast::TType;
+
+//DW_ATE_signed and DW_ATE_signed_char
+const ENCODING_SIGNED : LLVMDWARFTypeEncoding = 0x05;
+const ENCODING_SIGNED_CHAR : LLVMDWARFTypeEncoding = 0x06;
+
+//builds the DWARF metadata of a module: one compile unit for the source
+//file, a subprogram per function and a location for every instruction
+pub struct DebugInfo{
+    builder : LLVMDIBuilderRef,
+    context : LLVMContextRef,
+    file : LLVMMetadataRef,
+    int_ty : LLVMMetadataRef,
+    string_ty : LLVMMetadataRef,
+    pointer_bits : u64,
+    //subprograms of the functions being generated, innermost last, with
+    //the line the builder was at when each one was entered
+    scopes : Vec<(LLVMMetadataRef, usize)>,
+    line : usize
+}
+
+//the size of a pointer in bits on the target the module is built for
+fn pointer_bits(module : LLVMModuleRef) -> u64{
+    unsafe{
+        let layout = LLVMCreateTargetData(LLVMGetDataLayoutStr(module));
+        let bits = LLVMPointerSize(layout) as u64 * 8;
+        LLVMDisposeTargetData(layout);
+        bits
+    }
+}
+
+impl DebugInfo{
+    //the module should have the data layout of its target already
+    pub fn new(context : LLVMContextRef, module : LLVMModuleRef, src_path : &str) -> Self{
+        unsafe{
+            let i32_ty = LLVMInt32TypeInContext(context);
+            let version = LLVMValueAsMetadata(LLVMConstInt(i32_ty, LLVMDebugMetadataVersion() as u64, 0));
+            LLVMAddModuleFlag(module, LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                              c_str_ptr!("Debug Info Version"), 18, version);
+            let dwarf = LLVMValueAsMetadata(LLVMConstInt(i32_ty, 4, 0));
+            LLVMAddModuleFlag(module, LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                              c_str_ptr!("Dwarf Version"), 13, dwarf);
+
+            let builder = LLVMCreateDIBuilder(module);
+            let path = Path::new(src_path);
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(String::from(src_path));
+            let dir = path.parent().map(|d| d.to_string_lossy().into_owned()).unwrap_or(String::new());
+            let file = LLVMDIBuilderCreateFile(builder,
+                                               c_str_ptr!(&*name), name.len(),
+                                               c_str_ptr!(&*dir), dir.len());
+            LLVMDIBuilderCreateCompileUnit(builder,
+                                           LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+                                           file,
+                                           c_str_ptr!("charon"), 6,
+                                           0,
+                                           c_str_ptr!(""), 0,
+                                           0,
+                                           c_str_ptr!(""), 0,
+                                           LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+                                           0, 0, 0,
+                                           c_str_ptr!(""), 0,
+                                           c_str_ptr!(""), 0);
+            let int_ty = LLVMDIBuilderCreateBasicType(builder, c_str_ptr!("int"), 3, 32,
+                                                      ENCODING_SIGNED, LLVMDIFlagZero);
+            let char_ty = LLVMDIBuilderCreateBasicType(builder, c_str_ptr!("char"), 4, 8,
+                                                       ENCODING_SIGNED_CHAR, LLVMDIFlagZero);
+            let bits = pointer_bits(module);
+            let string_ty = LLVMDIBuilderCreatePointerType(builder, char_ty, bits, 0, 0,
+                                                           c_str_ptr!("string"), 6);
+            DebugInfo {
+                builder : builder,
+                context : context,
+                file : file,
+                int_ty : int_ty,
+                string_ty : string_ty,
+                pointer_bits : bits,
+                scopes : Vec::new(),
+                line : 0
+            }
+        }
+    }
+
+    //None for the types that have no debug description yet (records)
+    fn di_type(&self, ty : &TType) -> Option<LLVMMetadataRef>{
+        unsafe{
+            match *ty{
+                TType::TInt32 => Some(self.int_ty),
+                TType::TString => Some(self.string_ty),
+                //the variable points to the elements on the heap
+                TType::TArray(ref elem_ty) => {
+                    let elem = match self.di_type(elem_ty){
+                        Some(e) => e,
+                        None => return None
+                    };
+                    Some(LLVMDIBuilderCreatePointerType(self.builder, elem, self.pointer_bits, 0, 0,
+                                                        ptr::null(), 0))
+                },
+                _ => None
+            }
+        }
+    }
+
+    //attaches a subprogram to `function` and makes it the scope of the locations that follow
+    pub fn begin_function(&mut self, builder : LLVMBuilderRef, function : LLVMValueRef,
+                          name : &str, ret_ty : &TType, param_tys : &[TType], line : usize){
+        unsafe{
+            let mut tys = vec![self.di_type(ret_ty).unwrap_or(ptr::null_mut())];
+            for ty in param_tys{
+                tys.push(self.di_type(ty).unwrap_or(ptr::null_mut()));
+            }
+            let fn_ty = LLVMDIBuilderCreateSubroutineType(self.builder, self.file,
+                                                          tys.as_mut_ptr(), tys.len() as u32,
+                                                          LLVMDIFlagZero);
+            let subprogram = LLVMDIBuilderCreateFunction(self.builder, self.file,
+                                                         c_str_ptr!(name), name.len(),
+                                                         c_str_ptr!(name), name.len(),
+                                                         self.file, line as u32, fn_ty,
+                                                         0, 1, line as u32,
+                                                         LLVMDIFlagZero, 0);
+            LLVMSetSubprogram(function, subprogram);
+            self.scopes.push((subprogram, self.line));
+        }
+        self.set_location(builder, line);
+    }
+
+    //goes back to the scope of the enclosing function
+    pub fn end_function(&mut self, builder : LLVMBuilderRef){
+        let (_, line) = self.scopes.pop().unwrap();
+        if self.scopes.is_empty(){
+            unsafe{
+                LLVMSetCurrentDebugLocation2(builder, ptr::null_mut());
+            }
+        }
+        else{
+            self.set_location(builder, line);
+        }
+    }
+
+    //gives the instructions built from now on the source line `line`.
+    //A line of 0 (unknown) keeps the current one
+    pub fn set_location(&mut self, builder : LLVMBuilderRef, line : usize){
+        if line != 0{
+            self.line = line;
+        }
+        if let Some(&(scope, _)) = self.scopes.last(){
+            unsafe{
+                let loc = LLVMDIBuilderCreateDebugLocation(self.context, self.line as u32, 0,
+                                                           scope, ptr::null_mut());
+                LLVMSetCurrentDebugLocation2(builder, loc);
+            }
+        }
+    }
+
+    //a variable of the function being generated. `arg_no` is the 1-based
+    //position for parameters
+    fn variable(&self, name : &str, ty : &TType, arg_no : Option<u32>) -> Option<(LLVMMetadataRef, LLVMMetadataRef)>{
+        let di_ty = match self.di_type(ty){
+            Some(t) => t,
+            None => return None
+        };
+        let scope = match self.scopes.last(){
+            Some(&(s, _)) => s,
+            None => return None
+        };
+        unsafe{
+            let var = match arg_no{
+                Some(n) => LLVMDIBuilderCreateParameterVariable(self.builder, scope,
+                                                                c_str_ptr!(name), name.len(), n,
+                                                                self.file, self.line as u32, di_ty,
+                                                                1, LLVMDIFlagZero),
+                None => LLVMDIBuilderCreateAutoVariable(self.builder, scope,
+                                                        c_str_ptr!(name), name.len(),
+                                                        self.file, self.line as u32, di_ty,
+                                                        1, LLVMDIFlagZero, 0)
+            };
+            Some((var, scope))
+        }
+    }
+
+    //describes the variable stored in `alloca`. `arg_no` is the 1-based
+    //position for parameters
+    pub fn declare_variable(&self, builder : LLVMBuilderRef, alloca : LLVMValueRef,
+                            name : &str, ty : &TType, arg_no : Option<u32>){
+        if let Some((var, scope)) = self.variable(name, ty, arg_no){
+            unsafe{
+                let loc = LLVMDIBuilderCreateDebugLocation(self.context, self.line as u32, 0,
+                                                           scope, ptr::null_mut());
+                LLVMDIBuilderInsertDeclareAtEnd(self.builder, alloca, var,
+                                                LLVMDIBuilderCreateExpression(self.builder, ptr::null_mut(), 0),
+                                                loc, LLVMGetInsertBlock(builder));
+            }
+        }
+    }
+
+    //describes a variable kept in SSA values, which starts out as `value`.
+    //The variable is what set_value() takes
+    pub fn ssa_variable(&self, builder : LLVMBuilderRef, value : LLVMValueRef,
+                        name : &str, ty : &TType, arg_no : Option<u32>) -> Option<LLVMMetadataRef>{
+        let var = self.variable(name, ty, arg_no).map(|(var, _)| var);
+        if let Some(var) = var{
+            self.set_value(builder, var, value);
+        }
+        var
+    }
+
+    //tells the debugger that the variable `var` holds `value` from here on
+    pub fn set_value(&self, builder : LLVMBuilderRef, var : LLVMMetadataRef, value : LLVMValueRef){
+        let scope = match self.scopes.last(){
+            Some(&(s, _)) => s,
+            None => return
+        };
+        unsafe{
+            let loc = LLVMDIBuilderCreateDebugLocation(self.context, self.line as u32, 0,
+                                                       scope, ptr::null_mut());
+            LLVMDIBuilderInsertDbgValueAtEnd(self.builder, value, var,
+                                             LLVMDIBuilderCreateExpression(self.builder, ptr::null_mut(), 0),
+                                             loc, LLVMGetInsertBlock(builder));
+        }
+    }
+
+    //must be called once all functions are generated
+    pub fn finalize(&self){
+        unsafe{
+            LLVMDIBuilderFinalize(self.builder);
+        }
+    }
+}
+
+impl Drop for DebugInfo{
+    fn drop(&mut self){
+        unsafe{
+            LLVMDisposeDIBuilder(self.builder);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+extern crate llvm_sys as llvm;
+extern crate libc;
+use std::ptr;
+use std::ffi;
+use std::mem;
+use std::process::Command;
+
+use self::llvm::prelude::{LLVMModuleRef};
+use self::llvm::core::*;
+use self::llvm::execution_engine::*;
+use self::llvm::support::LLVMAddSymbol;
+use self::llvm::target::{LLVM_InitializeNativeTarget, LLVM_InitializeNativeAsmPrinter};
+use session::link::{LinkOptions, TempDir};
+use base::Context;
+use link::link;
+use target::{TargetMachine, TargetOptions};
+
+//runtime functions the generated code calls into
+extern "C" {
+    fn printf(format : *const libc::c_char, ...) -> libc::c_int;
+    fn dprintf(fd : libc::c_int, format : *const libc::c_char, ...) -> libc::c_int;
+    fn sprintf(s : *mut libc::c_char, format : *const libc::c_char, ...) -> libc::c_int;
+    fn strlen(s : *const libc::c_char) -> libc::size_t;
+    fn atoi(s : *const libc::c_char) -> libc::c_int;
+    fn exit(status : libc::c_int) -> !;
+    fn fflush(stream : *mut libc::FILE) -> libc::c_int;
+    fn getchar() -> libc::c_int;
+    fn calloc(n : libc::size_t, size : libc::size_t) -> *mut libc::c_void;
+    fn memcpy(dst : *mut libc::c_void, src : *const libc::c_void, n : libc::size_t) -> *mut libc::c_void;
+    fn strcmp(a : *const libc::c_char, b : *const libc::c_char) -> libc::c_int;
+}
+
+type MainFn = extern "C" fn() -> i32;
+
+//makes the runtime resolvable by the jit regardless of what the
+//compiler binary itself happens to export
+fn register_runtime_symbols(){
+    let symbols : [(&str, *mut libc::c_void); 11] = [
+        ("printf", printf as *mut libc::c_void),
+        ("dprintf", dprintf as *mut libc::c_void),
+        ("sprintf", sprintf as *mut libc::c_void),
+        ("strlen", strlen as *mut libc::c_void),
+        ("atoi", atoi as *mut libc::c_void),
+        ("exit", exit as *mut libc::c_void),
+        ("fflush", fflush as *mut libc::c_void),
+        ("getchar", getchar as *mut libc::c_void),
+        ("calloc", calloc as *mut libc::c_void),
+        ("memcpy", memcpy as *mut libc::c_void),
+        ("strcmp", strcmp as *mut libc::c_void)
+    ];
+    for &(name, addr) in symbols.iter(){
+        unsafe{
+            LLVMAddSymbol(c_str_ptr!(name), addr);
+        }
+    }
+}
+
+fn take_message(msg : *mut libc::c_char) -> String{
+    if msg.is_null(){
+        return String::from("unknown error")
+    }
+    unsafe{
+        let s = ffi::CStr::from_ptr(msg).to_string_lossy().into_owned();
+        LLVMDisposeMessage(msg);
+        s
+    }
+}
+
+//compiles the module in memory and hands the address of its main() to `f`.
+//the module is given back to `ctxt` once `f` returns.
+fn with_main<T, F>(ctxt : &Context, f : F) -> Result<T, String> where F : FnOnce(MainFn) -> T{
+    unsafe{
+        LLVMLinkInMCJIT();
+        //modules that didn't come from base::translate haven't done this yet
+        if LLVM_InitializeNativeTarget() != 0{
+            return Err(String::from("the native target isn't available"))
+        }
+        LLVM_InitializeNativeAsmPrinter();
+        register_runtime_symbols();
+
+        let mut options : LLVMMCJITCompilerOptions = mem::zeroed();
+        LLVMInitializeMCJITCompilerOptions(&mut options, mem::size_of::<LLVMMCJITCompilerOptions>());
+        let mut engine : LLVMExecutionEngineRef = ptr::null_mut();
+        let mut err : *mut libc::c_char = ptr::null_mut();
+        if LLVMCreateMCJITCompilerForModule(&mut engine,
+                                            ctxt.module,
+                                            &mut options,
+                                            mem::size_of::<LLVMMCJITCompilerOptions>(),
+                                            &mut err) != 0{
+            return Err(format!("failed to create the execution engine - {}", take_message(err)))
+        }
+
+        let addr = LLVMGetFunctionAddress(engine, c_str_ptr!("main"));
+        let result = if addr == 0{
+            Err(String::from("function 'main' not found in the module"))
+        }
+        else{
+            Ok(f(mem::transmute::<usize, MainFn>(addr as usize)))
+        };
+
+        //the engine owns the module now; take it back so that the context can dispose it
+        let mut module : LLVMModuleRef = ptr::null_mut();
+        LLVMRemoveModule(engine, ctxt.module, &mut module, &mut err);
+        LLVMDisposeExecutionEngine(engine);
+        result
+    }
+}
+
+//runs the program in this process and returns the value main() returned.
+//a call to exit() in the program ends this process as well.
+pub fn run(ctxt : &Context) -> Result<i32, String>{
+    with_main(ctxt, |main| main())
+}
+
+//builds the program into an executable in a temporary directory and runs
+//it, returning its exit code and everything it wrote to stdout. Nothing is
+//forked, so other threads, like those of the test harness, can't leave a
+//lock in stdio held in the child
+pub fn run_captured(ctxt : &Context) -> Result<(i32, String), String>{
+    let machine = try!(TargetMachine::new(&TargetOptions::default()));
+    machine.configure(ctxt);
+    let tmp = try!(TempDir::new());
+    let exe = tmp.path.join("main");
+    try!(link(ctxt, &machine, &LinkOptions { output : exe.to_string_lossy().into_owned(), ..LinkOptions::default() }));
+    let out = try!(Command::new(&exe).output().map_err(|e| format!("could not run {} - {}", exe.display(), e)));
+    match out.status.code(){
+        Some(code) => Ok((code, String::from_utf8_lossy(&out.stdout).into_owned())),
+        None => Err(format!("program terminated by a signal ({})", out.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use base::translate;
+    use super::*;
+
+    fn run_src(src : &str) -> (i32, String){
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr).unwrap();
+        run_captured(&ctxt).unwrap()
+    }
+
+    #[test]
+    fn test_jit_main_returns_0(){
+        let mut p = Parser::new("1+1".to_string());
+        p.start_lexer();
+        let (_, b_expr) = p.expr().unwrap();
+        let ctxt = translate(&*b_expr).unwrap();
+        assert_eq!(run(&ctxt), Ok(0));
+    }
+
+    #[test]
+    fn test_jit_array_of_arrays(){
+        let src = "let type row = array of int\n type matrix = array of row\n var m := matrix[2] of row[3] of 0\n \
+                   in (m[1] := row[3] of 0; m[1][2] := 7; m[0][1] := m[1][2] + 1;\n \
+                   print(m[1][2]); print(m[0][1]); print(m[0][2]); print(m[1][1])) end";
+        assert_eq!(run_src(src), (0, String::from("7\n8\n0\n0\n")));
+    }
+
+    #[test]
+    fn test_jit_recursive_record(){
+        let src = "let type list = {value:int, next:list}\n var l := list{value=1, next=list{value=2, next=nil}}\n \
+                   in (print(l.next.value); l.next.value := 5; print(l.next.value); print(l.value)) end";
+        assert_eq!(run_src(src), (0, String::from("2\n5\n1\n")));
+    }
+
+    #[test]
+    fn test_jit_array_of_records(){
+        let src = "let type person = {name:string, age:int}\n type people = array of person\n var ps := people[2] of nil\n \
+                   in (for k := 0 to 1 do ps[k] := person{name=\"a\", age=k};\n \
+                   for k := 1 to 1 do ps[k].name := \"x\";\n \
+                   print(ps[0].name); print(ps[1].name); print(ps[1].age)) end";
+        assert_eq!(run_src(src), (0, String::from("ax1\n")));
+    }
+}
@@ -1,8 +1,15 @@
 #![feature(libc)]
 extern crate syntax;
+extern crate ir;
+extern crate session;
 
 #[macro_use]
 pub mod helpers;
 pub mod base;
+pub mod debuginfo;
 pub mod link; 
+pub mod lower;
+pub mod jit;
+pub mod opt;
 pub mod symbol; 
+pub mod target;
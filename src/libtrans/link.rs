@@ -1,38 +1,191 @@
-extern crate llvm_sys as llvm;
-extern crate libc;
-use std::ptr;
-use std::ffi;
-
-use self::llvm::target_machine::*;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process::Command;
+use session::link::{Linker, LinkOptions, TempDir};
 use base::Context;
+use target::{TargetMachine, RelocModel};
+
+//writes the object code of the module and, unless the linker is None, links it
+//into an executable with the c runtime
+pub fn link(ctxt: &Context, machine: &TargetMachine, opts: &LinkOptions) -> Result<(), String>{
+    if opts.linker == Linker::None{
+        return machine.emit_object(ctxt, &opts.output)
+    }
+
+    let tmp = try!(TempDir::new());
+    let obj = tmp.path.join("main.o");
+    try!(machine.emit_object(ctxt, &obj.to_string_lossy()));
+
+    let mut cmd = try!(linker_command(machine, opts, &obj));
+    let out = try!(cmd.output().map_err(|e| format!("could not run the linker - {}", e)));
+    if !out.status.success(){
+        return Err(format!("linking with {:?} failed ({})\n{}{}",
+                           cmd, out.status,
+                           String::from_utf8_lossy(&out.stdout),
+                           String::from_utf8_lossy(&out.stderr)))
+    }
+    //warnings from the linker
+    let _ = io::stderr().write_all(&out.stderr);
+    Ok(())
+}
+
+fn linker_command(machine: &TargetMachine, opts: &LinkOptions, obj: &Path) -> Result<Command, String>{
+    let mut cmd;
+    match opts.linker{
+        Linker::Cc | Linker::Clang => {
+            cmd = Command::new(if opts.linker == Linker::Cc {"cc"} else {"clang"});
+            if opts.linker == Linker::Clang{
+                cmd.arg(format!("--target={}", machine.triple));
+            }
+            match machine.reloc_model{
+                RelocModel::Pie => { cmd.arg("-pie"); },
+                RelocModel::Pic => {},
+                _ => { cmd.arg("-no-pie"); }
+            }
+            if opts.static_link{
+                cmd.arg("-static");
+            }
+            cmd.arg(obj);
+        },
+        Linker::Ld => {
+            cmd = Command::new("ld");
+            let pie = machine.reloc_model == RelocModel::Pie && !opts.static_link;
+            if opts.static_link{
+                cmd.arg("-static");
+            }
+            else{
+                cmd.arg("-dynamic-linker").arg(try!(dynamic_linker(&machine.triple)));
+            }
+            if pie{
+                cmd.arg("-pie");
+            }
+            //crtbegin/crtend run the constructors and destructors and come in
+            //one flavour for static, one for position independent and one for
+            //other executables
+            let (crtbegin, crtend) = if opts.static_link {
+                ("crtbeginT.o", "crtend.o")
+            } else if pie {
+                ("crtbeginS.o", "crtendS.o")
+            } else {
+                ("crtbegin.o", "crtend.o")
+            };
+            cmd.arg(try!(crt_file(if pie {"Scrt1.o"} else {"crt1.o"})))
+               .arg(try!(crt_file("crti.o")))
+               .arg(try!(crt_file(crtbegin)))
+               .arg(obj)
+               .arg(format!("-L{}", try!(crt_dir())))
+               .arg("-lc")
+               .arg(try!(crt_file(crtend)))
+               .arg(try!(crt_file("crtn.o")));
+        },
+        Linker::None => unreachable!()
+    }
+    for path in opts.lib_paths.iter(){
+        cmd.arg(format!("-L{}", path));
+    }
+    for lib in opts.libs.iter(){
+        cmd.arg(format!("-l{}", lib));
+    }
+    cmd.arg("-o").arg(&opts.output);
+    Ok(cmd)
+}
+
+fn dynamic_linker(triple : &str) -> Result<&'static str, String>{
+    let arch = triple.split('-').next().unwrap_or("");
+    match arch{
+        "x86_64" => Ok("/lib64/ld-linux-x86-64.so.2"),
+        "aarch64" => Ok("/lib/ld-linux-aarch64.so.1"),
+        "i386" | "i486" | "i586" | "i686" => Ok("/lib/ld-linux.so.2"),
+        _ => Err(format!("don't know the dynamic linker for '{}'. Link with cc or statically instead", triple))
+    }
+}
+
+fn crt_file(name : &str) -> Result<String, String>{
+    let out = try!(Command::new("cc")
+        .arg(format!("-print-file-name={}", name))
+        .output()
+        .map_err(|e| format!("could not locate {} - {}", name, e)));
+    let path = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    //cc echoes the bare name back when it can't find the file
+    if !out.status.success() || path == name{
+        return Err(format!("could not locate {}", name))
+    }
+    Ok(path)
+}
+
+fn crt_dir() -> Result<String, String>{
+    let crt1 = try!(crt_file("crt1.o"));
+    Ok(Path::new(&crt1).parent().unwrap().to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use base::translate;
+    use target::TargetOptions;
+    use super::*;
+
+    //links `src` into a fresh temp dir and returns the path of the output
+    fn build(src : &str, opts : LinkOptions, tmp : &TempDir) -> Result<PathBuf, String>{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr).unwrap();
+        let machine = TargetMachine::new(&TargetOptions::default()).unwrap();
+        machine.configure(&ctxt);
+        let output = tmp.path.join("out");
+        try!(link(&ctxt, &machine, &LinkOptions { output : output.to_string_lossy().into_owned(), ..opts }));
+        Ok(output)
+    }
+
+    fn run(exe : &Path) -> String{
+        String::from_utf8(Command::new(exe).output().unwrap().stdout).unwrap()
+    }
+
+    #[test]
+    fn test_link_with_cc(){
+        let tmp = TempDir::new().unwrap();
+        let exe = build("print(42)", LinkOptions::default(), &tmp).unwrap();
+        assert_eq!(run(&exe), "42\n");
+    }
+
+    #[test]
+    fn test_link_with_ld(){
+        let tmp = TempDir::new().unwrap();
+        let exe = build("print(\"hi\")", LinkOptions { linker : Linker::Ld, ..LinkOptions::default() }, &tmp).unwrap();
+        assert_eq!(run(&exe), "hi");
+    }
+
+    #[test]
+    fn test_ld_links_crtbegin_and_crtend(){
+        let machine = TargetMachine::new(&TargetOptions::default()).unwrap();
+        let cmd = linker_command(&machine, &LinkOptions { linker : Linker::Ld, ..LinkOptions::default() }, Path::new("main.o")).unwrap();
+        let args = format!("{:?}", cmd);
+        assert!(args.contains("crtbegin.o"), "{}", args);
+        assert!(args.contains("crtend.o"), "{}", args);
+    }
+
+    #[test]
+    fn test_emit_object_only(){
+        let tmp = TempDir::new().unwrap();
+        let obj = build("print(1)", LinkOptions { linker : Linker::None, ..LinkOptions::default() }, &tmp).unwrap();
+        assert!(obj.is_file());
+        //only the object file was written
+        assert_eq!(fs::read_dir(&tmp.path).unwrap().count(), 1);
+    }
 
-pub fn link(ctxt: &Context){
-    unsafe {
-        let target_ref = LLVMGetFirstTarget();
-        let target_mc = LLVMCreateTargetMachine(target_ref, 
-                                                LLVMGetDefaultTargetTriple(),
-                                                c_str_ptr!("i386"),
-                                                c_str_ptr!(""),
-                                                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
-                                                LLVMRelocMode::LLVMRelocDefault,
-                                                LLVMCodeModel::LLVMCodeModelDefault );
-        assert!(target_mc != ptr::null_mut());
-        LLVMTargetMachineEmitToFile(target_mc, 
-                                    ctxt.module,
-                                    c_str_mut_ptr!("tmp.o"),
-                                    LLVMCodeGenFileType::LLVMObjectFile,
-                                    c_str_mut_ptr!("") as *mut *mut libc::c_char);
-
-        let out = Command::new("gcc")
-            .arg("tmp.o")
-            .arg("-o")
-            .arg("a.out")
-            .output()
-            .unwrap_or_else(|e|{
-                panic!("failed to compile - {}", e);
-            });
-        println!("{}", String::from_utf8_lossy(&out.stdout));
-        println!("{}", String::from_utf8_lossy(&out.stderr));
+    #[test]
+    fn test_linker_failure_is_reported(){
+        let tmp = TempDir::new().unwrap();
+        let opts = LinkOptions { libs : vec![String::from("charon_no_such_lib")], ..LinkOptions::default() };
+        let err = build("print(1)", opts, &tmp).err().unwrap();
+        assert!(err.starts_with("linking with"));
+        assert!(err.contains("charon_no_such_lib"));
     }
 }
@@ -0,0 +1,515 @@
+extern crate llvm_sys as llvm;
+extern crate libc;
+use std::ffi;
+use std::ptr;
+use std::collections::HashMap;
+
+use self::llvm::prelude::{LLVMBasicBlockRef, LLVMBuilderRef, LLVMTypeRef, LLVMValueRef};
+use self::llvm::core::*;
+use self::llvm::{LLVMIntPredicate, LLVMLinkage};
+use ir::tree::{self, Program, Proc, Stm, Exp, Ty, Temp, Label, BinOp, RelOp};
+use base::Context;
+
+//turns the tree IR into an llvm module. Temps become allocas that mem2reg
+//promotes later, labels become basic blocks and the runtime library is
+//defined in the module on top of libc
+struct Lowering<'c, 'a : 'c>{
+    ctxt : &'c Context<'a>,
+    functions : HashMap<String, LLVMValueRef>,
+    strings : HashMap<Label, LLVMValueRef>,
+    //per function
+    function : LLVMValueRef,
+    entry_builder : LLVMBuilderRef,
+    temps : HashMap<usize, LLVMValueRef>,
+    blocks : HashMap<Label, LLVMBasicBlockRef>
+}
+
+impl<'c, 'a> Lowering<'c, 'a>{
+    fn i8_ty(&self) -> LLVMTypeRef{
+        unsafe { LLVMInt8TypeInContext(self.ctxt.context) }
+    }
+
+    fn i32_ty(&self) -> LLVMTypeRef{
+        unsafe { LLVMInt32TypeInContext(self.ctxt.context) }
+    }
+
+    //size_t; the runtime assumes a 64 bit target
+    fn size_ty(&self) -> LLVMTypeRef{
+        unsafe { LLVMInt64TypeInContext(self.ctxt.context) }
+    }
+
+    fn ptr_ty(&self) -> LLVMTypeRef{
+        unsafe { LLVMPointerType(self.i8_ty(), 0) }
+    }
+
+    fn llvm_ty(&self, ty : Ty) -> LLVMTypeRef{
+        match ty{
+            Ty::I32 => self.i32_ty(),
+            Ty::Ptr => self.ptr_ty(),
+            Ty::Void => unsafe { LLVMVoidTypeInContext(self.ctxt.context) }
+        }
+    }
+
+    fn const_i32(&self, n : i32) -> LLVMValueRef{
+        unsafe { LLVMConstInt(self.i32_ty(), n as u64, 1) }
+    }
+
+    fn add_function(&mut self, name : &str, params : &[LLVMTypeRef], ret : LLVMTypeRef, variadic : bool) -> LLVMValueRef{
+        unsafe{
+            let mut params = params.to_vec();
+            let ty = LLVMFunctionType(ret, params.as_mut_ptr(), params.len() as u32, variadic as i32);
+            let f = LLVMAddFunction(self.ctxt.module, c_str_ptr!(name), ty);
+            self.functions.insert(String::from(name), f);
+            f
+        }
+    }
+
+    fn call(&self, name : &str, args : &mut [LLVMValueRef]) -> LLVMValueRef{
+        unsafe{
+            let f = self.functions[name];
+            let void = LLVMGetReturnType(LLVMGlobalGetValueType(f)) == LLVMVoidTypeInContext(self.ctxt.context);
+            let name = if void {""} else {"calltmp"};
+            LLVMBuildCall2(self.ctxt.builder, LLVMGlobalGetValueType(f), f, args.as_mut_ptr(), args.len() as u32, c_str_ptr!(name))
+        }
+    }
+
+    fn declare_libc(&mut self){
+        let (i8p, i32_ty, size) = (self.ptr_ty(), self.i32_ty(), self.size_ty());
+        let void = self.llvm_ty(Ty::Void);
+        self.add_function("printf", &[i8p], i32_ty, true);
+        self.add_function("dprintf", &[i32_ty, i8p], i32_ty, true);
+        self.add_function("fflush", &[i8p], i32_ty, false);
+        self.add_function("getchar", &[], i32_ty, false);
+        self.add_function("calloc", &[size, size], i8p, false);
+        self.add_function("strlen", &[i8p], size, false);
+        self.add_function("memcpy", &[i8p, i8p, size], i8p, false);
+        self.add_function("strcmp", &[i8p, i8p], i32_ty, false);
+        self.add_function("exit", &[i32_ty], void, false);
+    }
+
+    //the functions of tree::RUNTIME. They are internal so that unused ones
+    //are dropped and none of them clashes with a symbol of the c library
+    fn define_runtime(&mut self){
+        //declared first as they call each other
+        for &(name, params, ret) in tree::RUNTIME.iter(){
+            let param_tys : Vec<LLVMTypeRef> = params.iter().map(|t| self.llvm_ty(*t)).collect();
+            let ret_ty = self.llvm_ty(ret);
+            let f = self.add_function(name, &param_tys, ret_ty, false);
+            unsafe{
+                LLVMSetLinkage(f, LLVMLinkage::LLVMInternalLinkage);
+            }
+        }
+        for &(name, _, _) in tree::RUNTIME.iter(){
+            let f = self.functions[name];
+            unsafe{
+                let bb = LLVMAppendBasicBlockInContext(self.ctxt.context, f, c_str_ptr!("entry"));
+                LLVMPositionBuilderAtEnd(self.ctxt.builder, bb);
+                let p = |i| LLVMGetParam(f, i);
+                let b = self.ctxt.builder;
+                let ret = match name{
+                    "tiger_print" => {
+                        let fmt = LLVMBuildGlobalStringPtr(b, c_str_ptr!("%s"), c_str_ptr!(".str"));
+                        self.call("printf", &mut [fmt, p(0)]);
+                        None
+                    },
+                    "tiger_print_int" => {
+                        let fmt = LLVMBuildGlobalStringPtr(b, c_str_ptr!("%d\n"), c_str_ptr!(".str"));
+                        self.call("printf", &mut [fmt, p(0)]);
+                        None
+                    },
+                    "tiger_flush" => {
+                        self.call("fflush", &mut [LLVMConstPointerNull(self.ptr_ty())]);
+                        None
+                    },
+                    "tiger_getchar" => {
+                        //"" at the end of the input
+                        let c = self.call("getchar", &mut []);
+                        let eof = LLVMBuildICmp(b, LLVMIntPredicate::LLVMIntSLT, c, self.const_i32(0), c_str_ptr!("eof"));
+                        let c = LLVMBuildSelect(b, eof, self.const_i32(0), c, c_str_ptr!("c"));
+                        Some(self.call("tiger_chr", &mut [c]))
+                    },
+                    "tiger_ord" => {
+                        //-1 for the empty string
+                        let c = LLVMBuildZExt(b, LLVMBuildLoad2(b, self.i8_ty(), p(0), c_str_ptr!("c")), self.i32_ty(), c_str_ptr!("ord"));
+                        let empty = LLVMBuildICmp(b, LLVMIntPredicate::LLVMIntEQ, c, self.const_i32(0), c_str_ptr!("empty"));
+                        Some(LLVMBuildSelect(b, empty, self.const_i32(-1), c, c_str_ptr!("ord")))
+                    },
+                    "tiger_chr" => {
+                        let one = LLVMConstInt(self.size_ty(), 1, 0);
+                        let s = self.call("calloc", &mut [LLVMConstInt(self.size_ty(), 2, 0), one]);
+                        LLVMBuildStore(b, LLVMBuildTrunc(b, p(0), self.i8_ty(), c_str_ptr!("c")), s);
+                        Some(s)
+                    },
+                    "tiger_size" => {
+                        let len = self.call("strlen", &mut [p(0)]);
+                        Some(LLVMBuildTrunc(b, len, self.i32_ty(), c_str_ptr!("size")))
+                    },
+                    "tiger_substring" => {
+                        let n = LLVMBuildSExt(b, p(2), self.size_ty(), c_str_ptr!("n"));
+                        let one = LLVMConstInt(self.size_ty(), 1, 0);
+                        let s = self.call("calloc", &mut [LLVMBuildAdd(b, n, one, c_str_ptr!("len")), one]);
+                        let mut first = [p(1)];
+                        let src = LLVMBuildGEP2(b, self.i8_ty(), p(0), first.as_mut_ptr(), 1, c_str_ptr!("src"));
+                        self.call("memcpy", &mut [s, src, n]);
+                        Some(s)
+                    },
+                    "tiger_concat" => {
+                        let la = self.call("strlen", &mut [p(0)]);
+                        let lb = self.call("strlen", &mut [p(1)]);
+                        let one = LLVMConstInt(self.size_ty(), 1, 0);
+                        let len = LLVMBuildAdd(b, LLVMBuildAdd(b, la, lb, c_str_ptr!("len")), one, c_str_ptr!("len"));
+                        let s = self.call("calloc", &mut [len, one]);
+                        self.call("memcpy", &mut [s, p(0), la]);
+                        let mut offset = [la];
+                        let dst = LLVMBuildGEP2(b, self.i8_ty(), s, offset.as_mut_ptr(), 1, c_str_ptr!("dst"));
+                        self.call("memcpy", &mut [dst, p(1), lb]);
+                        Some(s)
+                    },
+                    "tiger_exit" => {
+                        self.call("exit", &mut [p(0)]);
+                        LLVMBuildUnreachable(b);
+                        continue
+                    },
+                    "tiger_alloc" => {
+                        let n = LLVMBuildSExt(b, p(0), self.size_ty(), c_str_ptr!("n"));
+                        Some(self.call("calloc", &mut [n, LLVMConstInt(self.size_ty(), 1, 0)]))
+                    },
+                    "tiger_strcmp" => Some(self.call("strcmp", &mut [p(0), p(1)])),
+                    "tiger_div_by_zero" => {
+                        let fmt = LLVMBuildGlobalStringPtr(b, c_str_ptr!("line %d: division by zero\n"), c_str_ptr!(".str"));
+                        self.call("dprintf", &mut [self.const_i32(2), fmt, p(0)]);
+                        self.call("exit", &mut [self.const_i32(1)]);
+                        LLVMBuildUnreachable(b);
+                        continue
+                    },
+                    _ => unreachable!()
+                };
+                match ret{
+                    Some(v) => LLVMBuildRet(b, v),
+                    None => LLVMBuildRetVoid(b)
+                };
+            }
+        }
+    }
+
+    fn define_strings(&mut self, strings : &[(Label, Vec<u8>)]){
+        for &(ref label, ref s) in strings{
+            unsafe{
+                let value = LLVMConstStringInContext(self.ctxt.context, s.as_ptr() as *const libc::c_char, s.len() as u32, 0);
+                let global = LLVMAddGlobal(self.ctxt.module, LLVMTypeOf(value), c_str_ptr!(&*label.0));
+                LLVMSetInitializer(global, value);
+                LLVMSetGlobalConstant(global, 1);
+                LLVMSetLinkage(global, LLVMLinkage::LLVMPrivateLinkage);
+                self.strings.insert(label.clone(), LLVMConstBitCast(global, self.ptr_ty()));
+            }
+        }
+    }
+
+    fn block(&mut self, label : &Label) -> LLVMBasicBlockRef{
+        if let Some(bb) = self.blocks.get(label){
+            return *bb
+        }
+        //appended to the function once the label is reached, so the
+        //blocks come out in the order of the IR
+        let bb = unsafe { LLVMCreateBasicBlockInContext(self.ctxt.context, c_str_ptr!(&*label.0)) };
+        self.blocks.insert(label.clone(), bb);
+        bb
+    }
+
+    fn terminated(&self) -> bool{
+        unsafe { LLVMGetBasicBlockTerminator(LLVMGetInsertBlock(self.ctxt.builder)) != ptr::null_mut() }
+    }
+
+    //code after a jump or return that no label leads to still needs a block
+    fn ensure_block(&self){
+        if self.terminated(){
+            unsafe{
+                let bb = LLVMAppendBasicBlockInContext(self.ctxt.context, self.function, c_str_ptr!("dead"));
+                LLVMPositionBuilderAtEnd(self.ctxt.builder, bb);
+            }
+        }
+    }
+
+    fn temp(&mut self, t : Temp) -> LLVMValueRef{
+        if let Some(a) = self.temps.get(&t.id){
+            return *a
+        }
+        let a = unsafe { LLVMBuildAlloca(self.entry_builder, self.llvm_ty(t.ty), c_str_ptr!(&*t.to_string())) };
+        self.temps.insert(t.id, a);
+        a
+    }
+
+    //pointer to a value of type `ty` at `addr`
+    fn address(&mut self, ty : Ty, addr : &Exp) -> Result<LLVMValueRef, String>{
+        let addr = try!(self.exp(addr));
+        unsafe{
+            Ok(LLVMBuildBitCast(self.ctxt.builder, addr, LLVMPointerType(self.llvm_ty(ty), 0), c_str_ptr!("addr")))
+        }
+    }
+
+    fn exp(&mut self, e : &Exp) -> Result<LLVMValueRef, String>{
+        let b = self.ctxt.builder;
+        unsafe{
+            match *e{
+                Exp::Const(n) => Ok(self.const_i32(n)),
+                Exp::Nil => Ok(LLVMConstPointerNull(self.ptr_ty())),
+                Exp::Name(ref l) => self.strings.get(l).cloned().ok_or(format!("unknown string {}", l)),
+                Exp::Temp(t) => {
+                    let a = self.temp(t);
+                    Ok(LLVMBuildLoad2(b, self.llvm_ty(t.ty), a, c_str_ptr!(&*t.to_string())))
+                },
+                Exp::BinOp(op, ref lhs, ref rhs) => {
+                    let l = try!(self.exp(lhs));
+                    let r = try!(self.exp(rhs));
+                    if lhs.ty() == Ty::Ptr{
+                        let mut offset = [r];
+                        return Ok(LLVMBuildGEP2(b, self.i8_ty(), l, offset.as_mut_ptr(), 1, c_str_ptr!("offset")))
+                    }
+                    Ok(match op{
+                        BinOp::Plus => LLVMBuildAdd(b, l, r, c_str_ptr!("add_tmp")),
+                        BinOp::Minus => LLVMBuildSub(b, l, r, c_str_ptr!("sub_tmp")),
+                        BinOp::Mul => LLVMBuildMul(b, l, r, c_str_ptr!("mul_tmp")),
+                        BinOp::Div => LLVMBuildSDiv(b, l, r, c_str_ptr!("div_tmp"))
+                    })
+                },
+                Exp::Mem(ty, ref addr) => {
+                    let p = try!(self.address(ty, addr));
+                    Ok(LLVMBuildLoad2(b, self.llvm_ty(ty), p, c_str_ptr!("load")))
+                },
+                Exp::Call(ref l, ref args, _) => {
+                    let mut values = Vec::new();
+                    for a in args{
+                        values.push(try!(self.exp(a)));
+                    }
+                    if !self.functions.contains_key(&l.0){
+                        return Err(format!("call to unknown function {}", l))
+                    }
+                    Ok(self.call(&l.0, &mut values))
+                },
+                Exp::ESeq(ref s, ref e) => {
+                    try!(self.stm(s));
+                    self.ensure_block();
+                    self.exp(e)
+                }
+            }
+        }
+    }
+
+    fn stm(&mut self, s : &Stm) -> Result<(), String>{
+        let b = self.ctxt.builder;
+        if let Stm::Label(ref l) = *s{
+            let bb = self.block(l);
+            unsafe{
+                if !self.terminated(){
+                    LLVMBuildBr(b, bb);
+                }
+                LLVMAppendExistingBasicBlock(self.function, bb);
+                LLVMPositionBuilderAtEnd(b, bb);
+            }
+            return Ok(())
+        }
+        if let Stm::Seq(ref stms) = *s{
+            for s in stms{
+                try!(self.stm(s));
+            }
+            return Ok(())
+        }
+        self.ensure_block();
+        unsafe{
+            match *s{
+                Stm::Move(Exp::Temp(t), ref src) => {
+                    let v = try!(self.exp(src));
+                    let a = self.temp(t);
+                    LLVMBuildStore(b, v, a);
+                },
+                Stm::Move(Exp::Mem(ty, ref addr), ref src) => {
+                    let p = try!(self.address(ty, addr));
+                    let v = try!(self.exp(src));
+                    LLVMBuildStore(b, v, p);
+                },
+                Stm::Move(ref dst, _) => return Err(format!("can't move into {}", dst)),
+                Stm::Exp(ref e) => { try!(self.exp(e)); },
+                Stm::Jump(ref l) => {
+                    let bb = self.block(l);
+                    LLVMBuildBr(b, bb);
+                },
+                Stm::CJump(op, ref lhs, ref rhs, ref t, ref f) => {
+                    let l = try!(self.exp(lhs));
+                    let r = try!(self.exp(rhs));
+                    let pred = match op{
+                        RelOp::Eq => LLVMIntPredicate::LLVMIntEQ,
+                        RelOp::Ne => LLVMIntPredicate::LLVMIntNE,
+                        RelOp::Lt => LLVMIntPredicate::LLVMIntSLT,
+                        RelOp::Le => LLVMIntPredicate::LLVMIntSLE,
+                        RelOp::Gt => LLVMIntPredicate::LLVMIntSGT,
+                        RelOp::Ge => LLVMIntPredicate::LLVMIntSGE
+                    };
+                    let cond = LLVMBuildICmp(b, pred, l, r, c_str_ptr!("cond"));
+                    let (t, f) = (self.block(t), self.block(f));
+                    LLVMBuildCondBr(b, cond, t, f);
+                },
+                Stm::Return(Some(ref e)) => {
+                    let v = try!(self.exp(e));
+                    LLVMBuildRet(b, v);
+                },
+                Stm::Return(None) => { LLVMBuildRetVoid(b); },
+                Stm::Label(_) | Stm::Seq(_) => unreachable!()
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_proc(&mut self, p : &Proc){
+        let params : Vec<LLVMTypeRef> = p.params.iter().map(|t| self.llvm_ty(t.ty)).collect();
+        let ret = self.llvm_ty(p.ret);
+        self.add_function(&p.name.0, &params, ret, false);
+    }
+
+    fn define_proc(&mut self, p : &Proc) -> Result<(), String>{
+        self.function = self.functions[&p.name.0];
+        self.temps.clear();
+        self.blocks.clear();
+        unsafe{
+            //the entry block only holds the allocas of the temps; it branches
+            //to the code once the whole body has been lowered
+            let entry = LLVMAppendBasicBlockInContext(self.ctxt.context, self.function, c_str_ptr!("entry"));
+            LLVMPositionBuilderAtEnd(self.entry_builder, entry);
+            let start = LLVMAppendBasicBlockInContext(self.ctxt.context, self.function, c_str_ptr!("start"));
+            LLVMPositionBuilderAtEnd(self.ctxt.builder, start);
+            for (i, t) in p.params.iter().enumerate(){
+                let a = self.temp(*t);
+                LLVMBuildStore(self.ctxt.builder, LLVMGetParam(self.function, i as u32), a);
+            }
+            for s in p.body.iter(){
+                try!(self.stm(s));
+            }
+            if !self.terminated(){
+                if p.ret == Ty::Void{
+                    LLVMBuildRetVoid(self.ctxt.builder);
+                }
+                else{
+                    LLVMBuildUnreachable(self.ctxt.builder);
+                }
+            }
+            LLVMBuildBr(self.entry_builder, start);
+        }
+        Ok(())
+    }
+}
+
+//builds an llvm module out of the IR
+pub fn lower(prog : &Program) -> Result<Context<'static>, String>{
+    let ctxt = Context::new("main_module");
+    let result = {
+        let mut l = Lowering {
+            ctxt : &ctxt,
+            functions : HashMap::new(),
+            strings : HashMap::new(),
+            function : ptr::null_mut(),
+            entry_builder : unsafe { LLVMCreateBuilderInContext(ctxt.context) },
+            temps : HashMap::new(),
+            blocks : HashMap::new()
+        };
+        l.declare_libc();
+        l.define_runtime();
+        l.define_strings(&prog.strings);
+        for p in prog.procs.iter(){
+            l.declare_proc(p);
+        }
+        let mut result = Ok(());
+        for p in prog.procs.iter(){
+            result = l.define_proc(p);
+            if result.is_err(){
+                break
+            }
+        }
+        unsafe{
+            LLVMDisposeBuilder(l.entry_builder);
+        }
+        result
+    };
+    try!(result);
+    Ok(ctxt)
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use ir::translate::translate;
+    use ir::opt::{Pass, ALL_PASSES};
+    use ir::opt::optimize as optimize_ir;
+    use jit::run_captured;
+    use opt::{optimize, OptLevel};
+    use super::*;
+
+    fn run_src(src : &str, level : OptLevel) -> (i32, String){
+        run_optimized(src, &[], level)
+    }
+
+    //runs the IR passes before lowering
+    fn run_optimized(src : &str, passes : &[Pass], level : OptLevel) -> (i32, String){
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        optimize_ir(&mut prog, passes, None).unwrap();
+        let ctxt = lower(&prog).unwrap();
+        ctxt.verify().unwrap();
+        optimize(&ctxt, None, level, None).unwrap();
+        run_captured(&ctxt).unwrap()
+    }
+
+    #[test]
+    fn test_lower_print(){
+        assert_eq!(run_src("(print(\"sum: \"); print(1+2))", OptLevel::O0), (0, String::from("sum: 3\n")));
+    }
+
+    #[test]
+    fn test_lower_function_call(){
+        let src = "let function add(a:int, b:int) : int = a + b in print(add(40, 2)) end";
+        assert_eq!(run_src(src, OptLevel::O0).1, "42\n");
+        assert_eq!(run_src(src, OptLevel::O2).1, "42\n");
+    }
+
+    #[test]
+    fn test_lower_for_loop(){
+        assert_eq!(run_src("for i := 1 to 3 do print(i)", OptLevel::O0).1, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn test_lower_ir_passes(){
+        let src = "let var a : int := 0 in let var b : int := 2 in for i := 1 to 4 do (a := a + i; b := a + 1; print(b)) end end end";
+        assert_eq!(run_src(src, OptLevel::O0).1, "2\n4\n7\n11\n");
+        assert_eq!(run_optimized(src, ALL_PASSES, OptLevel::O0).1, "2\n4\n7\n11\n");
+        for &pass in ALL_PASSES{
+            assert_eq!(run_optimized(src, &[pass], OptLevel::O0).1, "2\n4\n7\n11\n");
+        }
+    }
+
+    #[test]
+    fn test_lower_array(){
+        let src = "let var a : array := array of int[3] of 7 in (a[1] := 5; print(a[1]); print(a[2])) end";
+        assert_eq!(run_src(src, OptLevel::O0).1, "5\n7\n");
+    }
+
+    #[test]
+    fn test_lower_strings(){
+        let src = "(print(chr(100)); print(size(\"abc\")); print(ord(\"b\")))";
+        assert_eq!(run_src(src, OptLevel::O1).1, "d3\n98\n");
+    }
+
+    #[test]
+    fn test_lower_division_checks(){
+        assert_eq!(run_src("(print(1); print(7 / (1 - 1)))", OptLevel::O0), (1, String::from("1\n")));
+        let src = "let var min : int := 0 - 2147483647 - 1 in (print(min / (0 - 1)); print(min mod (0 - 1))) end";
+        assert_eq!(run_src(src, OptLevel::O2), (0, String::from("-2147483648\n0\n")));
+    }
+
+    #[test]
+    fn test_lower_exit_code(){
+        assert_eq!(run_src("exit(4)", OptLevel::O0).0, 4);
+    }
+}
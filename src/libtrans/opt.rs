@@ -0,0 +1,129 @@
+extern crate llvm_sys as llvm;
+use std::ptr;
+use std::ffi;
+
+use self::llvm::error::*;
+use self::llvm::target_machine::LLVMCodeGenOptLevel;
+use self::llvm::transforms::pass_builder::*;
+use base::Context;
+use target::TargetMachine;
+pub use session::config::OptLevel;
+
+//the pass pipeline run over the module at `level`. -O1 promotes the allocas
+//to registers and cleans up with instcombine/simplifycfg; -O2 adds inlining,
+//gvn and the loop passes; -O3 is more aggressive about both
+fn pipeline(level : OptLevel) -> Option<&'static str>{
+    match level{
+        OptLevel::O0 => None,
+        OptLevel::O1 => Some("default<O1>"),
+        OptLevel::O2 => Some("default<O2>"),
+        OptLevel::O3 => Some("default<O3>")
+    }
+}
+
+//the level the code generator runs at, rising with the IR level
+pub fn codegen_level(level : OptLevel) -> LLVMCodeGenOptLevel{
+    match level{
+        OptLevel::O0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+        OptLevel::O1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+        OptLevel::O2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+        OptLevel::O3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive
+    }
+}
+
+//runs `passes`, a pipeline in llvm's textual syntax (e.g. "mem2reg,instcombine,gvn"),
+//over the module. The machine, when given, lets the passes query the target
+fn run_passes(ctxt : &Context, machine : Option<&TargetMachine>, passes : &str) -> Result<(), String>{
+    unsafe{
+        let options = LLVMCreatePassBuilderOptions();
+        let err = LLVMRunPasses(ctxt.module,
+                                c_str_ptr!(passes),
+                                machine.map(|m| m.as_raw()).unwrap_or(ptr::null_mut()),
+                                options);
+        LLVMDisposePassBuilderOptions(options);
+        if err != ptr::null_mut(){
+            let msg = LLVMGetErrorMessage(err);
+            let s = ffi::CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeErrorMessage(msg);
+            return Err(format!("invalid pass pipeline '{}' - {}", passes, s))
+        }
+    }
+    Ok(())
+}
+
+//optimizes the module either with a custom list of passes or the pipeline of `level`
+pub fn optimize(ctxt : &Context, machine : Option<&TargetMachine>, level : OptLevel, passes : Option<&str>) -> Result<(), String>{
+    match passes.or(pipeline(level)){
+        Some(p) => run_passes(ctxt, machine, p),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use base::translate;
+    use jit::run_captured;
+    use super::*;
+
+    //`a` is kept in a register, the argument of the built-in `not` in memory
+    const SRC : &'static str = "let var a : int := 3 in (a := a + not(0) + 4; print(a)) end";
+
+    //the optimized IR and what running it prints
+    fn optimized(src : &str, level : OptLevel, passes : Option<&str>) -> Result<(String, String), String>{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let ctxt = translate(&*b_expr).unwrap();
+        try!(optimize(&ctxt, None, level, passes));
+        Ok((ctxt.ir(), run_captured(&ctxt).unwrap().1))
+    }
+
+    #[test]
+    fn test_codegen_levels_are_monotonic(){
+        let levels : Vec<u32> = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3].iter()
+            .map(|l| codegen_level(*l) as u32).collect();
+        assert_eq!(codegen_level(OptLevel::O0) as u32, LLVMCodeGenOptLevel::LLVMCodeGenLevelNone as u32);
+        assert!(levels.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_o0_keeps_allocas(){
+        assert!(optimized(SRC, OptLevel::O0, None).unwrap().0.contains("alloca"));
+    }
+
+    #[test]
+    fn test_o2_promotes_allocas(){
+        let (ir, out) = optimized(SRC, OptLevel::O2, None).unwrap();
+        assert!(!ir.contains("alloca"));
+        assert_eq!(out, "8\n");
+    }
+
+    #[test]
+    fn test_mutual_tail_calls_take_no_stack(){
+        //only self calls become jumps in base; the calls between even and odd
+        //are marked tail, and llvm 15's C API can't make them musttail. The
+        //optimizer turns them into jumps
+        let src = "let function even(n:int) : int =\n \
+                   let function odd(m:int) : int = if m > 0 then even(m - 1) else 0\n \
+                   in if n > 0 then odd(n - 1) else 1 end\n \
+                   in (print(even(1000000)); print(even(1000001))) end";
+        assert_eq!(optimized(src, OptLevel::O2, None).unwrap().1, "1\n0\n");
+    }
+
+    #[test]
+    fn test_custom_passes(){
+        let (ir, out) = optimized(SRC, OptLevel::O0, Some("mem2reg")).unwrap();
+        assert!(!ir.contains("alloca"));
+        assert_eq!(out, "8\n");
+    }
+
+    #[test]
+    fn test_invalid_pass(){
+        let err = optimized(SRC, OptLevel::O0, Some("no-such-pass")).err().unwrap();
+        assert!(err.starts_with("invalid pass pipeline 'no-such-pass'"));
+    }
+}
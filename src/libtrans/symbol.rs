@@ -1,5 +1,5 @@
 extern crate llvm_sys as llvm;
-use self::llvm::prelude::{LLVMValueRef};
+use self::llvm::prelude::{LLVMValueRef, LLVMMetadataRef};
 use syntax::ast::TType;
 
 #[derive(Clone, Debug)]
@@ -17,17 +17,31 @@ pub trait Symbol{
 
 pub trait VarSymbol : Symbol{
     fn var_type(&self) -> &TType;
-    fn alloca_ref(&self) ->  LLVMValueRef;
+    fn storage(&self) -> Storage;
 }
 
 pub trait FunctionSymbol : Symbol{
     fn value_ref(&self) -> LLVMValueRef;
 }
 
+pub trait TypeSymbol : Symbol{
+    fn ty(&self) -> &TType;
+}
+
+//where the value of a variable is. The variables nested functions use live
+//in a stack slot; the others are the SSA value they were last given
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage{
+    Alloca(LLVMValueRef),
+    Value(LLVMValueRef)
+}
+
 pub struct Var{
     id : String,
     var_type : TType,
-    alloca_ref : LLVMValueRef
+    storage : Storage,
+    //the variable in the debug info, for the SSA values that describe it
+    debug : Option<LLVMMetadataRef>
 }
 
 impl Var{
@@ -35,9 +49,36 @@ impl Var{
         Var {
             id : id,
             var_type : ty,
-            alloca_ref : alloca_ref
+            storage : Storage::Alloca(alloca_ref),
+            debug : None
         } 
     }
+
+    pub fn ssa(id : String, ty : TType, value : LLVMValueRef, debug : Option<LLVMMetadataRef>) -> Self{
+        Var {
+            id : id,
+            var_type : ty,
+            storage : Storage::Value(value),
+            debug : debug
+        }
+    }
+
+    //gives an SSA variable its new value
+    pub fn set_value(&mut self, value : LLVMValueRef){
+        debug_assert!(self.is_ssa(), "'{}' lives in a stack slot", self.id);
+        self.storage = Storage::Value(value);
+    }
+
+    pub fn is_ssa(&self) -> bool{
+        match self.storage{
+            Storage::Value(_) => true,
+            Storage::Alloca(_) => false
+        }
+    }
+
+    pub fn debug(&self) -> Option<LLVMMetadataRef>{
+        self.debug
+    }
 }
 
 impl Symbol for Var{
@@ -54,8 +95,8 @@ impl VarSymbol for Var{
        &self.var_type
    }
 
-   fn alloca_ref(&self) -> LLVMValueRef{
-       self.alloca_ref
+   fn storage(&self) -> Storage{
+       self.storage
    }
 }
 
@@ -88,3 +129,34 @@ impl FunctionSymbol for Function{
         self.value_ref
     }
 }
+
+//a type declaration; `ty` is what the name stands for
+pub struct Type{
+    id : String,
+    ty : TType
+}
+
+impl Type{
+    pub fn new(id : String, ty : TType) -> Self{
+        Type{
+            id : id,
+            ty : ty
+        }
+    }
+}
+
+impl Symbol for Type{
+    fn id(&self) -> String{
+        self.id.clone()
+    }
+
+    fn kind() -> SymbolKind{
+        SymbolKind::Type
+    }
+}
+
+impl TypeSymbol for Type{
+    fn ty(&self) -> &TType{
+        &self.ty
+    }
+}
@@ -0,0 +1,214 @@
+extern crate llvm_sys as llvm;
+extern crate libc;
+use std::ptr;
+use std::ffi;
+
+use self::llvm::LLVMModuleFlagBehavior;
+use self::llvm::core::*;
+use self::llvm::target::*;
+use self::llvm::target_machine::*;
+use base::Context;
+use opt::codegen_level;
+pub use session::config::{RelocModel, CodeModel, TargetOptions};
+
+//the llvm relocation mode of `model`
+fn reloc_mode(model : RelocModel) -> LLVMRelocMode{
+    match model{
+        RelocModel::Default => LLVMRelocMode::LLVMRelocDefault,
+        RelocModel::Static => LLVMRelocMode::LLVMRelocStatic,
+        //pie is pic code that also knows it won't be interposed; see TargetMachine::configure
+        RelocModel::Pic | RelocModel::Pie => LLVMRelocMode::LLVMRelocPIC,
+        RelocModel::DynamicNoPic => LLVMRelocMode::LLVMRelocDynamicNoPic
+    }
+}
+
+fn code_model(model : CodeModel) -> LLVMCodeModel{
+    match model{
+        CodeModel::Default => LLVMCodeModel::LLVMCodeModelDefault,
+        CodeModel::Small => LLVMCodeModel::LLVMCodeModelSmall,
+        CodeModel::Kernel => LLVMCodeModel::LLVMCodeModelKernel,
+        CodeModel::Medium => LLVMCodeModel::LLVMCodeModelMedium,
+        CodeModel::Large => LLVMCodeModel::LLVMCodeModelLarge
+    }
+}
+
+pub struct TargetMachine{
+    machine : LLVMTargetMachineRef,
+    pub triple : String,
+    pub reloc_model : RelocModel
+}
+
+fn take_message(msg : *mut libc::c_char) -> String{
+    unsafe{
+        let s = ffi::CStr::from_ptr(msg).to_string_lossy().into_owned();
+        LLVMDisposeMessage(msg);
+        s
+    }
+}
+
+fn initialize_targets(){
+    unsafe{
+        LLVM_InitializeAllTargetInfos();
+        LLVM_InitializeAllTargets();
+        LLVM_InitializeAllTargetMCs();
+        LLVM_InitializeAllAsmPrinters();
+    }
+}
+
+//names of the targets llvm was built with, e.g. "x86-64"
+pub fn available_targets() -> Vec<String>{
+    initialize_targets();
+    let mut names = Vec::new();
+    unsafe{
+        let mut target = LLVMGetFirstTarget();
+        while target != ptr::null_mut(){
+            names.push(ffi::CStr::from_ptr(LLVMGetTargetName(target)).to_string_lossy().into_owned());
+            target = LLVMGetNextTarget(target);
+        }
+    }
+    names
+}
+
+impl TargetMachine{
+    pub fn new(opts : &TargetOptions) -> Result<TargetMachine, String>{
+        initialize_targets();
+        unsafe{
+            let triple = match opts.triple{
+                Some(ref t) => take_message(LLVMNormalizeTargetTriple(c_str_ptr!(&**t))),
+                None => take_message(LLVMGetDefaultTargetTriple())
+            };
+            let mut target = ptr::null_mut();
+            let mut err : *mut libc::c_char = ptr::null_mut();
+            if LLVMGetTargetFromTriple(c_str_ptr!(&*triple), &mut target, &mut err) != 0{
+                return Err(format!("unknown target '{}' ({}). Available targets: {}",
+                                   triple, take_message(err), available_targets().join(", ")))
+            }
+
+            let (cpu, features) = if opts.cpu == "native"{
+                let mut features = take_message(LLVMGetHostCPUFeatures());
+                if !opts.features.is_empty(){
+                    features = format!("{},{}", features, opts.features);
+                }
+                (take_message(LLVMGetHostCPUName()), features)
+            }
+            else{
+                (opts.cpu.clone(), opts.features.clone())
+            };
+
+            let machine = LLVMCreateTargetMachine(target,
+                                                  c_str_ptr!(&*triple),
+                                                  c_str_ptr!(&*cpu),
+                                                  c_str_ptr!(&*features),
+                                                  codegen_level(opts.opt_level),
+                                                  reloc_mode(opts.reloc_model),
+                                                  code_model(opts.code_model));
+            if machine == ptr::null_mut(){
+                return Err(format!("could not create a target machine for '{}' with cpu '{}'", triple, cpu))
+            }
+            Ok(TargetMachine {
+                machine : machine,
+                triple : triple,
+                reloc_model : opts.reloc_model
+            })
+        }
+    }
+
+    pub fn as_raw(&self) -> LLVMTargetMachineRef{
+        self.machine
+    }
+
+    //the data layout string of the target, e.g. "e-m:e-p:32:32-..."
+    pub fn data_layout(&self) -> String{
+        unsafe{
+            let layout = LLVMCreateTargetDataLayout(self.machine);
+            let s = take_message(LLVMCopyStringRepOfTargetData(layout));
+            LLVMDisposeTargetData(layout);
+            s
+        }
+    }
+
+    //stamps the module with the triple and data layout of this machine
+    pub fn configure(&self, ctxt : &Context){
+        unsafe{
+            LLVMSetTarget(ctxt.module, c_str_ptr!(&*self.triple));
+            let layout = LLVMCreateTargetDataLayout(self.machine);
+            LLVMSetModuleDataLayout(ctxt.module, layout);
+            LLVMDisposeTargetData(layout);
+
+            let level = match self.reloc_model{
+                RelocModel::Pic | RelocModel::Pie => 2,
+                _ => return
+            };
+            let i32_ty = LLVMInt32TypeInContext(LLVMGetModuleContext(ctxt.module));
+            let value = LLVMValueAsMetadata(LLVMConstInt(i32_ty, level, 0));
+            LLVMAddModuleFlag(ctxt.module, LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorError,
+                              c_str_ptr!("PIC Level"), 9, value);
+            if self.reloc_model == RelocModel::Pie{
+                LLVMAddModuleFlag(ctxt.module, LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorError,
+                                  c_str_ptr!("PIE Level"), 9, value);
+            }
+        }
+    }
+
+    pub fn emit_object(&self, ctxt : &Context, path : &str) -> Result<(), String>{
+        unsafe{
+            let mut err : *mut libc::c_char = ptr::null_mut();
+            if LLVMTargetMachineEmitToFile(self.machine,
+                                           ctxt.module,
+                                           c_str_mut_ptr!(path),
+                                           LLVMCodeGenFileType::LLVMObjectFile,
+                                           &mut err) != 0{
+                return Err(format!("could not write {} - {}", path, take_message(err)))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TargetMachine{
+    fn drop(&mut self){
+        unsafe{
+            LLVMDisposeTargetMachine(self.machine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::parse::parser::{Parser};
+    use base::translate;
+    use super::*;
+
+    #[test]
+    fn test_unknown_triple(){
+        let opts = TargetOptions { triple : Some(String::from("foo-bar-baz")), ..TargetOptions::default() };
+        assert!(TargetMachine::new(&opts).err().unwrap().starts_with("unknown target"));
+    }
+
+    #[test]
+    fn test_configure_sets_triple_and_layout(){
+        let mut p = Parser::new(String::from("1+1"));
+        p.start_lexer();
+        let (_, b_expr) = p.expr().unwrap();
+        let ctxt = translate(&*b_expr).unwrap();
+        let machine = TargetMachine::new(&TargetOptions::default()).unwrap();
+        machine.configure(&ctxt);
+        let ir = ctxt.ir();
+        assert!(ir.contains(&format!("target triple = \"{}\"", machine.triple)));
+        assert!(ir.contains("target datalayout = "));
+        assert!(!ir.contains("PIC Level"));
+    }
+
+    #[test]
+    fn test_configure_pic(){
+        let mut p = Parser::new(String::from("1+1"));
+        p.start_lexer();
+        let (_, b_expr) = p.expr().unwrap();
+        let ctxt = translate(&*b_expr).unwrap();
+        let opts = TargetOptions { reloc_model : RelocModel::Pic, ..TargetOptions::default() };
+        TargetMachine::new(&opts).unwrap().configure(&ctxt);
+        let ir = ctxt.ir();
+        assert!(ir.contains("PIC Level"));
+        assert!(!ir.contains("PIE Level"));
+    }
+}
@@ -0,0 +1,201 @@
+//! Lowering of the canonical trees to wasm instructions.
+//!
+//! Wasm has no goto, so the basic blocks of a function become the cases of
+//! a dispatch loop: a `loop` around one `block` per basic block, with a
+//! `br_table` on the `pc` local in the innermost one. The code of basic
+//! block k follows the `end` of block k and falls through into the code of
+//! block k+1. Any other jump sets `pc` and branches back to the loop.
+//!
+//! Temps become locals. Every value is an i32 and so is every slot of the
+//! memory, even though the IR gives them WORD_SIZE bytes.
+
+use std::collections::HashMap;
+use ir::tree::*;
+use ir::canon;
+use module::{Module, Instr};
+
+struct Codegen<'m>{
+    module : &'m Module,
+    //addresses of the string literals
+    strings : &'m HashMap<Label, u32>,
+    locals : HashMap<usize, u32>,
+    next_local : u32,
+    //the local holding the number of the next basic block
+    pc : u32,
+    //the index of the basic block a label starts
+    blocks : HashMap<Label, u32>,
+    code : Vec<Instr>
+}
+
+//the address of a memory access split into an expression and a constant
+//offset for the instruction
+fn address(addr : &Exp) -> (&Exp, u32){
+    match *addr{
+        Exp::BinOp(BinOp::Plus, ref base, ref offset) => match **offset{
+            Exp::Const(c) if c >= 0 => (base, c as u32),
+            _ => (addr, 0)
+        },
+        _ => (addr, 0)
+    }
+}
+
+fn relop(op : RelOp) -> Instr{
+    match op{
+        RelOp::Eq => Instr::I32Eq,
+        RelOp::Ne => Instr::I32Ne,
+        RelOp::Lt => Instr::I32LtS,
+        RelOp::Le => Instr::I32LeS,
+        RelOp::Gt => Instr::I32GtS,
+        RelOp::Ge => Instr::I32GeS
+    }
+}
+
+impl<'m> Codegen<'m>{
+    fn emit(&mut self, i : Instr){
+        self.code.push(i);
+    }
+
+    fn local(&mut self, t : Temp) -> u32{
+        if let Some(&l) = self.locals.get(&t.id){
+            return l
+        }
+        self.locals.insert(t.id, self.next_local);
+        self.next_local += 1;
+        self.next_local - 1
+    }
+
+    fn exp(&mut self, e : &Exp){
+        match *e{
+            Exp::Const(n) => self.emit(Instr::I32Const(n)),
+            Exp::Nil => self.emit(Instr::I32Const(0)),
+            Exp::Name(ref l) => {
+                let addr = self.strings[l];
+                self.emit(Instr::I32Const(addr as i32));
+            },
+            Exp::Temp(t) => {
+                let l = self.local(t);
+                self.emit(Instr::LocalGet(l));
+            },
+            Exp::BinOp(op, ref lhs, ref rhs) => {
+                self.exp(lhs);
+                self.exp(rhs);
+                self.emit(match op{
+                    BinOp::Plus => Instr::I32Add,
+                    BinOp::Minus => Instr::I32Sub,
+                    BinOp::Mul => Instr::I32Mul,
+                    BinOp::Div => Instr::I32DivS
+                });
+            },
+            Exp::Mem(_, ref addr) => {
+                let (base, offset) = address(addr);
+                self.exp(base);
+                self.emit(Instr::I32Load(offset));
+            },
+            Exp::Call(ref l, ref args, _) => {
+                for a in args.iter(){
+                    self.exp(a);
+                }
+                match self.module.func_index(&l.0){
+                    Some(f) => self.emit(Instr::Call(f)),
+                    None => panic!("call to the unknown function {}", l)
+                }
+            },
+            Exp::ESeq(..) => panic!("{} is not in canonical form", e)
+        }
+    }
+
+    //a jump from the code of basic block `from` that is nested in `ifs` ifs
+    fn jump(&mut self, to : &Label, from : u32, ifs : u32){
+        let to = self.blocks[to];
+        if to == from + 1{
+            return
+        }
+        let n = self.blocks.len() as u32;
+        let pc = self.pc;
+        self.emit(Instr::I32Const(to as i32));
+        self.emit(Instr::LocalSet(pc));
+        self.emit(Instr::Br(n - 1 - from + ifs));
+    }
+
+    fn stm(&mut self, s : &Stm, block : u32){
+        match *s{
+            Stm::Move(Exp::Temp(t), ref src) => {
+                self.exp(src);
+                let l = self.local(t);
+                self.emit(Instr::LocalSet(l));
+            },
+            Stm::Move(Exp::Mem(_, ref addr), ref src) => {
+                let (base, offset) = address(addr);
+                self.exp(base);
+                self.exp(src);
+                self.emit(Instr::I32Store(offset));
+            },
+            Stm::Exp(ref e) => {
+                self.exp(e);
+                if e.ty() != Ty::Void{
+                    self.emit(Instr::Drop);
+                }
+            },
+            Stm::Jump(ref l) => self.jump(l, block, 0),
+            Stm::CJump(op, ref lhs, ref rhs, ref t, ref f) => {
+                self.exp(lhs);
+                self.exp(rhs);
+                //a branch to the next block is left out
+                if self.blocks[f] == block + 1{
+                    self.emit(relop(op));
+                    self.emit(Instr::If);
+                    self.jump(t, block, 1);
+                }
+                else if self.blocks[t] == block + 1{
+                    self.emit(relop(op.negate()));
+                    self.emit(Instr::If);
+                    self.jump(f, block, 1);
+                }
+                else{
+                    self.emit(relop(op));
+                    self.emit(Instr::If);
+                    self.jump(t, block, 1);
+                    self.emit(Instr::Else);
+                    self.jump(f, block, 1);
+                }
+                self.emit(Instr::End);
+            },
+            Stm::Return(ref e) => {
+                if let Some(ref e) = *e{
+                    self.exp(e);
+                }
+                self.emit(Instr::Return);
+            },
+            _ => panic!("{} is not in canonical form", s)
+        }
+    }
+}
+
+//the number of locals after the parameters and the code of a procedure
+pub fn codegen(p : Proc, module : &Module, strings : &HashMap<Label, u32>, gen : &mut Gen) -> (u32, Vec<Instr>){
+    let stms = canon::linearize(p.body, gen);
+    let blocks = canon::basic_blocks(stms, p.ret, gen);
+    let n = blocks.len() as u32;
+    let params = p.params.len() as u32;
+    let mut g = Codegen {
+        module : module,
+        strings : strings,
+        locals : p.params.iter().enumerate().map(|(i, t)| (t.id, i as u32)).collect(),
+        next_local : params + 1,
+        pc : params,
+        blocks : blocks.iter().enumerate().map(|(i, b)| (b.label.clone(), i as u32)).collect(),
+        code : vec![Instr::Loop]
+    };
+    g.code.extend(vec![Instr::Block; n as usize]);
+    g.emit(Instr::LocalGet(params));
+    g.emit(Instr::BrTable((0..n).collect(), n - 1));
+    for (k, b) in blocks.iter().enumerate(){
+        g.emit(Instr::End);
+        for s in b.stms.iter(){
+            g.stm(s, k as u32);
+        }
+    }
+    g.emit(Instr::End);
+    g.emit(Instr::Unreachable);
+    (g.next_local - params, g.code)
+}
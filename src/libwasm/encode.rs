@@ -0,0 +1,267 @@
+//! The binary format of a module, version 1 of the WebAssembly core spec.
+
+use module::*;
+
+pub const MAGIC : &'static [u8] = b"\0asm";
+pub const VERSION : u32 = 1;
+
+const I32 : u8 = 0x7f;
+//the type of blocks without parameters and results
+const EMPTY : u8 = 0x40;
+
+fn uint(out : &mut Vec<u8>, mut n : u32){
+    loop{
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0{
+            out.push(b);
+            return
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn int(out : &mut Vec<u8>, mut n : i32){
+    loop{
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        //done when the rest is all sign bits, including the sign bit of `b`
+        if (n == 0 && b & 0x40 == 0) || (n == -1 && b & 0x40 != 0){
+            out.push(b);
+            return
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn name(out : &mut Vec<u8>, s : &str){
+    uint(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn section(out : &mut Vec<u8>, id : u8, count : usize, contents : Vec<u8>){
+    if count == 0{
+        return
+    }
+    let mut body = Vec::new();
+    uint(&mut body, count as u32);
+    body.extend(contents);
+    out.push(id);
+    uint(out, body.len() as u32);
+    out.extend(body);
+}
+
+//memory instructions take the log2 of the alignment and the offset
+fn memarg(out : &mut Vec<u8>, op : u8, align : u32, offset : u32){
+    out.push(op);
+    uint(out, align);
+    uint(out, offset);
+}
+
+fn instr(out : &mut Vec<u8>, instr : &Instr){
+    let op = match *instr{
+        Instr::Unreachable => 0x00,
+        Instr::Block => return out.extend_from_slice(&[0x02, EMPTY]),
+        Instr::Loop => return out.extend_from_slice(&[0x03, EMPTY]),
+        Instr::If => return out.extend_from_slice(&[0x04, EMPTY]),
+        Instr::Else => 0x05,
+        Instr::End => 0x0b,
+        Instr::Br(n) => {
+            out.push(0x0c);
+            return uint(out, n)
+        },
+        Instr::BrIf(n) => {
+            out.push(0x0d);
+            return uint(out, n)
+        },
+        Instr::BrTable(ref targets, default) => {
+            out.push(0x0e);
+            uint(out, targets.len() as u32);
+            for &t in targets.iter(){
+                uint(out, t);
+            }
+            return uint(out, default)
+        },
+        Instr::Return => 0x0f,
+        Instr::Call(f) => {
+            out.push(0x10);
+            return uint(out, f)
+        },
+        Instr::Drop => 0x1a,
+        Instr::Select => 0x1b,
+        Instr::LocalGet(n) => {
+            out.push(0x20);
+            return uint(out, n)
+        },
+        Instr::LocalSet(n) => {
+            out.push(0x21);
+            return uint(out, n)
+        },
+        Instr::LocalTee(n) => {
+            out.push(0x22);
+            return uint(out, n)
+        },
+        Instr::GlobalGet(n) => {
+            out.push(0x23);
+            return uint(out, n)
+        },
+        Instr::GlobalSet(n) => {
+            out.push(0x24);
+            return uint(out, n)
+        },
+        Instr::I32Load(offset) => return memarg(out, 0x28, 2, offset),
+        Instr::I32Load8U(offset) => return memarg(out, 0x2d, 0, offset),
+        Instr::I32Store(offset) => return memarg(out, 0x36, 2, offset),
+        Instr::I32Store8(offset) => return memarg(out, 0x3a, 0, offset),
+        Instr::MemorySize => return out.extend_from_slice(&[0x3f, 0x00]),
+        Instr::MemoryGrow => return out.extend_from_slice(&[0x40, 0x00]),
+        Instr::I32Const(n) => {
+            out.push(0x41);
+            return int(out, n)
+        },
+        Instr::I32Eqz => 0x45,
+        Instr::I32Eq => 0x46,
+        Instr::I32Ne => 0x47,
+        Instr::I32LtS => 0x48,
+        Instr::I32LtU => 0x49,
+        Instr::I32GtS => 0x4a,
+        Instr::I32GtU => 0x4b,
+        Instr::I32LeS => 0x4c,
+        Instr::I32LeU => 0x4d,
+        Instr::I32GeS => 0x4e,
+        Instr::I32GeU => 0x4f,
+        Instr::I32Add => 0x6a,
+        Instr::I32Sub => 0x6b,
+        Instr::I32Mul => 0x6c,
+        Instr::I32DivS => 0x6d,
+        Instr::I32And => 0x71,
+        Instr::I32Shl => 0x74,
+        Instr::I32ShrU => 0x76
+    };
+    out.push(op);
+}
+
+//i32.const n followed by the end of the constant expression
+fn const_expr(out : &mut Vec<u8>, n : i32){
+    out.push(0x41);
+    int(out, n);
+    out.push(0x0b);
+}
+
+pub fn encode(module : &Module) -> Vec<u8>{
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&[VERSION as u8, 0, 0, 0]);
+    let types = module.types();
+    let type_index = |ty : FuncType| types.iter().position(|&t| t == ty).unwrap() as u32;
+
+    let mut s = Vec::new();
+    for ty in types.iter(){
+        s.push(0x60);
+        uint(&mut s, ty.params);
+        for _ in 0..ty.params{
+            s.push(I32);
+        }
+        if ty.result{
+            s.extend_from_slice(&[1, I32]);
+        }
+        else{
+            s.push(0);
+        }
+    }
+    section(&mut out, 1, types.len(), s);
+
+    let mut s = Vec::new();
+    for i in module.imports.iter(){
+        name(&mut s, &i.module);
+        name(&mut s, &i.name);
+        s.push(0x00);
+        uint(&mut s, type_index(i.ty));
+    }
+    section(&mut out, 2, module.imports.len(), s);
+
+    let mut s = Vec::new();
+    for f in module.functions.iter(){
+        uint(&mut s, type_index(f.ty));
+    }
+    section(&mut out, 3, module.functions.len(), s);
+
+    let mut s = vec![0x00];
+    uint(&mut s, module.memory_pages);
+    section(&mut out, 5, 1, s);
+
+    let mut s = Vec::new();
+    for g in module.globals.iter(){
+        s.extend_from_slice(&[I32, 0x01]);
+        const_expr(&mut s, g.init);
+    }
+    section(&mut out, 6, module.globals.len(), s);
+
+    let mut s = Vec::new();
+    name(&mut s, "memory");
+    s.extend_from_slice(&[0x02, 0x00]);
+    let exports : Vec<u32> = module.functions.iter().filter(|f| f.export)
+        .map(|f| module.func_index(&f.name).unwrap()).collect();
+    for &e in exports.iter(){
+        name(&mut s, module.func_name(e));
+        s.push(0x00);
+        uint(&mut s, e);
+    }
+    section(&mut out, 7, exports.len() + 1, s);
+
+    let mut s = Vec::new();
+    for f in module.functions.iter(){
+        let mut body = Vec::new();
+        if f.locals > 0{
+            body.push(1);
+            uint(&mut body, f.locals);
+            body.push(I32);
+        }
+        else{
+            body.push(0);
+        }
+        for i in f.body.iter(){
+            instr(&mut body, i);
+        }
+        body.push(0x0b);
+        uint(&mut s, body.len() as u32);
+        s.extend(body);
+    }
+    section(&mut out, 10, module.functions.len(), s);
+
+    let mut s = Vec::new();
+    for &(addr, ref bytes) in module.data.iter(){
+        s.push(0x00);
+        const_expr(&mut s, addr as i32);
+        uint(&mut s, bytes.len() as u32);
+        s.extend_from_slice(bytes);
+    }
+    section(&mut out, 11, module.data.len(), s);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128(){
+        let mut out = Vec::new();
+        uint(&mut out, 624485);
+        assert_eq!(out, [0xe5, 0x8e, 0x26]);
+        out.clear();
+        int(&mut out, -123456);
+        assert_eq!(out, [0xc0, 0xbb, 0x78]);
+        out.clear();
+        int(&mut out, 64);
+        int(&mut out, -64);
+        assert_eq!(out, [0xc0, 0x00, 0x40]);
+    }
+
+    #[test]
+    fn test_empty_module(){
+        assert_eq!(encode(&Module::default()),
+                   [0, b'a', b's', b'm', 1, 0, 0, 0,
+                    5, 3, 1, 0, 0,
+                    7, 10, 1, 6, b'm', b'e', b'm', b'o', b'r', b'y', 2, 0]);
+    }
+}
@@ -0,0 +1,78 @@
+//! Runs modules in the wasmi interpreter, with the host functions of
+//! runtime::IMPORTS.
+
+use std::io::{Read, Write};
+use wasmi::{self, Caller, Engine, Extern, Linker, Store};
+use wasmi::core::Trap;
+use runtime::HOST;
+
+struct Host<'a>{
+    out : &'a mut Write,
+    input : &'a mut Read
+}
+
+fn io_error(e : ::std::io::Error) -> Trap{
+    Trap::new(e.to_string())
+}
+
+//the NUL terminated string at `ptr`
+fn string(caller : &Caller<Host>, ptr : i32) -> Result<Vec<u8>, Trap>{
+    let memory = match caller.get_export("memory").and_then(Extern::into_memory){
+        Some(m) => m,
+        None => return Err(Trap::new("the module doesn't export its memory"))
+    };
+    let data = memory.data(caller);
+    match data.get(ptr as u32 as usize..).and_then(|d| d.iter().position(|&b| b == 0).map(|end| &d[..end])){
+        Some(s) => Ok(s.to_vec()),
+        None => Err(Trap::new(format!("no string at address {}", ptr)))
+    }
+}
+
+//parses and validates a module
+pub fn validate(bytes : &[u8]) -> Result<(), String>{
+    wasmi::Module::new(&Engine::default(), bytes).map(|_| ()).map_err(|e| format!("invalid wasm module: {}", e))
+}
+
+//calls the exported main function and returns its result, or the code
+//given to exit
+pub fn run(bytes : &[u8], out : &mut Write, input : &mut Read) -> Result<i32, String>{
+    let engine = Engine::default();
+    let module = try!(wasmi::Module::new(&engine, bytes).map_err(|e| format!("invalid wasm module: {}", e)));
+    let mut store = Store::new(&engine, Host { out : out, input : input });
+    let mut linker = <Linker<Host>>::new(&engine);
+    linker.func_wrap(HOST, "print", |mut caller : Caller<Host>, ptr : i32| -> Result<(), Trap>{
+        let s = try!(string(&caller, ptr));
+        caller.data_mut().out.write_all(&s).map_err(io_error)
+    }).unwrap();
+    linker.func_wrap(HOST, "print_int", |mut caller : Caller<Host>, n : i32| -> Result<(), Trap>{
+        writeln!(caller.data_mut().out, "{}", n).map_err(io_error)
+    }).unwrap();
+    linker.func_wrap(HOST, "flush", |mut caller : Caller<Host>| -> Result<(), Trap>{
+        caller.data_mut().out.flush().map_err(io_error)
+    }).unwrap();
+    linker.func_wrap(HOST, "getchar", |mut caller : Caller<Host>| -> Result<i32, Trap>{
+        let mut b = [0];
+        match caller.data_mut().input.read(&mut b){
+            Ok(0) => Ok(-1),
+            Ok(_) => Ok(b[0] as i32),
+            Err(e) => Err(io_error(e))
+        }
+    }).unwrap();
+    linker.func_wrap(HOST, "exit", |_ : Caller<Host>, code : i32| -> Result<(), Trap>{
+        Err(Trap::i32_exit(code))
+    }).unwrap();
+    linker.func_wrap(HOST, "div_by_zero", |_ : Caller<Host>, line : i32| -> Result<(), Trap>{
+        let _ = writeln!(::std::io::stderr(), "line {}: division by zero", line);
+        Err(Trap::i32_exit(1))
+    }).unwrap();
+    let instance = try!(linker.instantiate(&mut store, &module).and_then(|i| i.start(&mut store))
+        .map_err(|e| e.to_string()));
+    let main = try!(instance.get_typed_func::<(), i32>(&store, "main").map_err(|e| e.to_string()));
+    match main.call(&mut store, ()){
+        Ok(code) => Ok(code),
+        Err(trap) => match trap.i32_exit_status(){
+            Some(code) => Ok(code),
+            None => Err(trap.to_string())
+        }
+    }
+}
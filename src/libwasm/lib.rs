@@ -0,0 +1,207 @@
+#![crate_name = "wasm"]
+#![crate_type = "dylib"]
+#![crate_type = "rlib"]
+//! WebAssembly backend: turns the mid-level IR into a wasm module that
+//! imports its input and output from the host, and runs it with wasmi.
+//!
+//! The memory starts with a few unused bytes, so that nil is no valid
+//! address, followed by the string literals and then the heap.
+
+extern crate ir;
+extern crate wasmi;
+#[cfg(test)]
+extern crate syntax;
+
+pub mod module;
+pub mod codegen;
+pub mod runtime;
+pub mod encode;
+pub mod wat;
+pub mod host;
+
+use std::collections::HashMap;
+use ir::tree::{Program, Ty};
+use module::{Module, Function, FuncType, PAGE_SIZE};
+
+//where the string literals start
+pub const DATA_START : u32 = 16;
+
+//the module of a whole program, runtime included. Its `main` is exported
+pub fn compile(prog : Program) -> Module{
+    let mut gen = prog.gen;
+    let mut module = Module::default();
+    runtime::add(&mut module);
+
+    let mut strings = HashMap::new();
+    let mut data = Vec::new();
+    for (l, s) in prog.strings{
+        strings.insert(l, DATA_START + data.len() as u32);
+        data.extend_from_slice(&s);
+        data.push(0);
+    }
+    let heap = (DATA_START + data.len() as u32 + 7) & !7;
+    module.globals[runtime::HEAP as usize].init = heap as i32;
+    module.memory_pages = heap / PAGE_SIZE + 1;
+    if !data.is_empty(){
+        module.data.push((DATA_START, data));
+    }
+
+    //every function is declared before any is generated, so that calls can
+    //refer to the ones that come later
+    let first = module.functions.len();
+    for p in prog.procs.iter(){
+        module.functions.push(Function {
+            name : p.name.0.clone(),
+            ty : FuncType { params : p.params.len() as u32, result : p.ret != Ty::Void },
+            locals : 0,
+            body : Vec::new(),
+            export : p.name.0 == "main"
+        });
+    }
+    for (i, p) in prog.procs.into_iter().enumerate(){
+        let (locals, body) = codegen::codegen(p, &module, &strings, &mut gen);
+        module.functions[first + i].locals = locals;
+        module.functions[first + i].body = body;
+    }
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use syntax::visit::{Visitor};
+    use syntax::visitor_impl::{TypeChecker};
+    use syntax::parse::parser::{Parser};
+    use ir::translate::translate;
+    use ir::opt::{optimize, ALL_PASSES};
+    use ir::tree::*;
+    use super::*;
+
+    fn module(src : &str, optimized : bool) -> Module{
+        let mut p = Parser::new(src.to_string());
+        p.start_lexer();
+        let (_, mut b_expr) = p.expr().unwrap();
+        TypeChecker::new().visit_expr(&mut *b_expr);
+        let mut prog = translate(&*b_expr).unwrap();
+        if optimized{
+            optimize(&mut prog, ALL_PASSES, None).unwrap();
+        }
+        compile(prog)
+    }
+
+    //the exit code and output of the program, built with and without the IR passes
+    fn run_with_input(src : &str, input : &str) -> (i32, String){
+        let mut results = Vec::new();
+        for &optimized in [false, true].iter(){
+            let bytes = encode::encode(&module(src, optimized));
+            host::validate(&bytes).unwrap();
+            let mut out = Vec::new();
+            let code = host::run(&bytes, &mut out, &mut input.as_bytes()).unwrap();
+            results.push((code, String::from_utf8(out).unwrap()));
+        }
+        assert_eq!(results[0], results[1]);
+        results.pop().unwrap()
+    }
+
+    fn run(src : &str) -> (i32, String){
+        run_with_input(src, "")
+    }
+
+    #[test]
+    fn test_wasm_print(){
+        assert_eq!(run("(print(\"sum: \"); print(1+2))"), (0, String::from("sum: 3\n")));
+    }
+
+    #[test]
+    fn test_wasm_loops(){
+        let src = "let var a : int := 0 in let var b : int := 2 in for i := 1 to 4 do (a := a + i; b := a + 1; print(b)) end end end";
+        assert_eq!(run(src).1, "2\n4\n7\n11\n");
+        assert_eq!(run("let var a : int := 0 in while 10 > a do (a := a + 3; print(a)) end").1, "3\n6\n9\n12\n");
+    }
+
+    #[test]
+    fn test_wasm_functions(){
+        let src = "let function add(a:int, b:int) : int = a + b in print(add(40, 2)) end";
+        assert_eq!(run(src).1, "42\n");
+        let src = "let function g(n:int) : int = n + 1 in let function f(n:int) : int = g(5) + n in print(f(3)) end end";
+        assert_eq!(run(src).1, "9\n");
+    }
+
+    #[test]
+    fn test_wasm_arrays_and_records(){
+        let src = "let var a : array := array of int[3] of 7 in (a[1] := 5; print(a[1]); print(a[2])) end";
+        assert_eq!(run(src).1, "5\n7\n");
+        //fields start out zeroed
+        let src = "let var r : rec := {f:int, g:string} in print(r.f) end";
+        assert_eq!(run(src).1, "0\n");
+    }
+
+    #[test]
+    fn test_wasm_strings(){
+        assert_eq!(run("(print(chr(100)); print(size(\"abc\")); print(ord(\"b\")); print(ord(\"\")))").1, "d3\n98\n-1\n");
+        let src = "let function f(a:string) = (print(concat(a, a)); print(substring(a, 1, 2)); print(concat(a, getchar()))) in f(\"hey\") end";
+        assert_eq!(run_with_input(src, "!").1, "heyheyeyhey!");
+    }
+
+    #[test]
+    fn test_wasm_strcmp(){
+        //the parser can't read string comparisons yet
+        let strings = vec![(Label(String::from("L0")), b"ab".to_vec()), (Label(String::from("L1")), b"abc".to_vec())];
+        let strcmp = |a : &str, b : &str| Stm::Exp(Exp::Call(Label(String::from("tiger_print_int")),
+            vec![Exp::Call(Label(String::from("tiger_strcmp")), vec![Exp::Name(Label(String::from(a))), Exp::Name(Label(String::from(b)))], Ty::I32)], Ty::Void));
+        let body = vec![strcmp("L0", "L0"), strcmp("L0", "L1"), strcmp("L1", "L0"), Stm::Return(Some(Exp::Const(0)))];
+        let prog = Program { procs : vec![Proc { name : Label(String::from("main")), params : Vec::new(), ret : Ty::I32, body : body }],
+                             strings : strings, gen : Gen::default() };
+        let mut out = Vec::new();
+        host::run(&encode::encode(&compile(prog)), &mut out, &mut "".as_bytes()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0\n-99\n99\n");
+    }
+
+    #[test]
+    fn test_wasm_getchar(){
+        assert_eq!(run_with_input("(print(getchar()); print(getchar()); print(ord(getchar())))", "hi").1, "hi-1\n");
+    }
+
+    #[test]
+    fn test_wasm_exit_code(){
+        assert_eq!(run("exit(4)").0, 4);
+        assert_eq!(run("print(1)").0, 0);
+    }
+
+    #[test]
+    fn test_wasm_grows_memory(){
+        //more than the first page of the memory
+        let src = "let var a : array := array of int[20000] of 1 in (a[19999] := 3; print(a[19999]); print(a[0])) end";
+        assert_eq!(run(src).1, "3\n1\n");
+    }
+
+    #[test]
+    fn test_wasm_division(){
+        assert_eq!(run("let var a : int := 0 - 7 in (print(17 mod 5); print(a mod 3); print(a / 2)) end").1, "2\n-1\n-3\n");
+        assert_eq!(run("let var a : int := 0 in (print(1); print(5 / a)) end"), (1, String::from("1\n")));
+        assert_eq!(run("let var a : int := 0 in print(5 mod a) end"), (1, String::new()));
+        let src = "let var min : int := 0 - 2147483647 - 1 in let var m : int := 0 - 1 in (print(min / m); print(min mod m)) end end";
+        assert_eq!(run(src), (0, String::from("-2147483648\n0\n")));
+    }
+
+    #[test]
+    fn test_wasm_traps(){
+        //a division the translation didn't guard
+        let div = Exp::BinOp(BinOp::Div, Box::new(Exp::Const(10)), Box::new(Exp::Const(0)));
+        let body = vec![Stm::Exp(Exp::Call(Label(String::from("tiger_print_int")), vec![div], Ty::Void)), Stm::Return(Some(Exp::Const(0)))];
+        let prog = Program { procs : vec![Proc { name : Label(String::from("main")), params : Vec::new(), ret : Ty::I32, body : body }],
+                             strings : Vec::new(), gen : Gen::default() };
+        let bytes = encode::encode(&compile(prog));
+        let err = host::run(&bytes, &mut Vec::new(), &mut "".as_bytes()).unwrap_err();
+        assert!(err.contains("divide by zero"), "{}", err);
+        assert!(host::validate(b"\0asm\x02\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_wat(){
+        let wat = wat::format(&module("print(\"hi\")", false));
+        assert!(wat.contains("(import \"env\" \"print\" (func $tiger_print (param i32)))"), "{}", wat);
+        assert!(wat.contains("(func $main (export \"main\") (result i32)"), "{}", wat);
+        assert!(wat.contains("(data (i32.const 16) \"hi\\00\")"), "{}", wat);
+        assert!(wat.contains("      i32.const 16\n      call $tiger_print\n"), "{}", wat);
+    }
+}
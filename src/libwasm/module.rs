@@ -0,0 +1,193 @@
+//! The WebAssembly module the backend builds, before it is written out as
+//! text or binary.
+//!
+//! Tiger only needs one value type: integers and pointers into the linear
+//! memory are both i32, so a function type is just the number of parameters
+//! and whether there is a result.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FuncType{
+    pub params : u32,
+    pub result : bool
+}
+
+//blocks, loops and ifs never take or leave values
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Instr{
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    //relative depth of the target block
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    //index in the function space, imports first
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    //the static offset added to the address
+    I32Load(u32),
+    I32Load8U(u32),
+    I32Store(u32),
+    I32Store8(u32),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtS,
+    I32GtU,
+    I32LeS,
+    I32LeU,
+    I32GeS,
+    I32GeU,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivS,
+    I32And,
+    I32Shl,
+    I32ShrU
+}
+
+impl Instr{
+    //the text format name
+    pub fn name(&self) -> &'static str{
+        match *self{
+            Instr::Unreachable => "unreachable",
+            Instr::Block => "block",
+            Instr::Loop => "loop",
+            Instr::If => "if",
+            Instr::Else => "else",
+            Instr::End => "end",
+            Instr::Br(_) => "br",
+            Instr::BrIf(_) => "br_if",
+            Instr::BrTable(..) => "br_table",
+            Instr::Return => "return",
+            Instr::Call(_) => "call",
+            Instr::Drop => "drop",
+            Instr::Select => "select",
+            Instr::LocalGet(_) => "local.get",
+            Instr::LocalSet(_) => "local.set",
+            Instr::LocalTee(_) => "local.tee",
+            Instr::GlobalGet(_) => "global.get",
+            Instr::GlobalSet(_) => "global.set",
+            Instr::I32Load(_) => "i32.load",
+            Instr::I32Load8U(_) => "i32.load8_u",
+            Instr::I32Store(_) => "i32.store",
+            Instr::I32Store8(_) => "i32.store8",
+            Instr::MemorySize => "memory.size",
+            Instr::MemoryGrow => "memory.grow",
+            Instr::I32Const(_) => "i32.const",
+            Instr::I32Eqz => "i32.eqz",
+            Instr::I32Eq => "i32.eq",
+            Instr::I32Ne => "i32.ne",
+            Instr::I32LtS => "i32.lt_s",
+            Instr::I32LtU => "i32.lt_u",
+            Instr::I32GtS => "i32.gt_s",
+            Instr::I32GtU => "i32.gt_u",
+            Instr::I32LeS => "i32.le_s",
+            Instr::I32LeU => "i32.le_u",
+            Instr::I32GeS => "i32.ge_s",
+            Instr::I32GeU => "i32.ge_u",
+            Instr::I32Add => "i32.add",
+            Instr::I32Sub => "i32.sub",
+            Instr::I32Mul => "i32.mul",
+            Instr::I32DivS => "i32.div_s",
+            Instr::I32And => "i32.and",
+            Instr::I32Shl => "i32.shl",
+            Instr::I32ShrU => "i32.shr_u"
+        }
+    }
+}
+
+//a function imported from the host
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import{
+    pub module : String,
+    pub name : String,
+    //the name of the function inside the module
+    pub func : String,
+    pub ty : FuncType
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function{
+    pub name : String,
+    pub ty : FuncType,
+    //locals after the parameters
+    pub locals : u32,
+    //without the final `end`
+    pub body : Vec<Instr>,
+    pub export : bool
+}
+
+//a mutable i32 global and its initial value
+#[derive(Debug, PartialEq, Clone)]
+pub struct Global{
+    pub name : String,
+    pub init : i32
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Module{
+    pub imports : Vec<Import>,
+    pub functions : Vec<Function>,
+    pub globals : Vec<Global>,
+    //initial size of the memory in 64k pages. It is exported as "memory"
+    pub memory_pages : u32,
+    //(address, bytes)
+    pub data : Vec<(u32, Vec<u8>)>
+}
+
+pub const PAGE_SIZE : u32 = 65536;
+
+impl Module{
+    //the index of a function or import in the function space
+    pub fn func_index(&self, name : &str) -> Option<u32>{
+        self.imports.iter().map(|i| &i.func).chain(self.functions.iter().map(|f| &f.name))
+            .position(|n| n == name).map(|i| i as u32)
+    }
+
+    pub fn func_name(&self, index : u32) -> &str{
+        let i = index as usize;
+        if i < self.imports.len() {&self.imports[i].func} else {&self.functions[i - self.imports.len()].name}
+    }
+
+    //the distinct function types in the order of their first use
+    pub fn types(&self) -> Vec<FuncType>{
+        let mut types = Vec::new();
+        for ty in self.imports.iter().map(|i| i.ty).chain(self.functions.iter().map(|f| f.ty)){
+            if !types.contains(&ty){
+                types.push(ty);
+            }
+        }
+        types
+    }
+}
+
+impl fmt::Display for FuncType{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        for _ in 0..self.params{
+            try!(f.write_str(" (param i32)"));
+        }
+        if self.result{
+            try!(f.write_str(" (result i32)"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! The runtime library of tree::RUNTIME for the wasm backend. Output, input
+//! and exit are imported from the host; everything else works on the linear
+//! memory in wasm itself.
+//!
+//! Strings are NUL terminated like in the other backends. Memory comes from
+//! a bump allocator and is never freed, so every allocation is memory wasm
+//! has zeroed when the module started or grew.
+//!
+//! The helpers that aren't part of tree::RUNTIME have a '.' in their name so
+//! that they can't clash with a Tiger function.
+
+use module::*;
+use module::Instr::*;
+
+//(the IR name, the name of the import, parameters, result)
+pub const IMPORTS : &'static [(&'static str, &'static str, u32, bool)] = &[
+    ("tiger_print", "print", 1, false),
+    ("tiger_print_int", "print_int", 1, false),
+    ("tiger_flush", "flush", 0, false),
+    //the next byte of the input, or -1 at its end
+    ("env.getchar", "getchar", 0, true),
+    ("tiger_exit", "exit", 1, false),
+    //(line) reports a division by zero there and exits with 1
+    ("tiger_div_by_zero", "div_by_zero", 1, false)
+];
+
+//the module all imports come from
+pub const HOST : &'static str = "env";
+
+//the global with the address of the next allocation
+pub const HEAP : u32 = 0;
+
+fn function(module : &mut Module, name : &str, params : u32, result : bool, locals : u32, body : Vec<Instr>){
+    module.functions.push(Function {
+        name : String::from(name),
+        ty : FuncType { params : params, result : result },
+        locals : locals,
+        body : body,
+        export : false
+    });
+}
+
+//local `n` += 1
+fn incr(n : u32) -> Vec<Instr>{
+    vec![LocalGet(n), I32Const(1), I32Add, LocalSet(n)]
+}
+
+//adds the imports, the heap pointer and the runtime functions to an empty module
+pub fn add(module : &mut Module){
+    for &(func, name, params, result) in IMPORTS.iter(){
+        module.imports.push(Import {
+            module : String::from(HOST),
+            name : String::from(name),
+            func : String::from(func),
+            ty : FuncType { params : params, result : result }
+        });
+    }
+    module.globals.push(Global { name : String::from("heap"), init : 0 });
+    let f = |module : &Module, name : &str| Call(module.func_index(name).unwrap());
+
+    //(n) rounds the heap pointer up to 8 bytes and grows the memory when it
+    //moves past its end
+    function(module, "tiger_alloc", 1, true, 1, vec![
+        GlobalGet(HEAP), LocalSet(1),
+        GlobalGet(HEAP), LocalGet(0), I32Add, I32Const(7), I32Add, I32Const(-8), I32And, GlobalSet(HEAP),
+        Block,
+        GlobalGet(HEAP), MemorySize, I32Const(16), I32Shl, I32LeU, BrIf(0),
+        GlobalGet(HEAP), MemorySize, I32Const(16), I32Shl, I32Sub, I32Const(16), I32ShrU, I32Const(1), I32Add,
+        MemoryGrow, I32Const(-1), I32Ne, BrIf(0),
+        Unreachable,
+        End,
+        LocalGet(1)]);
+
+    //(dst, src, n)
+    let mut body = vec![Block, Loop, LocalGet(2), I32Eqz, BrIf(1),
+                        LocalGet(0), LocalGet(1), I32Load8U(0), I32Store8(0)];
+    body.extend(incr(0));
+    body.extend(incr(1));
+    body.extend(vec![LocalGet(2), I32Const(-1), I32Add, LocalSet(2), Br(0), End, End]);
+    function(module, "tiger.memcpy", 3, false, 0, body);
+
+    let mut body = vec![Block, Loop, LocalGet(0), LocalGet(1), I32Add, I32Load8U(0), I32Eqz, BrIf(1)];
+    body.extend(incr(1));
+    body.extend(vec![Br(0), End, End, LocalGet(1)]);
+    function(module, "tiger_size", 1, true, 1, body);
+
+    let body = vec![I32Const(2), f(module, "tiger_alloc"), LocalTee(1), LocalGet(0), I32Store8(0), LocalGet(1)];
+    function(module, "tiger_chr", 1, true, 1, body);
+
+    //-1 for the empty string
+    function(module, "tiger_ord", 1, true, 1, vec![LocalGet(0), I32Load8U(0), LocalTee(1), I32Const(-1), LocalGet(1), Select]);
+
+    //"" at the end of the input
+    let body = vec![f(module, "env.getchar"), LocalTee(0), I32Const(0), I32LtS,
+                    If, I32Const(1), f(module, "tiger_alloc"), Return, End,
+                    LocalGet(0), f(module, "tiger_chr")];
+    function(module, "tiger_getchar", 0, true, 1, body);
+
+    //(s, first, n)
+    let body = vec![LocalGet(2), I32Const(1), I32Add, f(module, "tiger_alloc"), LocalTee(3),
+                    LocalGet(0), LocalGet(1), I32Add, LocalGet(2), f(module, "tiger.memcpy"),
+                    LocalGet(3)];
+    function(module, "tiger_substring", 3, true, 1, body);
+
+    let (size, memcpy) = (f(module, "tiger_size"), f(module, "tiger.memcpy"));
+    let body = vec![LocalGet(0), size.clone(), LocalSet(2),
+                    LocalGet(1), size, LocalSet(3),
+                    LocalGet(2), LocalGet(3), I32Add, I32Const(1), I32Add, f(module, "tiger_alloc"), LocalSet(4),
+                    LocalGet(4), LocalGet(0), LocalGet(2), memcpy.clone(),
+                    LocalGet(4), LocalGet(2), I32Add, LocalGet(1), LocalGet(3), memcpy,
+                    LocalGet(4)];
+    function(module, "tiger_concat", 2, true, 3, body);
+
+    //the difference of the first bytes that differ
+    let mut body = vec![Block, Loop,
+                        LocalGet(0), I32Load8U(0), LocalSet(2),
+                        LocalGet(1), I32Load8U(0), LocalSet(3),
+                        LocalGet(2), LocalGet(3), I32Ne, BrIf(1),
+                        LocalGet(2), I32Eqz, BrIf(1)];
+    body.extend(incr(0));
+    body.extend(incr(1));
+    body.extend(vec![Br(0), End, End, LocalGet(2), LocalGet(3), I32Sub]);
+    function(module, "tiger_strcmp", 2, true, 2, body);
+}
@@ -0,0 +1,112 @@
+//! The text format of a module, as wat2wasm and wasm-tools read it.
+
+use module::*;
+
+//a string literal of the text format
+fn escape(bytes : &[u8]) -> String{
+    let mut out = String::new();
+    for &b in bytes{
+        match b{
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b if b >= 0x20 && b < 0x7f => out.push(b as char),
+            _ => out.push_str(&format!("\\{:02x}", b))
+        }
+    }
+    out
+}
+
+fn instr(module : &Module, instr : &Instr) -> String{
+    let name = instr.name();
+    match *instr{
+        Instr::Br(n) | Instr::BrIf(n) | Instr::LocalGet(n) | Instr::LocalSet(n) | Instr::LocalTee(n) =>
+            format!("{} {}", name, n),
+        Instr::BrTable(ref targets, default) => {
+            let targets : Vec<String> = targets.iter().chain(Some(&default)).map(|t| t.to_string()).collect();
+            format!("{} {}", name, targets.join(" "))
+        },
+        Instr::Call(f) => format!("{} ${}", name, module.func_name(f)),
+        Instr::GlobalGet(g) | Instr::GlobalSet(g) => format!("{} ${}", name, module.globals[g as usize].name),
+        Instr::I32Load(offset) | Instr::I32Load8U(offset) | Instr::I32Store(offset) | Instr::I32Store8(offset) if offset > 0 =>
+            format!("{} offset={}", name, offset),
+        Instr::I32Const(n) => format!("{} {}", name, n),
+        _ => String::from(name)
+    }
+}
+
+pub fn format(module : &Module) -> String{
+    let mut out = String::from("(module\n");
+    for i in module.imports.iter(){
+        out.push_str(&format!("  (import \"{}\" \"{}\" (func ${}{}))\n", i.module, i.name, i.func, i.ty));
+    }
+    out.push_str(&format!("  (memory (export \"memory\") {})\n", module.memory_pages));
+    for g in module.globals.iter(){
+        out.push_str(&format!("  (global ${} (mut i32) (i32.const {}))\n", g.name, g.init));
+    }
+    for f in module.functions.iter(){
+        out.push_str(&format!("  (func ${}", f.name));
+        if f.export{
+            out.push_str(&format!(" (export \"{}\")", f.name));
+        }
+        out.push_str(&format!("{}\n", f.ty));
+        if f.locals > 0{
+            out.push_str(&format!("    (local{})\n", " i32".repeat(f.locals as usize)));
+        }
+        let mut depth = 2;
+        for i in f.body.iter(){
+            if *i == Instr::End || *i == Instr::Else{
+                depth -= 1;
+            }
+            out.push_str(&format!("{}{}\n", "  ".repeat(depth), instr(module, i)));
+            match *i{
+                Instr::Block | Instr::Loop | Instr::If | Instr::Else => depth += 1,
+                _ => ()
+            }
+        }
+        out.push_str("  )\n");
+    }
+    for &(addr, ref bytes) in module.data.iter(){
+        out.push_str(&format!("  (data (i32.const {}) \"{}\")\n", addr, escape(bytes)));
+    }
+    out.push_str(")\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format(){
+        let module = Module {
+            imports : vec![Import { module : String::from("env"), name : String::from("print_int"), func : String::from("tiger_print_int"), ty : FuncType { params : 1, result : false } }],
+            functions : vec![Function {
+                name : String::from("main"),
+                ty : FuncType { params : 0, result : true },
+                locals : 2,
+                body : vec![Instr::Block, Instr::I32Const(16), Instr::I32Load(8), Instr::Call(0), Instr::End, Instr::I32Const(0)],
+                export : true
+            }],
+            globals : vec![Global { name : String::from("heap"), init : 32 }],
+            memory_pages : 1,
+            data : vec![(16, b"hi \"x\"\n\0".to_vec())]
+        };
+        assert_eq!(format(&module), "\
+(module
+  (import \"env\" \"print_int\" (func $tiger_print_int (param i32)))
+  (memory (export \"memory\") 1)
+  (global $heap (mut i32) (i32.const 32))
+  (func $main (export \"main\") (result i32)
+    (local i32 i32)
+    block
+      i32.const 16
+      i32.load offset=8
+      call $tiger_print_int
+    end
+    i32.const 0
+  )
+  (data (i32.const 16) \"hi \\\"x\\\"\\0a\\00\")
+)
+");
+    }
+}
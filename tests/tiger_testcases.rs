@@ -0,0 +1,106 @@
+extern crate syntax;
+extern crate ir;
+#[cfg(feature = "llvm")]
+extern crate trans;
+extern crate native;
+extern crate bytecode;
+extern crate wasm;
+extern crate cgen;
+
+use std::fs::File;
+use std::io::Read;
+use self::syntax::ast::Expr;
+use self::syntax::ptr::B;
+use self::syntax::parse::parser::{Parser};
+use self::syntax::visit::{Visitor};
+use self::syntax::visitor_impl::{TypeChecker};
+use self::syntax::escape::find_escapes;
+#[cfg(feature = "llvm")]
+use self::trans::base::translate;
+#[cfg(feature = "llvm")]
+use self::trans::lower::lower;
+
+//the programs of the tiger test suite are in tests/testcases. Most of the
+//ones with errors only have type errors, so the parser accepts them
+fn path(name : &str) -> String{
+    format!("{}/tests/testcases/{}.tig", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn parse(name : &str) -> Result<B<Expr>, String>{
+    let mut src = String::new();
+    File::open(&path(name)).unwrap().read_to_string(&mut src).unwrap();
+    Parser::new(src).parse().map_err(|errors| {
+        errors.iter().map(|e| format!("{}: {}", name, e)).collect::<Vec<_>>().join("\n")
+    })
+}
+
+//a valid program has to get through every backend. The llvm modules, when
+//charon is built with llvm, are checked by llvm's verifier as well
+fn compile(name : &str) -> Result<(), String>{
+    let mut expr = try!(parse(name));
+    TypeChecker::new().visit_expr(&mut *expr);
+    find_escapes(&expr);
+    let failed = |backend : &str, e : String| format!("{} ({} backend): {}", name, backend, e);
+    try!(bytecode::compile::compile(&expr).map_err(|e| failed("bytecode", e)));
+    try!(cgen::compile(&expr, &path(name)).map_err(|e| failed("c", e)));
+    let prog = try!(ir::translate::translate(&*expr).map_err(|e| failed("ir", e)));
+    #[cfg(feature = "llvm")]
+    try!(lower(&prog).and_then(|ctxt| ctxt.verify()).map_err(|e| failed("ir", e)));
+    native::compile(prog);
+    wasm::compile(try!(ir::translate::translate(&*expr).map_err(|e| failed("wasm", e))));
+    #[cfg(feature = "llvm")]
+    try!(translate(&*expr).and_then(|ctxt| ctxt.verify()).map_err(|e| failed("llvm", e)));
+    Ok(())
+}
+
+macro_rules! compiles{
+    ($($name : ident),*) => {
+        $(
+            #[test]
+            fn $name(){
+                if let Err(error) = compile(stringify!($name)){
+                    panic!("Expected the program to compile but found\n{}", error);
+                }
+            }
+        )*
+    }
+}
+
+macro_rules! accepts{
+    ($($name : ident),*) => {
+        $(
+            #[test]
+            fn $name(){
+                if let Err(errors) = parse(stringify!($name)){
+                    panic!("Expected the program to parse but found\n{}", errors);
+                }
+            }
+        )*
+    }
+}
+
+macro_rules! rejects{
+    ($($name : ident),*) => {
+        $(
+            #[test]
+            fn $name(){
+                assert!(parse(stringify!($name)).is_err(), "Expected a syntax error");
+            }
+        )*
+    }
+}
+
+compiles!(test1, test2, test3, test5, test8, test12, test27, test30, test37, test41,
+          test42, test47, test48);
+
+//the programs with type errors, and the valid ones the type checker
+//doesn't handle yet: test4, test6, test7, test44, test46, queens and merge
+accepts!(test4, test6, test7, test9, test10,
+         test11, test13, test14, test15, test16, test17, test18, test19, test20,
+         test21, test22, test23, test24, test25, test26, test28, test29,
+         test31, test32, test33, test34, test35, test36, test38, test39, test40,
+         test43, test44, test45, test46,
+         queens, merge);
+
+//nil can't follow a type id
+rejects!(test49);